@@ -0,0 +1,142 @@
+//! Rejection-guided tuning for `run --adaptive`. A bandit per generation knob picks the
+//! settings of each program, rewarded by how far into the program the verifier got, and
+//! templates the verifier keeps stopping at are drawn less often.
+
+use crate::{instruction_slots, GenOptions, Instruction, LiveRegs};
+use rand::Rng;
+
+/// Share of programs each knob takes a random setting for rather than the best one so far.
+/// Knobs explore independently, so their effects are told apart.
+const EXPLORATION: f64 = 0.1;
+
+/// Factor the weight of a template the verifier stops at is multiplied by
+const PENALTY: f64 = 0.9;
+
+/// Factor the weights of the templates of accepted programs are multiplied by
+const BONUS: f64 = 1.05;
+
+/// Bounds of template weights, so no template drops out or takes over
+const MIN_WEIGHT: f64 = 0.05;
+const MAX_WEIGHT: f64 = 20.0;
+
+const LIVE_REGS: [LiveRegs; 3] = [LiveRegs::Off, LiveRegs::Prefer, LiveRegs::Require];
+const REACHABLE_ONLY: [bool; 2] = [false, true];
+const DEPENDENCY_BIAS: [f64; 3] = [0.0, 0.5, 0.9];
+
+/// Epsilon-greedy choice among the settings of one knob
+struct Bandit {
+    pulls: Vec<u32>,
+    rewards: Vec<f64>,
+    chosen: usize,
+}
+
+impl Bandit {
+    fn new(settings: usize) -> Self {
+        Self { pulls: vec![0; settings], rewards: vec![0.0; settings], chosen: 0 }
+    }
+
+    fn mean(&self, arm: usize) -> f64 {
+        self.rewards[arm] / self.pulls[arm].max(1) as f64
+    }
+
+    /// Tries every setting once, then mostly the one with the best mean reward
+    fn pick<R: Rng>(&mut self, rng: &mut R) -> usize {
+        let untried: Vec<usize> = (0..self.pulls.len()).filter(|&arm| self.pulls[arm] == 0).collect();
+        self.chosen = if !untried.is_empty() {
+            untried[rng.random_range(0..untried.len())]
+        } else if rng.random_bool(EXPLORATION) {
+            rng.random_range(0..self.pulls.len())
+        } else {
+            (0..self.pulls.len()).max_by(|&a, &b| self.mean(a).total_cmp(&self.mean(b))).unwrap()
+        };
+        self.chosen
+    }
+
+    fn reward(&mut self, reward: f64) {
+        self.pulls[self.chosen] += 1;
+        self.rewards[self.chosen] += reward;
+    }
+
+    fn print<T: std::fmt::Debug>(&self, name: &str, settings: &[T]) {
+        let means: Vec<String> = settings.iter().enumerate().map(|(i, s)| format!("{:?} {:.2} ({})", s, self.mean(i), self.pulls[i])).collect();
+        println!("  {}: {}", name, means.join(", "));
+    }
+}
+
+/// Index of the last instruction the verifier log mentions, where the verifier stopped
+fn stop_index(log: &str) -> Option<usize> {
+    log.lines().rev().find_map(|line| line.trim().split_once(": ").and_then(|(index, _)| index.parse().ok()))
+}
+
+pub struct Tuner {
+    live_regs: Bandit,
+    reachable_only: Bandit,
+    dependency_bias: Bandit,
+    /// Weights of the enabled templates
+    weights: Vec<f64>,
+}
+
+impl Tuner {
+    pub fn new(opts: &GenOptions) -> Self {
+        Self {
+            live_regs: Bandit::new(LIVE_REGS.len()),
+            reachable_only: Bandit::new(REACHABLE_ONLY.len()),
+            dependency_bias: Bandit::new(DEPENDENCY_BIAS.len()),
+            weights: vec![1.0; opts.templates.len()],
+        }
+    }
+
+    /// Options to generate the next program with
+    pub fn options<R: Rng>(&mut self, rng: &mut R, opts: &GenOptions) -> GenOptions {
+        let mut tuned = opts.clone();
+        tuned.live_regs = LIVE_REGS[self.live_regs.pick(rng)];
+        tuned.reachable_only = REACHABLE_ONLY[self.reachable_only.pick(rng)];
+        tuned.dependency_bias = DEPENDENCY_BIAS[self.dependency_bias.pick(rng)];
+        tuned.template_weights = self.weights.clone();
+        tuned
+    }
+
+    /// Learns from the target accepting the program generated with the latest options, or
+    /// rejecting it with `log`. Rejections score the share of the program before the
+    /// instruction the verifier stopped at, and make that instruction's template rarer.
+    pub fn update(&mut self, opts: &GenOptions, bytes: &[u8], accepted: bool, log: &str) {
+        let starts = instruction_slots(bytes);
+        let template = |pc: usize| {
+            let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+            opts.templates.iter().position(|t| t.matches(&insn))
+        };
+        let reward = if accepted {
+            for &pc in &starts {
+                if let Some(t) = template(pc) {
+                    self.weights[t] = (self.weights[t] * BONUS).min(MAX_WEIGHT);
+                }
+            }
+            1.0
+        } else {
+            match stop_index(log).and_then(|pc| starts.iter().position(|&s| s == pc).map(|i| (pc, i))) {
+                Some((pc, i)) => {
+                    if let Some(t) = template(pc) {
+                        self.weights[t] = (self.weights[t] * PENALTY).max(MIN_WEIGHT);
+                    }
+                    i as f64 / starts.len() as f64
+                }
+                None => 0.0,
+            }
+        };
+        self.live_regs.reward(reward);
+        self.reachable_only.reward(reward);
+        self.dependency_bias.reward(reward);
+    }
+
+    /// Prints the mean reward of every knob setting, and the templates drawn least often
+    pub fn print(&self, opts: &GenOptions) {
+        println!("adaptive generation, mean depth reached per setting (programs):");
+        self.live_regs.print("live-regs", &LIVE_REGS);
+        self.reachable_only.print("reachable-only", &REACHABLE_ONLY);
+        self.dependency_bias.print("dependency-bias", &DEPENDENCY_BIAS);
+        let mut order: Vec<usize> = (0..self.weights.len()).collect();
+        order.sort_by(|&a, &b| self.weights[a].total_cmp(&self.weights[b]));
+        let rarest: Vec<String> = order.iter().take(10).map(|&t| format!("{:#04x} {:.2}", opts.templates[t].opcode, self.weights[t])).collect();
+        println!("  rarest templates: {}", rarest.join(", "));
+    }
+}
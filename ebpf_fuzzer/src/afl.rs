@@ -0,0 +1,244 @@
+//! Runs AFL-instrumented targets through their forkserver, and reads the coverage bitmap
+//! they write to shared memory. This speaks the classic forkserver protocol: a 4-byte hello
+//! on the status pipe, then for each run a 4-byte request on the control pipe answered by
+//! the child's pid and its wait status.
+
+use crate::fuzz::bucket;
+use crate::{limits, repro, Args};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// Size of the coverage bitmap instrumented targets write to
+const MAP_SIZE: usize = 1 << 16;
+
+/// Descriptors the forkserver reads requests from and writes statuses to
+const CONTROL_FD: i32 = 198;
+const STATUS_FD: i32 = 199;
+
+/// How a run of the target ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Exited(i32),
+    Signaled(i32),
+    TimedOut,
+}
+
+impl Status {
+    fn from_wait(status: i32) -> Status {
+        match status & 0x7f {
+            0 => Status::Exited((status >> 8) & 0xff),
+            // Stopped: a persistent-mode child done with its input
+            0x7f => Status::Exited(0),
+            signal => Status::Signaled(signal),
+        }
+    }
+}
+
+/// The System V shared memory segment holding the coverage bitmap
+struct Shm {
+    id: i32,
+    map: *mut u8,
+}
+
+impl Shm {
+    fn new() -> io::Result<Shm> {
+        // SAFETY: plain System V calls; the segment is checked before use
+        unsafe {
+            let id = libc::shmget(libc::IPC_PRIVATE, MAP_SIZE, libc::IPC_CREAT | libc::IPC_EXCL | 0o600);
+            if id < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let map = libc::shmat(id, std::ptr::null(), 0);
+            if map as isize == -1 {
+                let error = io::Error::last_os_error();
+                libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut());
+                return Err(error);
+            }
+            Ok(Shm { id, map: map as *mut u8 })
+        }
+    }
+
+    fn bitmap(&mut self) -> &mut [u8] {
+        // SAFETY: the segment is MAP_SIZE bytes and stays attached while self lives
+        unsafe { std::slice::from_raw_parts_mut(self.map, MAP_SIZE) }
+    }
+}
+
+impl Drop for Shm {
+    fn drop(&mut self) {
+        // SAFETY: detaches and removes the segment created in new
+        unsafe {
+            libc::shmdt(self.map as *const libc::c_void);
+            libc::shmctl(self.id, libc::IPC_RMID, std::ptr::null_mut());
+        }
+    }
+}
+
+fn pipe_pair() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe fills both descriptors on success, which the files then own
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])))
+    }
+}
+
+/// Reads a 4-byte word from the forkserver, or None if none arrives within the timeout
+fn read_word(file: &mut File, timeout: Duration) -> Option<i32> {
+    let mut fd = libc::pollfd { fd: file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    // SAFETY: polls a single descriptor the file owns
+    let ready = unsafe { libc::poll(&mut fd, 1, timeout.as_millis().min(i32::MAX as u128) as i32) };
+    if ready <= 0 {
+        return None;
+    }
+    let mut word = [0u8; 4];
+    file.read_exact(&mut word).ok()?;
+    Some(i32::from_ne_bytes(word))
+}
+
+/// A running forkserver, with the file inputs are written to
+pub struct Forkserver {
+    process: Child,
+    control: File,
+    status: File,
+    input: File,
+    input_path: PathBuf,
+    stderr: File,
+    stderr_path: PathBuf,
+    shm: Shm,
+    /// Bucketed hit counts no run has reached yet
+    virgin: Vec<u8>,
+    timeout: Duration,
+    killed: bool,
+}
+
+impl Forkserver {
+    /// Starts an AFL-instrumented target, with @@ in `target_cmd` replaced by the path to
+    /// the input and stdin used without @@, under the resource limits in `args`. Its stderr
+    /// is kept for `stderr`.
+    pub fn start(args: &Args, target_cmd: &str, timeout: Duration) -> io::Result<Forkserver> {
+        let temp = |name: &str| std::env::temp_dir().join(format!("ebpf_fuzzer_afl_{}_{}", name, std::process::id()));
+        let open = |path: &PathBuf| OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path);
+        let (input_path, stderr_path) = (temp("input"), temp("stderr"));
+        let (input, stderr) = (open(&input_path)?, open(&stderr_path)?);
+        let shm = Shm::new()?;
+
+        // Through sh for the same quoting as other target commands, but exec'd so that the
+        // forkserver is the process started
+        let cmd = target_cmd.replace("@@", &repro::quote(&input_path.to_string_lossy()));
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("exec {}", cmd))
+            .env("__AFL_SHM_ID", shm.id.to_string())
+            // Recent AFL++ runtimes only speak the classic protocol when asked to
+            .env("AFL_OLD_FORKSERVER", "1")
+            .stdin(if target_cmd.contains("@@") { Stdio::null() } else { Stdio::from(input.try_clone()?) })
+            .stdout(Stdio::null())
+            .stderr(Stdio::from(stderr.try_clone()?));
+        limits::apply(&mut command, args);
+
+        let (control_read, control_write) = pipe_pair()?;
+        let (status_read, status_write) = pipe_pair()?;
+        let fds = [control_read.as_raw_fd(), control_write.as_raw_fd(), status_read.as_raw_fd(), status_write.as_raw_fd()];
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::dup2(fds[0], CONTROL_FD) < 0 || libc::dup2(fds[3], STATUS_FD) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                for fd in fds.into_iter().filter(|&fd| fd != CONTROL_FD && fd != STATUS_FD) {
+                    libc::close(fd);
+                }
+                Ok(())
+            });
+        }
+        let process = command.spawn()?;
+        drop((control_read, status_write));
+
+        let mut forkserver = Forkserver {
+            process,
+            control: control_write,
+            status: status_read,
+            input,
+            input_path,
+            stderr,
+            stderr_path,
+            shm,
+            virgin: vec![0xff; MAP_SIZE],
+            timeout,
+            killed: false,
+        };
+        // Instrumented targets may take a while to start, e.g. under a sanitizer
+        if read_word(&mut forkserver.status, timeout * 10).is_none() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no forkserver hello; is the target AFL-instrumented?"));
+        }
+        Ok(forkserver)
+    }
+
+    /// Runs the target on one input. Persistent-mode targets, which run several inputs in
+    /// one child, are driven too.
+    pub fn run(&mut self, input: &[u8]) -> Status {
+        // The children share these files' offsets with us, so they read and write from the start
+        self.input.set_len(0).expect("Failed to write target input");
+        self.input.seek(SeekFrom::Start(0)).expect("Failed to write target input");
+        self.input.write_all(input).expect("Failed to write target input");
+        self.input.seek(SeekFrom::Start(0)).expect("Failed to write target input");
+        self.stderr.set_len(0).expect("Failed to clear target stderr");
+        self.stderr.seek(SeekFrom::Start(0)).expect("Failed to clear target stderr");
+        self.shm.bitmap().fill(0);
+
+        // Tells a persistent-mode forkserver whether its stopped child is gone
+        let request = (self.killed as i32).to_ne_bytes();
+        self.control.write_all(&request).expect("Forkserver went away");
+        let pid = read_word(&mut self.status, self.timeout * 10).expect("Forkserver did not fork");
+
+        self.killed = false;
+        let status = read_word(&mut self.status, self.timeout).unwrap_or_else(|| {
+            // SAFETY: signals the child the forkserver just reported
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+            self.killed = true;
+            read_word(&mut self.status, self.timeout * 10).expect("Forkserver did not reap a timed out child");
+            -1
+        });
+        if self.killed { Status::TimedOut } else { Status::from_wait(status) }
+    }
+
+    /// What the last run wrote to stderr
+    pub fn stderr(&mut self) -> Vec<u8> {
+        let mut stderr = Vec::new();
+        self.stderr.seek(SeekFrom::Start(0)).expect("Failed to read target stderr");
+        self.stderr.read_to_end(&mut stderr).expect("Failed to read target stderr");
+        stderr
+    }
+
+    /// Whether the last run reached a hit-count bucket no earlier run did
+    pub fn new_coverage(&mut self) -> bool {
+        let mut new = false;
+        let bitmap = self.shm.bitmap();
+        for (count, virgin) in bitmap.iter().zip(self.virgin.iter_mut()) {
+            let hit = bucket(*count);
+            if hit & *virgin != 0 {
+                *virgin &= !hit;
+                new = true;
+            }
+        }
+        new
+    }
+}
+
+impl Drop for Forkserver {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        let _ = fs::remove_file(&self.input_path);
+        let _ = fs::remove_file(&self.stderr_path);
+    }
+}
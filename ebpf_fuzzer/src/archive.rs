@@ -0,0 +1,149 @@
+//! Corpus archives: the files a --manifest lists and the manifest itself in one
+//! zstd-compressed tar file, to share a corpus between machines as a single file
+
+use crate::manifest;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path};
+
+/// Name of the manifest inside an archive, whose paths are relative to the archive root
+const MANIFEST: &str = "manifest.toml";
+
+/// zstd level archives are compressed at; programs are small and compress well
+const LEVEL: i32 = 19;
+
+const BLOCK: usize = 512;
+
+/// Appends a ustar entry for a regular file
+fn append(tar: &mut Vec<u8>, name: &str, data: &[u8]) {
+    assert!(name.len() <= 100, "Archive names are limited to 100 bytes: {}", name);
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // The checksum is taken with its own field as spaces
+    header[148..156].copy_from_slice(b"        ");
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    tar.extend_from_slice(&header);
+    tar.extend_from_slice(data);
+    tar.resize(tar.len().next_multiple_of(BLOCK), 0);
+}
+
+/// A NUL-terminated header field
+fn field(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).expect("Archive header field is not UTF-8")
+}
+
+/// The regular files of a tar archive, as names and contents
+fn entries(tar: &[u8]) -> Vec<(String, &[u8])> {
+    let mut entries = Vec::new();
+    let mut at = 0;
+    while at + BLOCK <= tar.len() && tar[at..at + BLOCK].iter().any(|&b| b != 0) {
+        let header = &tar[at..at + BLOCK];
+        let sum: u32 = header.iter().enumerate().map(|(i, &b)| if (148..156).contains(&i) { b' ' as u32 } else { b as u32 }).sum();
+        let recorded = u32::from_str_radix(field(&header[148..156]).trim(), 8).expect("Malformed archive checksum");
+        assert_eq!(sum, recorded, "Archive header checksum mismatch at offset {}", at);
+        let size = usize::from_str_radix(field(&header[124..136]).trim(), 8).expect("Malformed archive entry size");
+        let prefix = field(&header[345..500]);
+        let name = field(&header[..100]);
+        let name = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+        at += BLOCK;
+        assert!(at + size <= tar.len(), "Archive entry {} is truncated", name);
+        if matches!(header[156], b'0' | 0) {
+            entries.push((name.trim_start_matches("./").to_string(), &tar[at..at + size]));
+        }
+        at += size.next_multiple_of(BLOCK);
+    }
+    entries
+}
+
+/// Where a file a manifest in `base` lists goes in an archive
+fn archived_name(base: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    let relative = path.strip_prefix(base).ok().filter(|p| is_contained(p));
+    let name = match relative {
+        Some(relative) => relative.to_path_buf(),
+        None if is_contained(path) => path.to_path_buf(),
+        None => path.file_name().expect("Manifest lists a path without a file name").into(),
+    };
+    name.to_str().expect("Manifest lists a path that is not UTF-8").to_string()
+}
+
+/// Whether a path stays inside the directory it is relative to
+fn is_contained(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Packs the files `manifest_path` lists and the manifest into `archive`. The manifest in the
+/// archive lists the files by their names in it.
+pub fn export(manifest_path: &str, archive: &str) {
+    let text = fs::read_to_string(manifest_path).expect("Failed to read manifest");
+    let base = Path::new(manifest_path).parent().unwrap_or(Path::new(""));
+    let mut listing = manifest::read(&text);
+    let mut tar = Vec::new();
+    let mut names = HashSet::new();
+    for listed in &mut listing.file {
+        let name = archived_name(base, &listed.path);
+        assert!(name != MANIFEST && names.insert(name.clone()), "Two files of the manifest map to {} in the archive", name);
+        let data = fs::read(&listed.path).unwrap_or_else(|err| panic!("Failed to read {}: {}", listed.path, err));
+        if format!("{:016x}", manifest::fnv1a(&data)) != listed.fnv1a {
+            eprintln!("warning: {} changed since the manifest was written", listed.path);
+        }
+        append(&mut tar, &name, &data);
+        listed.path = name;
+    }
+
+    append(&mut tar, MANIFEST, listing.to_toml().as_bytes());
+    tar.resize(tar.len() + 2 * BLOCK, 0);
+
+    let compressed = zstd::encode_all(tar.as_slice(), LEVEL).expect("Failed to compress archive");
+    fs::write(archive, compressed).expect("Failed to write archive");
+    println!("Packed {} files and the manifest into {}", listing.file.len(), archive);
+}
+
+/// Unpacks an archive `export` wrote into `dir` and checks the files against the manifest in it
+pub fn import(archive: &str, dir: &str) {
+    let compressed = fs::read(archive).expect("Failed to read archive");
+    let tar = zstd::decode_all(compressed.as_slice()).expect("Failed to decompress archive");
+    let mut text = None;
+    for (name, data) in entries(&tar) {
+        assert!(is_contained(Path::new(&name)), "Archive entry {} escapes the target directory", name);
+        let path = Path::new(dir).join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("Failed to create output directory");
+        }
+        fs::write(&path, data).unwrap_or_else(|err| panic!("Failed to write {}: {}", path.display(), err));
+        if name == MANIFEST {
+            text = Some(String::from_utf8(data.to_vec()).expect("Archived manifest is not UTF-8"));
+        }
+    }
+
+    let listed = manifest::read(&text.expect("Archive holds no manifest.toml")).file;
+    let mut bad = 0;
+    for file in &listed {
+        let path = Path::new(dir).join(&file.path);
+        match fs::read(&path) {
+            Ok(data) if format!("{:016x}", manifest::fnv1a(&data)) == file.fnv1a => {}
+            Ok(_) => {
+                println!("{}: does not match its hash in the manifest", path.display());
+                bad += 1;
+            }
+            Err(err) => {
+                println!("{}: {}", path.display(), err);
+                bad += 1;
+            }
+        }
+    }
+    println!("Unpacked {} files into {}, {} of them bad", listed.len(), dir, bad);
+    if bad > 0 {
+        std::process::exit(1);
+    }
+}
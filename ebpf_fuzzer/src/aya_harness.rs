@@ -0,0 +1,131 @@
+//! Loads generated ELF objects through aya and through a libbpf-based command, and reports
+//! programs the two loaders handle differently: one rejects what the other accepts, or they
+//! fail at different stages
+
+use crate::rejections::Tally;
+use crate::{elf_object, generate_test, limits, output_path, repro, write_output, Args, GenOptions};
+use aya::programs::{Program, ProgramError};
+use aya::{Ebpf, EbpfError};
+use rand::Rng;
+use std::fmt;
+use std::fs;
+use std::process::{Command, Output};
+
+/// How far a loader got with an object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// The object could not be parsed, or has no program named prog
+    Parse,
+    /// Relocations, maps or BTF could not be processed
+    Relocate,
+    /// The kernel verifier rejected the program
+    Verify,
+    /// The program was loaded
+    Accepted,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Parse => write!(f, "parse error"),
+            Stage::Relocate => write!(f, "relocation error"),
+            Stage::Verify => write!(f, "verifier rejection"),
+            Stage::Accepted => write!(f, "accepted"),
+        }
+    }
+}
+
+fn load(program: &mut Program) -> Result<(), ProgramError> {
+    match program {
+        Program::SocketFilter(p) => p.load(),
+        Program::KProbe(p) => p.load(),
+        Program::TracePoint(p) => p.load(),
+        Program::SchedClassifier(p) => p.load(),
+        Program::Xdp(p) => p.load(),
+        _ => Err(ProgramError::UnexpectedProgramType),
+    }
+}
+
+fn aya_stage(object: &[u8]) -> (Stage, String) {
+    let mut ebpf = match Ebpf::load(object) {
+        Ok(ebpf) => ebpf,
+        Err(err @ EbpfError::ParseError(_)) => return (Stage::Parse, err.to_string()),
+        Err(err) => return (Stage::Relocate, err.to_string()),
+    };
+    let Some(program) = ebpf.program_mut("prog") else {
+        return (Stage::Parse, "no program named prog".to_string());
+    };
+    match load(program) {
+        Ok(()) => (Stage::Accepted, String::new()),
+        Err(err @ ProgramError::LoadError { .. }) => (Stage::Verify, err.to_string()),
+        Err(err) => (Stage::Relocate, err.to_string()),
+    }
+}
+
+/// Reads the stage off the exit status of the loader --loader writes: 1 when opening the
+/// object fails, 2 when loading does, which covers relocations as well as the verifier
+fn libbpf_stage(output: &Output) -> Stage {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    match output.status.code() {
+        Some(0) | Some(3) => Stage::Accepted,
+        Some(1) => Stage::Parse,
+        _ if stderr.contains("relo") => Stage::Relocate,
+        _ => Stage::Verify,
+    }
+}
+
+fn execute(args: &Args, libbpf_cmd: &str, path: &str) -> Output {
+    let cmd = libbpf_cmd.replace("@@", &repro::quote(path));
+    let mut command = Command::new("sh");
+    limits::apply(&mut command, args);
+    command.arg("-c").arg(&cmd).output().expect("Failed to run libbpf loader")
+}
+
+/// Loads every generated program with both loaders and archives those they disagree on. With
+/// `rejections`, programs each loader rejects are grouped by the reason in its log.
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, libbpf_cmd: &str, rejections: bool) {
+    let object_path = std::env::temp_dir().join(format!("ebpf_fuzzer_aya_{}.o", std::process::id()));
+    let object_path = object_path.to_string_lossy().into_owned();
+    let mut findings = 0;
+    let (mut aya_tally, mut libbpf_tally) = (Tally::default(), Tally::default());
+
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, opts);
+        let object = elf_object(args, &bytes);
+        fs::write(&object_path, &object).expect("Failed to write ELF object");
+
+        let (aya, aya_log) = aya_stage(&object);
+        let output = execute(args, libbpf_cmd, &object_path);
+        let libbpf = libbpf_stage(&output);
+        if rejections && aya != Stage::Accepted {
+            aya_tally.add(&aya_log);
+        }
+        if rejections && libbpf != Stage::Accepted {
+            libbpf_tally.add(&String::from_utf8_lossy(&output.stderr));
+        }
+        if aya == libbpf {
+            continue;
+        }
+        println!("program {}: aya {}, libbpf {}", i, aya, libbpf);
+        findings += 1;
+
+        write_output(args, i, &bytes, result);
+        if let Some(path) = output_path(args, i) {
+            let log = format!("aya: {}\n{}\n\nlibbpf:\n{}", aya, aya_log, String::from_utf8_lossy(&output.stderr));
+            fs::write(format!("{}.log", path), log).expect("Failed to write loader logs");
+            let object = format!("{}.o", path);
+            fs::write(&object, elf_object(args, &bytes)).expect("Failed to write ELF object");
+            repro::write(&path, i.into(), opts.seed, &libbpf_cmd.replace("@@", &repro::quote(&object)));
+        }
+    }
+
+    if rejections {
+        aya_tally.print("aya");
+        libbpf_tally.print("libbpf");
+    }
+    println!("{} findings across {} programs", findings, args.count);
+    if findings > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,74 @@
+//! Self-contained bundles of findings: everything needed to reproduce and triage one, in a
+//! directory of its own
+
+use crate::interp;
+use crate::oracle;
+use crate::rejections::reason;
+use crate::vm;
+use crate::{config, elf_object, encode, format_program, pseudo, repro, Args, Program};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// The problems the oracles find with a program, with the numbers left out
+fn group(bytes: &[u8]) -> Vec<String> {
+    let problems = oracle::problems(bytes, None, &vm::run(bytes), &interp::run(bytes));
+    problems.iter().map(|p| reason(p).unwrap_or_default()).collect()
+}
+
+/// Removes instructions one at a time, last first, as long as the oracles still find the
+/// same problems, or None if they find none to begin with
+fn minimize(bytes: &[u8]) -> Option<Vec<u8>> {
+    let wanted = group(bytes);
+    if wanted.is_empty() {
+        return None;
+    }
+    let mut program = Program::from_bytes(bytes);
+    for i in (0..program.len()).rev() {
+        if program.len() == 1 {
+            break;
+        }
+        let mut candidate = program.clone();
+        candidate.remove(i..i + 1);
+        // Removing instructions only brings branches closer to their targets
+        if group(&candidate.encode().expect("Branch out of range after removing instructions")) == wanted {
+            program = candidate;
+        }
+    }
+    Some(program.encode().expect("Branch out of range after removing instructions"))
+}
+
+/// Writes the program in every format, the seed and effective command line, the outcomes
+/// of both oracles, the reference interpreter's trace and, for findings the oracles
+/// reproduce, a minimized program into `dir`
+pub fn write(args: &Args, dir: &Path, seed: Option<u64>, bytes: &[u8], result: Option<u64>) {
+    let file = |name: &str, data: &[u8]| fs::write(dir.join(name), data).expect("Failed to write finding bundle");
+
+    file("program.data", format_program(bytes, args.endian, None, result).as_bytes());
+    file("program.c", pseudo::render_numbered(bytes).as_bytes());
+    file("program.o", &elf_object(args, bytes));
+    file("program.bin", &encode(bytes, args.endian));
+
+    let outcome = vm::run(bytes);
+    let (reference, steps) = interp::steps(bytes);
+    let mut outcomes = format!("rbpf: {}\nreference: {}\n", outcome, reference);
+    for problem in oracle::problems(bytes, result, &outcome, &reference) {
+        let _ = writeln!(outcomes, "problem: {}", problem);
+    }
+    file("outcomes", outcomes.as_bytes());
+    file("trace", format!("{}\n", steps.join("\n")).as_bytes());
+
+    if let Some(minimized) = minimize(bytes) {
+        file("minimized.data", format_program(&minimized, args.endian, None, None).as_bytes());
+    }
+
+    let words = match seed {
+        Some(seed) => repro::args_with_seed(seed),
+        None => config::args().to_vec(),
+    };
+    let command: Vec<String> = words.iter().map(|w| repro::quote(w)).collect();
+    file("command", format!("{}\n", command.join(" ")).as_bytes());
+    if let Some(seed) = seed {
+        file("seed", format!("{}\n", seed).as_bytes());
+    }
+}
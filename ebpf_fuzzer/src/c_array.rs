@@ -0,0 +1,45 @@
+//! Programs as C arrays, to include in C harnesses and kernel test code as they are
+
+use crate::eval::{self, Expected};
+use crate::{encode, Endian, Instruction};
+use std::fmt::Write as _;
+
+/// Renders a program as a `uint64_t` array of its instructions, read in the byte order of
+/// `endian` so that they lie in memory as a target of that byte order loads them, or with
+/// `insns` as a `struct bpf_insn` array, which the compiler lays out itself
+pub fn render(bytes: &[u8], endian: Endian, result: Option<u64>, insns: bool) -> String {
+    let mut output = String::from("/* Generated by ebpf_fuzzer");
+    match result.map(Expected::Returns).or_else(|| eval::evaluate(bytes)) {
+        Some(Expected::Error(err)) => {
+            let _ = write!(output, ", expected error: {}", err.replace("*/", "* /"));
+        }
+        Some(Expected::Returns(r0)) => {
+            let _ = write!(output, ", expected r0: {:#x}", r0);
+        }
+        None => {}
+    }
+    output.push_str(" */\n");
+
+    if insns {
+        output.push_str("#include <linux/bpf.h>\n\nstatic const struct bpf_insn prog[] = {\n");
+        for insn in bytes.chunks_exact(8).map(Instruction::from_bytes) {
+            let _ = writeln!(
+                output,
+                "\t{{ .code = {:#04x}, .dst_reg = {}, .src_reg = {}, .off = {}, .imm = {} }},",
+                insn.opcode, insn.dst, insn.src, insn.offset as i16, insn.imm as i32
+            );
+        }
+    } else {
+        output.push_str("#include <stdint.h>\n\nstatic const uint64_t prog[] = {\n");
+        for word in encode(bytes, endian).chunks_exact(8) {
+            let word: [u8; 8] = word.try_into().unwrap();
+            let v = match endian {
+                Endian::Le => u64::from_le_bytes(word),
+                Endian::Be => u64::from_be_bytes(word),
+            };
+            let _ = writeln!(output, "\t0x{:016x}ULL,", v);
+        }
+    }
+    output.push_str("};\n");
+    output
+}
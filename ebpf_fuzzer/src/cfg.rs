@@ -0,0 +1,387 @@
+//! Control-flow view of programs, for edits that must keep branches pointing at the same instructions
+
+use crate::Instruction;
+use std::ops::Range;
+
+/// Field a branch stores its relative target in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BranchField {
+    Offset,
+    Imm,
+}
+
+/// Where a branch or pseudo-call keeps its target, or None for other instructions
+fn branch_field(insn: &Instruction) -> Option<BranchField> {
+    let class = insn.opcode & 0x07;
+    if class != 0x05 && class != 0x06 {
+        return None;
+    }
+    match insn.opcode & 0xf0 {
+        0x00 if class == 0x06 => Some(BranchField::Imm),
+        0x80 if class == 0x05 && insn.src == 1 => Some(BranchField::Imm),
+        0x80 | 0x90 => None,
+        _ => Some(BranchField::Offset),
+    }
+}
+
+/// Whether execution may continue with the next instruction
+pub fn falls_through(insn: &Instruction) -> bool {
+    !matches!(insn.opcode, 0x05 | 0x06 | 0x95)
+}
+
+/// An instruction whose branch target, if it lands on an instruction, is kept as an index into the node list
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub insn: Instruction,
+    /// Second slot of a LD_DW_IMM
+    pub second: Option<[u8; 8]>,
+    /// Index of the node the instruction branches to. Branches that land outside the program
+    /// or inside a LD_DW_IMM have no target and keep their raw offset.
+    pub target: Option<usize>,
+}
+
+impl Node {
+    /// `ja` to the node at `target`
+    pub fn jump(target: usize) -> Self {
+        Self { insn: Instruction::new(0x05, 0, 0, 0, 0), second: None, target: Some(target) }
+    }
+
+    /// Slots the node takes, two for LD_DW_IMM
+    pub fn slots(&self) -> usize {
+        if self.second.is_some() { 2 } else { 1 }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Vec<Node> {
+    let slots = bytes.len() / 8;
+    let mut nodes = Vec::new();
+    // Node index of every slot that starts an instruction
+    let mut node_at = vec![None; slots];
+    let mut pc = 0;
+    while pc < slots {
+        let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+        node_at[pc] = Some(nodes.len());
+        let second = (insn.opcode == 0x18 && pc + 1 < slots).then(|| bytes[pc * 8 + 8..pc * 8 + 16].try_into().unwrap());
+        pc += if second.is_some() { 2 } else { 1 };
+        nodes.push(Node { insn, second, target: None });
+    }
+
+    let mut pc = 0;
+    for node in &mut nodes {
+        let relative = match branch_field(&node.insn) {
+            Some(BranchField::Offset) => Some(node.insn.offset as i16 as i64),
+            Some(BranchField::Imm) => Some(node.insn.imm as i32 as i64),
+            None => None,
+        };
+        if let Some(relative) = relative {
+            let target = pc as i64 + 1 + relative;
+            node.target = usize::try_from(target).ok().and_then(|t| node_at.get(t).copied().flatten());
+        }
+        pc += node.slots();
+    }
+    nodes
+}
+
+/// Encodes nodes back into bytes, recomputing the relative offset of every resolved branch,
+/// or fails if a branch no longer fits the distance to its target in its field
+pub fn encode(nodes: &[Node]) -> Result<Vec<u8>, String> {
+    let mut starts = Vec::with_capacity(nodes.len());
+    let mut pc = 0;
+    for node in nodes {
+        starts.push(pc as i64);
+        pc += node.slots();
+    }
+
+    let mut bytes = Vec::with_capacity(pc * 8);
+    for (i, node) in nodes.iter().enumerate() {
+        let mut insn = node.insn;
+        if let Some(target) = node.target {
+            let relative = starts[target] - starts[i] - 1;
+            let out_of_range = |_| format!("branch at slot {} cannot reach slot {}", starts[i], starts[target]);
+            match branch_field(&insn) {
+                Some(BranchField::Offset) => insn.offset = i16::try_from(relative).map_err(out_of_range)? as u16,
+                Some(BranchField::Imm) => insn.imm = i32::try_from(relative).map_err(out_of_range)? as u32,
+                None => {}
+            }
+        }
+        bytes.extend_from_slice(&insn.to_bytes());
+        if let Some(second) = node.second {
+            bytes.extend_from_slice(&second);
+        }
+    }
+    Ok(bytes)
+}
+
+/// Encodes the nodes an edit left over `bytes`, or leaves `bytes` as they were if a branch
+/// can no longer reach its target, returning whether the edit was kept
+pub fn encode_into(nodes: &[Node], bytes: &mut Vec<u8>) -> bool {
+    match encode(nodes) {
+        Ok(encoded) => {
+            *bytes = encoded;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Replaces `range` with `replacement` and retargets every branch, including those in
+/// `replacement`, whose targets are given as indices before the edit. Branches into the
+/// removed range move to the instruction that followed it, or lose their target if none did.
+pub fn splice(nodes: &mut Vec<Node>, range: Range<usize>, replacement: Vec<Node>) {
+    let inserted = replacement.len();
+    let removed = range.len();
+    nodes.splice(range.clone(), replacement);
+
+    let len = nodes.len();
+    for node in nodes.iter_mut() {
+        node.target = node.target.and_then(|t| {
+            let moved = if t < range.start { t } else { t.max(range.end) - removed + inserted };
+            (moved < len).then_some(moved)
+        });
+    }
+}
+
+/// Splits nodes into basic blocks: a block starts at the entry, at every branch target
+/// and after every branch or exit
+pub fn blocks(nodes: &[Node]) -> Vec<Range<usize>> {
+    let mut leaders = vec![false; nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(target) = node.target {
+            leaders[target] = true;
+        }
+        if (branch_field(&node.insn).is_some() || node.insn.opcode == 0x95) && i + 1 < nodes.len() {
+            leaders[i + 1] = true;
+        }
+    }
+    if let Some(entry) = leaders.first_mut() {
+        *entry = true;
+    }
+
+    let starts: Vec<usize> = (0..nodes.len()).filter(|&i| leaders[i]).collect();
+    starts.iter().enumerate().map(|(b, &start)| start..starts.get(b + 1).copied().unwrap_or(nodes.len())).collect()
+}
+
+/// Where execution may go after a node: its branch target, if it is a branch, and the next
+/// node, if it falls through. None stands for leaving the program, through a branch out of
+/// bounds or by running past the last instruction.
+fn successors(nodes: &[Node], i: usize) -> Vec<(bool, Option<usize>)> {
+    let insn = &nodes[i].insn;
+    let next = (i + 1 < nodes.len()).then_some(i + 1);
+    let jumps = matches!(insn.opcode & 0x07, 0x05 | 0x06) && !matches!(insn.opcode & 0xf0, 0x80 | 0x90);
+    let mut succs = Vec::new();
+    if jumps {
+        succs.push((true, nodes[i].target));
+    }
+    if falls_through(insn) {
+        succs.push((false, next));
+    }
+    succs
+}
+
+/// Marks the nodes some path from the entry reaches
+pub fn reachable(nodes: &[Node]) -> Vec<bool> {
+    let mut reachable = vec![false; nodes.len()];
+    let mut pending = vec![0];
+    while let Some(i) = pending.pop() {
+        if i >= nodes.len() || reachable[i] {
+            continue;
+        }
+        reachable[i] = true;
+        pending.extend(successors(nodes, i).into_iter().filter_map(|(_, s)| s));
+        // Local functions run from their calls
+        if nodes[i].insn.opcode == 0x85 {
+            pending.extend(nodes[i].target);
+        }
+    }
+    reachable
+}
+
+/// Removes the nodes no path from the entry reaches. Only unreachable branches can target
+/// them, so the branches left keep their targets.
+pub fn remove_unreachable(nodes: &mut Vec<Node>) {
+    let reachable = reachable(nodes);
+    let mut moved = Vec::with_capacity(nodes.len());
+    let mut kept = 0;
+    for &r in &reachable {
+        moved.push(kept);
+        kept += r as usize;
+    }
+    let mut i = 0;
+    nodes.retain(|_| {
+        i += 1;
+        reachable[i - 1]
+    });
+    for node in nodes.iter_mut() {
+        node.target = node.target.map(|t| moved[t]);
+    }
+}
+
+/// Makes every path from the entry reach an exit: branches and fall-throughs into code that
+/// can only loop forever or leave the program go to the node at `exit` instead, through a
+/// `ja` inserted after the node for fall-throughs. With `inline`, for programs that may not
+/// use `ja`, fall-throughs get a copy of the nodes from `exit` to the end instead, which
+/// must not branch.
+pub fn close_paths(nodes: &mut Vec<Node>, exit: usize, inline: bool) {
+    // Nodes from which an exit is reachable, to a fixed point
+    let mut exits = vec![false; nodes.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..nodes.len()).rev() {
+            let reaches = nodes[i].insn.opcode == 0x95 || successors(nodes, i).iter().any(|&(_, s)| s.is_some_and(|s| exits[s]));
+            if reaches && !exits[i] {
+                exits[i] = true;
+                changed = true;
+            }
+        }
+    }
+    assert!(exits[exit], "Paths must be closed towards a node that exits");
+
+    let reachable = reachable(nodes);
+    // Rebuilt with targets as indices before the edit, then remapped
+    let mut closed = Vec::with_capacity(nodes.len());
+    let mut moved = Vec::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let mut node = node.clone();
+        let mut patch = false;
+        if reachable[i] {
+            for (branch, succ) in successors(nodes, i) {
+                if succ.is_some_and(|s| exits[s]) {
+                    continue;
+                }
+                if branch {
+                    node.target = Some(exit);
+                } else {
+                    patch = true;
+                }
+            }
+        }
+        moved.push(closed.len());
+        closed.push(node);
+        if patch && inline {
+            closed.extend(nodes[exit..].iter().map(|node| Node { target: None, ..node.clone() }));
+        } else if patch {
+            closed.push(Node::jump(exit));
+        }
+    }
+    for node in &mut closed {
+        node.target = node.target.map(|t| moved[t]);
+    }
+    *nodes = closed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_program;
+
+    fn exit() -> Node {
+        Node { insn: Instruction::new(0x95, 0, 0, 0, 0), second: None, target: None }
+    }
+
+    fn mov(imm: u32) -> Node {
+        Node { insn: Instruction::new(0xb7, 0, 0, 0, imm), second: None, target: None }
+    }
+
+    fn targets(nodes: &[Node]) -> Vec<Option<usize>> {
+        nodes.iter().map(|node| node.target).collect()
+    }
+
+    #[test]
+    fn decode_resolves_targets_and_encode_restores_bytes() {
+        let bytes = encode_program(&[
+            // Over the two slots of the lddw and the ja, to the exit
+            Instruction::new(0x15, 1, 0, 3, 0),
+            Instruction::new(0x18, 0, 0, 0, 1),
+            Instruction::new(0x00, 0, 0, 0, 2),
+            // Before the entry, so it keeps its raw offset
+            Instruction::new(0x05, 0, 0, -5i16 as u16, 0),
+            Instruction::new(0x95, 0, 0, 0, 0),
+            // Local call back to the exit
+            Instruction::new(0x85, 0, 1, 0, -2i32 as u32),
+            // Into the second slot of the lddw
+            Instruction::new(0x05, 0, 0, -5i16 as u16, 0),
+        ]);
+        let nodes = decode(&bytes);
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(nodes[1].slots(), 2);
+        assert_eq!(targets(&nodes), [Some(3), None, None, None, Some(3), None]);
+        assert_eq!(encode(&nodes).unwrap(), bytes);
+    }
+
+    #[test]
+    fn encode_checks_offsets_fit() {
+        let far = i16::MAX as usize + 1;
+        let mut nodes = vec![Node::jump(far)];
+        nodes.extend((0..far).map(|_| exit()));
+        let bytes = encode(&nodes).unwrap();
+        assert_eq!(Instruction::from_bytes(&bytes[..8]).offset, i16::MAX as u16);
+
+        nodes[0].target = Some(far + 1);
+        nodes.push(exit());
+        assert!(encode(&nodes).is_err());
+
+        // Backwards, i16::MIN still fits
+        let mut nodes: Vec<Node> = (0..far).map(|_| exit()).collect();
+        nodes[far - 1] = Node::jump(0);
+        let bytes = encode(&nodes).unwrap();
+        assert_eq!(Instruction::from_bytes(&bytes[(far - 1) * 8..]).offset, i16::MIN as u16);
+
+        nodes[far - 1] = exit();
+        nodes.push(Node::jump(0));
+        assert!(encode(&nodes).is_err());
+    }
+
+    #[test]
+    fn encode_into_keeps_bytes_on_failure() {
+        let far = i16::MAX as usize + 2;
+        let mut nodes = vec![Node::jump(far)];
+        nodes.extend((0..far).map(|_| exit()));
+        let mut bytes = encode_program(&[Instruction::new(0x95, 0, 0, 0, 0)]);
+        assert!(!encode_into(&nodes, &mut bytes));
+        assert_eq!(bytes.len(), 8);
+
+        nodes.truncate(far);
+        nodes[0].target = Some(1);
+        assert!(encode_into(&nodes, &mut bytes));
+        assert_eq!(bytes.len(), far * 8);
+    }
+
+    #[test]
+    fn splice_retargets_branches() {
+        let original = vec![Node::jump(4), Node::jump(2), mov(1), mov(2), exit()];
+
+        // Branches into the removed range follow it, and the replacement's own targets move too
+        let mut nodes = original.clone();
+        splice(&mut nodes, 2..4, vec![Node::jump(4)]);
+        assert_eq!(targets(&nodes), [Some(3), Some(3), Some(3), None]);
+
+        let mut nodes = original.clone();
+        splice(&mut nodes, 1..1, vec![mov(3)]);
+        assert_eq!(targets(&nodes), [Some(5), None, Some(3), None, None, None]);
+
+        // Nothing follows the removed range, so the branch into it loses its target
+        let mut nodes = vec![Node::jump(1), exit()];
+        splice(&mut nodes, 1..2, Vec::new());
+        assert_eq!(targets(&nodes), [None]);
+    }
+
+    #[test]
+    fn close_paths_sends_dead_ends_to_the_exit() {
+        let jeq = |target| Node { insn: Instruction::new(0x15, 1, 0, 0, 0), second: None, target: Some(target) };
+
+        // The branch into the loop and the loop itself go to the exit, and the fall-through
+        // into the loop gets a `ja` to it
+        let mut nodes = vec![jeq(3), mov(1), exit(), mov(2), Node::jump(3)];
+        close_paths(&mut nodes, 2, false);
+        assert_eq!(targets(&nodes), [Some(2), None, None, None, Some(2), Some(2)]);
+        assert_eq!(nodes[4].insn.opcode, 0x05);
+
+        // Running off the end gets a copy of the exit instead
+        let mut nodes = vec![mov(1), jeq(3), exit(), mov(2)];
+        close_paths(&mut nodes, 2, true);
+        let opcodes: Vec<u8> = nodes.iter().map(|node| node.insn.opcode).collect();
+        assert_eq!(opcodes, [0xb7, 0x15, 0x95, 0xb7, 0x95, 0xb7]);
+        assert_eq!(targets(&nodes), [None, Some(2), None, None, None, None]);
+    }
+}
@@ -0,0 +1,249 @@
+//! Spreads a fuzz campaign over machines. A coordinator hands out round seeds, merges the
+//! corpus entries workers find and collects their findings. Workers connect once per request
+//! and send a line naming it, followed by a payload of the length the line gives:
+//!
+//! - `seed RAN`: RAN is the number of programs the worker ran since its last request for a
+//!   seed, for metrics, and the coordinator answers with the seed of the worker's next round, the seed of
+//!   the program of the round's index in a generation run, so rounds never share a seed
+//! - `put LEN`: a corpus entry of LEN raw bytes
+//! - `get FROM`: the coordinator answers with the number of its corpus entries from index
+//!   FROM on, then each as a line with its length followed by its raw bytes
+//! - `finding LEN CLASS DESCRIPTION`: a program of LEN raw bytes, the class of the finding
+//!   (`crash`, `hang` or `divergence`) and the problems found with it
+
+use crate::findings::{self, Class, Counts};
+use crate::fuzz::{Campaign, Finding};
+use crate::metrics::{self, Metrics};
+use crate::rejections::reason;
+use crate::{format_program, program_seed, read_program, Args, Endian, FuzzOptions, GenOptions};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Largest payload the coordinator accepts, well above any generated program
+const MAX_PAYLOAD: usize = 1 << 20;
+
+/// How long the coordinator waits on a worker's request or answer before dropping it, as it
+/// serves one worker at a time
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// File in the corpus directory holding the number of rounds handed out, so a restarted
+/// coordinator does not hand out the same seeds again
+const ROUNDS_FILE: &str = "rounds";
+
+fn read_payload(reader: &mut impl Read, len: &str) -> io::Result<Vec<u8>> {
+    let len: usize = len.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid payload length"))?;
+    if len > MAX_PAYLOAD {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "payload too large"));
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+struct Coordinator {
+    dir: String,
+    entries: Vec<Vec<u8>>,
+    known: HashSet<Vec<u8>>,
+    rounds: u32,
+    groups: BTreeMap<String, u32>,
+    saved: u32,
+    findings: Counts,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl Coordinator {
+    fn add(&mut self, bytes: Vec<u8>) {
+        if !self.known.insert(bytes.clone()) {
+            return;
+        }
+        let path = Path::new(&self.dir).join(format!("{}.data", self.entries.len()));
+        fs::write(path, format_program(&bytes, Endian::Le, None, None)).expect("Failed to write corpus entry");
+        self.entries.push(bytes);
+        if let Some(metrics) = &self.metrics {
+            metrics.corpus_entries.store(self.entries.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Saves the first finding of each group across all workers to the directory of its
+    /// class, with its description in `<finding>.log`
+    fn save(&mut self, args: &Args, peer: &str, bytes: &[u8], class: Class, description: &str) {
+        let count = self.groups.entry(reason(description).unwrap_or_default()).or_default();
+        *count += 1;
+        if *count > 1 {
+            return;
+        }
+        self.findings.add(class);
+        if let Some(metrics) = &self.metrics {
+            metrics.finding_groups.store(self.groups.len() as u64, Ordering::Relaxed);
+            let counter = match class {
+                Class::Crash => Some(&metrics.crashes),
+                Class::Hang => Some(&metrics.hangs),
+                Class::Divergence => Some(&metrics.divergences),
+                Class::Mismatch => None,
+            };
+            if let Some(counter) = counter {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        println!("{} {} from {}: {} ({})", class, self.saved, peer, description, self.findings);
+        if let Some(path) = findings::write(args, class, self.saved.into(), None, bytes, None) {
+            fs::write(format!("{}.log", path), format!("{}\nfrom {}\n", description, peer)).expect("Failed to write finding description");
+        }
+        self.saved += 1;
+    }
+
+    fn handle(&mut self, stream: TcpStream, args: &Args, opts: &GenOptions) -> io::Result<()> {
+        let peer = stream.peer_addr()?.to_string();
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut words = line.trim_end().splitn(4, ' ');
+
+        match (words.next(), words.next()) {
+            (Some("seed"), ran) => {
+                // Workers may leave the count out
+                let ran: u64 = ran.map_or(Ok(0), str::parse).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid program count"))?;
+                if let Some(metrics) = &self.metrics {
+                    metrics.execs.fetch_add(ran, Ordering::Relaxed);
+                }
+                let seed = program_seed(opts.seed, self.rounds);
+                self.rounds += 1;
+                fs::write(Path::new(&self.dir).join(ROUNDS_FILE), format!("{}\n", self.rounds)).expect("Failed to write round counter");
+                println!("round {} to {}", self.rounds, peer);
+                writeln!(writer, "{}", seed)
+            }
+            (Some("put"), Some(len)) => {
+                let bytes = read_payload(&mut reader, len)?;
+                self.add(bytes);
+                Ok(())
+            }
+            (Some("get"), Some(from)) => {
+                let from: usize = from.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid index"))?;
+                let entries = self.entries.get(from..).unwrap_or_default();
+                writeln!(writer, "{}", entries.len())?;
+                for entry in entries {
+                    writeln!(writer, "{}", entry.len())?;
+                    writer.write_all(entry)?;
+                }
+                Ok(())
+            }
+            (Some("finding"), Some(len)) => {
+                let class = words.next().unwrap_or_default().parse().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                let description = words.next().unwrap_or_default().to_string();
+                let bytes = read_payload(&mut reader, len)?;
+                self.save(args, &peer, &bytes, class, &description);
+                Ok(())
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown request {:?}", line.trim_end()))),
+        }
+    }
+}
+
+/// Serves workers on `listen` until killed, merging their corpus entries into `corpus_dir`
+/// and saving the first finding of each group to the output. A coordinator restarted on the
+/// same directory picks up the corpus and the round counter where it left off.
+pub fn coordinate(args: &Args, opts: &GenOptions, listen: &str, corpus_dir: &str, metrics_addr: Option<&str>) {
+    fs::create_dir_all(corpus_dir).expect("Failed to create corpus directory");
+    let mut paths: Vec<_> = fs::read_dir(corpus_dir)
+        .expect("Failed to read corpus directory")
+        .map(|entry| entry.expect("Failed to read corpus directory").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "data"))
+        .collect();
+    paths.sort();
+    let entries: Vec<Vec<u8>> = paths.iter().map(|path| read_program(&path.to_string_lossy())).collect();
+    let known = entries.iter().cloned().collect();
+    let rounds = match fs::read_to_string(Path::new(corpus_dir).join(ROUNDS_FILE)) {
+        Ok(text) => text.trim().parse().expect("Malformed round counter"),
+        Err(_) => 0,
+    };
+    let metrics = metrics_addr.map(|addr| {
+        let metrics = Metrics::new();
+        metrics.corpus_entries.store(entries.len() as u64, Ordering::Relaxed);
+        metrics::serve(addr, &metrics);
+        metrics
+    });
+    let mut coordinator =
+        Coordinator { dir: corpus_dir.to_string(), entries, known, rounds, groups: BTreeMap::new(), saved: 0, findings: Counts::default(), metrics };
+
+    let listener = TcpListener::bind(listen).expect("Failed to listen for workers");
+    println!("coordinating on {} with {} corpus entries after {} rounds", listen, coordinator.entries.len(), coordinator.rounds);
+    for stream in listener.incoming() {
+        // A misbehaving worker must not take the campaign down
+        let result = stream.and_then(|stream| coordinator.handle(stream, args, opts));
+        if let Err(err) = result {
+            eprintln!("worker request failed: {}", err);
+        }
+    }
+}
+
+/// Sends a request and returns the connection to read the answer from
+fn request(coordinator: &str, line: &str, payload: &[u8]) -> BufReader<TcpStream> {
+    let mut stream = TcpStream::connect(coordinator).expect("Failed to reach coordinator");
+    stream.write_all(format!("{}\n", line).as_bytes()).expect("Failed to send request");
+    stream.write_all(payload).expect("Failed to send request");
+    BufReader::new(stream)
+}
+
+fn read_number<T: std::str::FromStr>(reader: &mut impl BufRead) -> T {
+    let mut line = String::new();
+    reader.read_line(&mut line).expect("Failed to read answer from coordinator");
+    line.trim().parse().unwrap_or_else(|_| panic!("Unexpected answer from coordinator: {:?}", line))
+}
+
+/// Runs rounds of `args.count` programs with seeds from the coordinator, for `rounds` rounds
+/// or until killed. After each round, the worker sends its findings and new corpus entries
+/// and imports the entries other workers found.
+pub fn work(args: &Args, opts: &GenOptions, coordinator: &str, rounds: Option<u32>, fuzz: &FuzzOptions) {
+    let mut campaign = Campaign::new(args, fuzz);
+    // Entries the coordinator already has
+    let mut shared: HashSet<Vec<u8>> = HashSet::new();
+    let mut received = 0;
+
+    for round in 0..rounds.unwrap_or(u32::MAX) {
+        let ran = if round == 0 { 0 } else { args.count };
+        let seed: u64 = read_number(&mut request(coordinator, &format!("seed {}", ran), &[]));
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut findings: Vec<Finding> = Vec::new();
+        for _ in 0..args.count {
+            findings.extend(campaign.step(&mut rng, opts));
+        }
+
+        for finding in &findings {
+            // Descriptions go on the request line, and rbpf's errors may span lines
+            let description = finding.problems.join("; ").replace('\n', " ");
+            println!("{}: {}", finding.class, description);
+            request(coordinator, &format!("finding {} {} {}", finding.bytes.len(), finding.class, description), &finding.bytes);
+        }
+        let new: Vec<Vec<u8>> = campaign.corpus().filter(|bytes| !shared.contains(*bytes)).map(<[u8]>::to_vec).collect();
+        for entry in new {
+            request(coordinator, &format!("put {}", entry.len()), &entry);
+            shared.insert(entry);
+        }
+
+        let mut answer = request(coordinator, &format!("get {}", received), &[]);
+        let count: usize = read_number(&mut answer);
+        for _ in 0..count {
+            let len: usize = read_number(&mut answer);
+            let mut bytes = vec![0; len];
+            answer.read_exact(&mut bytes).expect("Failed to read corpus entry from coordinator");
+            // The coordinator also sends back this worker's own entries
+            if shared.insert(bytes.clone()) {
+                campaign.import(bytes);
+            }
+        }
+        received += count;
+    }
+
+    campaign.print();
+}
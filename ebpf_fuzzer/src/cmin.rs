@@ -0,0 +1,63 @@
+//! Corpus minimization: the smallest subset of a corpus, such as several merged campaigns,
+//! that still covers every signal the whole corpus does
+
+use crate::fuzz::signals;
+use crate::vm;
+use crate::{decode_input, templates_in, Args, CminSignal, GenOptions};
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+/// Picks programs greedily, each time the one covering the most signals not covered yet,
+/// the shorter on ties. Returns their indices in the order picked.
+fn cover<T: Eq + Hash>(features: &[HashSet<T>], sizes: &[usize]) -> Vec<usize> {
+    let mut covered: HashSet<&T> = HashSet::new();
+    let mut picked = Vec::new();
+    loop {
+        let best = (0..features.len())
+            .map(|i| (features[i].iter().filter(|f| !covered.contains(f)).count(), i))
+            .filter(|&(new, _)| new > 0)
+            .max_by_key(|&(new, i)| (new, std::cmp::Reverse(sizes[i]), std::cmp::Reverse(i)));
+        let Some((_, i)) = best else { return picked };
+        covered.extend(&features[i]);
+        picked.push(i);
+    }
+}
+
+/// Copies the programs of `input` that a minimal covering set keeps into --output, a
+/// directory, under their own names
+pub fn run(args: &Args, opts: &GenOptions, input: &str, signal: CminSignal) {
+    if args.output == "-" {
+        eprintln!("cmin needs --output to name a directory");
+        std::process::exit(2);
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(input)
+        .expect("Failed to read input directory")
+        .map(|entry| entry.expect("Failed to read input directory").path())
+        .filter(|path| path.is_file() && !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')))
+        .collect();
+    paths.sort();
+
+    let mut programs = Vec::new();
+    let mut skipped = 0;
+    for path in paths {
+        match decode_input(fs::read(&path).expect("Failed to read program")) {
+            Some(bytes) => programs.push((path, bytes)),
+            None => skipped += 1,
+        }
+    }
+
+    let sizes: Vec<usize> = programs.iter().map(|(_, bytes)| bytes.len()).collect();
+    let kept = match signal {
+        CminSignal::Execution => cover(&programs.iter().map(|(_, bytes)| signals(bytes, &vm::run(bytes))).collect::<Vec<_>>(), &sizes),
+        CminSignal::Templates => cover(&programs.iter().map(|(_, bytes)| templates_in(bytes, opts).into_iter().collect()).collect::<Vec<HashSet<usize>>>(), &sizes),
+    };
+
+    fs::create_dir_all(&args.output).expect("Failed to create output directory");
+    for &i in &kept {
+        let path = &programs[i].0;
+        fs::copy(path, Path::new(&args.output).join(path.file_name().unwrap())).expect("Failed to copy program");
+    }
+    println!("Kept {} of {} programs in {} ({} skipped as not programs)", kept.len(), programs.len(), args.output, skipped);
+}
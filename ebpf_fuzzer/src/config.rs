@@ -0,0 +1,114 @@
+//! Layers EBPF_FUZZER_* environment variables and a --config file under the command line.
+//! Options on the command line win over the environment, which wins over the config file,
+//! which wins over the defaults.
+
+use crate::{preset, Args};
+use clap::parser::ValueSource;
+use clap::{Arg, Command, CommandFactory, FromArgMatches};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::OnceLock;
+
+const ENV_PREFIX: &str = "EBPF_FUZZER_";
+
+/// Bounds on program size. A preset's are left out when any is given, so they neither cross
+/// the given ones nor conflict with them.
+const SIZE_BOUNDS: &[&str] = &["min-size", "max-size", "min-bytes", "max-bytes"];
+
+static ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Environment variable of an option, e.g. EBPF_FUZZER_MAX_SIZE for --max-size
+fn env_var(long: &str) -> String {
+    format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"))
+}
+
+/// Command-line words for a config file value: `--long=value`, the bare flag for true, or
+/// one word per element of an array
+fn words(long: &str, value: &toml::Value, takes_values: bool) -> Vec<String> {
+    match value {
+        toml::Value::Boolean(set) if !takes_values => if *set { vec![format!("--{}", long)] } else { vec![] },
+        toml::Value::Boolean(b) => vec![format!("--{}={}", long, b)],
+        toml::Value::Integer(i) => vec![format!("--{}={}", long, i)],
+        toml::Value::Float(f) => vec![format!("--{}={}", long, f)],
+        toml::Value::String(s) => vec![format!("--{}={}", long, s)],
+        toml::Value::Array(items) => items.iter().flat_map(|item| words(long, item, takes_values)).collect(),
+        _ => panic!("Unsupported value for {} in config file", long),
+    }
+}
+
+/// The command line with options from the environment and the config file put in front of
+/// it, leaving out those it sets itself
+fn layer() -> Vec<String> {
+    let cli: Vec<String> = std::env::args().collect();
+    let command = Args::command();
+    let matches = command.clone().get_matches_from(&cli);
+    let on_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    let parsed = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let path = parsed.config.or_else(|| std::env::var(env_var("config")).ok());
+    let config: BTreeMap<String, toml::Value> = path.map_or_else(BTreeMap::new, |path| {
+        let text = fs::read_to_string(&path).expect("Failed to read config file");
+        toml::from_str(&text).unwrap_or_else(|err| panic!("Invalid config file {}: {}", path, err))
+    });
+    for key in config.keys() {
+        let known = key != "config" && command.get_arguments().any(|arg| arg.get_long() == Some(key.as_str()));
+        assert!(known, "Unknown option {} in config file", key);
+    }
+
+    let mut layered = cli[..1].to_vec();
+    for arg in command.get_arguments() {
+        let Some(long) = arg.get_long() else { continue };
+        if matches!(long, "config" | "help" | "version") || on_cli(arg.get_id().as_str()) {
+            continue;
+        }
+        let takes_values = arg.get_action().takes_values();
+        if let Ok(value) = std::env::var(env_var(long)) {
+            if takes_values {
+                layered.push(format!("--{}={}", long, value));
+            } else if matches!(value.as_str(), "1" | "true") {
+                layered.push(format!("--{}", long));
+            } else if !matches!(value.as_str(), "" | "0" | "false") {
+                eprintln!("{} must be 1, true, 0 or false", env_var(long));
+                std::process::exit(2);
+            }
+        } else if let Some(value) = config.get(long) {
+            layered.extend(words(long, value, takes_values));
+        }
+    }
+    layered.extend_from_slice(&cli[1..]);
+    with_preset(&command, layered)
+}
+
+/// The layered command line with the options of its --preset put in front, leaving out
+/// those it sets itself or conflicts with
+fn with_preset(command: &Command, layered: Vec<String>) -> Vec<String> {
+    let matches = command.clone().get_matches_from(&layered);
+    let parsed = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let Some(preset) = parsed.preset else {
+        return layered;
+    };
+    let given = |arg: &Arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
+
+    // Conflicts are declared on one of the two options only
+    let conflict = |a: &Arg, b: &Arg| command.get_arg_conflicts_with(a).into_iter().any(|c| c.get_id() == b.get_id());
+    let sizes_given = command.get_arguments().any(|arg| arg.get_long().is_some_and(|long| SIZE_BOUNDS.contains(&long)) && given(arg));
+
+    let mut with = layered[..1].to_vec();
+    for word in preset::options(preset) {
+        let long = word.trim_start_matches("--").split('=').next().unwrap();
+        let arg = command.get_arguments().find(|arg| arg.get_long() == Some(long)).expect("Preset sets an unknown option");
+        let overridden = given(arg)
+            || command.get_arguments().any(|other| given(other) && (conflict(arg, other) || conflict(other, arg)))
+            || (sizes_given && SIZE_BOUNDS.contains(&long));
+        if !overridden {
+            with.push(word.to_string());
+        }
+    }
+    with.extend_from_slice(&layered[1..]);
+    with
+}
+
+/// The effective command line, as parsed and as recorded in snapshots and reproducers
+pub fn args() -> &'static [String] {
+    ARGS.get_or_init(layer)
+}
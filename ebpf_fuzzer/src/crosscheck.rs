@@ -0,0 +1,265 @@
+//! Cross-checks the crate's decoder against an external disassembler such as llvm-objdump
+
+#[cfg(feature = "tui")]
+use crate::dashboard::Dashboard;
+#[cfg(feature = "tui")]
+use crate::metrics::Metrics;
+use crate::{elf, generate_test, matches_template, output_path, repro, write_output, Args, Format, GenOptions, Instruction};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::process::Command;
+#[cfg(feature = "tui")]
+use std::sync::atomic::Ordering;
+#[cfg(feature = "tui")]
+use std::sync::Arc;
+
+/// Registers and numbers of an instruction, in the order llvm-objdump prints them
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Operands {
+    regs: Vec<u8>,
+    nums: Vec<i64>,
+}
+
+#[derive(Debug)]
+enum Mismatch {
+    /// Both sides decode the instruction but disagree on its operands
+    Operands { expected: Operands, actual: String },
+    /// The external tool cannot decode an instruction we consider valid
+    ToolRejected,
+    /// The external tool decodes an instruction we consider invalid
+    ToolAccepted { actual: String },
+    /// The external tool printed nothing for this slot
+    Missing,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Operands { expected, actual } => write!(
+                f,
+                "operands differ: expected regs {:?} nums {:?}, tool printed \"{}\"",
+                expected.regs, expected.nums, actual
+            ),
+            Mismatch::ToolRejected => write!(f, "tool cannot decode a valid instruction"),
+            Mismatch::ToolAccepted { actual } => write!(f, "tool decodes an invalid instruction as \"{}\"", actual),
+            Mismatch::Missing => write!(f, "missing from tool output"),
+        }
+    }
+}
+
+fn expected_operands(insn: &Instruction, next: Option<&Instruction>) -> Operands {
+    let (d, s) = (insn.dst, insn.src);
+    let off = insn.offset as i16 as i64;
+    let imm = insn.imm as i32 as i64;
+    let uses_src = insn.opcode & 0x08 != 0;
+
+    let (regs, nums) = match insn.opcode & 0x07 {
+        0x00 => {
+            let high = next.map_or(0, |n| n.imm as u64);
+            (vec![d], vec![((high << 32) | insn.imm as u64) as i64])
+        }
+        0x01 => (vec![d, s], vec![off]),
+        0x02 => (vec![d], vec![off, imm]),
+        0x03 if insn.opcode & 0xe0 == 0xc0 => match insn.imm {
+            0xf1 => (vec![0, d, 0, s], vec![off]),
+            fetch if fetch & 0x01 != 0 => (vec![s, d, s], vec![off]),
+            _ => (vec![d, s], vec![off]),
+        },
+        0x03 => (vec![d, s], vec![off]),
+        0x04 | 0x07 => match insn.opcode & 0xf0 {
+            // neg and byte swaps read and write dst
+            0x80 | 0xd0 => (vec![d, d], vec![]),
+            _ if uses_src => (vec![d, s], vec![]),
+            _ => (vec![d], vec![imm]),
+        },
+        class => match insn.opcode & 0xf0 {
+            0x00 if class == 0x06 => (vec![], vec![imm]),
+            0x00 => (vec![], vec![off]),
+            0x80 => (vec![], vec![imm]),
+            0x90 => (vec![], vec![]),
+            _ if uses_src => (vec![d, s], vec![off]),
+            _ => (vec![d], vec![imm, off]),
+        },
+    };
+    Operands { regs, nums }
+}
+
+fn parse_register(word: &str) -> Option<u8> {
+    let word = word.trim_start_matches(['-', '+']);
+    let digits = word.strip_prefix('r').or_else(|| word.strip_prefix('w'))?;
+    digits.parse().ok()
+}
+
+fn parse_number(word: &str) -> Option<i64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word.trim_start_matches('+')),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<u64>().ok()?,
+    } as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+/// Extracts operands from one line of disassembly, e.g. `*(u32 *)(r10 - 4) = r1`
+fn parse_operands(text: &str) -> Operands {
+    // Drop symbolic annotations such as `<prog+0x70>` and glue signs to memory offsets
+    let text = match text.rfind('<') {
+        Some(i) if text.ends_with('>') => &text[..i],
+        _ => text,
+    };
+    let text = text.replace(" - ", " -").replace(" + ", " +");
+
+    let mut operands = Operands::default();
+    let words = text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+'));
+    for word in words {
+        if let Some(reg) = parse_register(word) {
+            operands.regs.push(reg);
+        } else if let Some(num) = parse_number(word) {
+            operands.nums.push(num);
+        }
+    }
+    operands
+}
+
+/// Maps slot index to instruction text for llvm-objdump (`12: <bytes>\t<text>`)
+/// and bpftool (`12: (b7) <text>`) style listings
+fn parse_listing(output: &str) -> BTreeMap<usize, String> {
+    let mut listing = BTreeMap::new();
+    for line in output.lines() {
+        let Some((index, rest)) = line.trim_start().split_once(':') else {
+            continue;
+        };
+        let Ok(index) = index.parse::<usize>() else {
+            continue;
+        };
+        let text = rest.rsplit('\t').next().unwrap_or(rest).trim();
+        let text = match text.strip_prefix('(') {
+            Some(t) if t.get(2..3) == Some(")") => t[3..].trim(),
+            _ => text,
+        };
+        listing.insert(index, text.to_string());
+    }
+    listing
+}
+
+/// Substitutes the object path into the disassembler command, appending it if there is no {}
+fn disasm_command(disasm_cmd: &str, path: &str) -> String {
+    if disasm_cmd.contains("{}") {
+        disasm_cmd.replace("{}", path)
+    } else {
+        format!("{} {}", disasm_cmd, path)
+    }
+}
+
+fn disassemble(bytes: &[u8], disasm_cmd: &str) -> String {
+    let path = std::env::temp_dir().join(format!("ebpf_fuzzer_crosscheck_{}.o", std::process::id()));
+    std::fs::write(&path, elf::write_object("socket", bytes, &[], &[], 0, false)).expect("Failed to write ELF object");
+
+    let cmd = disasm_command(disasm_cmd, &path.to_string_lossy());
+    let output = Command::new("sh").arg("-c").arg(&cmd).output().expect("Failed to run disassembler");
+    if !output.status.success() {
+        panic!("Disassembler failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn check(bytes: &[u8], disasm_cmd: &str) -> Vec<(usize, Mismatch)> {
+    let listing = parse_listing(&disassemble(bytes, disasm_cmd));
+    let slots: Vec<Instruction> = bytes.chunks_exact(8).map(Instruction::from_bytes).collect();
+
+    let mut mismatches = Vec::new();
+    let mut pc = 0;
+    while pc < slots.len() {
+        let insn = &slots[pc];
+        let next = slots.get(pc + 1);
+        let expected = expected_operands(insn, next);
+        let valid = matches_template(insn) && expected.regs.iter().all(|&r| r <= 10);
+
+        match listing.get(&pc) {
+            None => mismatches.push((pc, Mismatch::Missing)),
+            Some(text) if text.contains("unknown") => {
+                if valid {
+                    mismatches.push((pc, Mismatch::ToolRejected));
+                }
+            }
+            Some(text) if !valid => mismatches.push((pc, Mismatch::ToolAccepted { actual: text.clone() })),
+            Some(text) => {
+                if parse_operands(text) != expected {
+                    mismatches.push((pc, Mismatch::Operands { expected, actual: text.clone() }));
+                }
+            }
+        }
+        pc += if insn.opcode == 0x18 && next.is_some() { 2 } else { 1 };
+    }
+    mismatches
+}
+
+/// Saves a reproducer next to a saved finding, keeping an ELF copy to disassemble
+/// when the finding itself is in another format
+fn save_reproducer(args: &Args, opts: &GenOptions, index: u32, bytes: &[u8], disasm_cmd: &str) {
+    let Some(path) = output_path(args, index) else {
+        return;
+    };
+    let object = if args.format == Format::Elf {
+        path.clone()
+    } else {
+        let object = format!("{}.o", path);
+        std::fs::write(&object, elf::write_object("socket", bytes, &[], &[], 0, false)).expect("Failed to write ELF object");
+        object
+    };
+    repro::write(&path, index.into(), opts.seed, &disasm_command(disasm_cmd, &repro::quote(&object)));
+}
+
+/// Generates programs and reports every instruction the external disassembler decodes differently
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, disasm_cmd: &str) {
+    let mut total = 0;
+    let mut programs = 0;
+    #[cfg(feature = "tui")]
+    let metrics = Metrics::new();
+    #[cfg(feature = "tui")]
+    let mut dashboard = args.tui.then(|| Dashboard::start(Arc::clone(&metrics)));
+
+    for i in 0..args.count {
+        #[cfg(feature = "tui")]
+        if dashboard.as_mut().is_some_and(|dashboard| !dashboard.tick()) {
+            break;
+        }
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, opts);
+        let mismatches = check(&bytes, disasm_cmd);
+        programs += 1;
+        #[cfg(feature = "tui")]
+        metrics.execs.fetch_add(1, Ordering::Relaxed);
+        if mismatches.is_empty() {
+            continue;
+        }
+
+        #[cfg(feature = "tui")]
+        metrics.divergences.fetch_add(1, Ordering::Relaxed);
+        for (pc, mismatch) in &mismatches {
+            let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+            let summary = format!("program {} insn {} ({:02x?}): {}", i, pc, insn.to_bytes(), mismatch);
+            #[cfg(feature = "tui")]
+            match &mut dashboard {
+                Some(dashboard) => dashboard.finding(summary),
+                None => println!("{}", summary),
+            }
+            #[cfg(not(feature = "tui"))]
+            println!("{}", summary);
+        }
+        total += mismatches.len();
+        write_output(args, i, &bytes, result);
+        save_reproducer(args, opts, i, &bytes, disasm_cmd);
+    }
+
+    // The terminal is back to normal before the summary goes to it
+    #[cfg(feature = "tui")]
+    drop(dashboard);
+    println!("{} mismatching instructions across {} programs", total, programs);
+    if total > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,132 @@
+//! Live terminal dashboard of a long run: throughput, coverage growth, the findings so far
+//! and the latest of them, redrawn from the run's metrics
+
+use crate::metrics::Metrics;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, Paragraph, Sparkline};
+use ratatui::DefaultTerminal;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the dashboard is redrawn and the keyboard checked
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often the coverage graph takes a sample
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Coverage samples kept, more than any terminal is wide
+const SAMPLES: usize = 1024;
+
+/// Findings listed, newest first
+const RECENT: usize = 64;
+
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    metrics: Arc<Metrics>,
+    /// Coverage signals of the corpus, sampled every `SAMPLE_INTERVAL`
+    coverage: VecDeque<u64>,
+    recent: VecDeque<String>,
+    last_draw: Instant,
+    last_sample: Instant,
+}
+
+impl Dashboard {
+    /// Takes over the terminal until the dashboard is dropped
+    pub fn start(metrics: Arc<Metrics>) -> Dashboard {
+        let terminal = ratatui::init();
+        let long_ago = Instant::now() - SAMPLE_INTERVAL;
+        Dashboard { terminal, metrics, coverage: VecDeque::new(), recent: VecDeque::new(), last_draw: long_ago, last_sample: long_ago }
+    }
+
+    /// Lists a finding at the top of the recent ones
+    pub fn finding(&mut self, summary: String) {
+        // Disassembler output and rbpf errors may span lines
+        self.recent.push_front(summary.replace('\n', " "));
+        self.recent.truncate(RECENT);
+    }
+
+    /// Redraws the dashboard if it is time to, returning false once q, Esc or Ctrl-C was
+    /// pressed, as the terminal no longer turns Ctrl-C into a signal
+    pub fn tick(&mut self) -> bool {
+        if self.last_draw.elapsed() < REDRAW_INTERVAL {
+            return true;
+        }
+        self.last_draw = Instant::now();
+        if self.last_sample.elapsed() >= SAMPLE_INTERVAL {
+            self.last_sample = Instant::now();
+            self.coverage.push_back(self.metrics.signals.load(Ordering::Relaxed));
+            if self.coverage.len() > SAMPLES {
+                self.coverage.pop_front();
+            }
+        }
+        self.draw();
+
+        while event::poll(Duration::ZERO).expect("Failed to read the terminal") {
+            if let Event::Key(key) = event::read().expect("Failed to read the terminal") {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn draw(&mut self) {
+        let metrics = &self.metrics;
+        let count = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+        let elapsed = metrics.elapsed().as_secs();
+        let stats = vec![
+            Line::from(format!(
+                "run time {}:{:02}:{:02}    programs {}    {:.0}/s",
+                elapsed / 3600,
+                elapsed / 60 % 60,
+                elapsed % 60,
+                count(&metrics.execs),
+                metrics.execs_per_second()
+            )),
+            Line::from(format!(
+                "corpus {}    queue {}    coverage signals {}",
+                count(&metrics.corpus_entries),
+                count(&metrics.queue_depth),
+                count(&metrics.signals)
+            )),
+            Line::from(format!(
+                "crashes {}    hangs {}    divergences {}    groups {}",
+                count(&metrics.crashes),
+                count(&metrics.hangs),
+                count(&metrics.divergences),
+                count(&metrics.finding_groups)
+            )),
+        ];
+
+        let coverage: Vec<u64> = self.coverage.iter().copied().collect();
+        let recent: Vec<String> = self.recent.iter().cloned().collect();
+        self.terminal
+            .draw(|frame| {
+                let [top, graph, list] = Layout::vertical([Constraint::Length(5), Constraint::Length(8), Constraint::Min(3)]).areas(frame.area());
+                frame.render_widget(Paragraph::new(stats).block(Block::bordered().title(" ebpf_fuzzer (q to stop) ")), top);
+
+                // The graph shows the latest samples that fit, rising from the lowest of them
+                let width = graph.width.saturating_sub(2) as usize;
+                let shown = &coverage[coverage.len().saturating_sub(width)..];
+                let floor = shown.iter().copied().min().unwrap_or(0);
+                let heights: Vec<u64> = shown.iter().map(|&signals| signals - floor + 1).collect();
+                let title = format!(" coverage signals, {}..{} ", floor, shown.last().copied().unwrap_or(0));
+                frame.render_widget(Sparkline::default().block(Block::bordered().title(title)).data(&heights), graph);
+
+                frame.render_widget(List::new(recent).block(Block::bordered().title(" recent findings ")), list);
+            })
+            .expect("Failed to draw the dashboard");
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
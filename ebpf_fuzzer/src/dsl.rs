@@ -0,0 +1,165 @@
+//! Program templates given on the command line: fixed instructions in rbpf's assembler
+//! syntax with random fields, and runs of random instructions between them
+
+use crate::{generate_program, parse_range, GenOptions};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+use std::ops::RangeInclusive;
+
+/// A part of a program template
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    /// An instruction, with placeholders for random fields
+    Insn(String),
+    /// A run of random instructions, of a length in the range
+    Any(RangeInclusive<u32>),
+}
+
+/// A program template, as --template gives it
+#[derive(Debug, Clone)]
+pub struct ProgramTemplate(Vec<Item>);
+
+/// The value of a placeholder: a register number, a decimal immediate or a signed offset,
+/// within the range after a colon if there is one, or the start of that range without `opts`
+fn placeholder<R: Rng>(rng: &mut R, spec: &str, opts: Option<&GenOptions>) -> Result<String, String> {
+    let (name, range) = match spec.split_once(':') {
+        Some((name, range)) => (name.trim(), Some(parse_range::<i64>(range)?)),
+        None => (spec.trim(), None),
+    };
+    let (lo, hi) = match name {
+        "r" => (0, 10),
+        "imm" => (i32::MIN as i64, u32::MAX as i64),
+        "off" => (i16::MIN as i64, i16::MAX as i64),
+        _ => return Err(format!("unknown placeholder {{{}}} (r, imm, off or any)", spec)),
+    };
+    let range = range.unwrap_or(lo..=hi);
+    if *range.start() < lo || *range.end() > hi {
+        return Err(format!("placeholder {{{}}} out of range", spec));
+    }
+    let value = match opts {
+        None => *range.start(),
+        // Registers the options allow, when no range says otherwise
+        Some(opts) if name == "r" && spec.trim() == "r" => {
+            let regs: Vec<u8> = opts.regs.iter().copied().filter(|&r| r <= 10).collect();
+            *regs.choose(rng).unwrap_or(&0) as i64
+        }
+        Some(_) if name == "imm" && range == (lo..=hi) => rng.random::<i32>() as i64,
+        Some(_) => rng.random_range(range),
+    };
+    Ok(if name == "off" { format!("{:+}", value) } else { value.to_string() })
+}
+
+/// An instruction with its placeholders filled in, at random or with their lowest values
+fn fill<R: Rng>(rng: &mut R, text: &str, opts: Option<&GenOptions>) -> Result<String, String> {
+    let mut filled = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}').ok_or_else(|| format!("unclosed placeholder in {}", text))? + start;
+        filled.push_str(&rest[..start]);
+        filled.push_str(&placeholder(rng, &rest[start + 1..end], opts)?);
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// Parses a template of items separated by `;` or newlines. `{any}` is a random instruction,
+/// `{any*N}` and `{any*LO..HI}` runs of them; anything else is an instruction rbpf's
+/// assembler takes, in which `{r}`, `{imm}` and `{off}` stand for a random register,
+/// immediate and offset, optionally within a range as in `{imm:0..255}`.
+pub fn parse(template: &str) -> Result<ProgramTemplate, String> {
+    let mut items = Vec::new();
+    for item in template.split([';', '\n']).map(str::trim).filter(|item| !item.is_empty()) {
+        let run = item.strip_prefix("{any").and_then(|rest| rest.strip_suffix('}'));
+        if let Some(count) = run {
+            let count = match count.strip_prefix('*') {
+                Some(count) => parse_range::<u32>(count)?,
+                None if count.is_empty() => 1..=1,
+                None => return Err(format!("expected {{any}} or {{any*N}}: {}", item)),
+            };
+            items.push(Item::Any(count));
+            continue;
+        }
+        // Catch syntax errors now rather than in the middle of a campaign
+        let lowest = fill(&mut rand::rng(), item, None)?;
+        rbpf::assembler::assemble(&lowest).map_err(|err| format!("{}: {}", item, err))?;
+        items.push(Item::Insn(item.to_string()));
+    }
+    if items.is_empty() {
+        return Err("empty template".to_string());
+    }
+    Ok(ProgramTemplate(items))
+}
+
+/// Generates a program from a template
+pub fn generate<R: Rng>(rng: &mut R, template: &ProgramTemplate, opts: &GenOptions) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for item in &template.0 {
+        match item {
+            Item::Any(count) => {
+                let count = rng.random_range(count.clone());
+                if count > 0 {
+                    bytes.extend(generate_program(rng, count, opts));
+                }
+            }
+            Item::Insn(text) => {
+                let insn = fill(rng, text, Some(opts)).expect("Template checked when parsed");
+                bytes.extend(rbpf::assembler::assemble(&insn).unwrap_or_else(|err| panic!("Failed to assemble {}: {}", insn, err)));
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_parse_into_instructions_and_runs() {
+        let cases = [
+            ("exit", vec![Item::Insn("exit".to_string())]),
+            (
+                "mov64 r{r}, {imm:0..255}; {any*2..4}\n exit",
+                vec![Item::Insn("mov64 r{r}, {imm:0..255}".to_string()), Item::Any(2..=4), Item::Insn("exit".to_string())],
+            ),
+            ("{any}; {any*0};; {any*3}", vec![Item::Any(1..=1), Item::Any(0..=0), Item::Any(3..=3)]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap().0, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn placeholders_fill_with_their_lowest_values() {
+        let cases = [
+            ("exit", "exit"),
+            ("add64 r{r}, {imm}", "add64 r0, -2147483648"),
+            ("add64 r{r:3..9}, {imm:7}", "add64 r3, 7"),
+            ("ja {off}", "ja -32768"),
+            ("jeq r{ r }, 0, { off : 4..8 }", "jeq r0, 0, +4"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(fill(&mut rand::rng(), input, None).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn malformed_templates_fail_with_their_reason() {
+        let cases = [
+            ("", "empty template"),
+            (" ;\n ; ", "empty template"),
+            ("{anything}", "expected {any} or {any*N}: {anything}"),
+            ("{any*}", "invalid value: "),
+            ("{any*3..1}", "empty range: 3..1"),
+            ("mov64 r{x}, 1", "unknown placeholder {x} (r, imm, off or any)"),
+            ("mov64 r{r:0..11}, 1", "placeholder {r:0..11} out of range"),
+            ("ja {off:-32769..0}", "placeholder {off:-32769..0} out of range"),
+            ("mov64 r0, {imm:a..2}", "invalid value: a"),
+            ("mov64 r{r, 1", "unclosed placeholder in mov64 r{r, 1"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap_err(), expected, "{:?}", input);
+        }
+    }
+}
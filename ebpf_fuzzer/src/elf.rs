@@ -0,0 +1,604 @@
+//! Minimal relocatable ELF writer for eBPF object files, and a reader pulling the programs
+//! out of the objects clang builds
+
+use crate::cfg::{self, Node};
+use crate::Instruction;
+use std::collections::BTreeSet;
+
+const EM_BPF: u16 = 247;
+const ET_REL: u16 = 1;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_REL: u32 = 9;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+
+const STB_GLOBAL: u8 = 1;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+const EHDR_SIZE: usize = 64;
+const SHDR_SIZE: usize = 64;
+const SYM_SIZE: usize = 24;
+const REL_SIZE: usize = 16;
+
+/// Relocation of the immediate of a LD_DW_IMM, against a map for map references
+const R_BPF_64_64: u64 = 1;
+/// Relocation of the immediate of a local call
+const R_BPF_64_32: u64 = 10;
+
+/// Source registers of LD_DW_IMM loading a map fd, a map value address or a function address
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const BPF_PSEUDO_MAP_VALUE: u8 = 2;
+const BPF_PSEUDO_FUNC: u8 = 4;
+/// Source register of a local call
+const BPF_PSEUDO_CALL: u8 = 1;
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const BTF_HDR_SIZE: u32 = 24;
+
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_PTR: u32 = 2;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+
+const BTF_INT_SIGNED: u32 = 1;
+const BTF_VAR_GLOBAL_ALLOCATED: u32 = 1;
+
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+
+/// Entries of each declared map, an array of u64 values with u32 keys
+const MAP_ENTRIES: u32 = 16;
+
+/// Size of a map definition in .maps, four pointers
+const MAP_DEF_SIZE: usize = 32;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    data: Vec<u8>,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+impl Section {
+    fn new(name: u32, kind: u32, flags: u64, data: Vec<u8>, align: u64) -> Self {
+        Self { name, kind, flags, data, link: 0, info: 0, align, entsize: 0 }
+    }
+}
+
+/// Byte buffer writing integers in the object's byte order
+struct Buf(Vec<u8>, bool);
+
+impl Buf {
+    fn new(big_endian: bool) -> Self {
+        Self(Vec::new(), big_endian)
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.0.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.0.extend_from_slice(&if self.1 { v.to_be_bytes() } else { v.to_le_bytes() });
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&if self.1 { v.to_be_bytes() } else { v.to_le_bytes() });
+    }
+
+    fn u64(&mut self, v: u64) {
+        self.0.extend_from_slice(&if self.1 { v.to_be_bytes() } else { v.to_le_bytes() });
+    }
+
+    fn align(&mut self, align: usize) {
+        while !self.0.len().is_multiple_of(align) {
+            self.0.push(0);
+        }
+    }
+}
+
+/// String table builder, returning offsets of added names
+struct StrTab(Vec<u8>);
+
+impl StrTab {
+    fn new() -> Self {
+        Self(vec![0])
+    }
+
+    fn add(&mut self, name: &str) -> u32 {
+        let offset = self.0.len() as u32;
+        self.0.extend_from_slice(name.as_bytes());
+        self.0.push(0);
+        offset
+    }
+}
+
+fn symbol(buf: &mut Buf, name: u32, bind: u8, kind: u8, shndx: u16, value: u64, size: u64) {
+    buf.u32(name);
+    buf.u8((bind << 4) | kind);
+    buf.u8(0);
+    buf.u16(shndx);
+    buf.u64(value);
+    buf.u64(size);
+}
+
+/// What a relocated instruction refers to
+pub enum Target {
+    /// A function of .text, called with a local call
+    Function(usize),
+    /// A map of .maps, loaded with a LD_DW_IMM
+    Map(usize),
+}
+
+/// An instruction loaders patch through a relocation
+pub struct Reloc {
+    /// Whether the instruction is in .text rather than in the program's section
+    pub in_text: bool,
+    /// Byte offset of the instruction in its section, .text holding the functions one after another
+    pub offset: usize,
+    pub target: Target,
+}
+
+/// Splits a little-endian program at the targets of its local calls: the code before the
+/// first target stays the program, and each target starts a function of .text. Calls to the
+/// functions get an immediate of -1 and a relocation against the function's symbol, like
+/// clang emits; branches between parts keep their offset, which now leaves their part.
+/// With `maps`, LD_DW_IMM of a map by fd (src 1) load 0 with a relocation against map
+/// `imm % maps` instead.
+pub fn relocate(bytes: &[u8], maps: usize) -> (Vec<u8>, Vec<Vec<u8>>, Vec<Reloc>) {
+    let nodes = cfg::decode(bytes);
+    let is_call = |node: &Node| node.insn.opcode == 0x85 && node.insn.src == 1;
+    let starts: BTreeSet<usize> = nodes.iter().filter(|n| is_call(n)).filter_map(|n| n.target).filter(|&t| t > 0).collect();
+    let bounds: Vec<usize> = std::iter::once(0).chain(starts).chain(std::iter::once(nodes.len())).collect();
+
+    let mut parts = Vec::with_capacity(bounds.len() - 1);
+    let mut relocs = Vec::new();
+    // Functions follow each other in .text
+    let mut text_len = 0;
+    for (part, range) in bounds.windows(2).map(|w| w[0]..w[1]).enumerate() {
+        let mut code: Vec<Node> = nodes[range.clone()].to_vec();
+        let in_text = part > 0;
+        let mut offset = if in_text { text_len } else { 0 };
+        for node in &mut code {
+            match node.target {
+                Some(target) if is_call(node) && target > 0 => {
+                    let function = bounds.partition_point(|&b| b <= target) - 2;
+                    relocs.push(Reloc { in_text, offset, target: Target::Function(function) });
+                    node.insn.imm = u32::MAX;
+                    node.target = None;
+                }
+                Some(target) if range.contains(&target) => node.target = Some(target - range.start),
+                Some(_) => node.target = None,
+                None => {}
+            }
+            if maps > 0 && node.insn.opcode == 0x18 && node.insn.src == 1 {
+                relocs.push(Reloc { in_text, offset, target: Target::Map(node.insn.imm as usize % maps) });
+                node.insn.src = 0;
+                node.insn.imm = 0;
+            }
+            offset += if node.second.is_some() { 16 } else { 8 };
+        }
+        // Each function keeps the distances its branches had in the whole program
+        let code = cfg::encode(&code).expect("Branch out of range in a function");
+        if part > 0 {
+            text_len += code.len();
+        }
+        parts.push(code);
+    }
+    let program = parts.remove(0);
+    (program, parts, relocs)
+}
+
+/// Relocation entries of `relocs` in .text or not. Function symbols follow the null symbol,
+/// and map symbols the program and license ones after them.
+fn relocations(relocs: &[Reloc], in_text: bool, functions: usize, big_endian: bool) -> Vec<u8> {
+    let mut rel = Buf::new(big_endian);
+    for reloc in relocs.iter().filter(|r| r.in_text == in_text) {
+        let (symbol, kind) = match reloc.target {
+            Target::Function(function) => (function + 1, R_BPF_64_32),
+            Target::Map(map) => (functions + 3 + map, R_BPF_64_64),
+        };
+        rel.u64(reloc.offset as u64);
+        rel.u64(((symbol as u64) << 32) | kind);
+    }
+    rel.0
+}
+
+/// BTF type entry header
+fn btf_type(buf: &mut Buf, name: u32, kind: u32, vlen: u32, size_or_type: u32) {
+    buf.u32(name);
+    buf.u32((kind << 24) | vlen);
+    buf.u32(size_or_type);
+}
+
+/// BTF describing `maps` array maps declared the way libbpf's `__uint` and `__type` macros
+/// do, as pointers to arrays whose length is the value and pointers to the key and value types
+fn btf(maps: usize, big_endian: bool) -> Vec<u8> {
+    let mut strings = StrTab::new();
+    let mut types = Buf::new(big_endian);
+    // 1 int, 2 unsigned int, 3 unsigned long long
+    btf_type(&mut types, strings.add("int"), BTF_KIND_INT, 0, 4);
+    types.u32((BTF_INT_SIGNED << 24) | 32);
+    btf_type(&mut types, strings.add("unsigned int"), BTF_KIND_INT, 0, 4);
+    types.u32(32);
+    btf_type(&mut types, strings.add("unsigned long long"), BTF_KIND_INT, 0, 8);
+    types.u32(64);
+    // 4 int[BPF_MAP_TYPE_ARRAY] and 5 a pointer to it, for the map type
+    btf_type(&mut types, 0, BTF_KIND_ARRAY, 0, 0);
+    types.u32(1);
+    types.u32(1);
+    types.u32(BPF_MAP_TYPE_ARRAY);
+    btf_type(&mut types, 0, BTF_KIND_PTR, 0, 4);
+    // 6 and 7 pointers to the key and value types
+    btf_type(&mut types, 0, BTF_KIND_PTR, 0, 2);
+    btf_type(&mut types, 0, BTF_KIND_PTR, 0, 3);
+    // 8 int[MAP_ENTRIES] and 9 a pointer to it, for the entry count
+    btf_type(&mut types, 0, BTF_KIND_ARRAY, 0, 0);
+    types.u32(1);
+    types.u32(1);
+    types.u32(MAP_ENTRIES);
+    btf_type(&mut types, 0, BTF_KIND_PTR, 0, 8);
+    // 10 the anonymous map definition struct, with members at bit offsets
+    btf_type(&mut types, 0, BTF_KIND_STRUCT, 4, MAP_DEF_SIZE as u32);
+    for (i, (name, kind)) in [("type", 5), ("key", 6), ("value", 7), ("max_entries", 9)].into_iter().enumerate() {
+        types.u32(strings.add(name));
+        types.u32(kind);
+        types.u32(i as u32 * 64);
+    }
+    // 11 onwards one variable per map, then the section holding them
+    for map in 0..maps {
+        btf_type(&mut types, strings.add(&format!("map{}", map)), BTF_KIND_VAR, 0, 10);
+        types.u32(BTF_VAR_GLOBAL_ALLOCATED);
+    }
+    btf_type(&mut types, strings.add(".maps"), BTF_KIND_DATASEC, maps as u32, (maps * MAP_DEF_SIZE) as u32);
+    for map in 0..maps {
+        types.u32(11 + map as u32);
+        types.u32((map * MAP_DEF_SIZE) as u32);
+        types.u32(MAP_DEF_SIZE as u32);
+    }
+
+    let mut out = Buf::new(big_endian);
+    out.u16(BTF_MAGIC);
+    out.u8(1);
+    out.u8(0);
+    out.u32(BTF_HDR_SIZE);
+    out.u32(0);
+    out.u32(types.0.len() as u32);
+    out.u32(types.0.len() as u32);
+    out.u32(strings.0.len() as u32);
+    out.0.extend_from_slice(&types.0);
+    out.0.extend_from_slice(&strings.0);
+    out.0
+}
+
+/// Builds an object file holding program `prog` in `section`, plus a GPL license. `functions`
+/// go in .text as static functions, and `maps` BTF-defined array maps in .maps, which the
+/// code refers to through `relocs`. Code must already be encoded in the object's byte order.
+pub fn write_object(section: &str, bytes: &[u8], functions: &[Vec<u8>], relocs: &[Reloc], maps: usize, big_endian: bool) -> Vec<u8> {
+    let mut strtab = StrTab::new();
+    let strtab_name = strtab.add(".strtab");
+    let text_name = strtab.add(section);
+    let license_name = strtab.add("license");
+    let symtab_name = strtab.add(".symtab");
+    let prog_sym = strtab.add("prog");
+    let license_sym = strtab.add("_license");
+    let function_syms: Vec<u32> = (0..functions.len()).map(|i| strtab.add(&format!("func{}", i))).collect();
+    let map_syms: Vec<u32> = (0..maps).map(|i| strtab.add(&format!("map{}", i))).collect();
+    let functions_name = (!functions.is_empty()).then(|| strtab.add(".text"));
+    let maps_names = (maps > 0).then(|| (strtab.add(".maps"), strtab.add(".BTF")));
+    let rel_names = (!relocs.is_empty()).then(|| (strtab.add(&format!(".rel{}", section)), strtab.add(".rel.text")));
+
+    // Sections 1 .strtab, 2 program, 3 license and 4 .symtab come first, then .text, .maps
+    // and .BTF if needed, and the relocation sections last
+    let text_index = 5;
+    let maps_index = if functions.is_empty() { 5 } else { 6 };
+
+    let mut symtab = Buf::new(big_endian);
+    symtab.0.resize(SYM_SIZE, 0);
+    // Local symbols come first
+    let mut value = 0;
+    for (&name, code) in function_syms.iter().zip(functions) {
+        symbol(&mut symtab, name, STB_LOCAL, STT_FUNC, text_index, value, code.len() as u64);
+        value += code.len() as u64;
+    }
+    symbol(&mut symtab, prog_sym, STB_GLOBAL, STT_FUNC, 2, 0, bytes.len() as u64);
+    symbol(&mut symtab, license_sym, STB_GLOBAL, STT_OBJECT, 3, 0, 4);
+    for (map, &name) in map_syms.iter().enumerate() {
+        symbol(&mut symtab, name, STB_GLOBAL, STT_OBJECT, maps_index, (map * MAP_DEF_SIZE) as u64, MAP_DEF_SIZE as u64);
+    }
+
+    let mut sections = vec![
+        Section::new(strtab_name, SHT_STRTAB, 0, strtab.0, 1),
+        Section::new(text_name, SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, bytes.to_vec(), 8),
+        Section::new(license_name, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, b"GPL\0".to_vec(), 1),
+        // Linked to .strtab, with info pointing at the first global symbol
+        Section {
+            link: 1,
+            info: functions.len() as u32 + 1,
+            entsize: SYM_SIZE as u64,
+            ..Section::new(symtab_name, SHT_SYMTAB, 0, symtab.0, 8)
+        },
+    ];
+    if let Some(name) = functions_name {
+        sections.push(Section::new(name, SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, functions.concat(), 8));
+    }
+    if let Some((maps_name, btf_name)) = maps_names {
+        sections.push(Section::new(maps_name, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, vec![0; maps * MAP_DEF_SIZE], 8));
+        sections.push(Section::new(btf_name, SHT_PROGBITS, 0, btf(maps, big_endian), 4));
+    }
+    if let Some((rel_name, rel_text_name)) = rel_names {
+        // Linked to .symtab, with info pointing at the section they apply to
+        for (name, in_text, target) in [(rel_name, false, 2), (rel_text_name, true, text_index as u32)] {
+            let data = relocations(relocs, in_text, functions.len(), big_endian);
+            if !data.is_empty() {
+                let rel = Section::new(name, SHT_REL, SHF_INFO_LINK, data, 8);
+                sections.push(Section { link: 4, info: target, entsize: REL_SIZE as u64, ..rel });
+            }
+        }
+    }
+
+    // Section contents follow the ELF header, then the section header table
+    let mut body = Buf::new(big_endian);
+    let mut offsets = Vec::with_capacity(sections.len());
+    for section in &sections {
+        body.align(section.align as usize);
+        offsets.push(EHDR_SIZE + body.0.len());
+        body.0.extend_from_slice(&section.data);
+    }
+    body.align(8);
+    let shoff = EHDR_SIZE + body.0.len();
+
+    let mut out = Buf::new(big_endian);
+    let data = if big_endian { 2 } else { 1 };
+    out.0.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, data, 1, 0]);
+    out.0.resize(16, 0);
+    out.u16(ET_REL);
+    out.u16(EM_BPF);
+    out.u32(1);
+    out.u64(0);
+    out.u64(0);
+    out.u64(shoff as u64);
+    out.u32(0);
+    out.u16(EHDR_SIZE as u16);
+    out.u16(0);
+    out.u16(0);
+    out.u16(SHDR_SIZE as u16);
+    out.u16(sections.len() as u16 + 1);
+    out.u16(1);
+    out.0.extend_from_slice(&body.0);
+
+    // Null section header, then one per section
+    out.0.resize(out.0.len() + SHDR_SIZE, 0);
+    for (section, offset) in sections.iter().zip(offsets) {
+        out.u32(section.name);
+        out.u32(section.kind);
+        out.u64(section.flags);
+        out.u64(0);
+        out.u64(offset as u64);
+        out.u64(section.data.len() as u64);
+        out.u32(section.link);
+        out.u32(section.info);
+        out.u64(section.align);
+        out.u64(section.entsize);
+    }
+    out.0
+}
+
+/// Reads integers in the object's byte order, failing past its end
+struct Reader<'a>(&'a [u8], bool);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, at: usize) -> Result<[u8; N], String> {
+        self.0.get(at..).and_then(|rest| rest.get(..N)).and_then(|b| b.try_into().ok()).ok_or_else(|| "truncated object".to_string())
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, String> {
+        let b = self.bytes(at)?;
+        Ok(if self.1 { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, String> {
+        let b = self.bytes(at)?;
+        Ok(if self.1 { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, String> {
+        let b = self.bytes(at)?;
+        Ok(if self.1 { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) })
+    }
+}
+
+struct Header {
+    name: String,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    info: u32,
+}
+
+struct Symbol {
+    shndx: u16,
+    value: u64,
+}
+
+/// NUL-terminated name at `offset` of a string table
+fn name(strtab: &[u8], offset: u32) -> String {
+    let name = strtab.get(offset as usize..).unwrap_or_default();
+    String::from_utf8_lossy(name.split(|&b| b == 0).next().unwrap_or_default()).into_owned()
+}
+
+/// Contents of a section, empty for .bss and the like
+fn contents<'a>(data: &'a [u8], header: &Header) -> Result<&'a [u8], String> {
+    if header.kind != SHT_PROGBITS && header.kind != SHT_SYMTAB && header.kind != SHT_STRTAB && header.kind != SHT_REL {
+        return Ok(&[]);
+    }
+    let start = usize::try_from(header.offset).ok();
+    let len = usize::try_from(header.size).ok();
+    start.zip(len).and_then(|(start, len)| data.get(start..)?.get(..len)).ok_or_else(|| format!("section {} past the end of the object", header.name))
+}
+
+/// Instructions of a code section, with le and be exchanged back in big-endian objects the
+/// way `encode` exchanges them
+fn instructions(code: &[u8], big_endian: bool) -> Vec<Instruction> {
+    let mut insns = Vec::with_capacity(code.len() / 8);
+    let mut second_slot = false;
+    for b in code.chunks_exact(8) {
+        let mut insn = if big_endian {
+            Instruction::new(b[0], b[1] >> 4, b[1] & 0xF, u16::from_be_bytes([b[2], b[3]]), u32::from_be_bytes([b[4], b[5], b[6], b[7]]))
+        } else {
+            Instruction::from_bytes(b)
+        };
+        if big_endian && !second_slot {
+            insn.opcode = match insn.opcode {
+                0xd4 => 0xdc,
+                0xdc => 0xd4,
+                op => op,
+            };
+        }
+        second_slot = !second_slot && insn.opcode == 0x18;
+        insns.push(insn);
+    }
+    insns
+}
+
+/// Reads the programs of an object built by clang's BPF backend, one per executable section,
+/// as little-endian bytes named after their section. Functions in .text are appended to the
+/// programs referring to them, which call or load them directly instead; .text is a program
+/// of its own only in objects without any other code. LD_DW_IMM relocated against the i-th
+/// map of the object load map fd i (src 1), and those against global data an address in map
+/// i (src 2) for the i-th data section, at the symbol's offset in the second slot.
+pub fn read_object(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    if !data.starts_with(b"\x7fELF") {
+        return Err("not an ELF object".to_string());
+    }
+    let big_endian = match (data.get(4), data.get(5)) {
+        (Some(2), Some(1)) => false,
+        (Some(2), Some(2)) => true,
+        _ => return Err("not a 64-bit ELF object".to_string()),
+    };
+    let r = Reader(data, big_endian);
+    if r.u16(18)? != EM_BPF {
+        return Err("not a BPF object".to_string());
+    }
+
+    let shoff = usize::try_from(r.u64(40)?).map_err(|_| "truncated object")?;
+    let shnum = r.u16(60)? as usize;
+    let strtab_index = r.u16(62)? as usize;
+    let mut headers = Vec::with_capacity(shnum);
+    let mut names = Vec::with_capacity(shnum);
+    for i in 0..shnum {
+        let at = shoff.checked_add(i * SHDR_SIZE).ok_or("truncated object")?;
+        names.push(r.u32(at)?);
+        let (kind, flags, offset, size) = (r.u32(at + 4)?, r.u64(at + 8)?, r.u64(at + 24)?, r.u64(at + 32)?);
+        headers.push(Header { name: String::new(), kind, flags, offset, size, info: r.u32(at + 44)? });
+    }
+    let strtab = match headers.get(strtab_index) {
+        Some(header) => contents(data, header)?,
+        None => &[],
+    };
+    for (header, &offset) in headers.iter_mut().zip(&names) {
+        header.name = name(strtab, offset);
+    }
+
+    let mut symbols = Vec::new();
+    if let Some(symtab) = headers.iter().find(|h| h.kind == SHT_SYMTAB) {
+        let table = Reader(contents(data, symtab)?, big_endian);
+        for at in (0..table.0.len() / SYM_SIZE).map(|i| i * SYM_SIZE) {
+            symbols.push(Symbol { shndx: table.u16(at + 6)?, value: table.u64(at + 8)? });
+        }
+    }
+    let relocs = |section: usize| -> Result<Vec<(usize, u64, usize)>, String> {
+        let mut relocs = Vec::new();
+        for header in headers.iter().filter(|h| h.kind == SHT_REL && h.info as usize == section) {
+            let table = Reader(contents(data, header)?, big_endian);
+            for at in (0..table.0.len() / REL_SIZE).map(|i| i * REL_SIZE) {
+                let info = table.u64(at + 8)?;
+                relocs.push((table.u64(at)? as usize / 8, info & 0xffff_ffff, (info >> 32) as usize));
+            }
+        }
+        Ok(relocs)
+    };
+
+    let is_code = |h: &Header| h.kind == SHT_PROGBITS && h.flags & SHF_EXECINSTR != 0 && h.size >= 8;
+    let is_maps = |h: &Header| h.name == ".maps" || h.name == "maps";
+    let text = headers.iter().position(|h| is_code(h) && h.name == ".text");
+    let mut programs: Vec<usize> = (0..headers.len()).filter(|&i| is_code(&headers[i]) && Some(i) != text).collect();
+    if programs.is_empty() {
+        programs.extend(text);
+    }
+    let data_sections: Vec<usize> =
+        (0..headers.len()).filter(|&i| headers[i].flags & SHF_ALLOC != 0 && !is_code(&headers[i]) && !is_maps(&headers[i])).collect();
+    let mut maps: Vec<usize> = (0..symbols.len())
+        .filter(|&i| headers.get(symbols[i].shndx as usize).is_some_and(is_maps))
+        .collect();
+    maps.sort_by_key(|&i| (symbols[i].shndx, symbols[i].value));
+
+    let mut out = Vec::with_capacity(programs.len());
+    for section in programs {
+        let mut code = instructions(contents(data, &headers[section])?, big_endian);
+        // Where the code of each section included starts in the program
+        let mut bases = vec![(section, 0)];
+        if let Some(text) = text.filter(|&text| text != section) {
+            let uses_text = relocs(section)?.iter().any(|&(_, _, sym)| symbols.get(sym).is_some_and(|s| s.shndx as usize == text));
+            if uses_text {
+                bases.push((text, code.len()));
+                code.extend(instructions(contents(data, &headers[text])?, big_endian));
+            }
+        }
+
+        for &(included, base) in &bases {
+            for (offset, kind, sym) in relocs(included)? {
+                let slot = base + offset;
+                let Some(symbol) = symbols.get(sym) else {
+                    return Err("relocation against a missing symbol".to_string());
+                };
+                let Some(&insn) = code.get(slot) else {
+                    return Err("relocation past the end of its section".to_string());
+                };
+                let target_base = bases.iter().find(|&&(s, _)| s == symbol.shndx as usize).map(|&(_, base)| base as i64);
+                match kind {
+                    // Calls count in instructions from the one after them, and so does the
+                    // immediate clang leaves relative to the symbol
+                    R_BPF_64_32 if insn.opcode == 0x85 => {
+                        if let Some(target_base) = target_base {
+                            let target = target_base + (symbol.value / 8) as i64 + insn.imm as i32 as i64 + 1;
+                            code[slot].src = BPF_PSEUDO_CALL;
+                            code[slot].imm = (target - slot as i64 - 1) as u32;
+                        }
+                    }
+                    R_BPF_64_64 if insn.opcode == 0x18 && slot + 1 < code.len() => {
+                        if let Some(target_base) = target_base {
+                            let target = target_base + (symbol.value.wrapping_add(insn.imm as u64) / 8) as i64;
+                            code[slot].src = BPF_PSEUDO_FUNC;
+                            code[slot].imm = (target - slot as i64 - 1) as u32;
+                        } else if let Some(map) = maps.iter().position(|&m| m == sym) {
+                            code[slot].src = BPF_PSEUDO_MAP_FD;
+                            code[slot].imm = map as u32;
+                        } else if let Some(data) = data_sections.iter().position(|&d| d == symbol.shndx as usize) {
+                            code[slot].src = BPF_PSEUDO_MAP_VALUE;
+                            code[slot].imm = data as u32;
+                            code[slot + 1].imm = symbol.value.wrapping_add(insn.imm as u64) as u32;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        out.push((headers[section].name.clone(), code.iter().flat_map(|insn| insn.to_bytes()).collect()));
+    }
+    Ok(out)
+}
@@ -0,0 +1,86 @@
+//! Exhaustive enumeration of tiny programs: every sequence of a few instructions over the
+//! enabled templates, with their free fields drawn from a small set of values each
+
+use crate::{reserved_fields, write_output, writes_dst, Args, GenOptions, Instruction};
+use std::collections::HashSet;
+
+/// Values free fields take: registers that are both inputs and the result, immediates on
+/// either side of zero and the smallest forward jump past the next instruction
+const REGS: [u8; 2] = [0, 1];
+const IMMS: [u32; 3] = [0, 1, u32::MAX];
+const OFFSETS: [u16; 2] = [0, 1];
+
+/// Registers of `REGS` among `allowed`, or the first allowed one if there are none
+fn regs(allowed: &[u8]) -> Vec<u8> {
+    let regs: Vec<u8> = REGS.iter().copied().filter(|r| allowed.contains(r)).collect();
+    if regs.is_empty() {
+        allowed[..1].to_vec()
+    } else {
+        regs
+    }
+}
+
+/// Encodings of every template with each combination of values for its free fields,
+/// reserved ones zero, each encoded once
+fn variants(opts: &GenOptions) -> Vec<Vec<u8>> {
+    let mut seen = HashSet::new();
+    let mut variants = Vec::new();
+    for template in &opts.templates {
+        let reserved = reserved_fields(template.opcode);
+        let dsts = if reserved.dst { vec![0] } else { regs(if writes_dst(template.opcode) { &opts.writable_regs } else { &opts.regs }) };
+        let srcs = match template.src {
+            Some(src) => vec![src],
+            None if reserved.src => vec![0],
+            None => regs(&opts.regs),
+        };
+        let offsets = match template.offset {
+            Some(offset) => vec![offset],
+            None if reserved.offset => vec![0],
+            None => OFFSETS.to_vec(),
+        };
+        let imms = match template.imm {
+            Some(imm) => vec![imm],
+            None if reserved.imm => vec![0],
+            None => IMMS.to_vec(),
+        };
+
+        for &dst in &dsts {
+            for &src in &srcs {
+                for &offset in &offsets {
+                    for &imm in &imms {
+                        let mut bytes = Instruction::new(template.opcode, dst, src, offset, imm).to_bytes().to_vec();
+                        // LD_DW_IMM loads the same value into both halves
+                        if template.opcode == 0x18 {
+                            bytes.extend_from_slice(&Instruction::new(0, 0, 0, 0, imm).to_bytes());
+                        }
+                        if seen.insert(bytes.clone()) {
+                            variants.push(bytes);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    variants
+}
+
+/// Writes every program of 1 to `size` instructions, each a variant of an enabled template
+pub fn run(args: &Args, opts: &GenOptions, size: u32) {
+    let variants = variants(opts);
+    let mut index = 0;
+    for len in 1..=size as usize {
+        // Count through the programs of this length like an odometer, last digit fastest
+        let mut digits = vec![0; len];
+        loop {
+            let bytes: Vec<u8> = digits.iter().flat_map(|&d| variants[d].iter().copied()).collect();
+            write_output(args, index, &bytes, None);
+            index += 1;
+            let Some(i) = digits.iter().rposition(|&d| d + 1 < variants.len()) else {
+                break;
+            };
+            digits[i] += 1;
+            digits[i + 1..].fill(0);
+        }
+    }
+    eprintln!("{} programs of up to {} instructions over {} instruction variants", index, size, variants.len());
+}
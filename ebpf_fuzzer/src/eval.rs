@@ -0,0 +1,253 @@
+//! Predicts the outcome of programs whose behavior is statically determinable: no memory
+//! accesses, no calls, and no reads of registers the caller sets up, such as r1 and r10
+
+use crate::{cfg, program_malformations, writes_dst, Instruction};
+use std::fmt;
+
+/// Steps after which a program is assumed not to terminate, and left undecided
+pub const MAX_STEPS: usize = 1 << 20;
+
+/// Outcome of a program, as the spec defines it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    /// The program exits with r0
+    Returns(u64),
+    /// The program is invalid, or runs into an error
+    Error(String),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Returns(r0) => write!(f, "returns {:#x}", r0),
+            Expected::Error(err) => write!(f, "error: {}", err),
+        }
+    }
+}
+
+/// Applies an ALU or ALU64 operation, returning the new dst value
+pub fn alu(insn: &Instruction, dst: u64, src: u64) -> u64 {
+    let is64 = insn.opcode & 0x07 == 0x07;
+    let operand = if insn.opcode & 0x08 != 0 { src } else { insn.imm as i32 as i64 as u64 };
+    let signed = insn.offset == 1;
+
+    if is64 {
+        let (d, s) = (dst, operand);
+        match insn.opcode & 0xf0 {
+            0x00 => d.wrapping_add(s),
+            0x10 => d.wrapping_sub(s),
+            0x20 => d.wrapping_mul(s),
+            0x30 if s == 0 => 0,
+            0x30 if signed => (d as i64).wrapping_div(s as i64) as u64,
+            0x30 => d / s,
+            0x40 => d | s,
+            0x50 => d & s,
+            0x60 => d << (s & 63),
+            0x70 => d >> (s & 63),
+            0x80 => d.wrapping_neg(),
+            0x90 if s == 0 => d,
+            0x90 if signed => (d as i64).wrapping_rem(s as i64) as u64,
+            0x90 => d % s,
+            0xa0 => d ^ s,
+            0xb0 => match insn.offset {
+                8 => s as i8 as i64 as u64,
+                16 => s as i16 as i64 as u64,
+                32 => s as i32 as i64 as u64,
+                _ => s,
+            },
+            0xc0 => ((d as i64) >> (s & 63)) as u64,
+            // bswap
+            _ => swap(d, insn.imm),
+        }
+    } else {
+        match insn.opcode {
+            // to_le truncates on a little-endian machine, to_be swaps bytes
+            0xd4 => return truncate(dst, insn.imm),
+            0xdc => return swap(dst, insn.imm),
+            _ => {}
+        }
+        let (d, s) = (dst as u32, operand as u32);
+        let result = match insn.opcode & 0xf0 {
+            0x00 => d.wrapping_add(s),
+            0x10 => d.wrapping_sub(s),
+            0x20 => d.wrapping_mul(s),
+            0x30 if s == 0 => 0,
+            0x30 if signed => (d as i32).wrapping_div(s as i32) as u32,
+            0x30 => d / s,
+            0x40 => d | s,
+            0x50 => d & s,
+            0x60 => d << (s & 31),
+            0x70 => d >> (s & 31),
+            0x80 => d.wrapping_neg(),
+            0x90 if s == 0 => d,
+            0x90 if signed => (d as i32).wrapping_rem(s as i32) as u32,
+            0x90 => d % s,
+            0xa0 => d ^ s,
+            0xb0 => match insn.offset {
+                8 => s as i8 as i32 as u32,
+                16 => s as i16 as i32 as u32,
+                _ => s,
+            },
+            _ => ((d as i32) >> (s & 31)) as u32,
+        };
+        // 32-bit operations zero-extend their result
+        result as u64
+    }
+}
+
+/// Keeps the low `bits` bits of `value`
+fn truncate(value: u64, bits: u32) -> u64 {
+    match bits {
+        16 => value as u16 as u64,
+        32 => value as u32 as u64,
+        _ => value,
+    }
+}
+
+/// Swaps the low `bits` bits of `value` byte-wise, clearing the rest
+fn swap(value: u64, bits: u32) -> u64 {
+    match bits {
+        16 => (value as u16).swap_bytes() as u64,
+        32 => (value as u32).swap_bytes() as u64,
+        _ => value.swap_bytes(),
+    }
+}
+
+/// Whether a conditional jump is taken
+pub fn condition(insn: &Instruction, dst: u64, src: u64) -> bool {
+    let is64 = insn.opcode & 0x07 == 0x05;
+    let operand = if insn.opcode & 0x08 != 0 { src } else { insn.imm as i32 as i64 as u64 };
+    let (d, s, sd, ss) = if is64 {
+        (dst, operand, dst as i64, operand as i64)
+    } else {
+        (dst as u32 as u64, operand as u32 as u64, dst as i32 as i64, operand as i32 as i64)
+    };
+    match insn.opcode & 0xf0 {
+        0x10 => d == s,
+        0x20 => d > s,
+        0x30 => d >= s,
+        0x40 => d & s != 0,
+        0x50 => d != s,
+        0x60 => sd > ss,
+        0x70 => sd >= ss,
+        0xa0 => d < s,
+        0xb0 => d <= s,
+        0xc0 => sd < ss,
+        0xd0 => sd <= ss,
+        _ => false,
+    }
+}
+
+/// Rejects programs any implementation must refuse to load, whatever they would compute
+pub fn check(bytes: &[u8], nodes: &[cfg::Node]) -> Result<(), String> {
+    let malformations: Vec<&str> = program_malformations(bytes).iter().map(|m| m.name()).collect();
+    if !malformations.is_empty() {
+        return Err(format!("invalid program: {}", malformations.join(",")));
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        let class = node.insn.opcode & 0x07;
+        let branch = matches!(class, 0x05 | 0x06) && !matches!(node.insn.opcode & 0xf0, 0x80 | 0x90);
+        let local_call = node.insn.opcode == 0x85 && node.insn.src == 1;
+        if (branch || local_call) && node.target.is_none() {
+            return Err(format!("jump out of bounds at instruction {}", i));
+        }
+        if writes_dst(node.insn.opcode) && node.insn.dst == 10 {
+            return Err(format!("write to read-only r10 at instruction {}", i));
+        }
+    }
+    Ok(())
+}
+
+/// Predicts the outcome of a program, or returns None if it depends on memory, helpers,
+/// the caller's registers or does not terminate within `MAX_STEPS`
+pub fn evaluate(bytes: &[u8]) -> Option<Expected> {
+    let nodes = cfg::decode(bytes);
+    if let Err(err) = check(bytes, &nodes) {
+        return Some(Expected::Error(err));
+    }
+
+    // Nothing is known of registers on entry
+    let mut regs: [Option<u64>; 11] = [None; 11];
+    let mut pc = 0;
+    for _ in 0..MAX_STEPS {
+        let Some(node) = nodes.get(pc) else {
+            return Some(Expected::Error("execution falls off the end of the program".to_string()));
+        };
+        let insn = &node.insn;
+        let (dst, src) = (insn.dst as usize, insn.src as usize);
+        match insn.opcode & 0x07 {
+            0x00 if insn.opcode == 0x18 && insn.src == 0 => {
+                let high = node.second.map_or(0, |s| u32::from_le_bytes(s[4..8].try_into().unwrap()));
+                regs[dst] = Some(((high as u64) << 32) | insn.imm as u64);
+                pc += 1;
+            }
+            0x04 | 0x07 => {
+                let reads_dst = insn.opcode & 0xf0 != 0xb0;
+                let reads_src = insn.opcode & 0x08 != 0 && !matches!(insn.opcode & 0xf0, 0x80 | 0xd0);
+                let d = if reads_dst { regs[dst]? } else { 0 };
+                let s = if reads_src { regs[src]? } else { 0 };
+                regs[dst] = Some(alu(insn, d, s));
+                pc += 1;
+            }
+            0x05 | 0x06 => match insn.opcode {
+                0x95 => return regs[0].map(Expected::Returns),
+                0x85 => return None,
+                // ja and gotol
+                _ if insn.opcode & 0xf0 == 0x00 => pc = node.target?,
+                _ => {
+                    let d = regs[dst]?;
+                    let s = if insn.opcode & 0x08 != 0 { regs[src]? } else { 0 };
+                    pc = if condition(insn, d, s) { node.target? } else { pc + 1 };
+                }
+            },
+            // Memory accesses, and lddw pseudo sources
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_program;
+
+    #[test]
+    fn alu_operations() {
+        let op = |opcode, offset, dst, src| alu(&Instruction::new(opcode, 0, 1, offset, 0), dst, src);
+        assert_eq!(op(0x0f, 0, u64::MAX, 2), 1);
+        // 32-bit results are zero-extended
+        assert_eq!(op(0x0c, 0, u64::MAX, 2), 1);
+        assert_eq!(op(0x1c, 0, 0, 1), 0xffff_ffff);
+        // Signed division with offset 1
+        assert_eq!(op(0x3f, 0, -7i64 as u64, 2), (-7i64 as u64) / 2);
+        assert_eq!(op(0x3f, 1, -7i64 as u64, 2), -3i64 as u64);
+        assert_eq!(op(0x9f, 1, -7i64 as u64, 2), -1i64 as u64);
+        // Shifts mask their amount
+        assert_eq!(op(0x6f, 0, 1, 65), 2);
+        assert_eq!(op(0xcf, 0, -8i64 as u64, 1), -4i64 as u64);
+        // movsx
+        assert_eq!(op(0xbf, 8, 0, 0x80), -128i64 as u64);
+        assert_eq!(alu(&Instruction::new(0xdc, 0, 0, 0, 16), 0x1234, 0), 0x3412);
+    }
+
+    #[test]
+    fn evaluates_known_programs() {
+        let bytes = encode_program(&[
+            Instruction::new(0xb7, 0, 0, 0, 6),
+            Instruction::new(0x27, 0, 0, 0, 7),
+            Instruction::new(0x15, 0, 0, 1, 42),
+            Instruction::new(0xb7, 0, 0, 0, 0),
+            Instruction::new(0x95, 0, 0, 0, 0),
+        ]);
+        assert_eq!(evaluate(&bytes), Some(Expected::Returns(42)));
+
+        // r1 is the caller's, and memory is not modelled
+        assert_eq!(evaluate(&encode_program(&[Instruction::new(0xbf, 0, 1, 0, 0), Instruction::new(0x95, 0, 0, 0, 0)])), None);
+        let load = encode_program(&[Instruction::new(0x61, 0, 10, -8i16 as u16, 0), Instruction::new(0x95, 0, 0, 0, 0)]);
+        assert_eq!(evaluate(&load), None);
+
+        let out_of_bounds = encode_program(&[Instruction::new(0x05, 0, 0, 1, 0), Instruction::new(0x95, 0, 0, 0, 0)]);
+        assert!(matches!(evaluate(&out_of_bounds), Some(Expected::Error(_))));
+    }
+}
@@ -0,0 +1,115 @@
+//! Kinds of findings. Crashes, hangs and divergences each need their own triage, so each is
+//! counted on its own and saved to its own directory next to where --output points.
+
+use crate::bundle;
+use crate::{fill_path, write_program, Args};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// rbpf panicked, or the target died of a signal or a sanitizer report
+    Crash,
+    /// rbpf or the target ran past its timeout
+    Hang,
+    /// rbpf disagrees with the reference interpreter or the predicted result
+    Divergence,
+    /// A verifier loads a program the crate's model says is malformed, or refuses one the
+    /// model finds nothing wrong with
+    Mismatch,
+}
+
+impl Class {
+    /// Directory findings of the class are saved to
+    fn dir(self) -> &'static str {
+        match self {
+            Class::Crash => "crashes",
+            Class::Hang => "hangs",
+            Class::Divergence => "divergences",
+            Class::Mismatch => "mismatches",
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Class::Crash => write!(f, "crash"),
+            Class::Hang => write!(f, "hang"),
+            Class::Divergence => write!(f, "divergence"),
+            Class::Mismatch => write!(f, "mismatch"),
+        }
+    }
+}
+
+impl std::str::FromStr for Class {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Class, String> {
+        [Class::Crash, Class::Hang, Class::Divergence, Class::Mismatch]
+            .into_iter()
+            .find(|class| class.to_string() == s)
+            .ok_or_else(|| format!("unknown finding class {:?}", s))
+    }
+}
+
+/// Findings so far of each class
+#[derive(Debug, Default)]
+pub struct Counts {
+    pub crashes: u32,
+    pub hangs: u32,
+    pub divergences: u32,
+    pub mismatches: u32,
+}
+
+impl Counts {
+    pub fn add(&mut self, class: Class) {
+        match class {
+            Class::Crash => self.crashes += 1,
+            Class::Hang => self.hangs += 1,
+            Class::Divergence => self.divergences += 1,
+            Class::Mismatch => self.mismatches += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.crashes + self.hangs + self.divergences + self.mismatches
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} crashes, {} hangs, {} divergences, {} mismatches", self.crashes, self.hangs, self.divergences, self.mismatches)
+    }
+}
+
+/// Path of finding `index` of a class, in a directory named after the class next to the
+/// path the output format string gives, or None for stdout. %s stands for the seed of the
+/// run that found it, or the index without one. With --bundle, each finding gets a directory
+/// of its own in there, named after the finding without its extension.
+pub fn path(args: &Args, class: Class, index: u64, seed: Option<u64>) -> Option<String> {
+    if args.output == "-" {
+        return None;
+    }
+    let output = Path::new(&args.output);
+    let Some(file) = output.file_name().map(|file| file.to_string_lossy()) else {
+        eprintln!("--output {} has no file name to save findings under", args.output);
+        std::process::exit(2);
+    };
+    let mut dir = output.parent().unwrap_or(Path::new("")).join(class.dir());
+    if args.bundle {
+        dir = dir.join(Path::new(&*file).file_stem().unwrap_or_default());
+    }
+    Some(fill_path(&dir.join(&*file).to_string_lossy(), index, seed.unwrap_or(index)))
+}
+
+/// Writes finding `index` to stdout or to its path, which it returns, along with its bundle
+/// with --bundle
+pub fn write(args: &Args, class: Class, index: u64, seed: Option<u64>, bytes: &[u8], result: Option<u64>) -> Option<String> {
+    let path = path(args, class, index, seed);
+    write_program(args, path.as_deref(), bytes, result);
+    if let Some(dir) = path.as_deref().filter(|_| args.bundle).and_then(|path| Path::new(path).parent()) {
+        bundle::write(args, dir, seed, bytes, result);
+    }
+    path
+}
@@ -0,0 +1,418 @@
+//! A long-running campaign: generates and mutates programs, checks each against the oracles,
+//! keeps those that reach new behaviour in a corpus and saves one finding per kind of bug
+
+#[cfg(unix)]
+use crate::afl::{self, Forkserver};
+#[cfg(feature = "tui")]
+use crate::dashboard::Dashboard;
+use crate::findings::{self, Class, Counts};
+use crate::interp;
+use crate::metrics::{self, Metrics};
+use crate::rejections::reason;
+#[cfg(feature = "sancov")]
+use crate::sancov;
+use crate::vm::{self, Outcome};
+use crate::{decode_input, format_program, generate_test, mutate, oracle, read_program, render_program, repro, Args, Endian, FuzzOptions, GenOptions, Instruction, Schedule};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Largest number of mutation operators stacked on one corpus entry
+const MAX_STACKED_OPS: usize = 4;
+
+/// How often progress is printed, in programs
+const STATUS_INTERVAL: u64 = 10_000;
+
+/// How often the --watch directory is polled for new programs
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Behaviour a program exercises. A program joins the corpus when it reaches one the
+/// corpus has not seen yet.
+#[derive(PartialEq, Eq, Hash)]
+pub enum Signal {
+    /// An opcode followed by another in the program
+    Pair(u8, u8),
+    /// How rbpf ended the program, with errors grouped by their message
+    Outcome(String),
+}
+
+/// AFL's hit-count buckets, so that loops running a few more times are not new coverage
+pub fn bucket(count: u8) -> u8 {
+    match count {
+        0..=2 => count,
+        3 => 4,
+        4..=7 => 8,
+        8..=15 => 16,
+        16..=31 => 32,
+        32..=127 => 64,
+        _ => 128,
+    }
+}
+
+pub fn signals(bytes: &[u8], outcome: &Outcome) -> HashSet<Signal> {
+    let opcodes: Vec<u8> = bytes.chunks_exact(8).map(|slot| Instruction::from_bytes(slot).opcode).collect();
+    let mut signals: HashSet<Signal> = opcodes.windows(2).map(|pair| Signal::Pair(pair[0], pair[1])).collect();
+    let outcome = match outcome {
+        Outcome::Returned(_) => "returned".to_string(),
+        Outcome::Error(err) => reason(err).unwrap_or_default(),
+        Outcome::Panicked(msg) => format!("panicked: {}", reason(msg).unwrap_or_default()),
+        Outcome::TimedOut => "timed out".to_string(),
+    };
+    signals.insert(Signal::Outcome(outcome));
+    signals
+}
+
+struct Entry {
+    bytes: Vec<u8>,
+    /// Number of mutants made from this entry so far
+    fuzzed: u32,
+}
+
+/// The corpus and the queue of entries waiting to be mutated
+struct Corpus {
+    dir: String,
+    entries: Vec<Entry>,
+    queue: VecDeque<usize>,
+    /// Entries not mutated yet
+    unfuzzed: usize,
+    seen: HashSet<Signal>,
+    /// Behaviour hashes of the entries, from `interp::trace`
+    behaviours: HashSet<u64>,
+}
+
+impl Corpus {
+    /// Loads the programs a previous campaign left in `dir`
+    fn load(dir: &str) -> Corpus {
+        fs::create_dir_all(dir).expect("Failed to create corpus directory");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .expect("Failed to read corpus directory")
+            .map(|entry| entry.expect("Failed to read corpus directory").path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "data"))
+            .collect();
+        paths.sort();
+
+        let mut corpus = Corpus { dir: dir.to_string(), entries: Vec::new(), queue: VecDeque::new(), unfuzzed: 0, seen: HashSet::new(), behaviours: HashSet::new() };
+        for path in paths {
+            let bytes = read_program(&path.to_string_lossy());
+            corpus.seen.extend(signals(&bytes, &vm::run(&bytes)));
+            corpus.behaviours.insert(interp::trace(&bytes).1);
+            corpus.queue.push_back(corpus.entries.len());
+            corpus.entries.push(Entry { bytes, fuzzed: 0 });
+            corpus.unfuzzed += 1;
+        }
+        corpus
+    }
+
+    /// Adds the program if it is forced in, or if it reaches a new signal and behaves unlike
+    /// every entry, saving it to the corpus directory. Programs that only differ in ways
+    /// that do not change their behaviour would keep the corpus large but not diverse.
+    fn add(&mut self, bytes: &[u8], outcome: &Outcome, behaviour: u64, force: bool) {
+        if self.behaviours.contains(&behaviour) && !force {
+            return;
+        }
+        let before = self.seen.len();
+        self.seen.extend(signals(bytes, outcome));
+        if self.seen.len() == before && !force {
+            return;
+        }
+
+        let path = Path::new(&self.dir).join(format!("{}.data", self.entries.len()));
+        fs::write(path, format_program(bytes, Endian::Le, None, None)).expect("Failed to write corpus entry");
+        self.behaviours.insert(behaviour);
+        self.queue.push_back(self.entries.len());
+        self.entries.push(Entry { bytes: bytes.to_vec(), fuzzed: 0 });
+        self.unfuzzed += 1;
+    }
+
+    /// Takes the next entry to mutate off the queue, and puts it back at the end
+    fn next<R: Rng>(&mut self, rng: &mut R, schedule: Schedule) -> usize {
+        let at = match schedule {
+            Schedule::Fifo => 0,
+            Schedule::LeastFuzzed => {
+                let least = self.queue.iter().map(|&i| self.entries[i].fuzzed).min().unwrap();
+                let ties: Vec<usize> = (0..self.queue.len()).filter(|&at| self.entries[self.queue[at]].fuzzed == least).collect();
+                *ties.choose(rng).unwrap()
+            }
+        };
+        let index = self.queue.remove(at).unwrap();
+        self.queue.push_back(index);
+        if self.entries[index].fuzzed == 0 {
+            self.unfuzzed -= 1;
+        }
+        self.entries[index].fuzzed += 1;
+        index
+    }
+}
+
+/// Programs other fuzzers drop into a directory
+struct Watcher {
+    dir: String,
+    seen: HashSet<PathBuf>,
+    last_poll: Option<Instant>,
+    imported: u32,
+    skipped: u32,
+}
+
+impl Watcher {
+    /// Reads the files that appeared since the last poll, if it is time for another. Hidden
+    /// files and directories, such as AFL++'s .state, are left alone, and files that are
+    /// neither conformance files nor whole instructions are skipped.
+    fn poll(&mut self) -> Vec<Vec<u8>> {
+        if self.last_poll.is_some_and(|last| last.elapsed() < WATCH_INTERVAL) {
+            return Vec::new();
+        }
+        self.last_poll = Some(Instant::now());
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .expect("Failed to read watched directory")
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')))
+            .filter(|path| !self.seen.contains(path))
+            .collect();
+        paths.sort();
+
+        let mut programs = Vec::new();
+        for path in paths {
+            // The file may have been removed since the directory was read
+            let Ok(data) = fs::read(&path) else { continue };
+            self.seen.insert(path);
+            match decode_input(data) {
+                Some(bytes) => {
+                    programs.push(bytes);
+                    self.imported += 1;
+                }
+                None => self.skipped += 1,
+            }
+        }
+        programs
+    }
+}
+
+/// A program that is the first of its group of findings
+pub struct Finding {
+    pub class: Class,
+    pub bytes: Vec<u8>,
+    pub result: Option<u64>,
+    pub problems: Vec<String>,
+}
+
+/// The state of a campaign: its corpus, the programs waiting to be imported and the groups
+/// of findings so far
+pub struct Campaign<'a> {
+    args: &'a Args,
+    fuzz: &'a FuzzOptions,
+    /// AFL-instrumented target whose coverage guides the campaign
+    #[cfg(unix)]
+    target: Option<Forkserver>,
+    /// Coverage of rbpf itself
+    #[cfg(feature = "sancov")]
+    sancov: sancov::Feedback,
+    corpus: Corpus,
+    watcher: Option<Watcher>,
+    imports: VecDeque<Vec<u8>>,
+    groups: BTreeMap<String, u32>,
+    findings: Counts,
+    programs: u64,
+    metrics: Option<Arc<Metrics>>,
+    /// Whether progress lines are printed, which they are not under the dashboard
+    status: bool,
+}
+
+impl<'a> Campaign<'a> {
+    pub fn new(args: &'a Args, fuzz: &'a FuzzOptions) -> Campaign<'a> {
+        let corpus = Corpus::load(&fuzz.corpus);
+        println!("loaded {} corpus entries", corpus.entries.len());
+        #[cfg(unix)]
+        let target = fuzz.afl_target.as_deref().map(|cmd| {
+            Forkserver::start(args, cmd, Duration::from_millis(fuzz.afl_timeout)).unwrap_or_else(|e| {
+                eprintln!("Failed to start {} under its forkserver: {}", cmd, e);
+                std::process::exit(1);
+            })
+        });
+        let watcher = fuzz.watch.as_ref().map(|dir| Watcher { dir: dir.clone(), seen: HashSet::new(), last_poll: None, imported: 0, skipped: 0 });
+        let metrics = fuzz.metrics.as_deref().map(|addr| {
+            let metrics = Metrics::new();
+            metrics::serve(addr, &metrics);
+            metrics
+        });
+        Campaign {
+            args,
+            fuzz,
+            #[cfg(unix)]
+            target,
+            #[cfg(feature = "sancov")]
+            sancov: sancov::Feedback::new(),
+            corpus,
+            watcher,
+            imports: VecDeque::new(),
+            groups: BTreeMap::new(),
+            findings: Counts::default(),
+            programs: 0,
+            metrics,
+            status: true,
+        }
+    }
+
+    /// The campaign's counters, kept from now on if --metrics did not already, for a
+    /// dashboard shown instead of progress lines
+    #[cfg(feature = "tui")]
+    pub fn metrics(&mut self) -> Arc<Metrics> {
+        self.status = false;
+        Arc::clone(self.metrics.get_or_insert_with(Metrics::new))
+    }
+
+    /// Queues a program to run next and add to the corpus as a seed
+    pub fn import(&mut self, bytes: Vec<u8>) {
+        self.imports.push_back(bytes);
+    }
+
+    /// The corpus entries, in the order they joined
+    pub fn corpus(&self) -> impl Iterator<Item = &[u8]> {
+        self.corpus.entries.iter().map(|entry| entry.bytes.as_slice())
+    }
+
+    /// Number of programs that had findings
+    pub fn findings(&self) -> &Counts {
+        &self.findings
+    }
+
+    /// Runs one program. It is the next imported program if there is one, a fresh program
+    /// with probability `fresh_rate` or while the corpus is empty, and otherwise a mutant of
+    /// the next corpus entry the schedule picks. Imported programs, and programs reaching new
+    /// coverage in the AFL target or, built with the `sancov` feature, in rbpf, join the
+    /// corpus whatever else they reach. Crashes and hangs
+    /// of the target are findings too. Findings are grouped by their problem with numbers left
+    /// out, and only the first of each group is returned.
+    pub fn step<R: Rng>(&mut self, rng: &mut R, opts: &GenOptions) -> Option<Finding> {
+        if let Some(watcher) = &mut self.watcher {
+            self.imports.extend(watcher.poll());
+        }
+        let seed = self.imports.pop_front();
+        let imported = seed.is_some();
+        let (bytes, result) = if let Some(bytes) = seed {
+            (bytes, None)
+        } else if self.corpus.queue.is_empty() || rng.random_bool(self.fuzz.fresh_rate) {
+            let size = opts.random_size(rng);
+            generate_test(rng, size, opts)
+        } else {
+            let index = self.corpus.next(rng, self.fuzz.schedule);
+            let mut bytes = self.corpus.entries[index].bytes.clone();
+            for _ in 0..rng.random_range(1..=MAX_STACKED_OPS) {
+                let op = *self.fuzz.ops.choose(rng).unwrap();
+                mutate::apply(rng, op, &mut bytes, opts);
+            }
+            (bytes, None)
+        };
+
+        #[cfg(feature = "sancov")]
+        self.sancov.reset();
+        let outcome = vm::run(&bytes);
+        #[cfg(feature = "sancov")]
+        let mut covered = self.sancov.new_coverage();
+        #[cfg(not(feature = "sancov"))]
+        let mut covered = false;
+        let (reference, behaviour) = interp::trace(&bytes);
+        let mut problems = oracle::problems(&bytes, result, &outcome, &reference);
+        let mut class = oracle::class(&outcome);
+        #[cfg(unix)]
+        if let Some(target) = &mut self.target {
+            match target.run(&render_program(self.args, &bytes, result)) {
+                afl::Status::Signaled(signal) => {
+                    problems.push(format!("target killed by signal {}", signal));
+                    class = Class::Crash;
+                }
+                afl::Status::TimedOut => {
+                    problems.push("target timed out".to_string());
+                    if class != Class::Crash {
+                        class = Class::Hang;
+                    }
+                }
+                afl::Status::Exited(_) => {}
+            }
+            covered |= target.new_coverage();
+        }
+        self.corpus.add(&bytes, &outcome, behaviour, imported || covered);
+        self.programs += 1;
+        if self.status && self.programs.is_multiple_of(STATUS_INTERVAL) {
+            println!("{} programs, {} corpus entries, {} in {} groups", self.programs, self.corpus.entries.len(), self.findings, self.groups.len());
+        }
+
+        let finding = (!problems.is_empty()).then(|| {
+            self.findings.add(class);
+            let group = problems.iter().map(|p| reason(p).unwrap_or_default()).collect::<Vec<_>>().join("; ");
+            let count = self.groups.entry(group).or_default();
+            *count += 1;
+            *count == 1
+        });
+        self.update_metrics(finding.is_some().then_some(class));
+        finding.filter(|&first| first).map(|_| Finding { class, bytes, result, problems })
+    }
+
+    /// Records a program's run and the class of what it found
+    fn update_metrics(&self, found: Option<Class>) {
+        let Some(metrics) = &self.metrics else { return };
+        metrics.execs.fetch_add(1, Ordering::Relaxed);
+        metrics.queue_depth.store((self.imports.len() + self.corpus.unfuzzed) as u64, Ordering::Relaxed);
+        metrics.corpus_entries.store(self.corpus.entries.len() as u64, Ordering::Relaxed);
+        metrics.signals.store(self.corpus.seen.len() as u64, Ordering::Relaxed);
+        metrics.finding_groups.store(self.groups.len() as u64, Ordering::Relaxed);
+        let counter = match found {
+            Some(Class::Crash) => &metrics.crashes,
+            Some(Class::Hang) => &metrics.hangs,
+            Some(Class::Divergence) => &metrics.divergences,
+            Some(Class::Mismatch) | None => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Prints the corpus size, the imports and the groups of findings
+    pub fn print(&self) {
+        println!("{} corpus entries in {}", self.corpus.entries.len(), self.fuzz.corpus);
+        if let Some(watcher) = &self.watcher {
+            println!("{} programs imported from {}, {} files skipped", watcher.imported, watcher.dir, watcher.skipped);
+        }
+        println!("{} in {} groups across {} programs:", self.findings, self.groups.len(), self.programs);
+        for (group, count) in &self.groups {
+            println!("{:>8}  {}", count, group);
+        }
+    }
+}
+
+/// Runs a campaign of --iterations programs, or until killed, saving the first finding of
+/// each group to the directory of its class
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, fuzz: &FuzzOptions) {
+    let mut campaign = Campaign::new(args, fuzz);
+    #[cfg(feature = "tui")]
+    let mut dashboard = args.tui.then(|| Dashboard::start(campaign.metrics()));
+    for i in 0..fuzz.iterations.unwrap_or(u64::MAX) {
+        #[cfg(feature = "tui")]
+        if dashboard.as_mut().is_some_and(|dashboard| !dashboard.tick()) {
+            break;
+        }
+        let Some(finding) = campaign.step(rng, opts) else { continue };
+        let summary = format!("program {}: {}: {}", i, finding.class, finding.problems.join("; "));
+        #[cfg(feature = "tui")]
+        match &mut dashboard {
+            Some(dashboard) => dashboard.finding(summary),
+            None => println!("{}", summary),
+        }
+        #[cfg(not(feature = "tui"))]
+        println!("{}", summary);
+        if let Some(path) = findings::write(args, finding.class, i, Some(opts.seed), &finding.bytes, finding.result) {
+            repro::write(&path, i, opts.seed, &format!("cat {}", repro::quote(&path)));
+        }
+    }
+
+    // The terminal is back to normal before the summary goes to it
+    #[cfg(feature = "tui")]
+    drop(dashboard);
+    campaign.print();
+    if campaign.findings().total() > 0 {
+        std::process::exit(1);
+    }
+}
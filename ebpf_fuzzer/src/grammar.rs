@@ -0,0 +1,129 @@
+//! The enabled templates as a context-free grammar of programs in the `-- raw` section of
+//! the conformance format, for grammar-based fuzzers to generate from
+
+use crate::{reserved_fields, writes_dst, writes_src, GenOptions, GrammarFormat, ReservedFields};
+
+/// A piece of the right-hand side of a rule
+enum Part {
+    Text(String),
+    Rule(&'static str),
+}
+
+use Part::{Rule, Text};
+
+/// Alternatives of each rule, the start rule first
+type Grammar = Vec<(&'static str, Vec<Vec<Part>>)>;
+
+fn hex(value: u64, digits: usize) -> Part {
+    Text(format!("{:0width$x}", value, width = digits))
+}
+
+/// Digits of a field a template fixes, zeros for a reserved one with --reserved-fields zero,
+/// or else the rule that picks them
+fn field(fixed: Option<u32>, reserved: bool, digits: usize, rule: &'static str, opts: &GenOptions) -> Part {
+    match fixed {
+        Some(value) => hex(value as u64, digits),
+        None if reserved && opts.reserved_fields == ReservedFields::Zero => hex(0, digits),
+        None => Rule(rule),
+    }
+}
+
+/// The rules: a program is instructions of the enabled templates followed by an exit, each
+/// on a line as the 64-bit word `0x<imm><offset><src><dst><opcode>`, with LD_DW_IMM taking
+/// two. Written registers come from the writable ones and helper calls target known helpers.
+fn rules(opts: &GenOptions) -> Grammar {
+    let mut insns: Vec<Vec<Part>> = Vec::new();
+    let mut seen = Vec::new();
+    for template in &opts.templates {
+        let opcode = template.opcode;
+        let reserved = reserved_fields(opcode);
+        let imm = match template.imm {
+            None if opcode == 0x85 && template.src == Some(0) => Rule("helper"),
+            imm => field(imm, reserved.imm, 8, "imm", opts),
+        };
+        // Fetching atomics write the src register, which a fixed imm tells
+        let src_rule = if template.imm.is_some_and(|imm| writes_src(opcode, imm)) { "writable" } else { "reg" };
+        let dst_rule = if writes_dst(opcode) { "writable" } else { "reg" };
+        let mut alternative = vec![
+            Text("0x".to_string()),
+            imm,
+            field(template.offset.map(u32::from), reserved.offset, 4, "offset", opts),
+            field(template.src.map(u32::from), reserved.src, 1, src_rule, opts),
+            field(None, reserved.dst, 1, dst_rule, opts),
+            Text(format!("{:02x}\n", opcode)),
+        ];
+        if opcode == 0x18 {
+            alternative.extend([Text("0x".to_string()), Rule("imm"), Text("00000000\n".to_string())]);
+        }
+
+        // Templates differing only in fields --reserved-fields zero clears give the same rule
+        let key = render(&alternative, |rule| rule.to_string(), |text| text.to_string());
+        if !seen.contains(&key) {
+            seen.push(key);
+            insns.push(alternative);
+        }
+    }
+
+    let digits = |count: usize| vec![(0..count).map(|_| Rule("hex")).collect()];
+    let each = |values: &[u8]| values.iter().map(|&v| vec![hex(v as u64, 1)]).collect();
+    vec![
+        ("program", vec![vec![Text("-- raw\n".to_string()), Rule("body"), Rule("exit")]]),
+        ("body", vec![vec![Rule("insn"), Rule("body")], vec![]]),
+        ("exit", vec![vec![Text("0x0000000000000095\n".to_string())]]),
+        ("insn", insns),
+        ("imm", digits(8)),
+        ("offset", digits(4)),
+        ("reg", each(&opts.regs)),
+        ("writable", each(&opts.writable_regs)),
+        ("helper", opts.helpers.iter().map(|&id| vec![hex(id as u64, 8)]).collect()),
+        ("hex", (0..16).map(|digit| vec![hex(digit, 1)]).collect()),
+    ]
+}
+
+/// Concatenates an alternative, rendering rules and text with the format's syntax
+fn render(parts: &[Part], rule: impl Fn(&str) -> String, text: impl Fn(&str) -> String) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Rule(name) => rule(name),
+            Text(s) => text(s),
+        })
+        .collect()
+}
+
+/// A Nautilus JSON grammar: a list of [NONTERMINAL, "text with {NONTERMINAL}s"] pairs, one
+/// per alternative, starting from START
+fn nautilus(grammar: &Grammar) -> String {
+    let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+    let mut pairs = vec![r#"["START", "{PROGRAM}"]"#.to_string()];
+    for (name, alternatives) in grammar {
+        for alternative in alternatives {
+            let rhs = render(alternative, |rule| format!("{{{}}}", rule.to_uppercase()), |text| quote(text));
+            pairs.push(format!("[\"{}\", \"{}\"]", name.to_uppercase(), rhs));
+        }
+    }
+    format!("[\n  {}\n]\n", pairs.join(",\n  "))
+}
+
+/// An ANTLR v4 grammar, as Grammarinator takes, starting from program
+fn antlr(grammar: &Grammar) -> String {
+    let quote = |s: &str| format!(" '{}'", s.replace('\\', "\\\\").replace('\'', "\\'").replace('\n', "\\n"));
+    let mut g4 = String::from("grammar Ebpf;\n");
+    for (name, alternatives) in grammar {
+        let alternatives: Vec<String> = alternatives.iter().map(|a| render(a, |rule| format!(" {}", rule), quote)).collect();
+        g4.push_str(&format!("\n{}\n    :{}\n    ;\n", name, alternatives.join("\n    |")));
+    }
+    g4
+}
+
+/// Prints the grammar of programs over the enabled templates in `format`
+pub fn run(opts: &GenOptions, format: GrammarFormat) {
+    let grammar = rules(opts);
+    print!(
+        "{}",
+        match format {
+            GrammarFormat::Nautilus => nautilus(&grammar),
+            GrammarFormat::Antlr => antlr(&grammar),
+        }
+    );
+}
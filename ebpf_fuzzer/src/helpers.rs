@@ -0,0 +1,72 @@
+//! Helper functions generated calls may target
+
+use crate::{ProgType, Runtime};
+use rand::Rng;
+use std::ops::RangeInclusive;
+
+/// Kernel helper IDs, from bpf_map_lookup_elem (1) up to bpf_cgrp_storage_delete (211)
+pub const HELPER_IDS: RangeInclusive<u32> = 1..=211;
+
+/// Helpers every program type may call: map operations, time, random numbers,
+/// tail calls, queue/stack maps and spin locks
+const BASE: &[u32] = &[1, 2, 3, 5, 6, 7, 8, 12, 42, 87, 88, 89, 93, 94];
+
+const SOCKET_FILTER: &[u32] = &[26, 46, 47, 68];
+
+const TRACING: &[u32] = &[4, 14, 15, 16, 22, 25, 27, 35, 36, 37, 45, 55, 67];
+
+const SCHED_CLS: &[u32] = &[
+    9, 10, 11, 13, 17, 18, 19, 20, 21, 23, 26, 28, 29, 30, 31, 32, 33, 34, 38, 39, 40, 41, 43, 48, 50, 66, 68, 69,
+];
+
+const XDP: &[u32] = &[23, 25, 28, 44, 51, 54, 65, 69];
+
+/// Helper IDs the kernel lets a program type call. This is a conservative subset
+/// of what current kernels allow, so calls outside it are rejected everywhere.
+pub fn allowed(prog_type: ProgType) -> Vec<u32> {
+    let mut ids = BASE.to_vec();
+    match prog_type {
+        ProgType::SocketFilter => ids.extend_from_slice(SOCKET_FILTER),
+        // Only kprobes may override the probed function's return value
+        ProgType::Kprobe => ids.extend_from_slice(&[TRACING, &[58]].concat()),
+        ProgType::Tracepoint => ids.extend_from_slice(TRACING),
+        ProgType::SchedCls => ids.extend_from_slice(SCHED_CLS),
+        ProgType::Xdp => ids.extend_from_slice(XDP),
+    }
+    ids.sort_unstable();
+    ids
+}
+
+/// General helpers of eBPF for Windows, from bpf_map_lookup_elem (1) up to bpf_get_current_logon_id (26).
+/// Its numbering only matches Linux for the map helpers and tail calls.
+const WINDOWS: RangeInclusive<u32> = 1..=26;
+
+/// Helper IDs a runtime provides. rbpf and uBPF call whatever the embedder registers,
+/// which the internal VM does for every kernel ID.
+pub fn provided(runtime: Runtime) -> Vec<u32> {
+    match runtime {
+        Runtime::Windows => WINDOWS.collect(),
+        Runtime::Linux6_8 | Runtime::Rbpf | Runtime::Ubpf => HELPER_IDS.collect(),
+    }
+}
+
+/// A helper ID no runtime defines: negative, huge, or just past the last of `helpers` or of
+/// the kernel's helpers, where off-by-one bounds checks of dispatch tables fail
+pub fn hostile<R: Rng>(rng: &mut R, helpers: &[u32]) -> u32 {
+    let last = helpers.iter().copied().max().unwrap_or(0);
+    match rng.random_range(0..7) {
+        0 => u32::MAX,
+        1 => i32::MIN as u32,
+        2 => rng.random_range(i32::MIN..0) as u32,
+        3 => i32::MAX as u32,
+        4 => rng.random_range(0x10000..=i32::MAX as u32),
+        5 => last + rng.random_range(1..=2u32),
+        _ => HELPER_IDS.end() + rng.random_range(1..=2u32),
+    }
+}
+
+/// Stand-in for every helper when running programs internally. It ignores its
+/// arguments and returns 0, so results stay reproducible across implementations.
+pub fn stub(_r1: u64, _r2: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    0
+}
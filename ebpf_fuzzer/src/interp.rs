@@ -0,0 +1,435 @@
+//! A straightforward eBPF interpreter written from the spec, used as a reference to check
+//! other implementations against. It shares none of rbpf's code, so bugs the two have in
+//! common are unlikely.
+//!
+//! Values the spec leaves open are tracked alongside register and stack contents: addresses,
+//! registers the caller sets up or that helpers clobber, and stack bytes never written.
+//! Outcomes that depend on them are reported as unspecified rather than compared.
+
+use crate::eval::{self, MAX_STEPS};
+use crate::{cfg, helpers, vm};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Steps `steps` logs before leaving out the rest
+const MAX_LOGGED_STEPS: usize = 10_000;
+
+/// Stack of each call frame
+const STACK_SIZE: usize = 512;
+/// Call frames, including the program's own
+const MAX_CALL_DEPTH: usize = 8;
+/// Where the stack, the memory area and the metadata buffer of mbuff mode live in the
+/// interpreter's address space
+const STACK_BASE: u64 = 0x1_0000_0000;
+const MEM_BASE: u64 = 0x2_0000_0000;
+const MBUFF_BASE: u64 = 0x3_0000_0000;
+
+/// Outcome of a reference run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The program exits with r0
+    Returned(u64),
+    /// The program is invalid, or runs into an error
+    Error(String),
+    /// The outcome depends on values the spec leaves open, or the program does not
+    /// terminate within `MAX_STEPS`
+    Unspecified,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Returned(r0) => write!(f, "returned {:#x}", r0),
+            Outcome::Error(err) => write!(f, "error: {}", err),
+            Outcome::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+/// Registers saved across a local call
+struct Frame {
+    return_to: usize,
+    saved: [(u64, bool); 4],
+}
+
+/// A region of memory programs may access
+struct Area {
+    base: u64,
+    bytes: Vec<u8>,
+    /// Bytes whose value the spec leaves open
+    open: Vec<bool>,
+}
+
+struct Machine {
+    regs: [u64; 11],
+    /// Registers whose value the spec leaves open
+    open: [bool; 11],
+    /// The stack, the memory area and in mbuff mode the metadata buffer
+    areas: Vec<Area>,
+    frames: Vec<Frame>,
+}
+
+impl Machine {
+    /// Index of the area an access of `size` bytes at `addr` falls in, and of its first byte there
+    fn locate(&self, addr: u64, size: usize, pc: usize) -> Result<(usize, usize), String> {
+        for (i, area) in self.areas.iter().enumerate() {
+            let offset = addr.wrapping_sub(area.base) as usize;
+            if addr >= area.base && offset.checked_add(size).is_some_and(|end| end <= area.bytes.len()) {
+                return Ok((i, offset));
+            }
+        }
+        Err(format!("out of bounds access of {} bytes at {:#x} at instruction {}", size, addr, pc))
+    }
+
+    fn load(&self, addr: u64, size: usize, pc: usize) -> Result<(u64, bool), String> {
+        let (i, at) = self.locate(addr, size, pc)?;
+        let area = &self.areas[i];
+        let mut word = [0u8; 8];
+        word[..size].copy_from_slice(&area.bytes[at..at + size]);
+        Ok((u64::from_le_bytes(word), area.open[at..at + size].iter().any(|&open| open)))
+    }
+
+    fn store(&mut self, addr: u64, size: usize, (value, open): (u64, bool), pc: usize) -> Result<(), String> {
+        let (i, at) = self.locate(addr, size, pc)?;
+        let area = &mut self.areas[i];
+        area.bytes[at..at + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        area.open[at..at + size].fill(open);
+        Ok(())
+    }
+
+    fn reg(&self, r: u8) -> (u64, bool) {
+        (self.regs[r as usize], self.open[r as usize])
+    }
+
+    fn set(&mut self, r: u8, (value, open): (u64, bool)) {
+        self.regs[r as usize] = value;
+        self.open[r as usize] = open;
+    }
+}
+
+/// Bytes accessed by a load or store
+fn access_size(opcode: u8) -> usize {
+    match opcode & 0x18 {
+        0x00 => 4,
+        0x08 => 2,
+        0x10 => 1,
+        _ => 8,
+    }
+}
+
+fn sign_extend(value: u64, size: usize) -> u64 {
+    match size {
+        1 => value as i8 as u64,
+        2 => value as i16 as u64,
+        4 => value as i32 as u64,
+        _ => value,
+    }
+}
+
+/// Applies an atomic operation to memory, following the spec for 32 and 64-bit widths
+fn atomic(m: &mut Machine, insn: &crate::Instruction, pc: usize) -> Result<(), String> {
+    let size = access_size(insn.opcode);
+    let mask = if size == 4 { u32::MAX as u64 } else { u64::MAX };
+    let addr = m.regs[insn.dst as usize].wrapping_add(insn.offset as i16 as u64);
+    let (old, old_open) = m.load(addr, size, pc)?;
+    let (src, src_open) = m.reg(insn.src);
+    let open = old_open || src_open;
+    match insn.imm {
+        0xe1 => {
+            m.store(addr, size, (src, src_open), pc)?;
+            m.set(insn.src, (old, old_open));
+        }
+        0xf1 => {
+            let (r0, r0_open) = m.reg(0);
+            let new = if r0 & mask == old { src } else { old };
+            m.store(addr, size, (new, open || r0_open), pc)?;
+            m.set(0, (old, old_open));
+        }
+        imm => {
+            let new = match imm & !0x01 {
+                0x00 => old.wrapping_add(src),
+                0x40 => old | src,
+                0x50 => old & src,
+                _ => old ^ src,
+            };
+            m.store(addr, size, (new & mask, open), pc)?;
+            if imm & 0x01 != 0 {
+                m.set(insn.src, (old, old_open));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a program with an empty memory area, as `vm::run` does, calling `helpers::stub`
+/// for every helper the VM registers
+pub fn run(bytes: &[u8]) -> Outcome {
+    trace(bytes).0
+}
+
+/// Runs a program as `run` does, and also returns a hash of its behaviour: the way each
+/// conditional branch went, the registers at exit and the outcome
+pub fn trace(bytes: &[u8]) -> (Outcome, u64) {
+    let mut hasher = DefaultHasher::new();
+    let outcome = execute(bytes, &mut hasher, None);
+    outcome.to_string().hash(&mut hasher);
+    (outcome, hasher.finish())
+}
+
+/// Runs a program as `run` does, and also returns a line for each of the first
+/// `MAX_LOGGED_STEPS` instructions it executes, with its slot and the registers before it ran
+pub fn steps(bytes: &[u8]) -> (Outcome, Vec<String>) {
+    let mut steps = Vec::new();
+    let outcome = execute(bytes, &mut DefaultHasher::new(), Some(&mut steps));
+    (outcome, steps)
+}
+
+fn execute(bytes: &[u8], trace: &mut impl Hasher, mut steps: Option<&mut Vec<String>>) -> Outcome {
+    let nodes = cfg::decode(bytes);
+    // Slot of every node, as the steps show them
+    let slots: Vec<usize> = nodes.iter().scan(0, |slot, node| {
+        let start = *slot;
+        *slot += if node.second.is_some() { 2 } else { 1 };
+        Some(start)
+    }).collect();
+    if let Err(err) = eval::check(bytes, &nodes) {
+        return Outcome::Error(err);
+    }
+
+    let stack = STACK_SIZE * MAX_CALL_DEPTH;
+    let mut m = Machine {
+        regs: [0; 11],
+        open: [true; 11],
+        areas: vec![
+            Area { base: STACK_BASE, bytes: vec![0; stack], open: vec![true; stack] },
+            Area { base: MEM_BASE, bytes: vm::memory().to_vec(), open: vec![false; vm::memory().len()] },
+        ],
+        frames: Vec::new(),
+    };
+    m.regs[1] = MEM_BASE;
+    if vm::mbuff() {
+        // Where the VM puts the memory area is up to it, so the pointers to it are open
+        let mut open = [false; vm::MBUFF_SIZE];
+        open[vm::MBUFF_DATA..vm::MBUFF_DATA + 8].fill(true);
+        open[vm::MBUFF_DATA_END..vm::MBUFF_DATA_END + 8].fill(true);
+        m.areas.push(Area { base: MBUFF_BASE, bytes: vm::metadata(MEM_BASE).to_vec(), open: open.to_vec() });
+        m.regs[1] = MBUFF_BASE;
+    }
+    m.regs[10] = STACK_BASE + stack as u64;
+
+    // Once a branch goes a way the spec leaves open, so does everything after it
+    let mut determined = true;
+    let outcome = |result: Result<u64, String>, determined: bool| match result {
+        _ if !determined => Outcome::Unspecified,
+        Ok(r0) => Outcome::Returned(r0),
+        Err(err) => Outcome::Error(err),
+    };
+
+    let mut pc = 0;
+    for _ in 0..MAX_STEPS {
+        let Some(node) = nodes.get(pc) else {
+            return outcome(Err("execution falls off the end of the program".to_string()), determined);
+        };
+        if let Some(steps) = steps.as_mut().filter(|steps| steps.len() < MAX_LOGGED_STEPS) {
+            let regs: Vec<String> = m.regs.iter().enumerate().map(|(r, value)| format!("r{}={:#x}", r, value)).collect();
+            steps.push(format!("{:>4}  {}", slots[pc], regs.join(" ")));
+        }
+        let insn = &node.insn;
+        let offset = insn.offset as i16 as u64;
+        let step = match insn.opcode & 0x07 {
+            0x00 if insn.opcode == 0x18 && insn.src == 0 => {
+                let high = node.second.map_or(0, |s| u32::from_le_bytes(s[4..8].try_into().unwrap()));
+                m.set(insn.dst, (((high as u64) << 32) | insn.imm as u64, false));
+                Ok(pc + 1)
+            }
+            0x00 if insn.opcode == 0x18 => Err(format!("lddw source {} needs maps at instruction {}", insn.src, pc)),
+            0x00 => Err(format!("legacy packet access without a packet at instruction {}", pc)),
+            0x01 => {
+                let size = access_size(insn.opcode);
+                let addr = m.regs[insn.src as usize].wrapping_add(offset);
+                m.load(addr, size, pc).map(|(value, open)| {
+                    let value = if insn.opcode & 0xe0 == 0x80 { sign_extend(value, size) } else { value };
+                    m.set(insn.dst, (value, open));
+                    pc + 1
+                })
+            }
+            0x02 => {
+                let addr = m.regs[insn.dst as usize].wrapping_add(offset);
+                m.store(addr, access_size(insn.opcode), (insn.imm as i32 as u64, false), pc).map(|_| pc + 1)
+            }
+            0x03 if insn.opcode & 0xe0 == 0xc0 => atomic(&mut m, insn, pc).map(|_| pc + 1),
+            0x03 => {
+                let addr = m.regs[insn.dst as usize].wrapping_add(offset);
+                let value = m.reg(insn.src);
+                m.store(addr, access_size(insn.opcode), value, pc).map(|_| pc + 1)
+            }
+            0x04 | 0x07 => {
+                let reads_dst = insn.opcode & 0xf0 != 0xb0;
+                let reads_src = insn.opcode & 0x08 != 0 && !matches!(insn.opcode & 0xf0, 0x80 | 0xd0);
+                let (d, d_open) = m.reg(insn.dst);
+                let (s, s_open) = if reads_src { m.reg(insn.src) } else { (0, false) };
+                let value = eval::alu(insn, d, s);
+                m.set(insn.dst, (value, (reads_dst && d_open) || (reads_src && s_open)));
+                Ok(pc + 1)
+            }
+            _ => match insn.opcode {
+                0x95 => match m.frames.pop() {
+                    None if m.open[0] => return Outcome::Unspecified,
+                    None => {
+                        m.regs[..10].hash(trace);
+                        return outcome(Ok(m.regs[0]), determined);
+                    }
+                    Some(frame) => {
+                        for (r, saved) in (6..10).zip(frame.saved) {
+                            m.set(r, saved);
+                        }
+                        m.regs[10] += STACK_SIZE as u64;
+                        Ok(frame.return_to)
+                    }
+                },
+                0x85 if insn.src == 0 && vm::helpers().binary_search(&insn.imm).is_ok() => {
+                    let r = m.regs;
+                    m.set(0, (helpers::stub(r[1], r[2], r[3], r[4], r[5]), false));
+                    // Helpers may clobber their arguments
+                    m.open[1..6].fill(true);
+                    Ok(pc + 1)
+                }
+                0x85 if insn.src == 1 => {
+                    if m.frames.len() + 1 == MAX_CALL_DEPTH {
+                        Err(format!("call depth exceeds {} at instruction {}", MAX_CALL_DEPTH, pc))
+                    } else {
+                        let saved = [6, 7, 8, 9].map(|r| m.reg(r));
+                        m.frames.push(Frame { return_to: pc + 1, saved });
+                        m.regs[10] -= STACK_SIZE as u64;
+                        Ok(node.target.expect("checked calls have a target"))
+                    }
+                }
+                0x85 => Err(format!("unknown function {} at instruction {}", insn.imm as i32, pc)),
+                0x8d => Err(format!("callx to an unknown address at instruction {}", pc)),
+                // ja and gotol
+                _ if insn.opcode & 0xf0 == 0x00 => Ok(node.target.expect("checked jumps have a target")),
+                _ => {
+                    let (d, d_open) = m.reg(insn.dst);
+                    let (s, s_open) = if insn.opcode & 0x08 != 0 { m.reg(insn.src) } else { (0, false) };
+                    determined &= !d_open && !s_open;
+                    let taken = eval::condition(insn, d, s);
+                    (pc, taken).hash(trace);
+                    if taken {
+                        Ok(node.target.expect("checked jumps have a target"))
+                    } else {
+                        Ok(pc + 1)
+                    }
+                }
+            },
+        };
+        match step {
+            Ok(next) => pc = next,
+            Err(err) => return outcome(Err(err), determined),
+        }
+    }
+    Outcome::Unspecified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encode_program, Instruction};
+
+    fn exit() -> Instruction {
+        Instruction::new(0x95, 0, 0, 0, 0)
+    }
+
+    #[test]
+    fn returns_r0() {
+        assert_eq!(run(&encode_program(&[Instruction::new(0xb7, 0, 0, 0, 5), exit()])), Outcome::Returned(5));
+        // mov sign-extends its immediate, the 32-bit mov zero-extends
+        assert_eq!(run(&encode_program(&[Instruction::new(0xb7, 0, 0, 0, u32::MAX), exit()])), Outcome::Returned(u64::MAX));
+        assert_eq!(run(&encode_program(&[Instruction::new(0xb4, 0, 0, 0, u32::MAX), exit()])), Outcome::Returned(0xffff_ffff));
+        let lddw = encode_program(&[Instruction::new(0x18, 0, 0, 0, 2), Instruction::new(0x00, 0, 0, 0, 1), exit()]);
+        assert_eq!(run(&lddw), Outcome::Returned(0x1_0000_0002));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let divide = |opcode| {
+            run(&encode_program(&[
+                Instruction::new(0xb7, 0, 0, 0, 7),
+                Instruction::new(0xb7, 2, 0, 0, 0),
+                Instruction::new(opcode, 0, 2, 0, 0),
+                exit(),
+            ]))
+        };
+        assert_eq!(divide(0x3f), Outcome::Returned(0));
+        assert_eq!(divide(0x9f), Outcome::Returned(7));
+    }
+
+    #[test]
+    fn loops_and_branches() {
+        // r0 += 3 ten times
+        let bytes = encode_program(&[
+            Instruction::new(0xb7, 0, 0, 0, 0),
+            Instruction::new(0xb7, 2, 0, 0, 10),
+            Instruction::new(0x07, 0, 0, 0, 3),
+            Instruction::new(0x17, 2, 0, 0, 1),
+            Instruction::new(0x55, 2, 0, -3i16 as u16, 0),
+            exit(),
+        ]);
+        assert_eq!(run(&bytes), Outcome::Returned(30));
+
+        // Signed and unsigned comparisons of -1 with 1 go opposite ways
+        let compare = |opcode| {
+            run(&encode_program(&[
+                Instruction::new(0xb7, 0, 0, 0, 1),
+                Instruction::new(0xb7, 2, 0, 0, u32::MAX),
+                Instruction::new(opcode, 2, 0, 1, 1),
+                Instruction::new(0xb7, 0, 0, 0, 2),
+                exit(),
+            ]))
+        };
+        assert_eq!(compare(0x25), Outcome::Returned(1));
+        assert_eq!(compare(0x65), Outcome::Returned(2));
+    }
+
+    #[test]
+    fn stack_round_trip() {
+        let bytes = encode_program(&[
+            Instruction::new(0x62, 10, 0, -8i16 as u16, 42),
+            Instruction::new(0x61, 0, 10, -8i16 as u16, 0),
+            exit(),
+        ]);
+        assert_eq!(run(&bytes), Outcome::Returned(42));
+    }
+
+    #[test]
+    fn local_calls_keep_callee_saved_registers() {
+        let bytes = encode_program(&[
+            Instruction::new(0xb7, 6, 0, 0, 4),
+            Instruction::new(0x85, 0, 1, 0, 2),
+            Instruction::new(0x0f, 0, 6, 0, 0),
+            exit(),
+            // The callee returns 10 in r0 and clobbers r6 of its own frame
+            Instruction::new(0xb7, 6, 0, 0, 100),
+            Instruction::new(0xb7, 0, 0, 0, 10),
+            exit(),
+        ]);
+        assert_eq!(run(&bytes), Outcome::Returned(14));
+    }
+
+    #[test]
+    fn errors() {
+        let falls_off = run(&encode_program(&[Instruction::new(0xb7, 0, 0, 0, 1)]));
+        assert_eq!(falls_off, Outcome::Error("execution falls off the end of the program".to_string()));
+        assert!(matches!(run(&encode_program(&[Instruction::new(0x05, 0, 0, 5, 0), exit()])), Outcome::Error(_)));
+        assert!(matches!(run(&encode_program(&[Instruction::new(0xb7, 10, 0, 0, 0), exit()])), Outcome::Error(_)));
+    }
+
+    #[test]
+    fn unspecified() {
+        // Addresses, registers the caller sets up and programs that never stop are left open
+        assert_eq!(run(&encode_program(&[Instruction::new(0xbf, 0, 10, 0, 0), exit()])), Outcome::Unspecified);
+        assert_eq!(run(&encode_program(&[Instruction::new(0xbf, 0, 3, 0, 0), exit()])), Outcome::Unspecified);
+        assert_eq!(run(&encode_program(&[Instruction::new(0x61, 0, 10, -8i16 as u16, 0), exit()])), Outcome::Unspecified);
+        assert_eq!(run(&encode_program(&[Instruction::new(0x05, 0, 0, -1i16 as u16, 0)])), Outcome::Unspecified);
+    }
+}
@@ -0,0 +1,308 @@
+//! Instruction templates, loaded from the embedded isa.toml or a spec file given with --isa-spec,
+//! and regenerated from bpf_conformance's opcode_names.h
+
+use crate::{Runtime, Template, Version};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::OnceLock;
+
+const DEFAULT_SPEC: &str = include_str!("isa.toml");
+
+static TEMPLATES: OnceLock<Vec<Template>> = OnceLock::new();
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    instructions: Vec<Entry>,
+}
+
+/// One valid encoding; fields left out of the spec are free
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    opcode: u8,
+    version: u8,
+    src: Option<u8>,
+    imm: Option<u32>,
+    offset: Option<u16>,
+}
+
+fn parse(text: &str, origin: &str) -> Vec<Template> {
+    let spec: Spec = toml::from_str(text).unwrap_or_else(|err| panic!("Invalid ISA spec {}: {}", origin, err));
+    assert!(!spec.instructions.is_empty(), "ISA spec {} lists no instructions", origin);
+    spec.instructions
+        .into_iter()
+        .map(|e| {
+            let version = Version::from_value(e.version)
+                .unwrap_or_else(|| panic!("Unsupported CPU version {} for opcode {:#04x} in {}", e.version, e.opcode, origin));
+            Template::new(version, e.opcode, e.src, e.imm, e.offset)
+        })
+        .collect()
+}
+
+/// Whether `runtime` implements the encoding of template `t`
+fn supported(runtime: Runtime, t: &Template) -> bool {
+    let atomic = matches!(t.opcode, 0xc3 | 0xdb);
+    let src = t.src.unwrap_or(0);
+    match runtime {
+        Runtime::Linux6_8 => true,
+        Runtime::Rbpf => t.version.value() <= 3 && !atomic && src == 0,
+        Runtime::Ubpf => t.version.value() <= 3 && (src == 0 || (t.opcode == 0x85 && src == 1)),
+        // Map fd and map value loads, and local calls
+        Runtime::Windows => t.version.value() <= 3 && (src <= 1 || (t.opcode == 0x18 && src == 2)),
+    }
+}
+
+/// Loads the templates from the spec file at `path`, or from the embedded one, keeping
+/// only those `runtime` supports. Must run before anything looks at the templates.
+pub fn load(path: Option<&str>, runtime: Option<Runtime>) {
+    let mut templates = match path {
+        Some(path) => parse(&fs::read_to_string(path).expect("Failed to read ISA spec"), path),
+        None => parse(DEFAULT_SPEC, "isa.toml"),
+    };
+    if let Some(runtime) = runtime {
+        templates.retain(|t| supported(runtime, t));
+    }
+    if TEMPLATES.set(templates).is_err() {
+        panic!("ISA spec loaded after the templates were already in use");
+    }
+}
+
+/// Templates of the loaded spec, or of the embedded one if none was loaded
+pub fn templates() -> &'static [Template] {
+    TEMPLATES.get_or_init(|| parse(DEFAULT_SPEC, "isa.toml"))
+}
+
+/// Fields the templates of an opcode fix, in at least one of them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fixed {
+    pub src: bool,
+    pub imm: bool,
+    pub offset: bool,
+}
+
+static FIXED: OnceLock<[Fixed; 256]> = OnceLock::new();
+
+/// Fields the templates of `opcode` fix, worked out for every opcode on first use, as
+/// instantiating each instruction needs them
+pub fn fixed(opcode: u8) -> Fixed {
+    FIXED.get_or_init(|| {
+        let mut fixed = [Fixed::default(); 256];
+        for t in templates() {
+            let f = &mut fixed[t.opcode as usize];
+            f.src |= t.src.is_some();
+            f.imm |= t.imm.is_some();
+            f.offset |= t.offset.is_some();
+        }
+        fixed
+    })[opcode as usize]
+}
+
+/// Fields of a template that identify it, regardless of CPU version
+type Key = (u8, Option<u8>, Option<u32>, Option<u16>);
+
+fn key(t: &Template) -> Key {
+    (t.opcode, t.src, t.imm, t.offset)
+}
+
+/// An instruction listed in opcode_names.h, with all fields given
+struct Listed {
+    opcode: u8,
+    src: u8,
+    imm: u32,
+    offset: u16,
+    version: Option<Version>,
+    /// In the packet or callx groups, which the generator leaves out
+    disabled: bool,
+}
+
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('/') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |j| &rest[j..]);
+        } else if rest.starts_with("/*") {
+            rest = rest.find("*/").map_or("", |j| &rest[j + 2..]);
+        } else {
+            out.push('/');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn parse_int(word: &str) -> Option<u64> {
+    let word = word.trim_end_matches(['u', 'U', 'l', 'L']);
+    match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+/// Field names of the instruction struct, in declaration order, so initializers can be read
+/// positionally; falls back to opcode, src, imm, offset
+fn struct_fields(header: &str) -> Vec<String> {
+    let fields = header.split("struct").skip(1).find_map(|def| {
+        let body = &def[def.find('{')? + 1..def.find('}')?];
+        let names: Vec<String> = body
+            .split(';')
+            .filter_map(|decl| decl.split('=').next()?.split_whitespace().last())
+            .map(|name| name.trim_start_matches('*').to_string())
+            .collect();
+        names.iter().any(|n| n == "opcode").then_some(names)
+    });
+    fields.unwrap_or_else(|| ["opcode", "src", "imm", "offset"].map(String::from).to_vec())
+}
+
+/// Parses the entries of the `instructions_from_spec` initializer
+fn parse_header(text: &str) -> Vec<Listed> {
+    let header = strip_comments(text);
+    let fields = struct_fields(&header);
+    let start = header.find("instructions_from_spec").expect("No instructions_from_spec table in header");
+    let table = &header[start..];
+    let table = &table[table.find('{').expect("Malformed instructions_from_spec table") + 1..];
+
+    // Entries are the brace groups at depth 1; nested groups are flattened into them
+    let mut listed = Vec::new();
+    let mut depth = 0;
+    let mut entry = String::new();
+    for c in table.chars() {
+        match c {
+            '{' => {
+                depth += 1;
+                entry.push(',');
+            }
+            '}' if depth == 0 => break,
+            '}' => {
+                depth -= 1;
+                entry.push(',');
+                if depth == 0 {
+                    listed.push(parse_entry(&entry, &fields));
+                    entry.clear();
+                }
+            }
+            _ if depth > 0 => entry.push(c),
+            _ => {}
+        }
+    }
+    listed
+}
+
+fn parse_entry(entry: &str, fields: &[String]) -> Listed {
+    let mut listed = Listed { opcode: 0, src: 0, imm: 0, offset: 0, version: None, disabled: false };
+    let words = entry.split(',').map(str::trim).filter(|w| !w.is_empty());
+    for (i, word) in words.enumerate() {
+        let symbol = word.rsplit("::").next().unwrap_or(word);
+        if matches!(symbol, "packet" | "callx") {
+            listed.disabled = true;
+        } else if let Some(version) = symbol.strip_prefix('v').and_then(|v| v.parse().ok()).and_then(Version::from_value) {
+            listed.version = Some(version);
+        } else if let Some(value) = parse_int(word) {
+            let field = fields.get(i).map_or("", String::as_str);
+            if field.contains("opcode") {
+                listed.opcode = value as u8;
+            } else if field.contains("src") {
+                listed.src = value as u8;
+            } else if field.contains("imm") {
+                listed.imm = value as u32;
+            } else if field.contains("offset") {
+                listed.offset = value as i64 as u16;
+            }
+        }
+    }
+    listed
+}
+
+fn render(templates: &[(Template, bool)]) -> String {
+    let preamble = DEFAULT_SPEC.split("instructions = [").next().unwrap_or_default();
+    let mut out = format!("{}instructions = [\n", preamble);
+    for (t, disabled) in templates {
+        let mut entry = format!("{{ opcode = {:#04x}, version = {}", t.opcode, t.version.value());
+        if let Some(src) = t.src {
+            entry.push_str(&format!(", src = {}", src));
+        }
+        if let Some(imm) = t.imm {
+            entry.push_str(&format!(", imm = {:#04x}", imm));
+        }
+        if let Some(offset) = t.offset {
+            entry.push_str(&format!(", offset = {}", offset));
+        }
+        out.push_str(&format!("    {}{} }},\n", if *disabled { "# " } else { "" }, entry));
+    }
+    out.push_str("]\n");
+    out
+}
+
+/// Regenerates the spec from opcode_names.h on stdout, and reports on stderr how the
+/// loaded templates differ from it
+pub fn update(header_path: &str) {
+    let text = fs::read_to_string(header_path).expect("Failed to read opcode_names.h");
+    let listed = parse_header(&text);
+    assert!(!listed.is_empty(), "No instructions found in {}", header_path);
+
+    // A field is fixed for an opcode if any of its entries sets it, e.g. offset 1 of sdiv
+    // also fixes offset 0 of udiv; fields that are always 0 are left free
+    let mut fixed: BTreeMap<u8, (bool, bool, bool)> = BTreeMap::new();
+    for l in &listed {
+        let f = fixed.entry(l.opcode).or_default();
+        f.0 |= l.src != 0;
+        f.1 |= l.imm != 0;
+        f.2 |= l.offset != 0;
+    }
+
+    let local = templates();
+    let mut generated: Vec<(Template, bool)> = Vec::new();
+    for l in &listed {
+        let (src, imm, offset) = fixed[&l.opcode];
+        let k = (l.opcode, src.then_some(l.src), imm.then_some(l.imm), offset.then_some(l.offset));
+        if generated.iter().any(|(t, _)| key(t) == k) {
+            eprintln!("duplicate upstream entry: {}", describe(k));
+            continue;
+        }
+        let known = local.iter().find(|t| key(t) == k);
+        if known.is_none() && !l.disabled {
+            eprintln!("missing locally: {}", describe(k));
+        }
+        // Keep the local CPU version where the header does not give one
+        let version = l
+            .version
+            .or_else(|| known.map(|t| t.version))
+            .or_else(|| local.iter().filter(|t| t.opcode == l.opcode).map(|t| t.version).min_by_key(Version::value))
+            .unwrap_or(Version::V1);
+        if let (Some(t), Some(v)) = (known, l.version) {
+            if t.version.value() != v.value() {
+                eprintln!("version differs: {} is v{} locally, v{} upstream", describe(k), t.version.value(), v.value());
+            }
+        }
+        generated.push((Template::new(version, k.0, k.1, k.2, k.3), l.disabled));
+    }
+
+    for (i, t) in local.iter().enumerate() {
+        if local[..i].iter().any(|u| key(u) == key(t)) {
+            eprintln!("duplicate local entry: {}", describe(key(t)));
+        } else if !generated.iter().any(|(g, _)| key(g) == key(t)) {
+            eprintln!("missing upstream: {}", describe(key(t)));
+        }
+    }
+
+    print!("{}", render(&generated));
+}
+
+fn describe((opcode, src, imm, offset): Key) -> String {
+    let mut text = format!("opcode {:#04x}", opcode);
+    if let Some(src) = src {
+        text.push_str(&format!(" src {}", src));
+    }
+    if let Some(imm) = imm {
+        text.push_str(&format!(" imm {:#x}", imm));
+    }
+    if let Some(offset) = offset {
+        text.push_str(&format!(" offset {}", offset));
+    }
+    text
+}
@@ -0,0 +1,2774 @@
+//! The crate as a library: everything the `ebpf_fuzzer` binary does, which only calls
+//! `run`, and `Program` for cargo-fuzz targets taking structured programs:
+//!
+//! ```ignore
+//! fuzz_target!(|program: ebpf_fuzzer::Program| {
+//!     run(&program.to_bytes());
+//! });
+//! ```
+//!
+//! `Program` implements `Arbitrary` with the `arbitrary` feature.
+
+#[cfg(feature = "aya")]
+mod aya_harness;
+mod adaptive;
+#[cfg(unix)]
+mod afl;
+#[cfg(feature = "archive")]
+mod archive;
+mod bundle;
+mod c_array;
+mod cfg;
+mod cluster;
+mod cmin;
+mod config;
+mod crosscheck;
+#[cfg(feature = "tui")]
+mod dashboard;
+mod dsl;
+mod enumerate;
+mod elf;
+mod eval;
+mod findings;
+mod fuzz;
+mod grammar;
+mod helpers;
+mod interp;
+mod isa;
+mod limits;
+mod loader;
+mod manifest;
+mod metrics;
+mod mismatch;
+mod metamorphic;
+mod mutate;
+mod obfuscate;
+mod objects;
+mod oneline;
+mod pairwise;
+mod patterns;
+mod plugin;
+mod preset;
+pub mod program;
+mod oracle;
+mod proto;
+mod pseudo;
+mod queue;
+mod qemu;
+#[cfg(feature = "regressions")]
+mod regress;
+mod rejections;
+mod repl;
+mod report;
+mod repro;
+mod rules;
+mod runner;
+mod selftests;
+#[cfg(feature = "sancov")]
+mod sancov;
+#[cfg(feature = "smt")]
+mod smt;
+mod snapshot;
+mod structured;
+mod verifier;
+mod vm;
+
+use eval::Expected;
+use manifest::Manifest;
+use clap::{Parser, Subcommand, ValueEnum};
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
+use rand::seq::{IndexedRandom, SliceRandom};
+use rbpf::ebpf;
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use program::Program;
+
+/// CLI arguments for the program
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file of options keyed by their long name, e.g. `max-size = 20`. Options can also
+    /// be set with EBPF_FUZZER_* environment variables, e.g. EBPF_FUZZER_MAX_SIZE=20, which
+    /// win over the file; the command line wins over both [env: EBPF_FUZZER_CONFIG]
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// Minimum number of instructions to generate [default: 3, or set by --profile]
+    #[arg(long, global = true)]
+    min_size: Option<u32>,
+
+    /// Maximum number of instructions to generate [default: 40, or set by --profile]
+    #[arg(long, global = true)]
+    max_size: Option<u32>,
+
+    /// Minimum program size in bytes, a multiple of 8, sizing programs by bytes instead of
+    /// instructions [default: 8 times the minimum number of instructions]
+    #[arg(long, global = true, conflicts_with_all = ["min_size", "max_size"])]
+    min_bytes: Option<u32>,
+
+    /// Maximum program size in bytes, a multiple of 8, sizing programs by bytes instead of
+    /// instructions [default: 8 times the maximum number of instructions]
+    #[arg(long, global = true, conflicts_with_all = ["min_size", "max_size"])]
+    max_bytes: Option<u32>,
+
+    /// Number of programs to generate
+    #[arg(long, global = true, default_value_t = 1)]
+    count: u32,
+
+    /// Threads generating programs in parallel, without changing what they generate
+    #[arg(long, global = true, default_value_t = 1)]
+    jobs: u32,
+
+    /// Show a live dashboard of crosscheck and fuzz runs in the terminal instead of their
+    /// findings and progress lines
+    #[cfg(feature = "tui")]
+    #[arg(long, global = true)]
+    tui: bool,
+
+    /// Output format string (e.g. "./out/%d.bpf"), with %d the program's index and, when
+    /// generating, %s the seed that regenerates it alone
+    #[arg(long, global = true, default_value = "-")]
+    output: String,
+
+    /// Format to write programs in
+    #[arg(long, global = true, value_enum, default_value_t = Format::Conformance)]
+    format: Format,
+
+    /// Also write a libbpf loader `<path>.loader.c` next to each ELF object, which loads the
+    /// object and test-runs or attaches its program
+    #[arg(long, global = true)]
+    loader: bool,
+
+    /// Byte order to encode instructions in
+    #[arg(long, global = true, value_enum, default_value_t = Endian::Le)]
+    endian: Endian,
+
+    /// Version of the eBPF specification to use
+    #[arg(long, global = true, default_value_t = 3, help = "Maximum CPU version to generate instructions for (default: 3)")]
+    max_cpu_version: u8,
+
+    /// Highest register generated programs may use (e.g. 5 restricts them to r0-r5)
+    #[arg(long, global = true, conflicts_with = "regs", value_parser = clap::value_parser!(u8).range(0..=10))]
+    max_reg: Option<u8>,
+
+    /// Registers generated programs may use (e.g. "r0,r1,r6")
+    #[arg(long, global = true, value_delimiter = ',', value_parser = parse_register)]
+    regs: Option<Vec<u8>>,
+
+    /// Never write to the read-only frame pointer r10 (it may still be used as a load/store base)
+    #[arg(long, global = true)]
+    no_r10_writes: bool,
+
+    /// Registers each program may use at most, drawn per program from the allowed ones (r0
+    /// first when it is writable), so instructions reuse each other's registers
+    #[arg(long, global = true, value_parser = clap::value_parser!(u8).range(1..=16))]
+    max_distinct_regs: Option<u8>,
+
+    /// Generation profile tuning program shape for a particular kind of target
+    #[arg(long, global = true, value_enum)]
+    profile: Option<Profile>,
+
+    /// Bundle of template weights, sizes and options for a common campaign, which options
+    /// given otherwise override
+    #[arg(long, global = true, value_enum)]
+    preset: Option<Preset>,
+
+    /// Pad programs at the front with neutral instructions up to this many slots (N or LO..HI),
+    /// to push JIT output onto page and buffer-size boundaries
+    #[arg(long, global = true, value_parser = parse_range::<u32>)]
+    pad_to: Option<RangeInclusive<u32>>,
+
+    /// Force an instruction at an index of every program, as INDEX:OPCODE followed by any of
+    /// ,dst=N ,src=N ,off=N ,imm=N (e.g. "5:0xdb,dst=1,off=0"); fields left out are random.
+    /// Repeat for more. Programs shorter than the index get it appended.
+    #[arg(long, global = true, value_parser = parse_pin)]
+    pin: Vec<Pin>,
+
+    /// Offsets of loads and stores (LO..HI, or N), e.g. a region known to be valid, its edges
+    /// or just outside it [default: any 16-bit offset]
+    #[arg(long, global = true, value_parser = parse_range::<i16>)]
+    mem_offset_window: Option<RangeInclusive<i16>>,
+
+    /// Size in bytes of the memory area programs get through r1, filled with a fixed pattern
+    /// that conformance output records in `-- mem`; --structured programs load and store within it
+    #[arg(long, global = true, default_value_t = 0)]
+    mem_size: u32,
+
+    /// Values for fields the spec says must be zero, e.g. src of ALU-immediate operations
+    #[arg(long, global = true, value_enum, default_value_t = ReservedFields::Random)]
+    reserved_fields: ReservedFields,
+
+    /// Constants LD_DW_IMM loads, split across its two slots
+    #[arg(long, global = true, value_enum, default_value_t = LddwImm::Random)]
+    lddw_imm: LddwImm,
+
+    /// Whether instructions read registers already written in program order
+    #[arg(long, global = true, value_enum, default_value_t = LiveRegs::Off)]
+    live_regs: LiveRegs,
+
+    /// Seed for the random number generator [default: random]
+    #[arg(long, global = true)]
+    seed: Option<u64>,
+
+    /// Program type whose helper whitelist calls are drawn from; also names the ELF section
+    #[arg(long, global = true, value_enum)]
+    prog_type: Option<ProgType>,
+
+    /// Deliberately call helpers outside the --prog-type whitelist, to exercise rejection paths
+    #[arg(long, global = true, requires = "prog_type")]
+    foreign_helpers: bool,
+
+    /// Save every finding in a directory of its own, with the program in every format, the
+    /// seed and effective command line, the outcomes of both oracles, the reference
+    /// interpreter's trace and a minimized program where the oracles reproduce the finding
+    #[arg(long, global = true)]
+    bundle: bool,
+
+    /// Record the seed, arguments and files of a generation run, for the verify subcommand
+    #[arg(long, global = true)]
+    snapshot: Option<String>,
+
+    /// Write a TOML manifest of a generation run: its arguments, and every file written with
+    /// its seed, size, ISA features and hash
+    #[arg(long, global = true)]
+    manifest: Option<String>,
+
+    /// TOML file of instruction templates to generate from, replacing the embedded isa.toml
+    #[arg(long, global = true)]
+    isa_spec: Option<String>,
+
+    /// Build programs around algebraic identities so the result they return, written to `-- result`,
+    /// is known by construction
+    #[arg(long, global = true, conflicts_with = "profile")]
+    structured: bool,
+
+    /// A rule structured programs obey: `require r0 == VALUE at exit`, `forbid FAMILY[, FAMILY...]`
+    /// with instruction families such as div, lddw or jslt, or `stack_usage <= BYTES` to also
+    /// store to and load from that many bytes of stack below r10
+    #[arg(long, global = true, requires = "structured", value_parser = rules::parse)]
+    rule: Vec<rules::Rule>,
+
+    /// Build programs from a template of instructions in rbpf's assembler syntax separated by
+    /// `;`, where {r}, {imm} and {off} are random fields, optionally in a range as in
+    /// {imm:0..255}, and {any*N} or {any*LO..HI} runs of random instructions, e.g.
+    /// "mov r{r}, {imm}; {any*10}; exit"
+    #[arg(long, global = true, conflicts_with = "structured", value_parser = dsl::parse)]
+    template: Option<dsl::ProgramTemplate>,
+
+    /// Make sure every enabled template appears in at least one written program, adding
+    /// programs after the last one that hold those random generation missed
+    #[arg(long, global = true)]
+    cover_all_templates: bool,
+
+    /// Drop the instructions no path from the entry reaches, which verifiers reject programs for
+    #[arg(long, global = true)]
+    reachable_only: bool,
+
+    /// Append this many generated functions after each program and call them from it with
+    /// local calls, which --format elf turns into .text functions and call relocations
+    #[arg(long, global = true, default_value_t = 0, conflicts_with = "structured")]
+    functions: u32,
+
+    /// Declare this many BTF-defined array maps in a .maps section of ELF output, and relocate
+    /// every LD_DW_IMM of a map by fd against one of them instead of keeping its raw fd
+    #[arg(long, global = true, default_value_t = 0)]
+    maps: u32,
+
+    /// Also emit an instruction from the CPU version after --max-cpu-version in every program, while declaring
+    /// --max-cpu-version in its metadata, to test that runners reject features the target lacks
+    #[arg(long, global = true)]
+    version_gating: bool,
+
+    /// Runtime whose supported instructions and helpers are considered in-spec, on top of --max-cpu-version
+    #[arg(long, global = true, value_enum)]
+    isa_profile: Option<Runtime>,
+
+    /// Rewrite generated programs into equivalents that are harder to verify, keeping their result
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    obfuscate: Vec<Obfuscation>,
+
+    /// Probability of replacing each random instruction with a known-tricky sequence, such as
+    /// INT64_MIN / -1 or shifts by the register width or more
+    #[arg(long, global = true, default_value_t = 0.0, value_parser = parse_probability)]
+    pattern_rate: f64,
+
+    /// Helper IDs (N or LO..HI, comma-separated) to register with rbpf and the reference
+    /// interpreter when running programs in-process, which generated calls then stay within
+    /// [default: every kernel helper]
+    #[arg(long, global = true, value_parser = parse_range::<u32>, value_delimiter = ',')]
+    vm_helpers: Vec<RangeInclusive<u32>>,
+
+    /// Milliseconds a program may run in-process under rbpf before it counts as a hang
+    #[arg(long, global = true, default_value_t = 1000)]
+    vm_timeout: u64,
+
+    /// Probability of each helper call targeting a hostile ID instead of a helper: negative,
+    /// huge, or just past the last helper, to fuzz bounds checks of helper dispatch
+    #[arg(long, global = true, default_value_t = 0.0, value_parser = parse_probability)]
+    hostile_helpers: f64,
+
+    /// Probability of each register an instruction reads being one the last few instructions
+    /// wrote, building long dependency chains that carry small differences through to r0
+    /// [default: 0, or 0.75 with --profile divergence]
+    #[arg(long, global = true, value_parser = parse_probability)]
+    dependency_bias: Option<f64>,
+
+    /// Address space limit in MiB for the targets programs are fed to. Sanitizer runtimes
+    /// reserve terabytes of address space, so leave this out for sanitizer builds.
+    #[arg(long, global = true)]
+    limit_memory: Option<u64>,
+
+    /// CPU time limit in seconds for the targets programs are fed to
+    #[arg(long, global = true)]
+    limit_cpu: Option<u64>,
+
+    /// Limit in MiB on the size of files the targets programs are fed to write
+    #[arg(long, global = true)]
+    limit_file_size: Option<u64>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compare the crate's decoding of generated programs against an external disassembler
+    Crosscheck {
+        /// Disassembler command, with {} replaced by the path to an ELF object
+        #[arg(long, default_value = "llvm-objdump -d {}")]
+        disasm_cmd: String,
+    },
+    /// Run generated programs through rbpf and the crate's reference interpreter, and archive
+    /// those they disagree on or whose result the interpreter does not reproduce
+    Oracle,
+    /// Write every program of up to --size instructions over the enabled templates, with free
+    /// fields drawn from a few values each (r0 and r1, immediates 0, 1 and -1, offsets 0 and 1)
+    Enumerate {
+        /// Longest programs to write, in instructions; the count grows as a power of it
+        #[arg(long, default_value_t = 1)]
+        size: u32,
+    },
+    /// Write programs until every ordered pair of enabled opcodes appears adjacently in one,
+    /// for JIT bugs two particular instructions in a row trigger
+    Pairwise,
+    /// Run generated programs through rbpf's verifier only, report how often it accepts
+    /// programs with each template, and archive those it panics on or accepts though the
+    /// crate's model says they are malformed
+    Verifier,
+    /// Interactively generate, edit, run and save a single program
+    Repl,
+    /// Run generated programs and semantically equivalent transforms of them through rbpf,
+    /// and archive the pairs whose outcomes differ
+    Metamorphic {
+        /// Transforms to pick from, applied one to eight times to each program
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "commute,strength,neutral,reassociate")]
+        transforms: Vec<Transform>,
+    },
+    /// Write mutants of an existing program that passes the rbpf verifier
+    Mutate {
+        /// Program to mutate, in the conformance format or as raw bytes, or "-" for stdin
+        #[arg(long, default_value = "-")]
+        input: String,
+        /// Mutation operators to pick from for each mutant
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "bitflip")]
+        ops: Vec<MutationOp>,
+    },
+    /// Feed generated programs to an external target and archive those that trip a sanitizer or crash it
+    Run {
+        /// Target command, with @@ replaced by the path to the program (stdin is used without @@)
+        #[arg(long)]
+        target_cmd: String,
+        /// Group the programs the target rejects by the reason in its verifier log on stderr,
+        /// and print the counts at the end
+        #[arg(long)]
+        rejections: bool,
+        /// Tune generation as the run goes, from how far into each program the verifier in
+        /// the target got before rejecting it
+        #[arg(long)]
+        adaptive: bool,
+        /// Start the target once under its AFL forkserver and fork it for every program,
+        /// instead of starting it afresh. The target must be built with AFL instrumentation,
+        /// which also lets it use persistent mode to run several programs per fork.
+        #[cfg(unix)]
+        #[arg(long)]
+        forkserver: bool,
+        /// Milliseconds the target may run a program under --forkserver before it counts as
+        /// a hang
+        #[cfg(unix)]
+        #[arg(long, default_value_t = 1000)]
+        timeout: u64,
+    },
+    /// Run the bundled programs reproducing past bugs of BPF implementations through rbpf and
+    /// the reference interpreter, and report those that fail
+    #[cfg(feature = "regressions")]
+    Regress,
+    /// Synthesize programs meeting constraints on the values they compute and the way their
+    /// branches go, by solving ALU and jump semantics with z3 for the immediates of a random
+    /// skeleton of instructions loading the registers it uses first
+    #[cfg(feature = "smt")]
+    Smt {
+        /// A constraint: rN=VALUE or rN!=VALUE on a register at exit, or taken=N or
+        /// not-taken=N on the Nth conditional jump executed, counting from 0
+        #[arg(long = "constraint", required = true, value_parser = smt::parse)]
+        constraints: Vec<smt::Constraint>,
+    },
+    /// Load generated ELF objects through aya and a libbpf-based loader and archive those
+    /// they accept, reject or fail on differently
+    #[cfg(feature = "aya")]
+    Aya {
+        /// libbpf loader command, with @@ replaced by the path to the object, e.g. one built
+        /// from --loader output, which exits with 1 when opening fails and 2 when loading does
+        #[arg(long)]
+        libbpf_cmd: String,
+        /// Group the programs each loader rejects by the reason in its log, and print the
+        /// counts at the end
+        #[arg(long)]
+        rejections: bool,
+    },
+    /// Run a campaign of generated and mutated programs through rbpf and the reference
+    /// interpreter, growing a corpus of programs that reach new behaviour and archiving the
+    /// first finding of each kind, until --iterations programs have run or it is killed
+    Fuzz(FuzzOptions),
+    /// Copy the smallest subset of the programs in a directory that covers every signal the
+    /// whole directory does into --output, a directory, to shrink merged corpora
+    Cmin {
+        /// Directory of programs, such as several corpora merged
+        #[arg(long)]
+        input: String,
+        /// Signal the subset preserves
+        #[arg(long, value_enum, default_value_t = CminSignal::Execution)]
+        signal: CminSignal,
+    },
+    /// Coordinate fuzz workers on other machines: hand out round seeds, merge the corpus
+    /// entries they find and archive the first finding of each kind, until killed
+    Coordinator {
+        /// Address to listen on for workers
+        #[arg(long, default_value = "0.0.0.0:7878")]
+        listen: String,
+        /// Directory of the merged corpus, resumed from if it already holds programs
+        #[arg(long)]
+        corpus: String,
+        /// Address to serve Prometheus metrics of the whole campaign on at /metrics
+        #[arg(long)]
+        metrics: Option<String>,
+    },
+    /// Run fuzz rounds of --count programs with seeds from a coordinator, syncing corpus
+    /// entries and findings with it after each round
+    Worker {
+        /// Address of the coordinator
+        #[arg(long)]
+        coordinator: String,
+        /// Number of rounds to run [default: until killed]
+        #[arg(long)]
+        rounds: Option<u32>,
+        #[command(flatten)]
+        fuzz: FuzzOptions,
+    },
+    /// Run generated programs through rbpf natively and through this binary's `exec` under
+    /// qemu-user for other architectures, and archive those whose outcomes differ
+    Qemu {
+        /// Architecture name and command running `ebpf_fuzzer exec @@` on it, with @@ replaced
+        /// by the path to the program, e.g. "aarch64=qemu-aarch64 -L /usr/aarch64-linux-gnu
+        /// ./ebpf_fuzzer-aarch64 exec @@"; repeat for several architectures
+        #[arg(long = "arch", required = true)]
+        arches: Vec<String>,
+    },
+    /// Run a program in the conformance format or as raw bytes through rbpf and print its outcome
+    Exec {
+        /// Program to run, or "-" for stdin
+        #[arg(default_value = "-")]
+        file: String,
+    },
+    /// Act as a bpf_conformance plugin (--plugin_options plugin): run the program on stdin
+    /// through rbpf with the memory area in the argument, both in hex, and print r0 in hex
+    Plugin {
+        /// Memory area the runner passes, or none for an empty one
+        memory: Option<String>,
+    },
+    /// Write the programs of kernel BPF selftests spelled out with the instruction macros of
+    /// linux/filter.h, as in test_verifier, to the output as seeds
+    ImportSelftests {
+        /// Selftest C sources, or directories of them such as tools/testing/selftests/bpf/verifier
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// linux/bpf.h, for the IDs of helpers called by their BPF_FUNC_ name
+        #[arg(long)]
+        helper_header: Option<String>,
+    },
+    /// Write the programs of BPF objects built by clang, one per program section, to the
+    /// output as seeds
+    ImportObjects {
+        /// Object files, or directories of them such as a libbpf-tools build
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Write the queue entries of byte-level fuzzers such as AFL++ and libFuzzer, bare
+    /// little-endian instructions, to the output as conformance tests, with what the reference
+    /// interpreter returns as the expected result; entries it does not return from are skipped
+    ImportQueue {
+        /// Queue entries, or directories of them such as an AFL++ queue or crashes directory
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Write a markdown bug report next to every finding in a directory, with the program's
+    /// disassembly and bytes, both oracles' outcomes, the reproducer and the environment
+    Report {
+        /// Directory of findings, e.g. the crashes directory next to --output
+        dir: String,
+    },
+    /// Convert a Program message of proto/ebpf_program.proto, such as one libprotobuf-mutator
+    /// produced, to a program in --format
+    FromProto {
+        /// File holding the message in the protobuf wire encoding, or - for stdin
+        file: String,
+    },
+    /// Convert a fuzzer input, such as a cargo-fuzz crash artifact, to the Program a target
+    /// taking one got from it, in --format
+    #[cfg(feature = "arbitrary")]
+    FromArbitrary {
+        /// File holding the input
+        file: String,
+    },
+    /// Print the enabled templates as a grammar of programs in the `-- raw` section of the
+    /// conformance format, for grammar-based fuzzers such as Nautilus or Grammarinator
+    ExportGrammar {
+        #[arg(long, value_enum, default_value_t = GrammarFormat::Nautilus)]
+        format: GrammarFormat,
+    },
+    /// Regenerate the ISA spec from bpf_conformance's opcode_names.h on stdout, reporting
+    /// opcodes missing on either side and duplicate entries on stderr
+    UpdateSpec {
+        /// Path to opcode_names.h
+        #[arg(long)]
+        header: String,
+    },
+    /// Regenerate the corpus recorded with --snapshot and fail if any file differs
+    Verify {
+        /// Snapshot file written by --snapshot
+        file: String,
+    },
+    /// Pack the files a --manifest lists and the manifest itself into one zstd-compressed
+    /// tar archive
+    #[cfg(feature = "archive")]
+    ExportCorpus {
+        /// Manifest written by --manifest
+        manifest: String,
+        /// Archive to write, e.g. corpus.tar.zst
+        #[arg(long)]
+        archive: String,
+    },
+    /// Unpack an archive written by export-corpus and check every file against the hash the
+    /// manifest in it records
+    #[cfg(feature = "archive")]
+    ImportCorpus {
+        /// Archive written by export-corpus
+        archive: String,
+        /// Directory to unpack into, which the paths of the unpacked manifest are relative to
+        #[arg(long)]
+        dir: String,
+    },
+}
+
+impl Command {
+    /// Whether the command runs generated programs through rbpf in-process
+    fn runs_in_process(&self) -> bool {
+        matches!(
+            self,
+            Command::Oracle | Command::Repl | Command::Metamorphic { .. } | Command::Mutate { .. } | Command::Fuzz(_) | Command::Worker { .. } | Command::Qemu { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// bpf_conformance test file
+    Conformance,
+    /// C-like pseudocode
+    PseudoC,
+    /// ELF object file with the program in a section named after --prog-type ("socket" by default)
+    Elf,
+    /// The encoded instructions alone, in the byte order of --endian
+    Bin,
+    /// The little-endian instruction bytes as one line of hex
+    Hexline,
+    /// The little-endian instruction bytes as one line of base64
+    Base64,
+    /// C header declaring the program as `static const uint64_t prog[]`, in the byte order of --endian
+    CArray,
+    /// C header declaring the program as `static const struct bpf_insn prog[]`
+    InsnArray,
+    /// Program message of proto/ebpf_program.proto in the protobuf wire encoding, for
+    /// libprotobuf-mutator harnesses
+    Proto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Endian {
+    /// Little-endian (bpfel)
+    Le,
+    /// Big-endian (bpfeb), e.g. for s390x
+    Be,
+}
+
+/// eBPF runtime whose subset of the ISA and helpers generated programs stay within
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Runtime {
+    /// Linux 6.8 verifier and JITs: every template, including v4 and pseudo calls to kfuncs
+    #[value(name = "linux-6.8")]
+    Linux6_8,
+    /// rbpf: no atomics, plain lddw and helper calls only
+    Rbpf,
+    /// uBPF: atomics and local calls, plain lddw only
+    Ubpf,
+    /// eBPF for Windows (PREVAIL): map lddw forms, local calls and its own helper table
+    Windows,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgType {
+    SocketFilter,
+    Kprobe,
+    Tracepoint,
+    SchedCls,
+    Xdp,
+}
+
+impl ProgType {
+    /// ELF section name loaders recognize for the program type
+    fn section(&self) -> &'static str {
+        match self {
+            ProgType::SocketFilter => "socket",
+            ProgType::Kprobe => "kprobe/prog",
+            ProgType::Tracepoint => "tracepoint/prog",
+            ProgType::SchedCls => "tc",
+            ProgType::Xdp => "xdp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReservedFields {
+    /// Random values, like any other field
+    Random,
+    /// Always zero, as the spec requires
+    Zero,
+    /// Always nonzero, to check implementations consistently reject or ignore them
+    Nonzero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LddwImm {
+    /// Random halves
+    Random,
+    /// Constants at the edges of 32- and 64-bit ranges, and 32-bit values with the upper half
+    /// zero or their sign extended, which tell apart decoders mixing up the halves
+    Interesting,
+}
+
+/// How generated instructions pick the registers they read, given those defined so far:
+/// r1 and r10 on entry, and every register an earlier instruction wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LiveRegs {
+    /// Any register, defined or not
+    Off,
+    /// Mostly defined registers, so most programs pass verifiers' uninitialized-read checks
+    Prefer,
+    /// Only defined registers, whenever one is
+    Require,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum MutationOp {
+    /// Flip a few bits, preferring fields the verifier checks
+    Bitflip,
+    /// Stack random byte flips, byte arithmetic and block clones/overwrites
+    Havoc,
+    /// Insert a random instruction
+    Insert,
+    /// Delete a window of instructions
+    Delete,
+    /// Duplicate a block of instructions
+    Duplicate,
+    /// Shuffle basic blocks, adding jumps so execution order is unchanged
+    Reorder,
+}
+
+/// Options of the fuzz subcommand
+#[derive(clap::Args)]
+struct FuzzOptions {
+    /// Directory of the corpus, resumed from if it already holds programs
+    #[arg(long)]
+    corpus: String,
+    /// Programs to run before the campaign stops, where workers run rounds of --count instead
+    /// [default: no limit]
+    #[arg(long)]
+    iterations: Option<u64>,
+    /// Order in which corpus entries are taken off the work queue for mutation
+    #[arg(long, value_enum, default_value = "least-fuzzed")]
+    schedule: Schedule,
+    /// Probability of generating a fresh program instead of mutating a corpus entry
+    #[arg(long, default_value_t = 0.1, value_parser = parse_probability)]
+    fresh_rate: f64,
+    /// Mutation operators to pick from, stacked one to four times on each mutant
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "bitflip,havoc,insert,delete,duplicate,reorder")]
+    ops: Vec<MutationOp>,
+    /// Directory to poll for programs other fuzzers drop, e.g. an AFL++ queue, importing
+    /// each new one into the corpus as a seed
+    #[arg(long)]
+    watch: Option<String>,
+    /// Address to serve Prometheus metrics on at /metrics, e.g. "127.0.0.1:9100"
+    #[arg(long)]
+    metrics: Option<String>,
+    /// AFL-instrumented target to run every program through in --format, with @@ replaced
+    /// by the path to the program (stdin is used without @@). Programs reaching new
+    /// coverage in its shared-memory bitmap join the corpus, and crashes and hangs are findings.
+    #[cfg(unix)]
+    #[arg(long)]
+    afl_target: Option<String>,
+    /// Milliseconds a run of the AFL target may take before it counts as a hang
+    #[cfg(unix)]
+    #[arg(long, default_value_t = 1000)]
+    afl_timeout: u64,
+}
+
+/// Grammar formats of the export-grammar subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GrammarFormat {
+    /// Nautilus JSON grammar, a list of [NONTERMINAL, RHS] pairs starting from START
+    Nautilus,
+    /// ANTLR v4 grammar, as Grammarinator takes, starting from the program rule
+    Antlr,
+}
+
+/// Signals the cmin subcommand preserves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CminSignal {
+    /// What fuzz keeps corpus entries for: adjacent opcode pairs and how rbpf ended the program
+    Execution,
+    /// The enabled templates the instructions match
+    Templates,
+}
+
+/// Order in which the fuzz subcommand mutates corpus entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Schedule {
+    /// Cycle through the entries in the order they joined the corpus
+    Fifo,
+    /// Pick among the entries mutated the fewest times, so new entries catch up first
+    LeastFuzzed,
+}
+
+/// Semantics-preserving program transforms, for the metamorphic subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Transform {
+    /// Swap the operands of a commutative operation through an unused register
+    Commute,
+    /// Exchange multiplications by powers of two and shifts
+    Strength,
+    /// Insert an instruction that changes nothing, such as `r1 += 0` or `ja +0`
+    Neutral,
+    /// Split an immediate operation in two, or regroup two register operations
+    Reassociate,
+}
+
+/// Obfuscation passes stressing verifier value tracking
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Obfuscation {
+    /// Build constants from two instructions, e.g. `r1 = 3` as `r1 = 1; r1 += 2`
+    SplitConstants,
+    /// Spill registers to the stack and reload them right after they are defined
+    StackRoute,
+    /// Route jumps through chains of `ja` trampolines at the end of the program
+    JumpChains,
+}
+
+/// Campaigns --preset sets up. Every preset keeps programs within r0-r10, with reserved
+/// fields zero and only defined registers read, so verifiers reject few of them early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Preset {
+    /// Mostly ALU operations of both widths, chained through their results
+    AluHeavy,
+    /// Mostly loads and stores, with a memory area and offsets around it and the stack
+    MemHeavy,
+    /// Mostly conditional and unconditional jumps, without unreachable code
+    BranchHeavy,
+    /// Mostly atomic operations, with a memory area and offsets around it and the stack
+    Atomics,
+    /// Mostly helper and local calls, with two local functions per program
+    Calls,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Profile {
+    /// Tens of thousands of instructions with long jumps, stressing JIT offset fixups and code buffer growth
+    JitStress,
+    /// Encodings outside the spec, exercising rejection paths of parsers and verifiers
+    Malformed,
+    /// Programs past 32k instructions, so gotol targets lie beyond the reach of 16-bit offsets
+    LongJumps,
+    /// Mostly operations implementations historically disagree on (32-bit ALU zero-extension,
+    /// sign extension, signed division and modulo, byte swaps), chained into r0
+    Divergence,
+    /// Jumps to the edges of the program: themselves, the next instruction, the last one, one
+    /// past the end and as far back as their offset reaches, probing bounds checks of pc updates
+    JumpEdges,
+    /// LD_DW_IMM broken the ways verifiers have mishandled: missing its second slot at the
+    /// end, with an opcode in its second slot, or with a jump landing on its second slot
+    LddwSplit,
+    /// Programs for rbpf's mbuff VMs, which run them with r1 pointing to a metadata buffer:
+    /// they load the pointers to the memory area out of it first, then access the area and
+    /// the buffer in bounds and just past their edges
+    Mbuff,
+    /// Chains of local calls 7, 8 or 9 frames deep around the 8-frame limit, each frame
+    /// storing to its stack so together they use up to or just past 512 bytes
+    CallDepth,
+}
+
+fn parse_register(s: &str) -> Result<u8, String> {
+    let reg: u8 = s.trim().trim_start_matches('r').parse().map_err(|_| format!("invalid register: {}", s))?;
+    if reg > 10 {
+        return Err(format!("register out of range (r0-r10): {}", s));
+    }
+    Ok(reg)
+}
+
+/// An instruction --pin forces at an index; `None` fields are drawn at random
+#[derive(Debug, Clone)]
+struct Pin {
+    index: usize,
+    opcode: u8,
+    dst: Option<u8>,
+    src: Option<u8>,
+    offset: Option<u16>,
+    imm: Option<u32>,
+}
+
+/// Parses "INDEX:OPCODE[,FIELD=VALUE...]", with numbers in decimal or 0x hex
+fn parse_pin(s: &str) -> Result<Pin, String> {
+    let number = |v: &str| {
+        let (negative, digits) = v.trim().strip_prefix('-').map_or((false, v.trim()), |d| (true, d));
+        let value = match digits.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => digits.parse::<i64>(),
+        }
+        .map_err(|_| format!("invalid number: {}", v))?;
+        Ok::<i64, String>(if negative { -value } else { value })
+    };
+    let ranged = |field: &str, v: &str, lo: i64, hi: i64| {
+        let value = number(v)?;
+        if !(lo..=hi).contains(&value) {
+            return Err(format!("{} out of range: {}", field, v));
+        }
+        Ok(value)
+    };
+
+    let (index, rest) = s.split_once(':').ok_or_else(|| format!("expected INDEX:OPCODE: {}", s))?;
+    let mut fields = rest.split(',');
+    let mut pin = Pin {
+        index: ranged("index", index, 0, u32::MAX as i64)? as usize,
+        opcode: ranged("opcode", fields.next().unwrap_or_default(), 0, 0xff)? as u8,
+        dst: None,
+        src: None,
+        offset: None,
+        imm: None,
+    };
+    for field in fields {
+        let (name, value) = field.split_once('=').ok_or_else(|| format!("expected FIELD=VALUE: {}", field))?;
+        match name.trim() {
+            "dst" => pin.dst = Some(ranged(name, value, 0, 15)? as u8),
+            "src" => pin.src = Some(ranged(name, value, 0, 15)? as u8),
+            "off" => pin.offset = Some(ranged(name, value, i16::MIN as i64, u16::MAX as i64)? as u16),
+            "imm" => pin.imm = Some(ranged(name, value, i32::MIN as i64, u32::MAX as i64)? as u32),
+            _ => return Err(format!("unknown field {} (dst, src, off or imm)", name)),
+        }
+    }
+    Ok(pin)
+}
+
+/// Parses "N" or an inclusive range "LO..HI"
+fn parse_range<T: FromStr + PartialOrd + Copy>(s: &str) -> Result<RangeInclusive<T>, String> {
+    let parse = |v: &str| v.trim().parse::<T>().map_err(|_| format!("invalid value: {}", v));
+    let (lo, hi) = match s.split_once("..") {
+        Some((lo, hi)) => (parse(lo)?, parse(hi)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if lo > hi {
+        return Err(format!("empty range: {}", s));
+    }
+    Ok(lo..=hi)
+}
+
+/// Parses a 64-bit value in decimal, negative decimal or 0x hex, negative values as their
+/// two's complement
+fn parse_number(s: &str) -> Option<u64> {
+    match (s.strip_prefix("0x"), s.strip_prefix('-')) {
+        (Some(hex), _) => u64::from_str_radix(hex, 16).ok(),
+        (_, Some(digits)) => digits.parse::<i64>().ok().map(|d| d.wrapping_neg() as u64),
+        _ => s.parse::<u64>().ok(),
+    }
+}
+
+/// Parses a probability, between 0 and 1
+fn parse_probability(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("expected a probability between 0 and 1: {}", s)),
+    }
+}
+
+/// Options controlling instruction generation
+#[derive(Clone)]
+struct GenOptions {
+    profile: Option<Profile>,
+    min_size: u32,
+    max_size: u32,
+    /// Templates enabled for the selected CPU version
+    templates: Vec<&'static Template>,
+    /// Templates of the next CPU version, one of which goes in every program with --version-gating
+    gated_templates: Vec<&'static Template>,
+    /// Templates --profile divergence favors
+    prone_templates: Vec<&'static Template>,
+    /// Relative weights of `templates`, tuned by `run --adaptive`, or empty to draw them uniformly
+    template_weights: Vec<f64>,
+    /// Registers to pick dst/src from
+    regs: Vec<u8>,
+    /// Subset of `regs` that instructions may write to
+    writable_regs: Vec<u8>,
+    /// Size of the subset of `regs` each program is restricted to
+    max_distinct_regs: Option<usize>,
+    /// Helper IDs calls may target
+    helpers: Vec<u32>,
+    reserved_fields: ReservedFields,
+    lddw_imm: LddwImm,
+    live_regs: LiveRegs,
+    pad_to: Option<RangeInclusive<u32>>,
+    pins: Vec<Pin>,
+    mem_offset_window: Option<RangeInclusive<i16>>,
+    /// Slots programs must fill exactly, a random count in the range, with --min-bytes or --max-bytes
+    slots: Option<RangeInclusive<u32>>,
+    /// Seed of the run, recorded in reproducers
+    seed: u64,
+    structured: bool,
+    /// Rules structured programs obey
+    rules: rules::Rules,
+    template: Option<dsl::ProgramTemplate>,
+    reachable_only: bool,
+    functions: u32,
+    obfuscations: Vec<Obfuscation>,
+    pattern_rate: f64,
+    hostile_helpers: f64,
+    dependency_bias: f64,
+}
+
+impl GenOptions {
+    fn from_args(args: &Args) -> Self {
+        // By default any 4-bit value is used, including invalid registers
+        let regs: Vec<u8> = match (&args.regs, args.max_reg) {
+            (Some(regs), _) => regs.clone(),
+            (None, Some(max)) => (0..=max).collect(),
+            (None, None) => (0..16).collect(),
+        };
+        // Structured, template and call-chain programs address the stack through r10, so it
+        // stays the frame pointer there as with --no-r10-writes
+        let r10_read_only = args.no_r10_writes || args.structured || args.template.is_some() || args.profile == Some(Profile::CallDepth);
+        let writable_regs: Vec<u8> = regs.iter().copied().filter(|&r| !(r10_read_only && r == 10)).collect();
+        if writable_regs.is_empty() {
+            eprintln!("No writable registers left after excluding r10");
+            std::process::exit(2);
+        }
+        let default_bias = if args.profile == Some(Profile::Divergence) { DIVERGENCE_BIAS } else { 0.0 };
+        let dependency_bias = args.dependency_bias.unwrap_or(default_bias);
+
+        let mut helpers: Vec<u32> = match args.prog_type {
+            None => helpers::HELPER_IDS.collect(),
+            Some(prog_type) => {
+                let allowed = helpers::allowed(prog_type);
+                if args.foreign_helpers {
+                    helpers::HELPER_IDS.filter(|id| !allowed.contains(id)).collect()
+                } else {
+                    allowed
+                }
+            }
+        };
+        if let Some(runtime) = args.isa_profile {
+            let provided = helpers::provided(runtime);
+            helpers.retain(|id| provided.contains(id));
+            if helpers.is_empty() {
+                eprintln!("{:?} provides none of the selected helpers", runtime);
+                std::process::exit(2);
+            }
+        }
+        // Calls to helpers the VM lacks all fail the same way, so only --hostile-helpers makes them
+        if args.command.as_ref().is_some_and(Command::runs_in_process) {
+            helpers.retain(|id| vm::helpers().binary_search(id).is_ok());
+            if helpers.is_empty() {
+                eprintln!("--vm-helpers registers none of the selected helpers");
+                std::process::exit(2);
+            }
+        }
+
+        let (min_size, max_size) = match args.profile {
+            Some(Profile::JitStress) => (20_000, 60_000),
+            Some(Profile::LongJumps) => (33_000, 70_000),
+            Some(Profile::Malformed) | Some(Profile::Divergence) | Some(Profile::JumpEdges) | Some(Profile::LddwSplit) | Some(Profile::Mbuff) | Some(Profile::CallDepth) | None => (3, 40),
+        };
+        if args.profile == Some(Profile::LongJumps) && args.max_cpu_version < 4 {
+            eprintln!("--profile long-jumps needs --max-cpu-version 4 for gotol");
+            std::process::exit(2);
+        }
+        if args.profile == Some(Profile::CallDepth) && args.max_cpu_version < 3 {
+            eprintln!("--profile call-depth needs --max-cpu-version 3 for local calls");
+            std::process::exit(2);
+        }
+        if args.functions > 0 && args.max_cpu_version < 3 {
+            eprintln!("--functions needs --max-cpu-version 3 for local calls");
+            std::process::exit(2);
+        }
+        if args.loader && (args.format != Format::Elf || args.output == "-") {
+            eprintln!("--loader needs --format elf and --output");
+            std::process::exit(2);
+        }
+        if args.min_bytes.is_some_and(|min| !min.is_multiple_of(8)) || args.max_bytes.is_some_and(|max| !max.is_multiple_of(8)) {
+            eprintln!("--min-bytes and --max-bytes must be multiples of 8");
+            std::process::exit(2);
+        }
+        if let Some((min, max)) = args.min_bytes.zip(args.max_bytes).filter(|(min, max)| min > max) {
+            eprintln!("--min-bytes {} is more than --max-bytes {}", min, max);
+            std::process::exit(2);
+        }
+        let slots = (args.min_bytes.is_some() || args.max_bytes.is_some()).then(|| {
+            let min_bytes = args.min_bytes.unwrap_or(min_size * 8);
+            let max_bytes = args.max_bytes.unwrap_or(max_size * 8).max(min_bytes);
+            min_bytes / 8..=max_bytes / 8
+        });
+
+        let Some(max_version) = Version::from_value(args.max_cpu_version) else {
+            eprintln!("Unsupported CPU version {}", args.max_cpu_version);
+            std::process::exit(2);
+        };
+        let templates: Vec<&Template> = isa::templates()
+            .iter()
+            .filter(|t| t.version.value() <= max_version.value())
+            .collect();
+        let template_weights = args.preset.map_or_else(Vec::new, |preset| preset::weights(preset, &templates));
+        let prone_templates = if args.profile == Some(Profile::Divergence) {
+            templates.iter().copied().filter(|t| divergence_prone(t)).collect()
+        } else {
+            Vec::new()
+        };
+        let gated_templates: Vec<&Template> = if args.version_gating {
+            isa::templates().iter().filter(|t| t.version.value() == max_version.value() + 1).collect()
+        } else {
+            Vec::new()
+        };
+        if args.version_gating && gated_templates.is_empty() {
+            eprintln!("--version-gating needs templates newer than --max-cpu-version {}", args.max_cpu_version);
+            std::process::exit(2);
+        }
+
+        Self {
+            profile: args.profile,
+            min_size: args.min_size.unwrap_or(min_size),
+            max_size: args.max_size.unwrap_or(max_size),
+            templates,
+            gated_templates,
+            prone_templates,
+            template_weights,
+            regs,
+            writable_regs,
+            max_distinct_regs: args.max_distinct_regs.map(usize::from),
+            helpers,
+            reserved_fields: args.reserved_fields,
+            lddw_imm: args.lddw_imm,
+            live_regs: args.live_regs,
+            pad_to: args.pad_to.clone(),
+            pins: args.pin.clone(),
+            mem_offset_window: args.mem_offset_window.clone(),
+            slots,
+            seed: args.seed.unwrap_or_else(rand::random),
+            structured: args.structured,
+            rules: rules::Rules::new(&args.rule).unwrap_or_else(|err| {
+                eprintln!("--rule: {}", err);
+                std::process::exit(2)
+            }),
+            template: args.template.clone(),
+            reachable_only: args.reachable_only,
+            functions: args.functions,
+            obfuscations: args.obfuscate.clone(),
+            pattern_rate: args.pattern_rate,
+            hostile_helpers: args.hostile_helpers,
+            dependency_bias,
+        }
+    }
+
+    fn random_size<R: Rng>(&self, rng: &mut R) -> u32 {
+        rng.random_range(self.min_size..self.max_size)
+    }
+
+    /// The options with registers restricted to `count` of them at random: r0 if writable,
+    /// another writable register otherwise, then any of the others
+    fn with_distinct_regs<R: Rng>(&self, rng: &mut R, count: usize) -> Self {
+        let first = if self.writable_regs.contains(&0) { 0 } else { *self.writable_regs.choose(rng).unwrap() };
+        let mut others: Vec<u8> = self.regs.iter().copied().filter(|&r| r != first).collect();
+        others.shuffle(rng);
+        let mut regs: Vec<u8> = std::iter::once(first).chain(others.into_iter().take(count - 1)).collect();
+        regs.sort_unstable();
+        let writable_regs = self.writable_regs.iter().copied().filter(|r| regs.contains(r)).collect();
+        Self { regs, writable_regs, ..self.clone() }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instruction {
+    opcode: u8,
+    dst: u8,
+    src: u8,
+    offset: u16,
+    imm: u32,
+}
+
+impl Instruction {
+    pub fn new(opcode: u8, dst: u8, src: u8, offset: u16, imm: u32) -> Self {
+        Self { opcode, dst, src, offset, imm }
+    }
+
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0] = self.opcode;
+        bytes[1] = (self.src << 4) | (self.dst & 0xF);
+        bytes[2..4].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.imm.to_le_bytes());
+        bytes
+    }
+
+    /// Big-endian encoding, with dst in the high nibble of the register byte
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0] = self.opcode;
+        bytes[1] = (self.dst << 4) | (self.src & 0xF);
+        bytes[2..4].copy_from_slice(&self.offset.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.imm.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            opcode: bytes[0],
+            dst: bytes[1] & 0xF,
+            src: bytes[1] >> 4,
+            offset: u16::from_le_bytes([bytes[2], bytes[3]]),
+            imm: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+}
+
+/// Decodes a program into instructions, skipping the second slot of LD_DW_IMM
+fn decode_program(bytes: &[u8]) -> Vec<Instruction> {
+    let mut insns = Vec::with_capacity(bytes.len() / 8);
+    let mut i = 0;
+    while i + 8 <= bytes.len() {
+        let insn = Instruction::from_bytes(&bytes[i..i + 8]);
+        i += if insn.opcode == 0x18 { 16 } else { 8 };
+        insns.push(insn);
+    }
+    insns
+}
+
+/// Encodes instructions slot by slot, for tests writing programs out by hand
+#[cfg(test)]
+fn encode_program(insns: &[Instruction]) -> Vec<u8> {
+    insns.iter().flat_map(|insn| insn.to_bytes()).collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Version {
+    V1,
+    V2,
+    V3,
+    V4,
+}
+
+impl Version {
+    fn value(&self) -> u8 {
+        match self {
+            Version::V1 => 1,
+            Version::V2 => 2,
+            Version::V3 => 3,
+            Version::V4 => 4,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(Version::V1),
+            2 => Some(Version::V2),
+            3 => Some(Version::V3),
+            4 => Some(Version::V4),
+            _ => None,
+        }
+    }
+}
+
+/// ISA features gated behind a CPU version newer than v1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Feature {
+    /// jlt/jle/jslt/jsle
+    Jlt,
+    Jmp32,
+    Atomics,
+    /// Calls to functions of the program itself
+    LocalCall,
+    /// Calls to kernel functions by BTF ID
+    Kfunc,
+    Sdiv,
+    Smod,
+    Movsx,
+    Bswap,
+    Gotol,
+}
+
+impl Feature {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Jlt => "jlt",
+            Feature::Jmp32 => "jmp32",
+            Feature::Atomics => "atomics",
+            Feature::LocalCall => "local-call",
+            Feature::Kfunc => "kfunc",
+            Feature::Sdiv => "sdiv",
+            Feature::Smod => "smod",
+            Feature::Movsx => "movsx",
+            Feature::Bswap => "bswap",
+            Feature::Gotol => "gotol",
+        }
+    }
+
+    /// Returns the version-gated feature used by an instruction, if any
+    fn of(insn: &Instruction) -> Option<Self> {
+        match insn.opcode {
+            0x06 => Some(Feature::Gotol),
+            0xa5 | 0xad | 0xb5 | 0xbd | 0xc5 | 0xcd | 0xd5 | 0xdd => Some(Feature::Jlt),
+            op if op & 0x07 == 0x06 => Some(Feature::Jmp32),
+            0xc3 | 0xdb => Some(Feature::Atomics),
+            0x85 if insn.src == 1 => Some(Feature::LocalCall),
+            0x85 if insn.src == 2 => Some(Feature::Kfunc),
+            0x34 | 0x37 | 0x3c | 0x3f if insn.offset == 1 => Some(Feature::Sdiv),
+            0x94 | 0x97 | 0x9c | 0x9f if insn.offset == 1 => Some(Feature::Smod),
+            0xbc | 0xbf if insn.offset != 0 => Some(Feature::Movsx),
+            0xd7 => Some(Feature::Bswap),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the set of version-gated features used by a program
+fn program_features(bytes: &[u8]) -> BTreeSet<Feature> {
+    decode_program(bytes).iter().filter_map(Feature::of).collect()
+}
+
+/// Lowest CPU version whose templates in the loaded spec cover every instruction of a
+/// program, leaving out instructions no template matches
+fn program_min_version(bytes: &[u8]) -> u8 {
+    decode_program(bytes)
+        .iter()
+        .filter_map(|insn| isa::templates().iter().filter(|t| t.matches(insn)).map(|t| t.version.value()).min())
+        .max()
+        .unwrap_or(1)
+}
+
+/// Ways an encoding can fall outside the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Malformation {
+    /// Opcode or opcode/field combination not defined by the spec
+    UndefinedOpcode,
+    /// dst or src register above r10
+    BadRegister,
+    /// LD_DW_IMM in the last slot, missing its second half
+    TruncatedLddw,
+}
+
+impl Malformation {
+    const ALL: [Malformation; 3] = [Malformation::UndefinedOpcode, Malformation::BadRegister, Malformation::TruncatedLddw];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Malformation::UndefinedOpcode => "undefined-opcode",
+            Malformation::BadRegister => "bad-register",
+            Malformation::TruncatedLddw => "truncated-lddw",
+        }
+    }
+}
+
+/// Collects the ways a program's encoding falls outside the spec
+fn program_malformations(bytes: &[u8]) -> BTreeSet<Malformation> {
+    let slots = bytes.len() / 8;
+    let mut found = BTreeSet::new();
+    let mut pc = 0;
+    while pc < slots {
+        let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+        if insn.opcode == 0x18 && pc + 1 == slots {
+            found.insert(Malformation::TruncatedLddw);
+        } else if !matches_template(&insn) {
+            found.insert(Malformation::UndefinedOpcode);
+        }
+        if (uses_dst(insn.opcode) && insn.dst > 10) || (uses_src(insn.opcode) && insn.src > 10) {
+            found.insert(Malformation::BadRegister);
+        }
+        pc += if insn.opcode == 0x18 { 2 } else { 1 };
+    }
+    found
+}
+
+/// A valid encoding of an instruction; `None` fields are free
+pub struct Template {
+    version: Version,
+    opcode: u8,
+    src: Option<u8>,
+    imm: Option<u32>,
+    offset: Option<u16>,
+}
+
+impl Template {
+    pub const fn new(version: Version, opcode: u8, src: Option<u8>, imm: Option<u32>, offset: Option<u16>) -> Self {
+        Self { version, opcode, src, imm, offset }
+    }
+
+    /// Whether the instruction is an encoding of this template
+    fn matches(&self, insn: &Instruction) -> bool {
+        self.opcode == insn.opcode
+            && self.src.is_none_or(|src| src == insn.src)
+            && self.imm.is_none_or(|imm| imm == insn.imm)
+            && self.offset.is_none_or(|offset| offset == insn.offset)
+    }
+}
+
+/// Whether an instruction matches one of the spec templates, ignoring CPU versions
+fn matches_template(insn: &Instruction) -> bool {
+    isa::templates().iter().any(|t| t.matches(insn))
+}
+
+/// Whether the instruction writes its dst register (LD, LDX, ALU and ALU64 classes)
+pub fn writes_dst(opcode: u8) -> bool {
+    matches!(opcode & 0x07, 0x00 | 0x01 | 0x04 | 0x07)
+}
+
+/// Whether the instruction reads or writes its dst register (everything but ja, gotol, call and exit)
+pub fn uses_dst(opcode: u8) -> bool {
+    !matches!(opcode, 0x05 | 0x06 | 0x85 | 0x95)
+}
+
+/// Whether the instruction reads its dst register: stores, conditional jumps and ALU
+/// operations other than moves
+pub fn reads_dst(opcode: u8) -> bool {
+    match opcode & 0x07 {
+        0x00 | 0x01 => false,
+        0x04 | 0x07 => opcode & 0xf0 != 0xb0,
+        _ => uses_dst(opcode),
+    }
+}
+
+/// Whether the src field names a register: loads and stores from registers,
+/// and register-source ALU and jump operations
+pub fn uses_src(opcode: u8) -> bool {
+    match opcode & 0x07 {
+        0x01 | 0x03 => true,
+        0x04 | 0x07 => opcode & 0x08 != 0 && !matches!(opcode & 0xf0, 0x80 | 0xd0),
+        0x05 | 0x06 => opcode & 0x08 != 0 && !matches!(opcode & 0xf0, 0x00 | 0x80 | 0x90),
+        _ => false,
+    }
+}
+
+/// Fields of an instruction the spec says must be zero
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Reserved {
+    dst: bool,
+    src: bool,
+    offset: bool,
+    imm: bool,
+}
+
+pub fn reserved_fields(opcode: u8) -> Reserved {
+    let class = opcode & 0x07;
+    let op = opcode & 0xf0;
+    let x = opcode & 0x08 != 0;
+    Reserved {
+        dst: !uses_dst(opcode),
+        src: !uses_src(opcode) && !needs_src(opcode),
+        offset: match class {
+            0x00 => true,
+            0x04 | 0x07 => !needs_offset(opcode),
+            0x05 | 0x06 => matches!(opcode, 0x06 | 0x85 | 0x95),
+            _ => false,
+        },
+        imm: match class {
+            0x01 => true,
+            0x03 => opcode & 0xe0 == 0x60,
+            0x04 | 0x07 => op == 0x80 || (x && op != 0xd0),
+            0x05 | 0x06 => opcode == 0x05 || opcode == 0x95 || (x && op != 0x80),
+            _ => false,
+        },
+    }
+}
+
+/// Whether the instruction writes its src register (atomics with the FETCH flag)
+pub fn writes_src(opcode: u8, imm: u32) -> bool {
+    (opcode == 0xc3 || opcode == 0xdb) && imm & 0x01 != 0
+}
+
+/// Whether the spec fixes the src field of the opcode
+pub fn needs_src(opcode: u8) -> bool {
+    isa::fixed(opcode).src
+}
+
+/// Whether the spec fixes the imm field of the opcode
+pub fn needs_imm(opcode: u8) -> bool {
+    isa::fixed(opcode).imm
+}
+
+/// Whether the spec fixes the offset field of the opcode
+pub fn needs_offset(opcode: u8) -> bool {
+    isa::fixed(opcode).offset
+}
+
+fn random_register<R: Rng>(rng: &mut R, regs: &[u8]) -> u8 {
+    regs[rng.random_range(0..regs.len())]
+}
+
+fn generate_random_instruction<R: Rng>(rng: &mut R, opts: &GenOptions) -> Instruction {
+    // Pick a random template among those enabled for the CPU version
+    let template = if !opts.prone_templates.is_empty() && rng.random_bool(PRONE_SHARE) {
+        opts.prone_templates[rng.random_range(0..opts.prone_templates.len())]
+    } else if !opts.template_weights.is_empty() {
+        let mut x = rng.random_range(0.0..opts.template_weights.iter().sum::<f64>());
+        let i = opts.template_weights.iter().position(|&w| {
+            x -= w;
+            x < 0.0
+        });
+        opts.templates[i.unwrap_or(opts.templates.len() - 1)]
+    } else {
+        opts.templates[rng.random_range(0..opts.templates.len())]
+    };
+    instantiate(rng, template, opts)
+}
+
+/// Share of instructions --profile divergence draws from divergence-prone templates
+const PRONE_SHARE: f64 = 0.6;
+
+/// --dependency-bias of --profile divergence, so prone operations feed each other
+const DIVERGENCE_BIAS: f64 = 0.75;
+
+/// Whether implementations historically disagree on the template: 32-bit ALU operations
+/// and their zero-extension, sign-extending moves and loads, signed division and modulo,
+/// and byte swaps
+fn divergence_prone(t: &Template) -> bool {
+    let sign_extending_load = t.opcode & 0x07 == 0x01 && t.opcode & 0xe0 == 0x80;
+    let movsx = matches!(t.opcode, 0xbc | 0xbf) && t.offset.is_some_and(|o| o != 0);
+    let signed = matches!(t.opcode & 0xf0, 0x30 | 0x90) && t.offset == Some(1);
+    let swap = matches!(t.opcode, 0xd4 | 0xdc | 0xd7);
+    t.opcode & 0x07 == 0x04 || sign_extending_load || movsx || signed || swap
+}
+
+/// Builds an instruction from `template`, with random values for the fields it leaves free
+fn instantiate<R: Rng>(rng: &mut R, template: &Template, opts: &GenOptions) -> Instruction {
+    let opcode = template.opcode;
+
+    // Generate random values for fields the template leaves free, keeping written registers within the writable set
+    let mut dst = random_register(rng, if writes_dst(opcode) { &opts.writable_regs } else { &opts.regs });
+    let mut src = template.src.unwrap_or_else(|| random_register(rng, &opts.regs));
+    let mut offset = template.offset.unwrap_or_else(|| rng.random::<u16>());
+    let mut imm = template.imm.unwrap_or_else(|| rng.random::<u32>());
+
+    if writes_src(opcode, imm) {
+        src = random_register(rng, &opts.writable_regs);
+    }
+    // Loads and stores access --mem-offset-window
+    let memory = matches!(opcode & 0x07, 0x01..=0x03) && template.offset.is_none();
+    if let Some(window) = opts.mem_offset_window.clone().filter(|_| memory) {
+        offset = rng.random_range(window) as u16;
+    }
+
+    let reserved = reserved_fields(opcode);
+    match opts.reserved_fields {
+        ReservedFields::Random => {}
+        ReservedFields::Zero => {
+            dst = if reserved.dst { 0 } else { dst };
+            src = if reserved.src { 0 } else { src };
+            offset = if reserved.offset { 0 } else { offset };
+            imm = if reserved.imm { 0 } else { imm };
+        }
+        ReservedFields::Nonzero => {
+            dst = if reserved.dst { rng.random_range(1..16) } else { dst };
+            src = if reserved.src { rng.random_range(1..16) } else { src };
+            offset = if reserved.offset { rng.random_range(1..=u16::MAX) } else { offset };
+            imm = if reserved.imm { rng.random_range(1..=u32::MAX) } else { imm };
+        }
+    }
+
+    // Helper calls target known helper IDs, which have stubs when run internally
+    if opcode == 0x85 && src == 0 {
+        imm = opts.helpers[rng.random_range(0..opts.helpers.len())];
+        if opts.hostile_helpers > 0.0 && rng.random_bool(opts.hostile_helpers) {
+            imm = helpers::hostile(rng, &opts.helpers);
+        }
+    }
+
+    Instruction::new(opcode, dst, src, offset, imm)
+}
+
+/// Registers written by this many of the latest instructions are candidates for --dependency-bias
+const RECENT_WRITES: usize = 4;
+
+/// Makes the registers `insn` reads, with probability --dependency-bias each, ones that
+/// recent instructions wrote
+fn chain_registers<R: Rng>(rng: &mut R, insn: &mut Instruction, recent: &VecDeque<u8>, opts: &GenOptions) {
+    if recent.is_empty() {
+        return;
+    }
+    let class = insn.opcode & 0x07;
+    if uses_src(insn.opcode) && rng.random_bool(opts.dependency_bias) {
+        insn.src = recent[rng.random_range(0..recent.len())];
+    }
+    // ALU operations other than moves and conditional jumps read dst
+    let alu = matches!(class, 0x04 | 0x07) && insn.opcode & 0xf0 != 0xb0;
+    let jump = matches!(class, 0x05 | 0x06) && uses_dst(insn.opcode);
+    if (alu || jump) && rng.random_bool(opts.dependency_bias) {
+        insn.dst = recent[rng.random_range(0..recent.len())];
+    }
+}
+
+/// Records the registers `insn` writes as the latest ones
+fn note_writes(insn: &Instruction, recent: &mut VecDeque<u8>) {
+    if writes_dst(insn.opcode) {
+        recent.push_back(insn.dst);
+    }
+    if writes_src(insn.opcode, insn.imm) {
+        recent.push_back(insn.src);
+    }
+    while recent.len() > RECENT_WRITES {
+        recent.pop_front();
+    }
+}
+
+/// How often --live-regs prefer redraws a register an instruction reads that is not defined yet
+const PREFER_DEFINED: f64 = 0.9;
+
+fn redraw_undefined<R: Rng>(rng: &mut R, opts: &GenOptions) -> bool {
+    match opts.live_regs {
+        LiveRegs::Off => false,
+        LiveRegs::Prefer => rng.random_bool(PREFER_DEFINED),
+        LiveRegs::Require => true,
+    }
+}
+
+/// Replaces registers `insn` reads that are not in `live` with ones that are, per --live-regs
+fn use_defined<R: Rng>(rng: &mut R, insn: &mut Instruction, live: &BTreeSet<u8>, opts: &GenOptions) {
+    if uses_src(insn.opcode) && !live.contains(&insn.src) && redraw_undefined(rng, opts) {
+        let defined: Vec<u8> = opts.regs.iter().copied().filter(|r| live.contains(r)).collect();
+        if let Some(&r) = defined.choose(rng) {
+            insn.src = r;
+        }
+    }
+    if reads_dst(insn.opcode) && !live.contains(&insn.dst) && redraw_undefined(rng, opts) {
+        let candidates = if writes_dst(insn.opcode) { &opts.writable_regs } else { &opts.regs };
+        let defined: Vec<u8> = candidates.iter().copied().filter(|r| live.contains(r)).collect();
+        if let Some(&r) = defined.choose(rng) {
+            insn.dst = r;
+        }
+    }
+}
+
+/// Records the registers `insn` defines. Calls define r0 and leave r1-r5 undefined.
+fn note_defined(insn: &Instruction, live: &mut BTreeSet<u8>) {
+    if insn.opcode == 0x85 {
+        live.retain(|r| !(1..=5).contains(r));
+        live.insert(0);
+    }
+    if writes_dst(insn.opcode) {
+        live.insert(if insn.opcode & 0x07 == 0x00 && insn.opcode != 0x18 { 0 } else { insn.dst });
+    }
+    if writes_src(insn.opcode, insn.imm) {
+        live.insert(insn.src);
+    }
+}
+
+/// Registers defined on every path into each node, r1 and r10 at the entry and r1-r5 and r10
+/// at the targets of local calls, or None for nodes no path reaches
+fn defined_on_entry(nodes: &[cfg::Node]) -> Vec<Option<u16>> {
+    let mut defined: Vec<Option<u16>> = vec![None; nodes.len()];
+    let meet = |defined: &mut Vec<Option<u16>>, at: usize, regs: u16| {
+        let old = defined[at];
+        defined[at] = Some(old.map_or(regs, |old| old & regs));
+        defined[at] != old
+    };
+    if !nodes.is_empty() {
+        meet(&mut defined, 0, 1 << 1 | 1 << 10);
+    }
+    for node in nodes.iter().filter(|node| node.insn.opcode == 0x85 && node.insn.src == 1) {
+        if let Some(target) = node.target {
+            meet(&mut defined, target, 0b111110 | 1 << 10);
+        }
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, node) in nodes.iter().enumerate() {
+            let Some(mut regs) = defined[i] else { continue };
+            let mut live = BTreeSet::from_iter((0..16).filter(|r| regs & 1 << r != 0));
+            note_defined(&node.insn, &mut live);
+            regs = live.iter().fold(0, |regs, r| regs | 1 << r);
+
+            let opcode = node.insn.opcode;
+            let branch = matches!(opcode & 0x07, 0x05 | 0x06) && !matches!(opcode & 0xf0, 0x80 | 0x90);
+            if branch {
+                if let Some(target) = node.target {
+                    changed |= meet(&mut defined, target, regs);
+                }
+            }
+            let falls_through = !matches!(opcode, 0x05 | 0x06 | 0x95);
+            if falls_through && i + 1 < nodes.len() {
+                changed |= meet(&mut defined, i + 1, regs);
+            }
+        }
+    }
+    defined
+}
+
+/// Makes every register an instruction reads defined on every path to it, not only in
+/// program order: a register read before any write on some path, such as r1-r5 after a call
+/// or r0 at an exit with no write since the start, gets written right before the read, and
+/// branches to the reading instruction reach the write first. Registers outside the writable
+/// ones are left as they are.
+fn define_on_all_paths<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>, opts: &GenOptions) {
+    let mut nodes = cfg::decode(bytes);
+    let defined = defined_on_entry(&nodes);
+    for i in (0..nodes.len()).rev() {
+        let Some(regs) = defined[i] else { continue };
+        let insn = nodes[i].insn;
+        let mut reads = Vec::new();
+        if uses_src(insn.opcode) {
+            reads.push(insn.src);
+        }
+        if reads_dst(insn.opcode) {
+            reads.push(insn.dst);
+        }
+        if insn.opcode == 0x95 {
+            reads.push(0);
+        }
+        reads.retain(|&r| r < 10 && regs & 1 << r == 0 && opts.writable_regs.contains(&r));
+        reads.dedup();
+        if reads.is_empty() {
+            continue;
+        }
+
+        let writes: Vec<cfg::Node> = reads
+            .iter()
+            .map(|&r| cfg::Node { insn: Instruction::new(0xb7, r, 0, 0, rng.random()), second: None, target: None })
+            .collect();
+        let inserted = writes.len();
+        cfg::splice(&mut nodes, i..i, writes);
+        for node in &mut nodes {
+            if node.target == Some(i + inserted) {
+                node.target = Some(i);
+            }
+        }
+    }
+    *bytes = encode_generated(&nodes);
+}
+
+fn generate_program<R: Rng>(rng: &mut R, size: u32, opts: &GenOptions) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((size * 8) as usize);
+
+    // With --version-gating, one slot takes an instruction from the next CPU version
+    let gated = (!opts.gated_templates.is_empty() && size > 0).then(|| rng.random_range(0..size));
+
+    // Generate random instructions
+    let mut recent = VecDeque::new();
+    // r1 holds the context and r10 the frame pointer on entry
+    let mut live = BTreeSet::from([1, 10]);
+    for i in 0..size {
+        // Checking the rate only when set keeps seeds generating the same programs as before
+        if gated != Some(i) && opts.pattern_rate > 0.0 && rng.random_bool(opts.pattern_rate) {
+            if let Some(code) = patterns::pick(rng, opts) {
+                let mut second = false;
+                for insn in code {
+                    if !second {
+                        note_writes(&insn, &mut recent);
+                        note_defined(&insn, &mut live);
+                    }
+                    second = !second && insn.opcode == 0x18;
+                    bytes.extend_from_slice(&insn.to_bytes());
+                }
+                continue;
+            }
+        }
+        let mut insn = if gated == Some(i) {
+            let template = opts.gated_templates[rng.random_range(0..opts.gated_templates.len())];
+            instantiate(rng, template, opts)
+        } else {
+            generate_random_instruction(rng, opts)
+        };
+        if opts.dependency_bias > 0.0 {
+            chain_registers(rng, &mut insn, &recent, opts);
+            note_writes(&insn, &mut recent);
+        }
+        use_defined(rng, &mut insn, &live, opts);
+        // exit returns r0, so define it first if need be
+        if insn.opcode == 0x95 && !live.contains(&0) && opts.writable_regs.contains(&0) && redraw_undefined(rng, opts) {
+            bytes.extend_from_slice(&Instruction::new(0xb7, 0, 0, 0, rng.random()).to_bytes());
+            live.insert(0);
+        }
+        note_defined(&insn, &mut live);
+        let second = (insn.opcode == 0x18).then(|| lddw_second_slot(rng, &mut insn, opts));
+        bytes.extend_from_slice(&insn.to_bytes());
+        if let Some(second) = second {
+            bytes.extend_from_slice(&second);
+        }
+    }
+
+    // Carry the latest result into r0 so differences in it show
+    if opts.profile == Some(Profile::Divergence) {
+        if let Some(&reg) = recent.back().filter(|_| opts.writable_regs.contains(&0)) {
+            bytes.extend_from_slice(&Instruction::new(0xbf, 0, reg, 0, 0).to_bytes());
+        }
+        bytes.extend_from_slice(&Instruction::new(0x95, 0, 0, 0, 0).to_bytes());
+    }
+
+    if opts.profile == Some(Profile::JitStress) {
+        stretch_jumps(rng, &mut bytes);
+    } else {
+        place_gotols(rng, &mut bytes);
+    }
+    if opts.profile == Some(Profile::JumpEdges) {
+        edge_jumps(rng, &mut bytes);
+    }
+
+    if let Some(pad_to) = &opts.pad_to {
+        let slots = rng.random_range(pad_to.clone()) as usize;
+        bytes = pad_program(rng, &bytes, slots, opts);
+    }
+
+    // The prologue goes in front of everything else, while r1 still points to the buffer
+    if opts.profile == Some(Profile::Mbuff) {
+        bytes.splice(0..0, mbuff_prologue(rng));
+    }
+    if opts.profile == Some(Profile::CallDepth) {
+        bytes = call_chain(rng, &bytes, opts);
+    }
+
+    if opts.profile == Some(Profile::Malformed) {
+        malform(rng, &mut bytes);
+    }
+    if opts.profile == Some(Profile::LddwSplit) {
+        split_lddw(rng, &mut bytes, opts);
+    }
+
+    bytes
+}
+
+/// Generates a program for the selected mode, with the value it returns when that is known.
+/// With --min-bytes or --max-bytes, `size` is ignored and the program fills a random number
+/// of slots in their range.
+fn generate_test<R: Rng>(rng: &mut R, size: u32, opts: &GenOptions) -> (Vec<u8>, Option<u64>) {
+    // The whole program, padding included, keeps to one subset of registers
+    let narrowed;
+    let opts = match opts.max_distinct_regs {
+        Some(count) if count < opts.regs.len() => {
+            narrowed = opts.with_distinct_regs(rng, count);
+            &narrowed
+        }
+        _ => opts,
+    };
+    let Some(range) = &opts.slots else {
+        return generate_counted(rng, size, opts);
+    };
+    let target = rng.random_range(range.clone()) as usize;
+    let mut size = target as u32;
+    loop {
+        let (mut bytes, result) = generate_counted(rng, size, opts);
+        let slots = bytes.len() / 8;
+        if slots > target {
+            // Cutting programs of known result short would change it, so retry them smaller,
+            // down to the smallest there is, which only has to fit the range
+            if result.is_some() {
+                if size > 0 {
+                    size -= (slots - target).min(size as usize) as u32;
+                    continue;
+                }
+                if slots > *range.end() as usize {
+                    eprintln!("--structured programs need at least {} bytes, more than --max-bytes allows", slots * 8);
+                    std::process::exit(2);
+                }
+                return (bytes, result);
+            }
+            // At an instruction start, so no LD_DW_IMM is split, leaving room for the exit the
+            // program ends with, if it does
+            let exit = Instruction::new(0x95, 0, 0, 0, 0).to_bytes();
+            let ends_in_exit = bytes.ends_with(&exit);
+            let room = target - ends_in_exit as usize;
+            let end = instruction_slots(&bytes).into_iter().filter(|&pc| pc <= room).max().unwrap_or(0);
+            bytes.truncate(end * 8);
+            if ends_in_exit {
+                bytes.extend_from_slice(&exit);
+            }
+            drop_dangling_calls(rng, &mut bytes);
+        }
+        return (pad_program(rng, &bytes, target, opts), result);
+    }
+}
+
+/// Turns local calls to functions that are not in the program, e.g. as it was cut short,
+/// into writes of r0
+fn drop_dangling_calls<R: Rng>(rng: &mut R, bytes: &mut [u8]) {
+    let starts = instruction_starts(bytes);
+    for pc in instruction_slots(bytes) {
+        let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+        if insn.opcode != 0x85 || insn.src != 1 {
+            continue;
+        }
+        let target = pc as i64 + 1 + insn.imm as i32 as i64;
+        if !(0..starts.len() as i64).contains(&target) || !starts[target as usize] {
+            bytes[pc * 8..pc * 8 + 8].copy_from_slice(&Instruction::new(0xb7, 0, 0, 0, rng.random()).to_bytes());
+        }
+    }
+}
+
+/// Generates a program of about `size` instructions for the selected mode
+fn generate_counted<R: Rng>(rng: &mut R, size: u32, opts: &GenOptions) -> (Vec<u8>, Option<u64>) {
+    let (mut bytes, result) = if opts.structured {
+        let program = structured::generate(rng, size, opts);
+        (program.to_bytes(), program.result)
+    } else if let Some(template) = &opts.template {
+        (dsl::generate(rng, template, opts), None)
+    } else {
+        let mut bytes = generate_program(rng, size, opts);
+        if opts.functions > 0 {
+            add_functions(rng, &mut bytes, opts);
+        }
+        (bytes, None)
+    };
+    for &pass in &opts.obfuscations {
+        obfuscate::apply(rng, pass, &mut bytes, &opts.rules);
+    }
+    if opts.live_regs == LiveRegs::Require && !opts.structured {
+        define_on_all_paths(rng, &mut bytes, opts);
+    }
+    if opts.reachable_only {
+        let mut nodes = cfg::decode(&bytes);
+        cfg::remove_unreachable(&mut nodes);
+        bytes = encode_generated(&nodes);
+    }
+    if !opts.pins.is_empty() {
+        pin_instructions(rng, &mut bytes, opts);
+        // A pinned instruction may change what the program returns
+        return (bytes, None);
+    }
+    (bytes, result)
+}
+
+/// Replaces the instructions at the indices of `opts.pins` with the pinned ones, keeping
+/// their offsets as given, and appends those past the end of the program
+fn pin_instructions<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>, opts: &GenOptions) {
+    let mut nodes = cfg::decode(bytes);
+    for pin in &opts.pins {
+        let mut insn = Instruction::new(
+            pin.opcode,
+            pin.dst.unwrap_or_else(|| *opts.regs.choose(rng).unwrap()),
+            pin.src.unwrap_or_else(|| *opts.regs.choose(rng).unwrap()),
+            pin.offset.unwrap_or_else(|| rng.random()),
+            pin.imm.unwrap_or_else(|| rng.random()),
+        );
+        let second = (pin.opcode == 0x18).then(|| lddw_second_slot(rng, &mut insn, opts));
+        // Constants --lddw-imm picks span both slots, but a pinned imm stays
+        insn.imm = pin.imm.unwrap_or(insn.imm);
+        let node = cfg::Node { insn, second, target: None };
+        match nodes.get_mut(pin.index) {
+            Some(slot) => *slot = node,
+            None => nodes.push(node),
+        }
+    }
+    *bytes = encode_generated(&nodes);
+}
+
+/// Encodes the nodes a generation pass left, exiting if the program grew too long for a
+/// branch to reach its target
+fn encode_generated(nodes: &[cfg::Node]) -> Vec<u8> {
+    cfg::encode(nodes).unwrap_or_else(|err| {
+        eprintln!("Generated program too long: {}; lower --max-size", err);
+        std::process::exit(1);
+    })
+}
+
+/// Indices into `opts.templates` of the templates the instructions of a program match
+fn templates_in(bytes: &[u8], opts: &GenOptions) -> Vec<usize> {
+    instruction_slots(bytes)
+        .into_iter()
+        .filter_map(|pc| {
+            let insn = Instruction::from_bytes(&bytes[pc * 8..pc * 8 + 8]);
+            opts.templates.iter().position(|t| t.matches(&insn))
+        })
+        .collect()
+}
+
+/// Programs to add from index `first` on until every template not `covered` appears in one:
+/// generated programs with instances of them inserted at random places, each drawn from the
+/// seed of its index as other programs are. Inserting instructions loses the result
+/// structured programs return.
+fn cover_templates(opts: &GenOptions, first: u32, covered: &[bool]) -> Vec<Vec<u8>> {
+    let missing: Vec<usize> = (0..covered.len()).filter(|&t| !covered[t]).collect();
+    let per_program = opts.max_size.max(1) as usize;
+    let mut programs = Vec::new();
+    for (index, chunk) in (first..).zip(missing.chunks(per_program)) {
+        let (_, mut rng) = index_rng(opts, index);
+        let rng = &mut rng;
+        let size = opts.random_size(rng);
+        let (bytes, _) = generate_test(rng, size, opts);
+        let mut nodes = cfg::decode(&bytes);
+        for &t in chunk {
+            let template = opts.templates[t];
+            let mut insn = instantiate(rng, template, opts);
+            // Reserved fields and hostile helpers may have strayed from the template
+            insn.src = template.src.unwrap_or(insn.src);
+            insn.offset = template.offset.unwrap_or(insn.offset);
+            insn.imm = template.imm.unwrap_or(insn.imm);
+            let second = (insn.opcode == 0x18).then(|| lddw_second_slot(rng, &mut insn, opts));
+            insn.imm = template.imm.unwrap_or(insn.imm);
+            let at = rng.random_range(0..=nodes.len());
+            cfg::splice(&mut nodes, at..at, vec![cfg::Node { insn, second, target: None }]);
+        }
+        programs.push(encode_generated(&nodes));
+    }
+    programs
+}
+
+/// Writes the programs `cover_templates` adds after the first `args.count`, returning how
+/// many there are
+fn write_cover_programs(args: &Args, opts: &GenOptions, covered: &[bool], mut manifest: Option<&mut Manifest>) -> u32 {
+    let programs = cover_templates(opts, args.count, covered);
+    for (index, bytes) in (args.count..).zip(&programs) {
+        let path = program_path(args, index, program_seed(opts.seed, index));
+        let written = render_program(args, bytes, None);
+        write_rendered(args, path.as_deref(), &written);
+        if let Some(manifest) = manifest.as_deref_mut() {
+            manifest.add(path.as_deref(), index, None, bytes, &written);
+        }
+    }
+    if !programs.is_empty() {
+        let missing = covered.iter().filter(|&&covered| !covered).count();
+        eprintln!("{} programs added to cover {} templates no other program holds", programs.len(), missing);
+    }
+    programs.len() as u32
+}
+
+/// Appends `opts.functions` generated functions after the program, each ending with an exit,
+/// and calls each one from a random place of the program
+fn add_functions<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>, opts: &GenOptions) {
+    let mut program = Program::from_bytes(bytes);
+    let len = program.nodes_mut().len();
+    let mut functions = Vec::new();
+    for _ in 0..opts.functions {
+        let size = opts.random_size(rng);
+        let mut code = generate_program(rng, size, opts);
+        if !code.ends_with(&Instruction::new(0x95, 0, 0, 0, 0).to_bytes()) {
+            code.extend_from_slice(&Instruction::new(0x95, 0, 0, 0, 0).to_bytes());
+        }
+        functions.push((program.label(), code));
+    }
+    for (calls, (entry, _)) in functions.iter().enumerate() {
+        let at = rng.random_range(0..=len + calls);
+        program.insert(at, Instruction::new(0x85, 0, 1, 0, 0), Some(*entry));
+    }
+    // Functions follow the program
+    for (entry, code) in functions {
+        program.bind(entry).append(Program::from_bytes(&code));
+    }
+    *bytes = program.to_bytes();
+}
+
+/// Breaks the encoding in one or more of the ways listed in `Malformation`
+fn malform<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>) {
+    // A non-empty subset of the malformations, as a bit mask
+    let mask = rng.random_range(1..1 << Malformation::ALL.len());
+    for (i, malformation) in Malformation::ALL.into_iter().enumerate() {
+        if mask & (1 << i) == 0 {
+            continue;
+        }
+        let starts = instruction_slots(bytes);
+        let pc = if starts.is_empty() { 0 } else { starts[rng.random_range(0..starts.len())] };
+
+        match malformation {
+            Malformation::UndefinedOpcode if !starts.is_empty() => {
+                // Any opcode without a template, which includes reserved operations and modes of every class
+                bytes[pc * 8] = loop {
+                    let opcode = rng.random::<u8>();
+                    if !isa::templates().iter().any(|t| t.opcode == opcode) {
+                        break opcode;
+                    }
+                };
+            }
+            Malformation::BadRegister if !starts.is_empty() => {
+                let reg = rng.random_range(11..16);
+                let opcode = bytes[pc * 8];
+                if uses_dst(opcode) {
+                    bytes[pc * 8 + 1] = (bytes[pc * 8 + 1] & 0xf0) | reg;
+                } else if uses_src(opcode) {
+                    bytes[pc * 8 + 1] = (bytes[pc * 8 + 1] & 0x0f) | (reg << 4);
+                } else {
+                    let insn = Instruction::new(0xb7, reg, 0, 0, rng.random());
+                    bytes[pc * 8..pc * 8 + 8].copy_from_slice(&insn.to_bytes());
+                }
+            }
+            Malformation::TruncatedLddw => {
+                let insn = Instruction::new(0x18, rng.random_range(0..11), 0, 0, rng.random());
+                bytes.extend_from_slice(&insn.to_bytes());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Breaks LD_DW_IMM in one or more of three ways: an opcode in the second slot of one, a
+/// jump onto the second slot of one, and one in the last slot without a second slot. Pairs
+/// and jumps are inserted where the program has none to break.
+fn split_lddw<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>, opts: &GenOptions) {
+    let mask = rng.random_range(1..8);
+    let mut nodes = cfg::decode(bytes);
+
+    if mask & 3 != 0 {
+        let pairs: Vec<usize> = (0..nodes.len()).filter(|&i| nodes[i].second.is_some()).collect();
+        let mut pair = match pairs.choose(rng) {
+            Some(&pair) => pair,
+            None => {
+                let at = rng.random_range(0..=nodes.len());
+                let mut insn = Instruction::new(0x18, random_register(rng, &opts.writable_regs), 0, 0, rng.random());
+                let second = Some(lddw_second_slot(rng, &mut insn, opts));
+                cfg::splice(&mut nodes, at..at, vec![cfg::Node { insn, second, target: None }]);
+                at
+            }
+        };
+        if mask & 1 != 0 {
+            nodes[pair].second.as_mut().unwrap()[0] = rng.random_range(1..=u8::MAX);
+        }
+
+        // The jump's offset is patched in once slots are known, as no node starts there
+        let jump = (mask & 2 != 0).then(|| {
+            let at = rng.random_range(0..=nodes.len());
+            let opcode = *[0x05, 0x15, 0x55].choose(rng).unwrap();
+            let insn = Instruction::new(opcode, random_register(rng, &opts.regs), 0, 0, rng.random());
+            cfg::splice(&mut nodes, at..at, vec![cfg::Node { insn, second: None, target: None }]);
+            if at <= pair {
+                pair += 1;
+            }
+            at
+        });
+        *bytes = encode_generated(&nodes);
+        if let Some(jump) = jump {
+            let slot = |node: usize| nodes[..node].iter().map(|n| n.slots()).sum::<usize>() as i64;
+            let offset = slot(pair) + 1 - slot(jump) - 1;
+            let pc = slot(jump) as usize;
+            bytes[pc * 8 + 2..pc * 8 + 4].copy_from_slice(&(offset as i16).to_le_bytes());
+        }
+    }
+
+    if mask & 4 != 0 {
+        let insn = Instruction::new(0x18, random_register(rng, &opts.writable_regs), 0, 0, rng.random());
+        bytes.extend_from_slice(&insn.to_bytes());
+    }
+}
+
+/// 64-bit constants for --lddw-imm interesting
+const LDDW_CONSTANTS: &[u64] = &[
+    0,
+    1,
+    0x7fff_ffff,
+    0x8000_0000,
+    0xffff_ffff,
+    0x1_0000_0000,
+    0xffff_ffff_8000_0000,
+    0xffff_ffff_0000_0000,
+    0x8000_0000_0000_0000,
+    0x7fff_ffff_ffff_ffff,
+    0x8000_0000_8000_0000,
+    u64::MAX,
+];
+
+/// Data for the second slot of LD_DW_IMM following `first`. Only its imm half is defined,
+/// random by default; with --lddw-imm interesting, both halves of the constant are set.
+fn lddw_second_slot<R: Rng>(rng: &mut R, first: &mut Instruction, opts: &GenOptions) -> [u8; 8] {
+    let mut second = rng.random::<[u8; 8]>();
+    if opts.lddw_imm == LddwImm::Interesting {
+        let value = if rng.random_bool(0.5) {
+            LDDW_CONSTANTS[rng.random_range(0..LDDW_CONSTANTS.len())]
+        } else if rng.random_bool(0.5) {
+            rng.random::<i32>() as i64 as u64
+        } else {
+            rng.random::<u32>() as u64
+        };
+        first.imm = value as u32;
+        second[4..8].copy_from_slice(&((value >> 32) as u32).to_le_bytes());
+    }
+    match opts.reserved_fields {
+        ReservedFields::Random => {}
+        ReservedFields::Zero => second[..4].fill(0),
+        ReservedFields::Nonzero => second[0] = second[0].max(1),
+    }
+    second
+}
+
+/// Prepends neutral instructions until the program spans `slots` slots. Relative
+/// jump offsets within the original program stay intact. The padding runs before the
+/// program defines any register, so it only moves r1, which holds the context on entry,
+/// and keeps to instructions the rules allow, falling back to `ja +0`.
+fn pad_program<R: Rng>(rng: &mut R, bytes: &[u8], slots: usize, opts: &GenOptions) -> Vec<u8> {
+    let padding = slots.saturating_sub(bytes.len() / 8);
+    let mut padded = Vec::with_capacity(padding * 8 + bytes.len());
+
+    // Mix encodings so the JIT'd size of the padding can land on any byte count
+    let mut neutral = vec![Instruction::new(0x05, 0, 0, 0, 0)]; // ja +0
+    if opts.writable_regs.contains(&1) {
+        neutral.push(Instruction::new(0xbf, 1, 1, 0, 0)); // r1 = r1
+        neutral.push(Instruction::new(0x07, 1, 0, 0, 0)); // r1 += 0
+    }
+    neutral.retain(|insn| opts.rules.allows(insn));
+    if neutral.is_empty() {
+        neutral.push(Instruction::new(0x05, 0, 0, 0, 0));
+    }
+    for _ in 0..padding {
+        padded.extend_from_slice(&neutral.choose(rng).unwrap().to_bytes());
+    }
+    padded.extend_from_slice(bytes);
+    padded
+}
+
+/// Marks which slots start an instruction, i.e. are not the second slot of a LD_DW_IMM
+fn instruction_starts(bytes: &[u8]) -> Vec<bool> {
+    let slots = bytes.len() / 8;
+    let mut starts = vec![true; slots];
+    let mut pc = 0;
+    while pc < slots {
+        if bytes[pc * 8] == 0x18 && pc + 1 < slots {
+            starts[pc + 1] = false;
+            pc += 1;
+        }
+        pc += 1;
+    }
+    starts
+}
+
+/// Indices of the slots that start an instruction
+fn instruction_slots(bytes: &[u8]) -> Vec<usize> {
+    instruction_starts(bytes).iter().enumerate().filter(|(_, &start)| start).map(|(pc, _)| pc).collect()
+}
+
+/// Points every gotol at an in-bounds instruction, out of reach of a 16-bit offset whenever
+/// the program is big enough to have such targets
+fn place_gotols<R: Rng>(rng: &mut R, bytes: &mut [u8]) {
+    let starts = instruction_slots(bytes);
+    for &pc in &starts {
+        if bytes[pc * 8] != 0x06 {
+            continue;
+        }
+
+        // starts[..below] and starts[above..] are too far for a 16-bit offset
+        let next = pc as i64 + 1;
+        let below = starts.partition_point(|&t| (t as i64) < next + i16::MIN as i64);
+        let above = starts.partition_point(|&t| (t as i64) <= next + i16::MAX as i64);
+        let far = below + starts.len() - above;
+        let target = if far > 0 {
+            let k = rng.random_range(0..far);
+            if k < below { starts[k] } else { starts[above + k - below] }
+        } else {
+            starts[rng.random_range(0..starts.len())]
+        };
+
+        let offset = target as i64 - next;
+        bytes[pc * 8 + 4..pc * 8 + 8].copy_from_slice(&(offset as i32).to_le_bytes());
+    }
+}
+
+/// Code starting a program in mbuff mode: loads of the pointers to the start and end of the
+/// memory area into r2 and r3 out of the metadata buffer r1 points to, then loads to r0 and
+/// stores through them and r1, in bounds or one byte past either end, some behind the
+/// `data + n > data_end` check verifiers expect
+fn mbuff_prologue<R: Rng>(rng: &mut R) -> Vec<u8> {
+    use program::{R1, R2, R3, R4};
+    let len = vm::memory().len() as i64;
+    let mut code = Program::new();
+    code.ldxdw(R2, R1, vm::MBUFF_DATA as i16).ldxdw(R3, R1, vm::MBUFF_DATA_END as i16);
+    for _ in 0..rng.random_range(1..=4u32) {
+        let (bits, size) = *[(0x10, 1), (0x08, 2), (0x00, 4), (0x18, 8)].choose(rng).unwrap();
+        let (base, lo, hi) = match rng.random_range(0..3) {
+            0 => (R2, 0, len - size),
+            1 => (R3, -len, -size),
+            _ => (R1, 0, vm::MBUFF_SIZE as i64 - size),
+        };
+        let off = if lo > hi || rng.random_bool(0.25) {
+            *[lo - 1, hi + 1].choose(rng).unwrap()
+        } else {
+            rng.random_range(lo..=hi)
+        };
+        let off = off.clamp(i16::MIN as i64, i16::MAX as i64);
+        let guarded = base == R2 && rng.random_bool(0.5);
+        let access = if rng.random_bool(0.7) {
+            Instruction::new(0x61 | bits, 0, base, off as u16, 0)
+        } else {
+            Instruction::new(0x62 | bits, base, 0, off as u16, rng.random())
+        };
+        if guarded {
+            let skip = code.label();
+            code.mov64_reg(R4, R2).add64(R4, (off + size) as u32).jgt_reg(R4, R3, skip).push(access).bind(skip);
+        } else {
+            code.push(access);
+        }
+    }
+    code.to_bytes()
+}
+
+/// Frames a call chain may nest, as in Linux and rbpf
+const MAX_CALL_FRAMES: usize = 8;
+
+/// Bytes of stack all frames of a call chain may use together in Linux, and each one in rbpf
+const STACK_BYTES: usize = 512;
+
+/// Granularity of frame sizes: Linux rounds the stack depth of each frame up to 32 bytes
+/// (16 when it JITs) before adding them up
+const FRAME_ALIGN: usize = 32;
+
+/// Bytes below r10 a program reads or writes
+fn stack_depth(bytes: &[u8]) -> usize {
+    decode_program(bytes)
+        .iter()
+        .filter(|insn| match insn.opcode & 0x07 {
+            0x01 => insn.src == 10,
+            0x02 | 0x03 => insn.dst == 10,
+            _ => false,
+        })
+        .map(|insn| (insn.offset as i16).min(0).unsigned_abs() as usize)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Runs `body` at the bottom of a chain of local calls one frame short of, at or one frame
+/// past the call depth limit. Every frame stores to the bottom of the stack it uses, in
+/// steps of `FRAME_ALIGN` and counting the body's own stack in the deepest frame, so all
+/// together use exactly the stack limit or one step more, and every function returns what
+/// its callee did.
+fn call_chain<R: Rng>(rng: &mut R, body: &[u8], opts: &GenOptions) -> Vec<u8> {
+    use program::R10;
+    // Branches of the body that would leave it or never exit go to its exit instead, code
+    // it never reaches goes, local calls of its own, which would change the depth, become
+    // writes of r0, and registers it reads are defined on every path, as r1 and r10 are in
+    // every frame, so programs fail on nothing but the call depth or stack checks
+    let mut body = body.to_vec();
+    body.extend_from_slice(&Instruction::new(0x95, 0, 0, 0, 0).to_bytes());
+    let mut nodes = cfg::decode(&body);
+    for node in nodes.iter_mut().filter(|node| node.insn.opcode == 0x85 && node.insn.src == 1) {
+        *node = cfg::Node { insn: Instruction::new(0xb7, 0, 0, 0, rng.random()), second: None, target: None };
+    }
+    let exit = nodes.len() - 1;
+    cfg::close_paths(&mut nodes, exit, false);
+    cfg::remove_unreachable(&mut nodes);
+    body = encode_generated(&nodes);
+    define_on_all_paths(rng, &mut body, opts);
+
+    let frames = rng.random_range(MAX_CALL_FRAMES - 1..=MAX_CALL_FRAMES + 1);
+    let total = *[STACK_BYTES, STACK_BYTES + FRAME_ALIGN].choose(rng).unwrap();
+    let mut usage = vec![FRAME_ALIGN; frames];
+    usage[frames - 1] = stack_depth(&body).next_multiple_of(FRAME_ALIGN).max(FRAME_ALIGN);
+    // A body reaching deeper than the stack leaves nothing to spread
+    for _ in 0..total.saturating_sub(usage.iter().sum()) / FRAME_ALIGN {
+        usage[rng.random_range(0..frames)] += FRAME_ALIGN;
+    }
+
+    let mut chain = Program::new();
+    for (frame, &bytes) in usage.iter().enumerate() {
+        let stack_bottom = Instruction::new(0x7a, R10, 0, (bytes as i16).wrapping_neg() as u16, rng.random());
+        chain.push(stack_bottom);
+        if frame + 1 < frames {
+            let callee = chain.label();
+            chain.call(callee).exit().bind(callee);
+        } else {
+            chain.append(Program::from_bytes(&body));
+        }
+    }
+    chain.to_bytes()
+}
+
+/// Retargets every jump in an encoded program to an edge: itself, the next instruction, the
+/// last slot, one past the end, or the most negative offset its field holds
+fn edge_jumps<R: Rng>(rng: &mut R, bytes: &mut [u8]) {
+    let slots = bytes.len() as i64 / 8;
+    for pc in instruction_slots(bytes) {
+        let opcode = bytes[pc * 8];
+        let class = opcode & 0x07;
+        if (class != 0x05 && class != 0x06) || matches!(opcode & 0xf0, 0x80 | 0x90) {
+            continue;
+        }
+
+        let gotol = opcode == 0x06;
+        let next = pc as i64 + 1;
+        let offset = match rng.random_range(0..5) {
+            0 => -1,
+            1 => 0,
+            2 => slots - 1 - next,
+            3 => slots - next,
+            _ if gotol => i32::MIN as i64,
+            _ => i16::MIN as i64,
+        };
+
+        if gotol {
+            bytes[pc * 8 + 4..pc * 8 + 8].copy_from_slice(&(offset as i32).to_le_bytes());
+        } else {
+            bytes[pc * 8 + 2..pc * 8 + 4].copy_from_slice(&(offset as i16).to_le_bytes());
+        }
+    }
+}
+
+/// Retargets every jump in an encoded program to a distant, in-bounds instruction,
+/// patching offsets in place so huge programs don't need a second encoding pass
+fn stretch_jumps<R: Rng>(rng: &mut R, bytes: &mut [u8]) {
+    let slots = bytes.len() / 8;
+
+    // Second slots of LD_DW_IMM are not valid jump targets
+    let starts = instruction_starts(bytes);
+
+    for pc in 0..slots {
+        let opcode = bytes[pc * 8];
+        let class = opcode & 0x07;
+        if !starts[pc] || (class != 0x05 && class != 0x06) || matches!(opcode & 0xf0, 0x80 | 0x90) {
+            continue;
+        }
+
+        // gotol has a 32-bit offset, everything else is limited to 16 bits
+        let gotol = opcode == 0x06;
+        let reach = if gotol { slots as i64 } else { i16::MAX as i64 };
+        let forward = (slots as i64 - pc as i64 - 2).min(reach);
+        let backward = (pc as i64 + 1).min(reach);
+
+        // Prefer the far half of whichever direction has room
+        let offset = if forward > 0 && (backward <= 0 || rng.random_bool(0.5)) {
+            rng.random_range(forward / 2..=forward)
+        } else if backward > 0 {
+            -rng.random_range(backward / 2..=backward)
+        } else {
+            0
+        };
+        let mut target = (pc as i64 + 1 + offset) as usize;
+        if !starts[target] {
+            target -= 1;
+        }
+        let offset = target as i64 - pc as i64 - 1;
+
+        if gotol {
+            bytes[pc * 8 + 4..pc * 8 + 8].copy_from_slice(&(offset as i32).to_le_bytes());
+        } else {
+            bytes[pc * 8 + 2..pc * 8 + 4].copy_from_slice(&(offset as i16).to_le_bytes());
+        }
+    }
+}
+
+/// Re-encodes a little-endian program for the target byte order
+fn encode(bytes: &[u8], endian: Endian) -> Vec<u8> {
+    if endian == Endian::Le {
+        return bytes.to_vec();
+    }
+
+    let mut encoded = Vec::with_capacity(bytes.len());
+    let mut second_slot = false;
+    for chunk in bytes.chunks_exact(8) {
+        let mut insn = Instruction::from_bytes(chunk);
+        if !second_slot {
+            // Exchange le/be so the conversion still swaps bytes exactly when it did on little-endian
+            insn.opcode = match insn.opcode {
+                0xd4 => 0xdc,
+                0xdc => 0xd4,
+                op => op,
+            };
+        }
+        second_slot = !second_slot && insn.opcode == 0x18;
+        encoded.extend_from_slice(&insn.to_be_bytes());
+    }
+    encoded
+}
+
+/// Renders a conformance test, declaring `declared_version` as its CPU version if given.
+/// `result` is the expected r0, if known; otherwise the evaluator predicts it where it can.
+fn format_program(bytes: &[u8], endian: Endian, declared_version: Option<u8>, result: Option<u64>) -> String {
+    let mut output = String::new();
+
+    // Metadata goes in comments, which bpf_conformance ignores
+    let names: Vec<&str> = program_features(bytes).iter().map(|f| f.name()).collect();
+    output.push_str(&format!("# min-cpu-version: {}\n", program_min_version(bytes)));
+    if let Some(version) = declared_version {
+        output.push_str(&format!("# declared-cpu-version: {}\n", version));
+    }
+    output.push_str(&format!("# isa-features: {}\n", if names.is_empty() { "none".to_string() } else { names.join(",") }));
+    let malformations: Vec<&str> = program_malformations(bytes).iter().map(|m| m.name()).collect();
+    if !malformations.is_empty() {
+        output.push_str(&format!("# malformations: {}\n", malformations.join(",")));
+    }
+
+    // Since rbpf text format differs a bit from bpf_conformance, also emit the raw bytes
+    output.push_str("-- raw\n");
+    // Print 64 bits per line as a single hex value, in the target's byte order
+    let encoded = encode(bytes, endian);
+    for i in (0..encoded.len()).step_by(8) {
+        let word: [u8; 8] = encoded[i..i+8].try_into().unwrap();
+        let v = match endian {
+            Endian::Le => u64::from_le_bytes(word),
+            Endian::Be => u64::from_be_bytes(word),
+        };
+        output.push_str(&format!("0x{:016x}\n", v));
+    }
+
+    // The memory area r1 points to, as bpf_conformance hands it to the VM
+    let mem = vm::memory();
+    if !mem.is_empty() {
+        let hex: Vec<String> = mem.iter().map(|b| format!("{:02x}", b)).collect();
+        output.push_str(&format!("-- mem\n{}\n", hex.join(" ")));
+    }
+
+    // bpf_conformance expects a result or error
+    match result.map(Expected::Returns).or_else(|| eval::evaluate(bytes)) {
+        Some(Expected::Error(err)) => output.push_str(&format!("-- error\n{}\n", err)),
+        Some(Expected::Returns(r0)) => output.push_str(&format!("-- result\n{:#x}\n", r0)),
+        None => output.push_str("-- result\n0x0\n"),
+    }
+
+    output
+}
+
+fn render_program(args: &Args, bytes: &[u8], result: Option<u64>) -> Vec<u8> {
+    match args.format {
+        Format::Conformance => {
+            let declared_version = args.version_gating.then_some(args.max_cpu_version);
+            format_program(bytes, args.endian, declared_version, result).into_bytes()
+        }
+        // Pseudocode always shows the little-endian view of the program
+        Format::PseudoC => pseudo::render(bytes).into_bytes(),
+        Format::Elf => elf_object(args, bytes),
+        Format::Bin => encode(bytes, args.endian),
+        Format::Hexline => oneline::hex(bytes).into_bytes(),
+        Format::Base64 => oneline::base64(bytes).into_bytes(),
+        Format::CArray => c_array::render(bytes, args.endian, result, false).into_bytes(),
+        Format::InsnArray => c_array::render(bytes, args.endian, result, true).into_bytes(),
+        Format::Proto => proto::encode(bytes, result),
+    }
+}
+
+/// The program as an ELF object, in the section of --prog-type
+fn elf_object(args: &Args, bytes: &[u8]) -> Vec<u8> {
+    let section = args.prog_type.map_or("socket", |t| t.section());
+    let (program, functions, relocs) = elf::relocate(bytes, args.maps as usize);
+    let functions: Vec<Vec<u8>> = functions.iter().map(|f| encode(f, args.endian)).collect();
+    let maps = args.maps as usize;
+    elf::write_object(section, &encode(&program, args.endian), &functions, &relocs, maps, args.endian == Endian::Be)
+}
+
+/// Reads the `-- raw` section of a conformance file back into bytes
+fn parse_program(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut raw = false;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with("--") {
+            raw = line == "-- raw";
+        } else if raw && !line.is_empty() && !line.starts_with('#') {
+            let value = u64::from_str_radix(line.trim_start_matches("0x"), 16).expect("Invalid raw instruction");
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Reads a program in the conformance format, as a line of hex or base64 or as raw
+/// little-endian bytes, from stdin for "-"
+fn read_program(path: &str) -> Vec<u8> {
+    let data = if path == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data).expect("Failed to read program from stdin");
+        data
+    } else {
+        fs::read(path).expect("Failed to read program")
+    };
+
+    decode_input(data).unwrap_or_else(|| panic!("{} is neither a conformance file, a line of hex or base64 nor whole instructions", path))
+}
+
+/// Decodes a program in the conformance format, as a line of hex or base64 or as raw
+/// little-endian bytes, or None if the data is none of them
+fn decode_input(data: Vec<u8>) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(&data).ok().filter(|text| text.lines().any(|line| line.trim() == "-- raw"));
+    let bytes = match text {
+        Some(text) => parse_program(text),
+        None => oneline::decode(&data).filter(|bytes| bytes.len().is_multiple_of(8)).unwrap_or(data),
+    };
+    (!bytes.is_empty() && bytes.len().is_multiple_of(8)).then_some(bytes)
+}
+
+/// Path of program `index` from the output format string, or None for stdout
+fn output_path(args: &Args, index: u32) -> Option<String> {
+    (args.output != "-").then(|| args.output.replace("%d", &index.to_string()))
+}
+
+/// The seed program `index` of a run is generated from: the run's own for the first, so
+/// `--seed <seed> --count 1` regenerates any program, and a SplitMix64 mix of it and the
+/// index for the others
+fn program_seed(seed: u64, index: u32) -> u64 {
+    if index == 0 {
+        return seed;
+    }
+    let mut z = seed.wrapping_add((index as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The seed of its own that `program_seed` derives for program `index` of a run, and an rng
+/// seeded with it
+fn index_rng(opts: &GenOptions, index: u32) -> (u64, StdRng) {
+    let seed = program_seed(opts.seed, index);
+    (seed, StdRng::seed_from_u64(seed))
+}
+
+/// Generates program `index` of a run from the seed of its own that `program_seed` derives,
+/// returning that seed, the program and its result
+fn generate_index(opts: &GenOptions, index: u32) -> (u64, Vec<u8>, Option<u64>) {
+    let (seed, mut rng) = index_rng(opts, index);
+    let size = opts.random_size(&mut rng);
+    let (bytes, result) = generate_test(&mut rng, size, opts);
+    (seed, bytes, result)
+}
+
+/// Programs each thread generates between writes with --jobs
+const BATCH_PER_JOB: u32 = 64;
+
+/// Generates --count programs to --output on --jobs threads, returning which templates they
+/// hold. Each program comes from its own seed, so the corpus is the same whatever the number
+/// of threads, and programs are written in index order.
+fn generate_corpus(args: &Args, opts: &GenOptions, mut manifest: Option<&mut Manifest>) -> Vec<bool> {
+    let mut covered = vec![false; opts.templates.len()];
+    let jobs = args.jobs.max(1);
+    let batch = jobs * BATCH_PER_JOB;
+    let generate = |i: u32| {
+        // Each program has a seed of its own, so it regenerates alone with --count 1
+        let (seed, bytes, result) = generate_index(opts, i);
+        (i, seed, bytes, result)
+    };
+    for start in (0..args.count).step_by(batch as usize) {
+        let end = start.saturating_add(batch).min(args.count);
+        let mut programs: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|job| scope.spawn(move || (start + job..end).step_by(jobs as usize).map(generate).collect::<Vec<_>>()))
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap_or_else(|err| std::panic::resume_unwind(err))).collect()
+        });
+        programs.sort_by_key(|&(i, ..)| i);
+        for (i, seed, bytes, result) in programs {
+            for t in templates_in(&bytes, opts) {
+                covered[t] = true;
+            }
+            let path = program_path(args, i, seed);
+            let written = render_program(args, &bytes, result);
+            write_rendered(args, path.as_deref(), &written);
+            if let Some(manifest) = manifest.as_deref_mut() {
+                manifest.add(path.as_deref(), i, Some(seed), &bytes, &written);
+            }
+        }
+    }
+    covered
+}
+
+/// Path of program `index` of a generation run, generated from `seed`, with %s in the output
+/// format string standing for the seed
+fn program_path(args: &Args, index: u32, seed: u64) -> Option<String> {
+    (args.output != "-").then(|| fill_path(&args.output, index.into(), seed))
+}
+
+/// A path format string with %d standing for `index` and %s for `seed`
+fn fill_path(format: &str, index: u64, seed: u64) -> String {
+    format.replace("%d", &index.to_string()).replace("%s", &seed.to_string())
+}
+
+/// Writes program `index` to stdout or to its path from the output format string
+fn write_output(args: &Args, index: u32, bytes: &[u8], result: Option<u64>) {
+    write_program(args, output_path(args, index).as_deref(), bytes, result);
+}
+
+/// Writes a program to `path`, or to stdout without one
+fn write_program(args: &Args, path: Option<&str>, bytes: &[u8], result: Option<u64>) {
+    write_rendered(args, path, &render_program(args, bytes, result));
+}
+
+/// Writes a program rendered in --format to `path`, or to stdout without one
+fn write_rendered(args: &Args, path: Option<&str>, program: &[u8]) {
+    if let Some(output_path) = path {
+        // Create parent directory if it doesn't exist
+        if let Some(parent) = Path::new(output_path).parent() {
+            fs::create_dir_all(parent).expect("Failed to create output directory");
+        }
+        fs::write(output_path, program).expect("Failed to write program to file");
+        if args.loader {
+            loader::write(output_path, args.prog_type);
+        }
+    } else {
+        std::io::stdout().write_all(program).expect("Failed to write program to stdout");
+    }
+}
+
+/// Sets up the in-process VM as `args` ask: the helpers it registers, how long programs may
+/// run, the memory area and mbuff mode
+fn setup_vm(args: &Args) {
+    vm::register(&args.vm_helpers);
+    vm::set_timeout(Duration::from_millis(args.vm_timeout));
+    // The plugin runs programs on the memory area the conformance runner passes it
+    if !matches!(args.command, Some(Command::Plugin { .. })) {
+        vm::set_memory(args.mem_size as usize);
+    }
+    if args.profile == Some(Profile::Mbuff) {
+        vm::use_mbuff();
+    }
+}
+
+/// Runs the effective command line `config::args` gives, as the binary does
+pub fn run() {
+    let args = Args::parse_from(config::args());
+    // Snapshots regenerate from their recorded arguments, which may name their own ISA spec
+    // and VM setup
+    if let Some(Command::Verify { file }) = &args.command {
+        return snapshot::verify(file);
+    }
+    setup_vm(&args);
+    if let Some(Command::Exec { file }) = &args.command {
+        println!("{}", vm::run(&read_program(file)));
+        return;
+    }
+    if let Some(Command::Plugin { memory }) = &args.command {
+        return plugin::run(memory.as_deref());
+    }
+    isa::load(args.isa_spec.as_deref(), args.isa_profile);
+    let opts = GenOptions::from_args(&args);
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+
+    match &args.command {
+        Some(Command::Crosscheck { disasm_cmd }) => crosscheck::run(&mut rng, &args, &opts, disasm_cmd),
+        Some(Command::Metamorphic { transforms }) => metamorphic::run(&mut rng, &args, &opts, transforms),
+        Some(Command::Mutate { input, ops }) => {
+            let program = read_program(input);
+            if let Err(err) = vm::verify(&program) {
+                panic!("{} does not pass the rbpf verifier: {}", input, err);
+            }
+            for i in 0..args.count {
+                let mut mutant = program.clone();
+                let op = ops[rng.random_range(0..ops.len())];
+                mutate::apply(&mut rng, op, &mut mutant, &opts);
+                write_output(&args, i, &mutant, None);
+            }
+        }
+        Some(Command::Fuzz(fuzz)) => fuzz::run(&mut rng, &args, &opts, fuzz),
+        Some(Command::Cmin { input, signal }) => cmin::run(&args, &opts, input, *signal),
+        Some(Command::Coordinator { listen, corpus, metrics }) => cluster::coordinate(&args, &opts, listen, corpus, metrics.as_deref()),
+        Some(Command::Worker { coordinator, rounds, fuzz }) => cluster::work(&args, &opts, coordinator, *rounds, fuzz),
+        Some(Command::Oracle) => oracle::run(&mut rng, &args, &opts),
+        Some(Command::Verifier) => verifier::run(&mut rng, &args, &opts),
+        Some(Command::Pairwise) => pairwise::run(&mut rng, &args, &opts),
+        Some(Command::Enumerate { size }) => enumerate::run(&args, &opts, *size),
+        Some(Command::Repl) => repl::run(&mut rng, &args, &opts),
+        #[cfg(unix)]
+        Some(Command::Run { target_cmd, rejections, adaptive, forkserver, timeout }) => {
+            let forkserver = forkserver.then(|| Duration::from_millis(*timeout));
+            runner::run(&mut rng, &args, &opts, target_cmd, *rejections, *adaptive, forkserver)
+        }
+        #[cfg(not(unix))]
+        Some(Command::Run { target_cmd, rejections, adaptive }) => runner::run(&mut rng, &args, &opts, target_cmd, *rejections, *adaptive, None),
+        #[cfg(feature = "regressions")]
+        Some(Command::Regress) => regress::run(),
+        #[cfg(feature = "smt")]
+        Some(Command::Smt { constraints }) => smt::run(&mut rng, &args, &opts, constraints),
+        #[cfg(feature = "aya")]
+        Some(Command::Aya { libbpf_cmd, rejections }) => aya_harness::run(&mut rng, &args, &opts, libbpf_cmd, *rejections),
+        Some(Command::Qemu { arches }) => qemu::run(&mut rng, &args, &opts, arches),
+        Some(Command::ImportSelftests { paths, helper_header }) => selftests::run(&args, paths, helper_header.as_deref()),
+        Some(Command::ImportObjects { paths }) => objects::run(&args, paths),
+        Some(Command::ImportQueue { paths }) => queue::run(&args, paths),
+        Some(Command::Report { dir }) => report::run(dir),
+        Some(Command::FromProto { file }) => proto::run(&args, file),
+        #[cfg(feature = "arbitrary")]
+        Some(Command::FromArbitrary { file }) => program::run(&args, file),
+        Some(Command::ExportGrammar { format }) => grammar::run(&opts, *format),
+        Some(Command::UpdateSpec { header }) => isa::update(header),
+        #[cfg(feature = "archive")]
+        Some(Command::ExportCorpus { manifest, archive }) => archive::export(manifest, archive),
+        #[cfg(feature = "archive")]
+        Some(Command::ImportCorpus { archive, dir }) => archive::import(archive, dir),
+        Some(Command::Verify { .. } | Command::Exec { .. } | Command::Plugin { .. }) => unreachable!(),
+        None => {
+            let mut manifest = args.manifest.as_ref().map(|_| Manifest::default());
+            let covered = generate_corpus(&args, &opts, manifest.as_mut());
+            let added = if args.cover_all_templates { write_cover_programs(&args, &opts, &covered, manifest.as_mut()) } else { 0 };
+            if let (Some(path), Some(manifest)) = (&args.manifest, manifest) {
+                manifest.write(path, opts.seed);
+            }
+            if let Some(path) = &args.snapshot {
+                snapshot::record(path, &args, &opts, added);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Index, opcode, dst, src, offset and imm of a pin
+    type PinFields = (usize, u8, Option<u8>, Option<u8>, Option<u16>, Option<u32>);
+
+    #[test]
+    fn pins_parse_index_opcode_and_fields() {
+        let cases: &[(&str, PinFields)] = &[
+            ("3:0xb7", (3, 0xb7, None, None, None, None)),
+            ("0:183,dst=1,src=0x2", (0, 0xb7, Some(1), Some(2), None, None)),
+            ("7:0x05,off=-1", (7, 0x05, None, None, Some(0xffff), None)),
+            ("7:0x05,off=0xffff", (7, 0x05, None, None, Some(0xffff), None)),
+            ("1:0x18,imm=-1", (1, 0x18, None, None, None, Some(u32::MAX))),
+            ("1:0x18,imm=-0x80000000", (1, 0x18, None, None, None, Some(0x8000_0000))),
+            ("4294967295:0x95", (u32::MAX as usize, 0x95, None, None, None, None)),
+            (" 2 : 0x07 , dst = 15 , imm = 8 ", (2, 0x07, Some(15), None, None, Some(8))),
+        ];
+        for (input, expected) in cases {
+            let pin = parse_pin(input).unwrap();
+            assert_eq!((pin.index, pin.opcode, pin.dst, pin.src, pin.offset, pin.imm), *expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn malformed_pins_fail_with_their_reason() {
+        let cases = [
+            ("0xb7", "expected INDEX:OPCODE: 0xb7"),
+            ("1:", "invalid number: "),
+            ("1:zz", "invalid number: zz"),
+            ("x:0xb7", "invalid number: x"),
+            ("-1:0xb7", "index out of range: -1"),
+            ("4294967296:0xb7", "index out of range: 4294967296"),
+            ("1:0x100", "opcode out of range: 0x100"),
+            ("1:0xb7,dst=16", "dst out of range: 16"),
+            ("1:0xb7,src=-1", "src out of range: -1"),
+            ("1:0xb7,off=65536", "off out of range: 65536"),
+            ("1:0xb7,off=-32769", "off out of range: -32769"),
+            ("1:0xb7,imm=4294967296", "imm out of range: 4294967296"),
+            ("1:0xb7,imm=-2147483649", "imm out of range: -2147483649"),
+            ("1:0xb7,dst", "expected FIELD=VALUE: dst"),
+            ("1:0xb7,", "expected FIELD=VALUE: "),
+            ("1:0xb7,reg=1", "unknown field reg (dst, src, off or imm)"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_pin(input).unwrap_err(), expected, "{}", input);
+        }
+    }
+}
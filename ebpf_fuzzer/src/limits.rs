@@ -0,0 +1,43 @@
+//! Resource limits on the targets programs are fed to, so that a pathological program cannot
+//! exhaust the fuzzing host's memory or fill its disk
+
+use crate::Args;
+#[cfg(target_os = "linux")]
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// Applies the limits given in `args` to the processes `command` starts
+#[cfg(target_os = "linux")]
+pub fn apply(command: &mut Command, args: &Args) {
+    let mib = |limit: Option<u64>| limit.map(|limit| limit << 20);
+    let limits: Vec<_> = [(libc::RLIMIT_AS, mib(args.limit_memory)), (libc::RLIMIT_CPU, args.limit_cpu), (libc::RLIMIT_FSIZE, mib(args.limit_file_size))]
+        .into_iter()
+        .filter_map(|(resource, limit)| Some((resource, limit?)))
+        .collect();
+    if limits.is_empty() {
+        return;
+    }
+
+    // SAFETY: setrlimit is async-signal-safe, and nothing is allocated between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for &(resource, limit) in &limits {
+                // A second more lets the target die of SIGXCPU, telling it apart from other kills
+                let max = if resource == libc::RLIMIT_CPU { limit + 1 } else { limit };
+                if libc::setrlimit(resource, &libc::rlimit { rlim_cur: limit, rlim_max: max }) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Refuses the limits given in `args`, which are only set up on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_command: &mut Command, args: &Args) {
+    if args.limit_memory.is_some() || args.limit_cpu.is_some() || args.limit_file_size.is_some() {
+        eprintln!("--limit-memory, --limit-cpu and --limit-file-size are only supported on Linux");
+        std::process::exit(2);
+    }
+}
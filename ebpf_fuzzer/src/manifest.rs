@@ -0,0 +1,78 @@
+//! Manifests of generation runs, for pipelines to read instead of globbing directories: the
+//! effective command line, seed included, and every file written with the seed that
+//! regenerates it, its size, ISA features and a hash of its contents, in TOML
+
+use crate::{program_features, repro};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// A file of the run, as a manifest lists it. Seeds and hashes are strings, as TOML integers
+/// stop at i64::MAX.
+#[derive(Serialize, Deserialize)]
+pub struct Listed {
+    pub path: String,
+    pub index: u32,
+    /// None for programs that depend on the whole run, such as those --cover-all-templates adds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+    pub slots: usize,
+    pub isa_features: Vec<String>,
+    pub fnv1a: String,
+}
+
+/// A manifest as written: the run's seed and arguments, then its files
+#[derive(Serialize, Deserialize)]
+pub struct Listing {
+    pub seed: String,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub file: Vec<Listed>,
+}
+
+impl Listing {
+    /// The manifest as TOML, under a comment naming the version that wrote it
+    pub fn to_toml(&self) -> String {
+        let toml = toml::to_string(self).unwrap_or_else(|err| panic!("Failed to serialize manifest: {}", err));
+        format!("# ebpf_fuzzer {} manifest\n{}", env!("CARGO_PKG_VERSION"), toml)
+    }
+}
+
+#[derive(Default)]
+pub struct Manifest {
+    files: Vec<Listed>,
+}
+
+/// 64-bit FNV-1a of `data`
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl Manifest {
+    /// Records program `index`, generated as `bytes` and written to `path` as `written`
+    pub fn add(&mut self, path: Option<&str>, index: u32, seed: Option<u64>, bytes: &[u8], written: &[u8]) {
+        let Some(path) = path else {
+            eprintln!("--manifest needs --output to name program files");
+            std::process::exit(2);
+        };
+        self.files.push(Listed {
+            path: path.to_string(),
+            index,
+            seed: seed.map(|seed| seed.to_string()),
+            slots: bytes.len() / 8,
+            isa_features: program_features(bytes).iter().map(|f| f.name().to_string()).collect(),
+            fnv1a: format!("{:016x}", fnv1a(written)),
+        });
+    }
+
+    /// Writes the manifest of a run of `seed` to `path`
+    pub fn write(self, path: &str, seed: u64) {
+        let listing = Listing { seed: seed.to_string(), args: repro::args_with_seed(seed), file: self.files };
+        fs::write(path, listing.to_toml()).expect("Failed to write manifest");
+    }
+}
+
+/// Reads a manifest `Manifest::write` wrote
+#[cfg(feature = "archive")]
+pub fn read(text: &str) -> Listing {
+    toml::from_str(text).unwrap_or_else(|err| panic!("Invalid manifest: {}", err))
+}
@@ -0,0 +1,381 @@
+//! Semantics-preserving transforms for metamorphic testing: a program and its transformed
+//! version must have the same outcome on any implementation, so no second VM is needed
+
+use crate::cfg::{self, Node};
+use crate::interp::{self, Outcome as Reference};
+use crate::vm::{self, Outcome};
+use crate::{generate_test, output_path, render_program, repro, uses_dst, uses_src, write_output, Args, GenOptions, Instruction, Transform};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+use std::fs;
+
+// ALU operations whose operands may be swapped or regrouped: add, mul, or, and, xor
+const COMMUTATIVE: [u8; 5] = [0x00, 0x20, 0x40, 0x50, 0xa0];
+
+const MOV: u8 = 0xb0;
+const X: u8 = 0x08;
+
+/// Largest number of transforms stacked on one program
+const MAX_TRANSFORMS: usize = 8;
+
+fn node(insn: Instruction) -> Node {
+    Node { insn, second: None, target: None }
+}
+
+/// Whether the node is a register-source ALU or ALU64 operation
+fn is_alu_reg(node: &Node) -> bool {
+    matches!(node.insn.opcode & 0x07, 0x04 | 0x07) && node.insn.opcode & X != 0 && node.insn.offset == 0
+}
+
+fn is_alu_imm(node: &Node) -> bool {
+    matches!(node.insn.opcode & 0x07, 0x04 | 0x07) && node.insn.opcode & X == 0 && node.insn.offset == 0
+}
+
+/// Whether the instruction sets dst without reading it: loads and moves
+fn overwrites(insn: &Instruction) -> bool {
+    let class = insn.opcode & 0x07;
+    class == 0x01 || insn.opcode == 0x18 || (matches!(class, 0x04 | 0x07) && insn.opcode & 0xf0 == MOV)
+}
+
+/// Whether the instruction reads register `r`
+fn reads(insn: &Instruction, r: u8) -> bool {
+    (uses_src(insn.opcode) && insn.src == r) || (uses_dst(insn.opcode) && insn.dst == r && !overwrites(insn))
+}
+
+/// Whether register `r` is overwritten before it is read after node `i`, looking no further
+/// than the end of its basic block. Values left in r1-r9 at exit are never read.
+fn dead_after(nodes: &[Node], i: usize, r: u8) -> bool {
+    for node in &nodes[i + 1..] {
+        let insn = &node.insn;
+        if reads(insn, r) {
+            return false;
+        }
+        if insn.opcode == 0x95 || (insn.dst == r && overwrites(insn)) {
+            return true;
+        }
+        if !cfg::falls_through(insn) || node.target.is_some() || insn.opcode == 0x85 {
+            return false;
+        }
+    }
+    false
+}
+
+/// A register free to hold intermediate values after node `i`: one the program never
+/// mentions, or one that is dead after it. Helper arguments are left alone if the program
+/// makes calls.
+fn scratch<R: Rng>(rng: &mut R, nodes: &[Node], i: usize, avoid: &[u8], opts: &GenOptions) -> Option<u8> {
+    let calls = nodes.iter().any(|n| n.insn.opcode == 0x85);
+    let mentioned = |r: u8| {
+        nodes.iter().any(|n| (uses_dst(n.insn.opcode) && n.insn.dst == r) || (uses_src(n.insn.opcode) && n.insn.src == r))
+    };
+    let free: Vec<u8> = opts
+        .writable_regs
+        .iter()
+        .copied()
+        .filter(|&r| (1..=9).contains(&r) && !avoid.contains(&r))
+        .filter(|&r| (!mentioned(r) && (!calls || r > 5)) || dead_after(nodes, i, r))
+        .collect();
+    free.choose(rng).copied()
+}
+
+/// Replaces the nodes in `range` with `replacement`. Branches into the start of the range
+/// land on the first replacement node, unlike with `cfg::splice`.
+fn replace(nodes: &mut Vec<Node>, range: std::ops::Range<usize>, mut replacement: Vec<Node>) {
+    let rest = replacement.split_off(1);
+    nodes[range.start] = replacement.remove(0);
+    cfg::splice(nodes, range.start + 1..range.end, rest);
+}
+
+/// `dst op= src` becomes `tmp = src; tmp op= dst; dst = tmp`
+fn commute<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>, opts: &GenOptions) -> bool {
+    let sites: Vec<usize> = (0..nodes.len())
+        .filter(|&i| is_alu_reg(&nodes[i]) && COMMUTATIVE.contains(&(nodes[i].insn.opcode & 0xf0)) && nodes[i].insn.src != nodes[i].insn.dst)
+        .collect();
+    let Some(&i) = sites.choose(rng) else {
+        return false;
+    };
+    let Instruction { opcode, dst, src, .. } = nodes[i].insn;
+    let Some(tmp) = scratch(rng, nodes, i, &[dst, src], opts) else {
+        return false;
+    };
+    let class = opcode & 0x07;
+    // A 32-bit result is zero-extended, so the final 64-bit move keeps it
+    let code = vec![
+        node(Instruction::new(class | X | MOV, tmp, src, 0, 0)),
+        node(Instruction::new(opcode, tmp, dst, 0, 0)),
+        node(Instruction::new(0x07 | X | MOV, dst, tmp, 0, 0)),
+    ];
+    replace(nodes, i..i + 1, code);
+    true
+}
+
+/// Multiplications by powers of two become shifts, and back; `dst *= 2` may also become `dst += dst`
+fn strength<R: Rng>(rng: &mut R, nodes: &mut [Node]) -> bool {
+    let power = |imm: u32| imm.is_power_of_two() && imm < 1 << 31;
+    let sites: Vec<usize> = (0..nodes.len())
+        .filter(|&i| {
+            let insn = &nodes[i].insn;
+            is_alu_imm(&nodes[i])
+                && ((insn.opcode & 0xf0 == 0x20 && power(insn.imm)) || (insn.opcode & 0xf0 == 0x60 && insn.imm < 31))
+        })
+        .collect();
+    let Some(&i) = sites.choose(rng) else {
+        return false;
+    };
+    let insn = &mut nodes[i].insn;
+    let class = insn.opcode & 0x07;
+    if insn.opcode & 0xf0 == 0x60 {
+        *insn = Instruction::new(class | 0x20, insn.dst, 0, 0, 1 << insn.imm);
+    } else if insn.imm == 2 && rng.random_bool(0.5) {
+        *insn = Instruction::new(class | X, insn.dst, insn.dst, 0, 0);
+    } else {
+        *insn = Instruction::new(class | 0x60, insn.dst, 0, 0, insn.imm.trailing_zeros());
+    }
+    true
+}
+
+/// Inserts a 64-bit operation leaving its register unchanged after an instruction that
+/// defines it, or a `ja +0`
+fn neutral<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>) -> bool {
+    let sites: Vec<usize> =
+        (0..nodes.len()).filter(|&i| cfg::falls_through(&nodes[i].insn) && i + 1 < nodes.len()).collect();
+    let Some(&i) = sites.choose(rng) else {
+        return false;
+    };
+    let insn = nodes[i].insn;
+    let defines = (matches!(insn.opcode & 0x07, 0x01 | 0x04 | 0x07) || (insn.opcode == 0x18 && insn.src == 0)) && insn.dst < 10;
+    let inserted = if defines && rng.random_bool(0.8) {
+        let d = insn.dst;
+        let (opcode, src, imm) = *[
+            (0x07, 0, 0),           // add 0
+            (0x17, 0, 0),           // sub 0
+            (0x27, 0, 1),           // mul 1
+            (0x37, 0, 1),           // div 1
+            (0x47, 0, 0),           // or 0
+            (0x57, 0, u32::MAX),    // and -1
+            (0x67, 0, 0),           // lsh 0
+            (0x77, 0, 0),           // rsh 0
+            (0xa7, 0, 0),           // xor 0
+            (0xc7, 0, 0),           // arsh 0
+            (0xbf, d, 0),           // mov dst, dst
+        ]
+        .choose(rng)
+        .unwrap();
+        node(Instruction::new(opcode, d, src, 0, imm))
+    } else {
+        Node::jump(i + 1)
+    };
+    cfg::splice(nodes, i + 1..i + 1, vec![inserted]);
+    true
+}
+
+/// Splits an immediate add, and, or or xor into two whose combination is the same, or
+/// regroups `dst op= a; dst op= b` into `tmp = a; tmp op= b; dst op= tmp`
+fn reassociate<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>, opts: &GenOptions) -> bool {
+    let targeted = |i: usize| nodes.iter().any(|n| n.target == Some(i));
+    let pairs: Vec<usize> = (0..nodes.len().saturating_sub(1))
+        .filter(|&i| {
+            let (a, b) = (&nodes[i].insn, &nodes[i + 1].insn);
+            is_alu_reg(&nodes[i])
+                && a.opcode == b.opcode
+                && COMMUTATIVE.contains(&(a.opcode & 0xf0))
+                && a.dst == b.dst
+                && a.src != a.dst
+                && b.src != b.dst
+                && !targeted(i + 1)
+        })
+        .collect();
+    let splits: Vec<usize> = (0..nodes.len())
+        .filter(|&i| is_alu_imm(&nodes[i]) && matches!(nodes[i].insn.opcode & 0xf0, 0x00 | 0x40 | 0x50 | 0xa0))
+        .collect();
+
+    if let (Some(&i), true) = (pairs.choose(rng), splits.is_empty() || rng.random_bool(0.5)) {
+        let (a, b) = (nodes[i].insn, nodes[i + 1].insn);
+        let Some(tmp) = scratch(rng, nodes, i + 1, &[a.dst, a.src, b.src], opts) else {
+            return false;
+        };
+        let class = a.opcode & 0x07;
+        let code = vec![
+            node(Instruction::new(class | X | MOV, tmp, a.src, 0, 0)),
+            node(Instruction::new(a.opcode, tmp, b.src, 0, 0)),
+            node(Instruction::new(a.opcode, a.dst, tmp, 0, 0)),
+        ];
+        replace(nodes, i..i + 2, code);
+        return true;
+    }
+
+    let Some(&i) = splits.choose(rng) else {
+        return false;
+    };
+    let insn = nodes[i].insn;
+    let c = insn.imm;
+    let r: u32 = rng.random();
+    let (first, second) = match insn.opcode & 0xf0 {
+        // Immediates are sign-extended, so 64-bit sums must not wrap in 32 bits
+        0x00 if insn.opcode & 0x07 == 0x07 => {
+            let c = c as i32 as i64;
+            let a = rng.random_range((c - i32::MAX as i64).max(i32::MIN as i64)..=(c - i32::MIN as i64).min(i32::MAX as i64));
+            (a as u32, (c - a) as u32)
+        }
+        0x00 => (r, c.wrapping_sub(r)),
+        0x40 => (c & r, c & !r),
+        0x50 => (c | r, c | !r),
+        _ => (r, c ^ r),
+    };
+    let code = vec![
+        node(Instruction::new(insn.opcode, insn.dst, 0, 0, first)),
+        node(Instruction::new(insn.opcode, insn.dst, 0, 0, second)),
+    ];
+    replace(nodes, i..i + 1, code);
+    true
+}
+
+/// Applies one transform to a program in place, returning whether it found a place to apply it
+pub fn apply<R: Rng>(rng: &mut R, transform: Transform, bytes: &mut Vec<u8>, opts: &GenOptions) -> bool {
+    let mut nodes = cfg::decode(bytes);
+    let applied = match transform {
+        Transform::Commute => commute(rng, &mut nodes, opts),
+        Transform::Strength => strength(rng, &mut nodes),
+        Transform::Neutral => neutral(rng, &mut nodes),
+        Transform::Reassociate => reassociate(rng, &mut nodes, opts),
+    };
+    applied && cfg::encode_into(&nodes, bytes)
+}
+
+fn agrees(original: &Outcome, transformed: &Outcome) -> bool {
+    match (original, transformed) {
+        (Outcome::Returned(a), Outcome::Returned(b)) => a == b,
+        (Outcome::Error(_), Outcome::Error(_)) => true,
+        // rbpf never finishing the original is the oracle's to find, not a transform's
+        (Outcome::TimedOut, Outcome::TimedOut) => true,
+        _ => false,
+    }
+}
+
+/// Runs every generated program and a transformed version of it through rbpf, and archives
+/// the pairs whose outcomes differ. Programs whose outcome depends on addresses or other
+/// values the spec leaves open, per the reference interpreter, are skipped.
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, transforms: &[Transform]) {
+    let mut compared = 0;
+    let mut findings = 0;
+
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let (original, result) = generate_test(rng, size, opts);
+        if interp::run(&original) == Reference::Unspecified {
+            continue;
+        }
+        let mut transformed = original.clone();
+        let mut applied = Vec::new();
+        for _ in 0..rng.random_range(1..=MAX_TRANSFORMS) {
+            let transform = *transforms.choose(rng).unwrap();
+            if apply(rng, transform, &mut transformed, opts) {
+                applied.push(format!("{:?}", transform).to_lowercase());
+            }
+        }
+        if applied.is_empty() {
+            continue;
+        }
+        compared += 1;
+
+        let before = vm::run(&original);
+        let after = vm::run(&transformed);
+        if agrees(&before, &after) {
+            continue;
+        }
+        println!("program {}: {} before and {} after {}", i, before, after, applied.join(","));
+        findings += 1;
+
+        write_output(args, i, &transformed, result);
+        if let Some(path) = output_path(args, i) {
+            fs::write(format!("{}.original", path), render_program(args, &original, result))
+                .expect("Failed to write original program");
+            repro::write(&path, i.into(), opts.seed, &format!("diff {}.original {}", repro::quote(&path), repro::quote(&path)));
+        }
+    }
+
+    println!("{} findings across {} transformed programs", findings, compared);
+    if findings > 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode_program;
+    use clap::Parser;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const TRANSFORMS: [Transform; 4] = [Transform::Commute, Transform::Strength, Transform::Neutral, Transform::Reassociate];
+
+    /// A program with a site for every transform, branches around some of them included
+    fn program() -> Vec<u8> {
+        encode_program(&[
+            Instruction::new(0xb7, 0, 0, 0, 3),
+            Instruction::new(0xb7, 2, 0, 0, -7i32 as u32),
+            Instruction::new(0xb7, 3, 0, 0, 11),
+            Instruction::new(0x2f, 0, 2, 0, 0),
+            Instruction::new(0x55, 3, 0, 2, 11),
+            Instruction::new(0xaf, 0, 3, 0, 0),
+            Instruction::new(0xaf, 0, 2, 0, 0),
+            Instruction::new(0x27, 0, 0, 0, 8),
+            Instruction::new(0x67, 0, 0, 0, 4),
+            Instruction::new(0x24, 0, 0, 0, 2),
+            Instruction::new(0x07, 0, 0, 0, 0x7fff_ffff),
+            Instruction::new(0x4f, 0, 3, 0, 0),
+            Instruction::new(0x5f, 0, 2, 0, 0),
+            Instruction::new(0x95, 0, 0, 0, 0),
+        ])
+    }
+
+    fn options() -> GenOptions {
+        GenOptions::from_args(&Args::parse_from(["ebpf_fuzzer"]))
+    }
+
+    #[test]
+    fn transforms_keep_the_result() {
+        let opts = options();
+        let original = program();
+        let expected = interp::run(&original);
+        assert!(matches!(expected, Reference::Returned(_)));
+
+        for transform in TRANSFORMS {
+            for seed in 0..64 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut bytes = original.clone();
+                assert!(apply(&mut rng, transform, &mut bytes, &opts), "{:?} found no site", transform);
+                assert_ne!(bytes, original, "{:?} left the program as it was", transform);
+                assert_eq!(interp::run(&bytes), expected, "{:?} with seed {} changed the result", transform, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn stacked_transforms_keep_the_result() {
+        let opts = options();
+        let original = program();
+        let expected = interp::run(&original);
+
+        for seed in 0..64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut bytes = original.clone();
+            for _ in 0..MAX_TRANSFORMS {
+                let transform = *TRANSFORMS.choose(&mut rng).unwrap();
+                apply(&mut rng, transform, &mut bytes, &opts);
+            }
+            assert_eq!(interp::run(&bytes), expected, "seed {} changed the result", seed);
+        }
+    }
+
+    #[test]
+    fn transforms_without_a_site() {
+        let opts = options();
+        let mut bytes = encode_program(&[Instruction::new(0xb7, 0, 0, 0, 1), Instruction::new(0x95, 0, 0, 0, 0)]);
+        let mut rng = StdRng::seed_from_u64(0);
+        for transform in [Transform::Commute, Transform::Strength, Transform::Reassociate] {
+            assert!(!apply(&mut rng, transform, &mut bytes, &opts));
+        }
+    }
+}
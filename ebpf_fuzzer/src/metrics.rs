@@ -0,0 +1,118 @@
+//! Campaign metrics in the Prometheus text format, served over HTTP for dashboards
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes of a scrape's request read at most
+const MAX_REQUEST: u64 = 8 << 10;
+
+/// How long a scrape may take to send its request or read the answer
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and gauges the campaign updates as it goes
+pub struct Metrics {
+    start: Instant,
+    pub execs: AtomicU64,
+    pub queue_depth: AtomicU64,
+    pub corpus_entries: AtomicU64,
+    pub signals: AtomicU64,
+    pub crashes: AtomicU64,
+    pub hangs: AtomicU64,
+    pub divergences: AtomicU64,
+    pub finding_groups: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Metrics> {
+        Arc::new(Metrics {
+            start: Instant::now(),
+            execs: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            corpus_entries: AtomicU64::new(0),
+            signals: AtomicU64::new(0),
+            crashes: AtomicU64::new(0),
+            hangs: AtomicU64::new(0),
+            divergences: AtomicU64::new(0),
+            finding_groups: AtomicU64::new(0),
+        })
+    }
+
+    /// Time since the campaign started
+    #[cfg(feature = "tui")]
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Programs run per second since the start
+    pub fn execs_per_second(&self) -> f64 {
+        self.execs.load(Ordering::Relaxed) as f64 / self.start.elapsed().as_secs_f64()
+    }
+
+    fn render(&self) -> String {
+        let execs = self.execs.load(Ordering::Relaxed);
+        let metrics = [
+            ("execs_total", "counter", "Programs run", execs as f64),
+            ("execs_per_second", "gauge", "Programs run per second since the start", self.execs_per_second()),
+            ("queue_depth", "gauge", "Imported programs waiting to run and corpus entries not mutated yet", self.queue_depth.load(Ordering::Relaxed) as f64),
+            ("corpus_entries", "gauge", "Programs in the corpus", self.corpus_entries.load(Ordering::Relaxed) as f64),
+            ("coverage_signals", "gauge", "Distinct behaviours the corpus reaches", self.signals.load(Ordering::Relaxed) as f64),
+            ("crashes_total", "counter", "Programs rbpf panicked on or the AFL target crashed on", self.crashes.load(Ordering::Relaxed) as f64),
+            ("hangs_total", "counter", "Programs the AFL target timed out on", self.hangs.load(Ordering::Relaxed) as f64),
+            ("divergences_total", "counter", "Programs rbpf and the reference interpreter disagree on", self.divergences.load(Ordering::Relaxed) as f64),
+            ("finding_groups", "gauge", "Distinct kinds of findings", self.finding_groups.load(Ordering::Relaxed) as f64),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP ebpf_fuzzer_{} {}", name, help);
+            let _ = writeln!(text, "# TYPE ebpf_fuzzer_{} {}", name, kind);
+            let _ = writeln!(text, "ebpf_fuzzer_{} {}", name, value);
+        }
+        text
+    }
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(SCRAPE_TIMEOUT))?;
+    stream.set_write_timeout(Some(SCRAPE_TIMEOUT))?;
+    let mut reader = BufReader::new((&stream).take(MAX_REQUEST));
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Clients may wait for the whole request to be read before reading the answer, so the
+    // headers are read up to the blank line ending them, and ignored
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") => ("200 OK", metrics.render()),
+        _ => ("404 Not Found", "Metrics are served at /metrics\n".to_string()),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Serves the metrics at http://`addr`/metrics from a background thread
+pub fn serve(addr: &str, metrics: &Arc<Metrics>) {
+    let listener = TcpListener::bind(addr).expect("Failed to listen for metrics scrapes");
+    println!("serving metrics at http://{}/metrics", addr);
+
+    let served = Arc::clone(metrics);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A broken scrape only affects that scrape
+            let _ = respond(stream, &served);
+        }
+    });
+}
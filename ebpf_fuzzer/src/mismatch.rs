@@ -0,0 +1,42 @@
+//! Mismatches between the crate's model of programs any verifier must refuse and what a real
+//! verifier decides: programs it loads though the model says they are malformed point at
+//! verifier bugs, and programs it refuses though the model finds nothing wrong at gaps in the
+//! model or verifier oddities. Either way they are grouped by reason, and the first program
+//! of each group is a finding.
+
+use crate::cfg;
+use crate::eval;
+use crate::rejections::reason;
+use std::collections::BTreeMap;
+
+#[derive(Default)]
+pub struct Mismatches {
+    groups: BTreeMap<String, u32>,
+}
+
+impl Mismatches {
+    /// Compares the model's verdict on a program with a verifier's, `Err` with its log when
+    /// it refused the program. Returns a description of the mismatch if it is the first of
+    /// its group.
+    pub fn check(&mut self, bytes: &[u8], verdict: Result<(), &str>) -> Option<String> {
+        let group = match (eval::check(bytes, &cfg::decode(bytes)), verdict) {
+            (Err(err), Ok(())) => format!("verifier accepted a program the model rejects: {}", reason(&err).unwrap_or(err)),
+            (Ok(()), Err(log)) => format!("verifier rejected a program the model accepts: {}", reason(log).unwrap_or_else(|| "(no reason in log)".to_string())),
+            _ => return None,
+        };
+        let count = self.groups.entry(group.clone()).or_default();
+        *count += 1;
+        (*count == 1).then_some(group)
+    }
+
+    /// Prints the groups from the most common down
+    pub fn print(&self, title: &str) {
+        let total: u32 = self.groups.values().sum();
+        println!("{} verdict mismatches between the model and {}:", total, title);
+        let mut groups: Vec<(&String, &u32)> = self.groups.iter().collect();
+        groups.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (group, count) in groups {
+            println!("{:>8}  {}", count, group);
+        }
+    }
+}
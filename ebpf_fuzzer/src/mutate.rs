@@ -0,0 +1,162 @@
+//! Mutation operators for existing programs
+
+use crate::{cfg, generate_random_instruction, instruction_slots, lddw_second_slot, GenOptions, MutationOp, Program};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Bit ranges of an encoded slot the verifier inspects, weighted by how much it cares about them
+const BITFLIP_FIELDS: &[(std::ops::Range<u32>, u32)] = &[
+    (0..8, 4),   // opcode
+    (8..16, 3),  // dst and src registers
+    (16..32, 2), // offset, i.e. jump targets and memory offsets
+    (32..64, 1), // imm
+];
+
+/// Byte values that tend to hit boundary conditions
+const INTERESTING_BYTES: &[u8] = &[0x00, 0x01, 0x0a, 0x0b, 0x10, 0x7f, 0x80, 0xff];
+
+/// Largest block of slots havoc clones or overwrites at once
+const MAX_HAVOC_BLOCK: usize = 8;
+
+/// Largest number of instructions deleted or duplicated at once
+const MAX_BLOCK: usize = 4;
+
+/// Applies one mutation operator to a program in place
+pub fn apply<R: Rng>(rng: &mut R, op: MutationOp, bytes: &mut Vec<u8>, opts: &GenOptions) {
+    if bytes.is_empty() {
+        return;
+    }
+    match op {
+        MutationOp::Bitflip => bitflip(rng, bytes),
+        MutationOp::Havoc => havoc(rng, bytes),
+        MutationOp::Insert | MutationOp::Delete | MutationOp::Duplicate | MutationOp::Reorder => {
+            let mut program = Program::from_bytes(bytes);
+            match op {
+                MutationOp::Insert => insert(rng, &mut program, opts),
+                MutationOp::Delete => delete(rng, &mut program),
+                MutationOp::Duplicate => duplicate(rng, &mut program),
+                _ => reorder(rng, &mut program),
+            }
+            // Left as it was if a branch can no longer reach its target
+            if let Ok(encoded) = program.encode() {
+                *bytes = encoded;
+            }
+        }
+    }
+}
+
+/// Picks a block of one to `max_len` whole instructions
+fn random_block<R: Rng>(rng: &mut R, count: usize, max_len: usize) -> std::ops::Range<usize> {
+    let len = rng.random_range(1..=count.min(max_len));
+    let first = rng.random_range(0..=count - len);
+    first..first + len
+}
+
+// The structural edits below go through `Program::splice`, so existing branches keep
+// pointing at the same instructions and a LD_DW_IMM is never split.
+
+fn insert<R: Rng>(rng: &mut R, program: &mut Program, opts: &GenOptions) {
+    let at = rng.random_range(0..=program.len());
+    let mut insn = generate_random_instruction(rng, opts);
+    let second = (insn.opcode == 0x18).then(|| lddw_second_slot(rng, &mut insn, opts));
+    program.splice(at..at, vec![cfg::Node { insn, second, target: None }]);
+}
+
+/// Deletes a window of instructions, always leaving at least one
+fn delete<R: Rng>(rng: &mut R, program: &mut Program) {
+    if program.len() < 2 {
+        return;
+    }
+    let block = random_block(rng, program.len(), (program.len() - 1).min(MAX_BLOCK));
+    program.remove(block);
+}
+
+/// Inserts a copy of a block of instructions right after it
+fn duplicate<R: Rng>(rng: &mut R, program: &mut Program) {
+    let block = random_block(rng, program.len(), MAX_BLOCK);
+    let copy = program.nodes()[block.clone()].to_vec();
+    program.splice(block.end..block.end, copy);
+}
+
+/// Flips one to three bits of an instruction, preferring the fields the verifier checks,
+/// so a program that passes the verifier ends up a bit or two away from valid
+fn bitflip<R: Rng>(rng: &mut R, bytes: &mut [u8]) {
+    let starts = instruction_slots(bytes);
+    let pc = starts[rng.random_range(0..starts.len())];
+    let total: u32 = BITFLIP_FIELDS.iter().map(|(_, weight)| weight).sum();
+
+    let flips = if rng.random_bool(0.75) { 1 } else { rng.random_range(2..=3) };
+    for _ in 0..flips {
+        let mut pick = rng.random_range(0..total);
+        let (bits, _) = BITFLIP_FIELDS
+            .iter()
+            .find(|(_, weight)| {
+                let hit = pick < *weight;
+                pick = pick.saturating_sub(*weight);
+                hit
+            })
+            .expect("Weights cover the whole range");
+        let bit = rng.random_range(bits.clone()) as usize;
+        bytes[pc * 8 + bit / 8] ^= 1 << (bit % 8);
+    }
+}
+
+/// Stacks a handful of structure-blind byte and block mutations. Blocks are whole
+/// slots, so the program stays a sequence of 8-byte instructions.
+fn havoc<R: Rng>(rng: &mut R, bytes: &mut Vec<u8>) {
+    for _ in 0..rng.random_range(1..=8) {
+        let byte = rng.random_range(0..bytes.len());
+        match rng.random_range(0..6) {
+            0 => bytes[byte] ^= 1u8 << rng.random_range(0..8),
+            1 => bytes[byte] ^= 0xff,
+            2 => bytes[byte] = bytes[byte].wrapping_add(rng.random_range(1..=35)),
+            3 => bytes[byte] = bytes[byte].wrapping_sub(rng.random_range(1..=35)),
+            4 => bytes[byte] = INTERESTING_BYTES[rng.random_range(0..INTERESTING_BYTES.len())],
+            _ => {
+                let slots = bytes.len() / 8;
+                let len = rng.random_range(1..=slots.min(MAX_HAVOC_BLOCK));
+                let from = rng.random_range(0..=slots - len) * 8;
+                let block = bytes[from..from + len * 8].to_vec();
+                if rng.random_bool(0.5) {
+                    // Clone the block to a random slot boundary
+                    let to = rng.random_range(0..=slots) * 8;
+                    bytes.splice(to..to, block);
+                } else {
+                    let to = rng.random_range(0..=slots - len) * 8;
+                    bytes[to..to + len * 8].copy_from_slice(&block);
+                }
+            }
+        }
+    }
+}
+
+/// Shuffles every basic block but the entry. Blocks that fell through into their
+/// successor get a `ja` to it, and all branches are retargeted, so semantics are unchanged.
+fn reorder<R: Rng>(rng: &mut R, program: &mut Program) {
+    let nodes = program.nodes();
+    let blocks = cfg::blocks(nodes);
+    if blocks.len() < 3 {
+        return;
+    }
+    let mut order: Vec<usize> = (1..blocks.len()).collect();
+    order.shuffle(rng);
+    order.insert(0, 0);
+
+    // Targets stay in terms of the original node indices until everything is placed
+    let mut reordered = Vec::with_capacity(nodes.len() + blocks.len());
+    let mut moved_to = vec![0; nodes.len()];
+    for (pos, &b) in order.iter().enumerate() {
+        for i in blocks[b].clone() {
+            moved_to[i] = reordered.len();
+            reordered.push(nodes[i].clone());
+        }
+        let next = b + 1;
+        if next < blocks.len() && order.get(pos + 1) != Some(&next) && cfg::falls_through(&nodes[blocks[b].end - 1].insn) {
+            reordered.push(cfg::Node::jump(blocks[next].start));
+        }
+    }
+    for node in &mut reordered {
+        node.target = node.target.map(|t| moved_to[t]);
+    }
+    *program.nodes_mut() = reordered;
+}
@@ -0,0 +1,166 @@
+//! Equivalence-preserving obfuscation passes. They rewrite programs into forms that are
+//! harder for a verifier to track, while the result the program returns stays the same.
+
+use crate::cfg::{self, Node};
+use crate::rules::{self, Rules};
+use crate::{uses_src, Instruction, Obfuscation};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+
+/// Most places each pass rewrites in a program
+const MAX_SITES: usize = 4;
+
+/// Longest chain of jumps a direct jump is routed through
+const MAX_CHAIN: usize = 3;
+
+/// Programs past this many slots keep their jumps, which could no longer reach
+/// trampolines at the end with 16-bit offsets
+const MAX_CHAINED_SLOTS: usize = 30_000;
+
+fn node(insn: Instruction) -> Node {
+    Node { insn, second: None, target: None }
+}
+
+/// An immediate in the range of i32 such that `value - a` is one too
+fn split_sum<R: Rng>(rng: &mut R, value: i64) -> i64 {
+    let (min, max) = (i32::MIN as i64, i32::MAX as i64);
+    rng.random_range((value - max).max(min)..=(value - min).min(max))
+}
+
+/// `dst = imm` becomes `dst = a; dst += b` or `dst = a; dst ^= b`, and `dst = imm64`
+/// becomes a lddw of another constant followed by a xor, as far as the rules allow add and xor
+fn split_constant<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>, rules: &Rules) -> bool {
+    let sites: Vec<usize> = (0..nodes.len())
+        .filter(|&i| matches!(nodes[i].insn.opcode, 0xb4 | 0xb7) || (nodes[i].insn.opcode == 0x18 && nodes[i].insn.src == 0))
+        .filter(|&i| nodes[i].insn.offset == 0 && nodes[i].insn.dst < 10)
+        .collect();
+    let Some(&i) = sites.choose(rng) else {
+        return false;
+    };
+    let Node { insn, second, .. } = nodes[i].clone();
+    let class = insn.opcode & 0x07;
+    let add = rules.allows(&Instruction::new(class, 0, 0, 0, 0));
+    let xor = rules.allows(&Instruction::new(class | 0xa0, 0, 0, 0, 0));
+    let code = if insn.opcode == 0x18 {
+        let (Some(second), true) = (second, rules.allows(&Instruction::new(0xa7, 0, 0, 0, 0))) else {
+            return false;
+        };
+        let high = u32::from_le_bytes(second[4..8].try_into().unwrap());
+        let value = ((high as u64) << 32) | insn.imm as u64;
+        let mask: u32 = rng.random();
+        let loaded = value ^ mask as i32 as u64;
+        let mut second = second;
+        second[4..8].copy_from_slice(&((loaded >> 32) as u32).to_le_bytes());
+        vec![
+            Node { insn: Instruction::new(0x18, insn.dst, 0, 0, loaded as u32), second: Some(second), target: None },
+            node(Instruction::new(0xa7, insn.dst, 0, 0, mask)),
+        ]
+    } else if add && (!xor || rng.random_bool(0.5)) {
+        // Immediates are sign-extended, so 64-bit sums must not wrap in 32 bits
+        let (a, b) = if class == 0x07 {
+            let value = insn.imm as i32 as i64;
+            let a = split_sum(rng, value);
+            (a as u32, (value - a) as u32)
+        } else {
+            let a: u32 = rng.random();
+            (a, insn.imm.wrapping_sub(a))
+        };
+        vec![node(Instruction::new(insn.opcode, insn.dst, 0, 0, a)), node(Instruction::new(class, insn.dst, 0, 0, b))]
+    } else if xor {
+        let a: u32 = rng.random();
+        vec![node(Instruction::new(insn.opcode, insn.dst, 0, 0, a)), node(Instruction::new(class | 0xa0, insn.dst, 0, 0, insn.imm ^ a))]
+    } else {
+        return false;
+    };
+
+    // The first instruction takes the place of the original, so branches to it still land on the sequence
+    let mut code = code.into_iter();
+    nodes[i] = code.next().unwrap();
+    cfg::splice(nodes, i + 1..i + 1, code.collect());
+    true
+}
+
+/// Spills a register to the stack and reloads it right after the instruction defining it,
+/// in a slot below the deepest one the program accesses through r10. Programs that use r10
+/// other than as a load or store base are left alone, as they may access any stack slot,
+/// and so are programs with local calls, whose frames add up against the stack limit. The
+/// slot stays within the stack the rules allow, and stx and ldx must be allowed.
+fn stack_route<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>, rules: &Rules) -> bool {
+    let copies_r10 = nodes.iter().any(|n| uses_src(n.insn.opcode) && n.insn.src == 10 && n.insn.opcode & 0x07 != 0x01);
+    let calls = nodes.iter().any(|n| n.insn.opcode == 0x85 && n.insn.src == 1);
+    let spills = rules.allows(&Instruction::new(0x7b, 0, 0, 0, 0)) && rules.allows(&Instruction::new(0x79, 0, 0, 0, 0));
+    if copies_r10 || calls || !spills {
+        return false;
+    }
+    // The deepest slot the program accesses through r10 directly, in multiples of 8 bytes below it
+    let deepest = nodes
+        .iter()
+        .filter(|n| match n.insn.opcode & 0x07 {
+            0x01 => n.insn.src == 10,
+            0x02 | 0x03 => n.insn.dst == 10,
+            _ => false,
+        })
+        .map(|n| (n.insn.offset as i16 as i64).div_euclid(8))
+        .fold(0, i64::min);
+    let bottom = -(rules.stack_usage.unwrap_or(rules::STACK_SIZE) as i64 / 8);
+    let free: Vec<i64> = (bottom..deepest).collect();
+    let sites: Vec<usize> = (0..nodes.len())
+        .filter(|&i| {
+            let insn = &nodes[i].insn;
+            let defines = matches!(insn.opcode & 0x07, 0x01 | 0x04 | 0x07) || (insn.opcode == 0x18 && insn.src == 0);
+            defines && insn.dst < 10 && i + 1 < nodes.len()
+        })
+        .collect();
+    let (Some(&i), Some(&slot)) = (sites.choose(rng), free.choose(rng)) else {
+        return false;
+    };
+    let offset = (slot * 8) as i16 as u16;
+    let dst = nodes[i].insn.dst;
+    let spill = vec![node(Instruction::new(0x7b, 10, dst, offset, 0)), node(Instruction::new(0x79, dst, 10, offset, 0))];
+    // Branches to the next instruction skip the reload, which changes nothing for them
+    cfg::splice(nodes, i + 1..i + 1, spill);
+    true
+}
+
+/// Routes a jump through a chain of `ja` trampolines appended after the last instruction,
+/// if the rules allow ja
+fn jump_chain<R: Rng>(rng: &mut R, nodes: &mut Vec<Node>, rules: &Rules) -> bool {
+    // Trampolines must not become reachable by falling off the end
+    let closed = nodes.last().is_some_and(|n| !cfg::falls_through(&n.insn));
+    let slots: usize = nodes.iter().map(|n| if n.second.is_some() { 2 } else { 1 }).sum();
+    if !closed || slots > MAX_CHAINED_SLOTS || !rules.allows(&Node::jump(0).insn) {
+        return false;
+    }
+    let sites: Vec<usize> = (0..nodes.len())
+        .filter(|&i| matches!(nodes[i].insn.opcode & 0x07, 0x05 | 0x06) && nodes[i].insn.opcode != 0x85 && nodes[i].target.is_some())
+        .collect();
+    let Some(&i) = sites.choose(rng) else {
+        return false;
+    };
+    let target = nodes[i].target.unwrap();
+    let first = nodes.len();
+    let len = rng.random_range(1..=MAX_CHAIN);
+    for k in 0..len {
+        let next = if k + 1 == len { target } else { first + k + 1 };
+        nodes.push(Node::jump(next));
+    }
+    nodes[i].target = Some(first);
+    true
+}
+
+/// Applies an obfuscation pass to up to `MAX_SITES` places of a program, adding only
+/// instructions and stack the rules allow
+pub fn apply<R: Rng>(rng: &mut R, pass: Obfuscation, bytes: &mut Vec<u8>, rules: &Rules) {
+    let mut nodes = cfg::decode(bytes);
+    for _ in 0..rng.random_range(1..=MAX_SITES) {
+        let applied = match pass {
+            Obfuscation::SplitConstants => split_constant(rng, &mut nodes, rules),
+            Obfuscation::StackRoute => stack_route(rng, &mut nodes, rules),
+            Obfuscation::JumpChains => jump_chain(rng, &mut nodes, rules),
+        };
+        if !applied {
+            break;
+        }
+    }
+    cfg::encode_into(&nodes, bytes);
+}
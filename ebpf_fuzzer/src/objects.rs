@@ -0,0 +1,49 @@
+//! Imports the programs of compiled BPF objects, such as those of Cilium or libbpf-tools, as
+//! seeds
+
+use crate::elf;
+use crate::{write_output, Args};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Object files at `path`, searching directories recursively
+fn objects(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<_> = fs::read_dir(path)
+        .expect("Failed to read object directory")
+        .map(|entry| entry.expect("Failed to read object directory").path())
+        .filter(|path| path.is_dir() || path.extension().is_some_and(|ext| ext == "o"))
+        .flat_map(|path| objects(&path))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes every program in the objects at `paths`, files or directories of them, to the
+/// output. Files that are not BPF objects are skipped and counted by reason.
+pub fn run(args: &Args, paths: &[String]) {
+    let mut imported = 0;
+    let mut skipped: BTreeMap<String, u32> = BTreeMap::new();
+    for path in paths.iter().flat_map(|path| objects(Path::new(path))) {
+        let data = fs::read(&path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+        match elf::read_object(&data) {
+            Ok(programs) => {
+                for (_, bytes) in programs {
+                    write_output(args, imported, &bytes, None);
+                    imported += 1;
+                }
+            }
+            Err(reason) => *skipped.entry(reason).or_default() += 1,
+        }
+    }
+
+    println!("imported {} programs, skipped {} files", imported, skipped.values().sum::<u32>());
+    let mut reasons: Vec<_> = skipped.into_iter().collect();
+    reasons.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (reason, count) in reasons {
+        println!("{:>8}  {}", count, reason);
+    }
+}
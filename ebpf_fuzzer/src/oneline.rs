@@ -0,0 +1,62 @@
+//! Programs as a single line of hex or base64, to paste into chats and issue trackers. Both
+//! hold the little-endian bytes of the instructions.
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut line: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    line.push('\n');
+    line
+}
+
+/// Standard base64 with padding
+pub fn base64(bytes: &[u8]) -> String {
+    let mut line = String::with_capacity(bytes.len().div_ceil(3) * 4 + 1);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            line.push(if i <= chunk.len() { BASE64[(n >> (18 - 6 * i)) as usize & 63] as char } else { '=' });
+        }
+    }
+    line.push('\n');
+    line
+}
+
+/// Bytes of a string of hex digit pairs, or None if it is anything else
+pub fn parse_hex(line: &str) -> Option<Vec<u8>> {
+    if !line.len().is_multiple_of(2) || !line.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..line.len()).step_by(2).map(|i| u8::from_str_radix(&line[i..i + 2], 16).ok()).collect()
+}
+
+fn parse_base64(line: &str) -> Option<Vec<u8>> {
+    if !line.len().is_multiple_of(4) {
+        return None;
+    }
+    let data = line.trim_end_matches('=');
+    if line.len() - data.len() > 2 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in data.bytes() {
+        n = (n << 6) | BASE64.iter().position(|&b| b == c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Decodes a program written by `hex` or `base64`, or None if the data is not a single line
+/// of either
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let line = std::str::from_utf8(data).ok()?.trim();
+    if line.is_empty() || line.contains(char::is_whitespace) {
+        return None;
+    }
+    parse_hex(line).or_else(|| parse_base64(line))
+}
@@ -0,0 +1,127 @@
+//! Cross-checks rbpf against the reference interpreter, and the interpreter against the
+//! results the generator and the evaluator predict
+
+use crate::eval::{self, Expected};
+use crate::findings::{self, Class, Counts};
+use crate::interp::{self, Outcome as Reference};
+use crate::vm::{self, Outcome};
+use crate::{generate_test, repro, Args, GenOptions};
+use rand::Rng;
+
+/// What went wrong in a program, as far as the words of an error message tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorClass {
+    /// Refused before running: malformed encodings, jumps out of the program, writes to r10
+    Rejected,
+    OutOfBounds,
+    DivisionByZero,
+    /// Calls to helpers or functions that do not exist
+    UnknownCall,
+    CallDepth,
+    /// Messages none of the patterns cover
+    Other,
+}
+
+/// Class of an error from either runtime, matching rbpf's wording and the reference's
+fn classify(err: &str) -> ErrorClass {
+    let err = err.to_lowercase();
+    let has = |words: &[&str]| words.iter().any(|w| err.contains(w));
+    if has(&["call depth", "max_call_depth"]) {
+        ErrorClass::CallDepth
+    } else if has(&["[verifier]", "invalid program", "jump out of bounds", "read-only r10", "falls off the end", "lddw source"]) {
+        ErrorClass::Rejected
+    } else if has(&["out of bounds", "without a packet"]) {
+        ErrorClass::OutOfBounds
+    } else if has(&["division by 0", "divide by zero", "division by zero"]) {
+        ErrorClass::DivisionByZero
+    } else if has(&["unknown helper", "unknown function", "unknown address", "callx"]) {
+        ErrorClass::UnknownCall
+    } else {
+        ErrorClass::Other
+    }
+}
+
+/// Whether rbpf's outcome is consistent with the reference one. Errors must be of the same
+/// class, as every runtime words its messages differently; ones that fit no class agree
+/// with any error rather than flag wording.
+fn agrees(reference: &Reference, outcome: &Outcome) -> bool {
+    match (reference, outcome) {
+        (Reference::Returned(r0), Outcome::Returned(actual)) => r0 == actual,
+        (Reference::Error(expected), Outcome::Error(actual)) => {
+            let (expected, actual) = (classify(expected), classify(actual));
+            expected == actual || expected == ErrorClass::Other || actual == ErrorClass::Other
+        }
+        _ => false,
+    }
+}
+
+fn predicted(expected: &Expected, reference: &Reference) -> bool {
+    match (expected, reference) {
+        (Expected::Returns(r0), Reference::Returned(actual)) => r0 == actual,
+        (Expected::Error(_), Reference::Error(_)) => true,
+        _ => false,
+    }
+}
+
+/// Problems with one program: rbpf disagreeing with the reference interpreter, failing where
+/// it returns or for another reason, or panicking, or the interpreter missing the result the
+/// generator or the evaluator predict
+pub fn problems(bytes: &[u8], result: Option<u64>, outcome: &Outcome, reference: &Reference) -> Vec<String> {
+    let expected = result.map(Expected::Returns).or_else(|| eval::evaluate(bytes));
+
+    let mut problems = Vec::new();
+    if let Outcome::Panicked(_) | Outcome::TimedOut = outcome {
+        problems.push(format!("rbpf {}", outcome));
+    } else if *reference != Reference::Unspecified && !agrees(reference, outcome) {
+        problems.push(format!("reference {}, rbpf {}", reference, outcome));
+    }
+    // The crate disagreeing with itself: a bug in the generator, evaluator or interpreter
+    if let Some(expected) = expected.filter(|e| !predicted(e, reference)) {
+        problems.push(format!("expected {}, reference {}", expected, reference));
+    }
+    problems
+}
+
+/// Class of the findings in a program rbpf ran to `outcome`
+pub fn class(outcome: &Outcome) -> Class {
+    match outcome {
+        Outcome::Panicked(_) => Class::Crash,
+        Outcome::TimedOut => Class::Hang,
+        _ => Class::Divergence,
+    }
+}
+
+/// Runs every generated program through rbpf and the reference interpreter, and archives
+/// those where they disagree, where rbpf panics, or where the interpreter misses a
+/// predicted result
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions) {
+    let mut compared = 0;
+    let mut findings = Counts::default();
+
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, opts);
+        let outcome = vm::run(&bytes);
+        let reference = interp::run(&bytes);
+        if !matches!(outcome, Outcome::Panicked(_) | Outcome::TimedOut) && reference != Reference::Unspecified {
+            compared += 1;
+        }
+
+        let problems = problems(&bytes, result, &outcome, &reference);
+        if problems.is_empty() {
+            continue;
+        }
+        let class = class(&outcome);
+        println!("program {}: {}: {}", i, class, problems.join("; "));
+        findings.add(class);
+
+        if let Some(path) = findings::write(args, class, i.into(), Some(opts.seed), &bytes, result) {
+            repro::write(&path, i.into(), opts.seed, &format!("cat {}", repro::quote(&path)));
+        }
+    }
+
+    println!("{} across {} programs, {} with a specified outcome", findings, args.count, compared);
+    if findings.total() > 0 {
+        std::process::exit(1);
+    }
+}
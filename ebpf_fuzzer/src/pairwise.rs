@@ -0,0 +1,64 @@
+//! Corpora in which every ordered pair of enabled opcodes appears adjacently, for JIT bugs
+//! that only show when two particular instructions follow each other
+
+use crate::{instantiate, lddw_second_slot, write_output, Args, GenOptions, Instruction, Template};
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::Rng;
+
+/// A walk through every edge of the complete directed graph on `n` vertices, self-loops
+/// included, so each ordered pair of vertices follows each other exactly once
+fn circuit<R: Rng>(rng: &mut R, n: usize) -> Vec<usize> {
+    let mut unused: Vec<Vec<usize>> = (0..n)
+        .map(|_| {
+            let mut next: Vec<usize> = (0..n).collect();
+            next.shuffle(rng);
+            next
+        })
+        .collect();
+    // Hierholzer's algorithm: every vertex has as many edges in as out
+    let mut stack = vec![rng.random_range(0..n)];
+    let mut walk = Vec::with_capacity(n * n + 1);
+    while let Some(&v) = stack.last() {
+        match unused[v].pop() {
+            Some(w) => stack.push(w),
+            None => walk.push(stack.pop().unwrap()),
+        }
+    }
+    walk.reverse();
+    walk
+}
+
+/// Writes programs following an Eulerian circuit through the enabled opcodes, each a random
+/// size and starting with the opcode the previous one ended with, so pairs across two
+/// programs are covered too. Every program ends with an exit.
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions) {
+    let mut opcodes: Vec<u8> = opts.templates.iter().map(|t| t.opcode).collect();
+    opcodes.sort();
+    opcodes.dedup();
+    let templates: Vec<Vec<&Template>> = opcodes
+        .iter()
+        .map(|&opcode| opts.templates.iter().copied().filter(|t| t.opcode == opcode).collect())
+        .collect();
+
+    let walk = circuit(rng, opcodes.len());
+    let mut start = 0;
+    let mut index = 0;
+    while start + 1 < walk.len() {
+        let end = (start + opts.random_size(rng).max(2) as usize).min(walk.len());
+        let mut bytes = Vec::new();
+        for &op in &walk[start..end] {
+            let template = *templates[op].choose(rng).unwrap();
+            let mut insn = instantiate(rng, template, opts);
+            let second = (insn.opcode == 0x18).then(|| lddw_second_slot(rng, &mut insn, opts));
+            bytes.extend_from_slice(&insn.to_bytes());
+            if let Some(second) = second {
+                bytes.extend_from_slice(&second);
+            }
+        }
+        bytes.extend_from_slice(&Instruction::new(0x95, 0, 0, 0, 0).to_bytes());
+        write_output(args, index, &bytes, None);
+        index += 1;
+        start = end - 1;
+    }
+    eprintln!("{} ordered pairs of {} opcodes in {} programs", opcodes.len() * opcodes.len(), opcodes.len(), index);
+}
@@ -0,0 +1,96 @@
+//! Known-tricky instruction sequences, spliced into otherwise random programs: the corner
+//! cases implementations most often get wrong, reached far more often than by chance
+
+use crate::{GenOptions, Instruction};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+
+/// Attempts at finding a pattern the enabled templates allow before giving up
+const ATTEMPTS: usize = 8;
+
+fn lddw(dst: u8, value: u64) -> [Instruction; 2] {
+    [Instruction::new(0x18, dst, 0, 0, value as u32), Instruction::new(0, 0, 0, 0, (value >> 32) as u32)]
+}
+
+/// One tricky sequence computing into `dst`, using `src` as a second operand
+fn pattern<R: Rng>(rng: &mut R, dst: u8, src: u8) -> Vec<Instruction> {
+    let wide = rng.random_bool(0.5);
+    let (alu, width) = if wide { (0x07, 64) } else { (0x04, 32) };
+    match rng.random_range(0..5) {
+        // Shift counts at or past the register width, which implementations must mask
+        0 => {
+            let shift = *[0x60, 0x70, 0xc0].choose(rng).unwrap();
+            let count = rng.random_range(width..=255);
+            let mut code = lddw(dst, rng.random()).to_vec();
+            code.push(Instruction::new(0xb7, src, 0, 0, count));
+            code.push(Instruction::new(alu | 0x08 | shift, dst, src, 0, 0));
+            code
+        }
+        // INT_MIN / -1 and INT_MIN % -1, signed or not, which overflow in C
+        1 => {
+            let op = *[0x30, 0x90].choose(rng).unwrap();
+            let signed = rng.random_bool(0.5) as u16;
+            let mut code = if wide { lddw(dst, 1 << 63).to_vec() } else { vec![Instruction::new(0xb4, dst, 0, 0, 1 << 31)] };
+            if rng.random_bool(0.5) {
+                code.push(Instruction::new(alu | op, dst, 0, signed, u32::MAX));
+            } else {
+                code.push(Instruction::new(alu | 0xb0, src, 0, 0, u32::MAX));
+                code.push(Instruction::new(alu | 0x08 | op, dst, src, signed, 0));
+            }
+            code
+        }
+        // A chain of 32-bit operations that overflow, then a 64-bit one exposing whether
+        // the result was zero-extended
+        2 => {
+            let mut code = vec![Instruction::new(0xb4, dst, 0, 0, *[0x7fff_ffff, 0xffff_ffff, 0x8000_0000].choose(rng).unwrap())];
+            for _ in 0..rng.random_range(1..=4) {
+                let (op, imm) = *[(0x04, 1), (0x04, 0x7fff_ffff), (0x24, 2), (0x24, 0xffff_ffff), (0x14, 0x8000_0000)].choose(rng).unwrap();
+                code.push(Instruction::new(op, dst, 0, 0, imm));
+            }
+            let widen = [Instruction::new(0x0f, dst, dst, 0, 0), Instruction::new(0x2f, dst, dst, 0, 0), Instruction::new(0x77, dst, 0, 0, 32)];
+            code.push(*widen.choose(rng).unwrap());
+            code
+        }
+        // Negating the minimum value, which is its own negation
+        3 => {
+            if wide {
+                let mut code = lddw(dst, 1 << 63).to_vec();
+                code.push(Instruction::new(0x87, dst, 0, 0, 0));
+                code
+            } else {
+                vec![Instruction::new(0xb4, dst, 0, 0, 1 << 31), Instruction::new(0x84, dst, 0, 0, 0)]
+            }
+        }
+        // Back-to-back byte swaps of mixed widths and directions
+        _ => {
+            let mut code = lddw(dst, rng.random()).to_vec();
+            for _ in 0..rng.random_range(2..=3) {
+                let opcode = *[0xd4, 0xdc, 0xd7].choose(rng).unwrap();
+                code.push(Instruction::new(opcode, dst, 0, 0, *[16, 32, 64].choose(rng).unwrap()));
+            }
+            code
+        }
+    }
+}
+
+/// A tricky sequence on two distinct registers drawn from the writable ones, if there are
+/// two, using only enabled templates; None if none could be found
+pub fn pick<R: Rng>(rng: &mut R, opts: &GenOptions) -> Option<Vec<Instruction>> {
+    let regs: Vec<u8> = opts.writable_regs.iter().copied().filter(|&r| r < 10).collect();
+    for _ in 0..ATTEMPTS {
+        let pair: Vec<u8> = regs.choose_multiple(rng, 2).copied().collect();
+        let (dst, src) = (*pair.first()?, *pair.last()?);
+        let code = pattern(rng, dst, src);
+        // Second slots of lddw are not instructions of their own
+        let mut second = false;
+        let allowed = code.iter().all(|insn| {
+            let ok = second || opts.templates.iter().any(|t| t.matches(insn));
+            second = !second && insn.opcode == 0x18;
+            ok
+        });
+        if allowed {
+            return Some(code);
+        }
+    }
+    None
+}
@@ -0,0 +1,29 @@
+//! bpf_conformance plugin protocol, so the binary writing tests can also run them through
+//! rbpf: the runner writes the program to stdin and passes the memory area as an argument,
+//! both in hex, and reads r0 back in hex from stdout
+
+use crate::{oneline, vm};
+use std::io::Read;
+
+/// Bytes of hex digits, ignoring whitespace
+fn parse(text: &str, what: &str) -> Vec<u8> {
+    let digits: String = text.split_whitespace().collect();
+    oneline::parse_hex(&digits).unwrap_or_else(|| panic!("Invalid hex {} from the runner", what))
+}
+
+/// Runs the program on stdin with `memory` and prints r0, or the error to stderr with a
+/// failing exit status, which the runner takes as an error result
+pub fn run(memory: Option<&str>) {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).expect("Failed to read program from stdin");
+    let program = parse(&input, "program");
+    vm::load_memory(parse(memory.unwrap_or_default(), "memory"));
+
+    match vm::run(&program) {
+        vm::Outcome::Returned(r0) => println!("{:x}", r0),
+        outcome => {
+            eprintln!("{}", outcome);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Generation presets: bundles of options and template weights for common campaigns, which
+//! keep programs within r0-r10, reserved fields zero and registers defined before reads, so
+//! few of them are rejected for reasons unrelated to what the preset targets
+
+use crate::{Preset, Template};
+
+/// How much more often a preset draws the templates it favors
+const FAVORED_WEIGHT: f64 = 8.0;
+
+/// Options every preset sets
+const COMMON: &[&str] = &["--max-reg=10", "--no-r10-writes", "--reserved-fields=zero", "--live-regs=require"];
+
+/// Options a preset sets, as command-line words. Options given on the command line, in the
+/// environment or in a config file win over these.
+pub fn options(preset: Preset) -> Vec<&'static str> {
+    let specific: &[&str] = match preset {
+        Preset::AluHeavy => &["--min-size=20", "--max-size=80", "--dependency-bias=0.5"],
+        Preset::MemHeavy => &["--min-size=10", "--max-size=50", "--mem-size=64", "--mem-offset-window=-64..56"],
+        Preset::BranchHeavy => &["--min-size=20", "--max-size=80", "--reachable-only"],
+        Preset::Atomics => &["--min-size=10", "--max-size=40", "--mem-size=64", "--mem-offset-window=-64..56"],
+        Preset::Calls => &["--min-size=10", "--max-size=40", "--functions=2"],
+    };
+    COMMON.iter().chain(specific).copied().collect()
+}
+
+/// Whether a preset favors a template
+fn favors(preset: Preset, t: &Template) -> bool {
+    let class = t.opcode & 0x07;
+    let atomic = class == 0x03 && t.opcode & 0xe0 == 0xc0;
+    match preset {
+        Preset::AluHeavy => matches!(class, 0x04 | 0x07),
+        Preset::MemHeavy => matches!(class, 0x01..=0x03) && !atomic,
+        Preset::BranchHeavy => matches!(class, 0x05 | 0x06) && !matches!(t.opcode, 0x85 | 0x95),
+        Preset::Atomics => atomic,
+        Preset::Calls => t.opcode == 0x85,
+    }
+}
+
+/// Relative weights of `templates` under a preset
+pub fn weights(preset: Preset, templates: &[&Template]) -> Vec<f64> {
+    templates.iter().map(|t| if favors(preset, t) { FAVORED_WEIGHT } else { 1.0 }).collect()
+}
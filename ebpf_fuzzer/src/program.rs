@@ -0,0 +1,611 @@
+//! Programs as decoded instructions with labels and metadata, rather than bytes, written
+//! through a fluent builder: `prog.mov64(R1, 5).jgt_reg(R1, R2, done)`. Jumps and calls of
+//! local functions target labels, bound anywhere before the program is encoded, which
+//! computes their offsets and fails on those their field cannot hold. The structured,
+//! call-chain and --functions generators build with it, and the structural mutation
+//! operators and bundle minimization edit decoded programs through `splice`.
+//!
+//! With the `arbitrary` feature, programs are also built from fuzzer input, for cargo-fuzz
+//! targets taking structured programs: each instruction is an encoding of a template of
+//! the ISA spec, so libFuzzer's mutations and minimization act on whole instructions.
+
+use crate::cfg::{self, Node};
+use crate::Instruction;
+use std::ops::Range;
+#[cfg(feature = "arbitrary")]
+use crate::{isa, reserved_fields, vm, write_output, Args, Template};
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Result, Unstructured};
+#[cfg(feature = "arbitrary")]
+use std::fs;
+
+pub const R0: u8 = 0;
+pub const R1: u8 = 1;
+pub const R2: u8 = 2;
+pub const R3: u8 = 3;
+pub const R4: u8 = 4;
+pub const R10: u8 = 10;
+
+// ALU operations, the upper four bits of the opcode
+pub const ADD: u8 = 0x00;
+pub const SUB: u8 = 0x10;
+pub const MUL: u8 = 0x20;
+pub const DIV: u8 = 0x30;
+pub const OR: u8 = 0x40;
+pub const AND: u8 = 0x50;
+pub const LSH: u8 = 0x60;
+pub const RSH: u8 = 0x70;
+pub const NEG: u8 = 0x80;
+pub const MOD: u8 = 0x90;
+pub const XOR: u8 = 0xa0;
+pub const MOV: u8 = 0xb0;
+pub const ARSH: u8 = 0xc0;
+
+// Conditional jumps, the upper four bits of the opcode
+pub const JEQ: u8 = 0x10;
+pub const JGT: u8 = 0x20;
+pub const JGE: u8 = 0x30;
+pub const JSET: u8 = 0x40;
+pub const JNE: u8 = 0x50;
+pub const JSGT: u8 = 0x60;
+pub const JSGE: u8 = 0x70;
+pub const JLT: u8 = 0xa0;
+pub const JLE: u8 = 0xb0;
+pub const JSLT: u8 = 0xc0;
+pub const JSLE: u8 = 0xd0;
+
+// Access sizes of loads and stores
+pub const WORD: u8 = 0x00;
+pub const HALF: u8 = 0x08;
+pub const BYTE: u8 = 0x10;
+pub const DWORD: u8 = 0x18;
+
+/// Whether `op` is an ALU operation taking an operand, or NEG
+fn is_alu(op: u8) -> bool {
+    op & 0x0f == 0 && op <= ARSH
+}
+
+/// Whether `op` is a conditional jump
+fn is_condition(op: u8) -> bool {
+    op & 0x0f == 0 && op != 0 && !matches!(op, 0x80 | 0x90) && op <= JSLE
+}
+
+/// A position in a program for jumps to target, bound to an instruction by `bind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// A program under construction or decoded from bytes
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    nodes: Vec<Node>,
+    /// The node each label is bound to, if it is yet
+    labels: Vec<Option<usize>>,
+    /// Jumps to labels, by node, resolved on encoding
+    jumps: Vec<(usize, Label)>,
+    /// What r0 holds at exit, when known by construction
+    pub result: Option<u64>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a program, with branches that land on an instruction kept as its node
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self { nodes: cfg::decode(bytes), ..Self::default() }
+    }
+
+    /// The instructions, with jumps to labels resolved: the resolution pass of the assembler.
+    /// Fails on jumps to unbound labels and on offsets their field cannot hold.
+    fn resolved(&self) -> Result<Vec<Node>, String> {
+        let mut nodes = self.nodes.clone();
+        let mut starts = Vec::with_capacity(nodes.len() + 1);
+        let mut pc = 0i64;
+        for node in &nodes {
+            starts.push(pc);
+            pc += node.slots() as i64;
+        }
+        starts.push(pc);
+
+        for &(node, Label(label)) in &self.jumps {
+            let target = self.labels[label].ok_or_else(|| format!("Jump at node {} to unbound label {}", node, label))?;
+            let relative = starts[target] - starts[node] - 1;
+            // gotol and calls of local functions keep their offset in imm
+            let len = nodes.len();
+            let insn = &mut nodes[node].insn;
+            let wide = insn.opcode == 0x06 || (insn.opcode == 0x85 && insn.src == 1);
+            let fits = if wide { i32::try_from(relative).is_ok() } else { i16::try_from(relative).is_ok() };
+            if !fits {
+                return Err(format!("Jump at node {} to label {} is {} slots away, beyond its {}-bit offset", node, label, relative, if wide { 32 } else { 16 }));
+            }
+            if target < len {
+                nodes[node].target = Some(target);
+            } else if wide {
+                // Past the last instruction, as in code to splice in front of more
+                insn.imm = relative as u32;
+            } else {
+                insn.offset = relative as u16;
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// The instructions, for edits through `cfg` that keep branches on their targets. Jumps
+    /// to labels are resolved first, so every label must be bound.
+    pub fn nodes_mut(&mut self) -> &mut Vec<Node> {
+        self.nodes = self.resolved().unwrap_or_else(|err| panic!("{}", err));
+        self.jumps.clear();
+        &mut self.nodes
+    }
+
+    /// The encoded instructions, little-endian, with the offset of every jump to a label computed
+    pub fn encode(&self) -> Result<Vec<u8>, String> {
+        self.resolved().and_then(|nodes| cfg::encode(&nodes))
+    }
+
+    /// The encoded instructions, little-endian. Panics where `encode` fails.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Instructions in the program, counting LD_DW_IMM once
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// The instructions, with branches that land on one kept as its index
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// A new label, bound to nothing yet
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the next instruction pushed
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        assert!(self.labels[label.0].is_none(), "Label {} bound twice", label.0);
+        self.labels[label.0] = Some(self.nodes.len());
+        self
+    }
+
+    /// Appends a single-slot instruction
+    pub fn push(&mut self, insn: Instruction) -> &mut Self {
+        assert!(insn.opcode != 0x18, "LD_DW_IMM takes two slots; use lddw");
+        self.nodes.push(Node { insn, second: None, target: None });
+        self
+    }
+
+    /// Appends a branch, whose offset is set to reach `label`
+    pub fn jump(&mut self, insn: Instruction, label: Label) -> &mut Self {
+        self.jumps.push((self.nodes.len(), label));
+        self.push(insn)
+    }
+
+    /// Inserts a single-slot instruction before the node at `at`, jumping to `target` if
+    /// given. Labels and branches keep the instructions they point to.
+    pub fn insert(&mut self, at: usize, insn: Instruction, target: Option<Label>) -> &mut Self {
+        assert!(insn.opcode != 0x18, "LD_DW_IMM takes two slots; use lddw");
+        self.splice(at..at, vec![Node { insn, second: None, target: None }]);
+        self.jumps.extend(target.map(|label| (at, label)));
+        self
+    }
+
+    /// Replaces the nodes in `range` with `replacement`, as `cfg::splice` does. Labels bound
+    /// in the range move to the instruction that followed it, and jumps to labels from the
+    /// range go with it.
+    pub fn splice(&mut self, range: Range<usize>, replacement: Vec<Node>) -> &mut Self {
+        let (start, end) = (range.start, range.end);
+        let shift = |node: usize| node - (end - start) + replacement.len();
+        self.jumps.retain(|(node, _)| !range.contains(node));
+        for (node, _) in self.jumps.iter_mut().filter(|(node, _)| *node >= end) {
+            *node = shift(*node);
+        }
+        for bound in self.labels.iter_mut().flatten().filter(|bound| **bound >= start) {
+            *bound = shift((*bound).max(end));
+        }
+        cfg::splice(&mut self.nodes, range, replacement);
+        self
+    }
+
+    /// Removes the nodes in `range`, as `splice` does
+    pub fn remove(&mut self, range: Range<usize>) -> &mut Self {
+        self.splice(range, Vec::new())
+    }
+
+    /// Appends the instructions of `other`, with its labels and branches
+    pub fn append(&mut self, other: Program) -> &mut Self {
+        let (base, labels) = (self.nodes.len(), self.labels.len());
+        self.nodes.extend(other.nodes.into_iter().map(|node| Node { target: node.target.map(|t| t + base), ..node }));
+        self.labels.extend(other.labels.into_iter().map(|bound| bound.map(|b| b + base)));
+        self.jumps.extend(other.jumps.into_iter().map(|(node, Label(label))| (node + base, Label(label + labels))));
+        self
+    }
+
+    pub fn lddw(&mut self, dst: u8, value: u64) -> &mut Self {
+        let second = Instruction::new(0, 0, 0, 0, (value >> 32) as u32).to_bytes();
+        let insn = Instruction::new(0x18, dst, 0, 0, value as u32);
+        self.nodes.push(Node { insn, second: Some(second), target: None });
+        self
+    }
+
+    /// A 64-bit ALU operation with an immediate operand
+    pub fn alu64(&mut self, op: u8, dst: u8, imm: u32) -> &mut Self {
+        assert!(is_alu(op), "Not an ALU operation: {:#x}", op);
+        self.push(Instruction::new(op | 0x07, dst, 0, 0, imm))
+    }
+
+    /// A 64-bit ALU operation with a register operand
+    pub fn alu64_reg(&mut self, op: u8, dst: u8, src: u8) -> &mut Self {
+        assert!(is_alu(op), "Not an ALU operation: {:#x}", op);
+        self.push(Instruction::new(op | 0x0f, dst, src, 0, 0))
+    }
+
+    /// A 32-bit ALU operation with an immediate operand
+    pub fn alu32(&mut self, op: u8, dst: u8, imm: u32) -> &mut Self {
+        assert!(is_alu(op), "Not an ALU operation: {:#x}", op);
+        self.push(Instruction::new(op | 0x04, dst, 0, 0, imm))
+    }
+
+    /// A 32-bit ALU operation with a register operand
+    pub fn alu32_reg(&mut self, op: u8, dst: u8, src: u8) -> &mut Self {
+        assert!(is_alu(op), "Not an ALU operation: {:#x}", op);
+        self.push(Instruction::new(op | 0x0c, dst, src, 0, 0))
+    }
+
+    pub fn mov64(&mut self, dst: u8, imm: u32) -> &mut Self {
+        self.alu64(MOV, dst, imm)
+    }
+
+    pub fn mov64_reg(&mut self, dst: u8, src: u8) -> &mut Self {
+        self.alu64_reg(MOV, dst, src)
+    }
+
+    pub fn mov32(&mut self, dst: u8, imm: u32) -> &mut Self {
+        self.alu32(MOV, dst, imm)
+    }
+
+    pub fn add64(&mut self, dst: u8, imm: u32) -> &mut Self {
+        self.alu64(ADD, dst, imm)
+    }
+
+    pub fn neg64(&mut self, dst: u8) -> &mut Self {
+        self.alu64(NEG, dst, 0)
+    }
+
+    pub fn neg32(&mut self, dst: u8) -> &mut Self {
+        self.alu32(NEG, dst, 0)
+    }
+
+    /// Converts the low `bits` of `dst` from little-endian
+    pub fn le(&mut self, dst: u8, bits: u32) -> &mut Self {
+        self.push(Instruction::new(0xd4, dst, 0, 0, bits))
+    }
+
+    /// Converts the low `bits` of `dst` from big-endian
+    pub fn be(&mut self, dst: u8, bits: u32) -> &mut Self {
+        self.push(Instruction::new(0xdc, dst, 0, 0, bits))
+    }
+
+    /// Loads `size` bytes at `src + offset` into `dst`
+    pub fn ldx(&mut self, size: u8, dst: u8, src: u8, offset: i16) -> &mut Self {
+        self.push(Instruction::new(0x61 | size, dst, src, offset as u16, 0))
+    }
+
+    /// Stores the low `size` bytes of `src` at `dst + offset`
+    pub fn stx(&mut self, size: u8, dst: u8, offset: i16, src: u8) -> &mut Self {
+        self.push(Instruction::new(0x63 | size, dst, src, offset as u16, 0))
+    }
+
+    /// Stores `size` bytes of `imm` at `dst + offset`
+    pub fn st(&mut self, size: u8, dst: u8, offset: i16, imm: u32) -> &mut Self {
+        self.push(Instruction::new(0x62 | size, dst, 0, offset as u16, imm))
+    }
+
+    pub fn ldxdw(&mut self, dst: u8, src: u8, offset: i16) -> &mut Self {
+        self.ldx(DWORD, dst, src, offset)
+    }
+
+    pub fn stxdw(&mut self, dst: u8, offset: i16, src: u8) -> &mut Self {
+        self.stx(DWORD, dst, offset, src)
+    }
+
+    pub fn ja(&mut self, label: Label) -> &mut Self {
+        self.jump(Instruction::new(0x05, 0, 0, 0, 0), label)
+    }
+
+    /// `ja` with its offset in the 32-bit imm
+    pub fn gotol(&mut self, label: Label) -> &mut Self {
+        self.jump(Instruction::new(0x06, 0, 0, 0, 0), label)
+    }
+
+    /// Jumps to `label` if `op` holds between `dst` and `imm`, compared as 64-bit values
+    pub fn jmp(&mut self, op: u8, dst: u8, imm: u32, label: Label) -> &mut Self {
+        assert!(is_condition(op), "Not a conditional jump: {:#x}", op);
+        self.jump(Instruction::new(op | 0x05, dst, 0, 0, imm), label)
+    }
+
+    /// Jumps to `label` if `op` holds between `dst` and `src`, compared as 64-bit values
+    pub fn jmp_reg(&mut self, op: u8, dst: u8, src: u8, label: Label) -> &mut Self {
+        assert!(is_condition(op), "Not a conditional jump: {:#x}", op);
+        self.jump(Instruction::new(op | 0x0d, dst, src, 0, 0), label)
+    }
+
+    /// Jumps to `label` if `op` holds between `dst` and `imm`, compared as 32-bit values
+    pub fn jmp32(&mut self, op: u8, dst: u8, imm: u32, label: Label) -> &mut Self {
+        assert!(is_condition(op), "Not a conditional jump: {:#x}", op);
+        self.jump(Instruction::new(op | 0x06, dst, 0, 0, imm), label)
+    }
+
+    /// Jumps to `label` if `op` holds between `dst` and `src`, compared as 32-bit values
+    pub fn jmp32_reg(&mut self, op: u8, dst: u8, src: u8, label: Label) -> &mut Self {
+        assert!(is_condition(op), "Not a conditional jump: {:#x}", op);
+        self.jump(Instruction::new(op | 0x0e, dst, src, 0, 0), label)
+    }
+
+    pub fn jeq(&mut self, dst: u8, imm: u32, label: Label) -> &mut Self {
+        self.jmp(JEQ, dst, imm, label)
+    }
+
+    pub fn jeq_reg(&mut self, dst: u8, src: u8, label: Label) -> &mut Self {
+        self.jmp_reg(JEQ, dst, src, label)
+    }
+
+    pub fn jne(&mut self, dst: u8, imm: u32, label: Label) -> &mut Self {
+        self.jmp(JNE, dst, imm, label)
+    }
+
+    pub fn jne_reg(&mut self, dst: u8, src: u8, label: Label) -> &mut Self {
+        self.jmp_reg(JNE, dst, src, label)
+    }
+
+    pub fn jgt_reg(&mut self, dst: u8, src: u8, label: Label) -> &mut Self {
+        self.jmp_reg(JGT, dst, src, label)
+    }
+
+    /// Calls the local function at `label`
+    pub fn call(&mut self, label: Label) -> &mut Self {
+        self.jump(Instruction::new(0x85, 0, 1, 0, 0), label)
+    }
+
+    pub fn exit(&mut self) -> &mut Self {
+        self.push(Instruction::new(0x95, 0, 0, 0, 0))
+    }
+}
+
+/// Instructions a program holds at most, before its exit
+#[cfg(feature = "arbitrary")]
+const MAX_INSTRUCTIONS: usize = 4096;
+
+/// An instruction of `template`, with its free fields taken from the input: valid registers,
+/// known helpers for calls and zero for reserved fields
+#[cfg(feature = "arbitrary")]
+fn instantiate(u: &mut Unstructured<'_>, template: &Template) -> Result<Instruction> {
+    let reserved = reserved_fields(template.opcode);
+    let dst = if reserved.dst { 0 } else { u.int_in_range(0..=10)? };
+    let src = match template.src {
+        Some(src) => src,
+        None if reserved.src => 0,
+        None => u.int_in_range(0..=10)?,
+    };
+    let offset = match template.offset {
+        Some(offset) => offset,
+        None if reserved.offset => 0,
+        None => u.arbitrary()?,
+    };
+    let imm = match template.imm {
+        Some(imm) => imm,
+        None if reserved.imm => 0,
+        None if template.opcode == 0x85 && src == 0 => *u.choose(vm::helpers())?,
+        None => u.arbitrary()?,
+    };
+    Ok(Instruction::new(template.opcode, dst, src, offset, imm))
+}
+
+/// A single-slot instruction of a template of the ISA spec
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let templates: Vec<&Template> = isa::templates().iter().filter(|t| t.opcode != 0x18).collect();
+        let template = *u.choose(&templates)?;
+        instantiate(u, template)
+    }
+}
+
+/// A program of instructions of the ISA spec's templates, ending with an exit. Branches keep
+/// the offsets drawn for them.
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for Program {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut program = Program::new();
+        for _ in 0..MAX_INSTRUCTIONS {
+            // Each instruction starts with a byte saying whether there is one
+            if !u.arbitrary::<bool>()? {
+                break;
+            }
+            let template = u.choose(isa::templates())?;
+            let insn = instantiate(u, template)?;
+            if template.opcode == 0x18 {
+                let high = u.arbitrary::<u32>()?;
+                program.lddw(insn.dst, (high as u64) << 32 | insn.imm as u64);
+                // Templates may fix fields lddw leaves zero, such as src for map loads
+                let node = program.nodes.last_mut().unwrap();
+                node.insn = insn;
+            } else {
+                program.nodes.push(Node { insn, second: None, target: None });
+            }
+        }
+        program.exit();
+        Ok(program)
+    }
+}
+
+/// Converts a fuzzer input from `path`, such as a cargo-fuzz crash artifact, to the program
+/// a target taking `Program` got from it, in --format
+#[cfg(feature = "arbitrary")]
+pub(crate) fn run(args: &Args, path: &str) {
+    let data = fs::read(path).expect("Failed to read fuzzer input");
+    let program = Program::arbitrary_take_rest(Unstructured::new(&data)).expect("Fuzzer input too short for a program");
+    write_output(args, 0, &program.to_bytes(), None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opcode and offset of every slot of the encoded program
+    fn slots(program: &Program) -> Vec<(u8, i16)> {
+        program
+            .to_bytes()
+            .chunks(8)
+            .map(|slot| {
+                let insn = Instruction::from_bytes(slot);
+                (insn.opcode, insn.offset as i16)
+            })
+            .collect()
+    }
+
+    /// A conditional jump over a move, to the exit
+    fn skip() -> Program {
+        let mut program = Program::new();
+        let done = program.label();
+        program.jgt_reg(R1, R2, done).mov64(R0, 1).bind(done).exit();
+        program
+    }
+
+    #[test]
+    fn jumps_reach_their_labels() {
+        let mut program = Program::new();
+        let (top, done) = (program.label(), program.label());
+        program
+            .bind(top)
+            .lddw(R0, 1 << 40)
+            .jgt_reg(R0, R1, done)
+            .add64(R0, 1)
+            .jump(Instruction::new(0x05, 0, 0, 0, 0), top)
+            .bind(done)
+            .exit();
+        // Offsets count both slots of the lddw
+        assert_eq!(slots(&program), [(0x18, 0), (0x00, 0), (0x2d, 2), (0x07, 0), (0x05, -5), (0x95, 0)]);
+    }
+
+    #[test]
+    fn unbound_labels_fail() {
+        let mut program = Program::new();
+        let nowhere = program.label();
+        program.mov64(R0, 0).jgt_reg(R0, R1, nowhere).exit();
+        assert_eq!(program.encode(), Err("Jump at node 1 to unbound label 0".to_string()));
+    }
+
+    #[test]
+    fn offsets_fit_their_field() {
+        // `ja` over `skipped` moves to the exit
+        let far = |insn, skipped| {
+            let mut program = Program::new();
+            let label = program.label();
+            program.jump(insn, label);
+            for _ in 0..skipped {
+                program.mov64(R0, 0);
+            }
+            program.bind(label).exit();
+            program
+        };
+        let ja = Instruction::new(0x05, 0, 0, 0, 0);
+        assert_eq!(slots(&far(ja, i16::MAX as usize))[0], (0x05, i16::MAX));
+        let err = far(ja, i16::MAX as usize + 1).encode().unwrap_err();
+        assert_eq!(err, "Jump at node 0 to label 0 is 32768 slots away, beyond its 16-bit offset");
+
+        // gotol and local calls keep theirs in the 32-bit imm
+        for insn in [Instruction::new(0x06, 0, 0, 0, 0), Instruction::new(0x85, 0, 1, 0, 0)] {
+            let bytes = far(insn, i16::MAX as usize + 1).to_bytes();
+            assert_eq!(Instruction::from_bytes(&bytes).imm, 1 << 15);
+        }
+    }
+
+    #[test]
+    fn insert_keeps_labels_and_jumps_on_their_instructions() {
+        let mut program = Program::new();
+        let entry = program.label();
+        program.bind(entry).append(skip());
+        // Before the jump and the instruction the entry is bound to, between the jump and its
+        // label, and a jump back to the entry
+        program
+            .insert(0, Instruction::new(0xb7, 0, 0, 0, 2), None)
+            .insert(2, Instruction::new(0xb7, 0, 0, 0, 3), None)
+            .insert(4, Instruction::new(0x05, 0, 0, 0, 0), Some(entry));
+        assert_eq!(slots(&program), [(0xb7, 0), (0x2d, 3), (0xb7, 0), (0xb7, 0), (0x05, -4), (0x95, 0)]);
+    }
+
+    #[test]
+    fn append_rebases_labels_and_targets() {
+        let expected = [(0x2d, 1), (0xb7, 0), (0x95, 0), (0x2d, 1), (0xb7, 0), (0x95, 0)];
+        let mut program = skip();
+        program.append(skip());
+        assert_eq!(slots(&program), expected);
+
+        // Decoded branches keep their targets as nodes rather than labels
+        let mut program = skip();
+        program.append(Program::from_bytes(&skip().to_bytes()));
+        assert_eq!(slots(&program), expected);
+    }
+
+    #[test]
+    fn builder_encodes_every_class() {
+        let mut program = Program::new();
+        let done = program.label();
+        program
+            .alu64(SUB, R1, 2)
+            .alu64_reg(XOR, R1, R2)
+            .alu32(LSH, R2, 3)
+            .alu32_reg(ARSH, R2, R1)
+            .mov32(R3, 1)
+            .neg64(R3)
+            .neg32(R3)
+            .le(R3, 16)
+            .be(R3, 64)
+            .ldx(BYTE, R4, R10, -1)
+            .stx(HALF, R10, -4, R4)
+            .st(WORD, R10, -8, 7)
+            .stxdw(R10, -16, R1)
+            .jmp(JSGE, R1, 5, done)
+            .jmp_reg(JLT, R1, R2, done)
+            .jmp32(JSET, R2, 1, done)
+            .jmp32_reg(JSLE, R2, R3, done)
+            .jeq(R1, 0, done)
+            .jne_reg(R1, R2, done)
+            .ja(done)
+            .gotol(done)
+            .bind(done)
+            .exit();
+        let opcodes: Vec<u8> = slots(&program).into_iter().map(|(opcode, _)| opcode).collect();
+        assert_eq!(
+            opcodes,
+            [
+                0x17, 0xaf, 0x64, 0xcc, 0xb4, 0x87, 0x84, 0xd4, 0xdc, 0x71, 0x6b, 0x62, 0x7b, 0x75, 0xad, 0x46, 0xde, 0x15,
+                0x5d, 0x05, 0x06, 0x95
+            ]
+        );
+    }
+
+    #[test]
+    fn splice_moves_labels_and_jumps_off_removed_instructions() {
+        let mut program = Program::new();
+        let (skipped, done) = (program.label(), program.label());
+        program.jgt_reg(R1, R2, skipped).bind(skipped).mov64(R0, 1).ja(done).mov64(R0, 2).bind(done).exit();
+        // The label on the first move goes to the exit that follows the removed range, and
+        // the ja goes with the range
+        program.remove(1..4);
+        assert_eq!(slots(&program), [(0x2d, 0), (0x95, 0)]);
+
+        let mut program = skip();
+        program.splice(1..2, vec![Node { insn: Instruction::new(0xb7, 0, 0, 0, 2), second: None, target: None }; 2]);
+        assert_eq!(slots(&program), [(0x2d, 2), (0xb7, 0), (0xb7, 0), (0x95, 0)]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Programs in the protobuf encoding of proto/ebpf_program.proto, shared with C++ harnesses
+//! fuzzing with libprotobuf-mutator. The wire format is written and read by hand: the
+//! messages are a few varints each.
+
+use crate::eval::{self, Expected};
+use crate::{write_output, Args, Instruction};
+use std::fs;
+use std::io::Read;
+
+// Field numbers of Program
+const INSTRUCTIONS: u64 = 1;
+const EXPECTED_R0: u64 = 2;
+
+// Wire types
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LEN: u64 = 2;
+const FIXED32: u64 = 5;
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(data: &[u8], at: &mut usize) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*at).ok_or("truncated varint")?;
+        *at += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint longer than 10 bytes".to_string())
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn unzigzag(value: u64) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// A field of a message
+struct Field<'a> {
+    number: u64,
+    wire: u64,
+    /// The value of a varint field
+    value: u64,
+    /// The bytes of any other field
+    payload: &'a [u8],
+}
+
+/// Fields of a message, with those of unknown wire types rejected
+fn fields(data: &[u8]) -> Result<Vec<Field<'_>>, String> {
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while at < data.len() {
+        let key = get_varint(data, &mut at)?;
+        let (number, wire) = (key >> 3, key & 0x07);
+        let (value, payload) = match wire {
+            VARINT => (get_varint(data, &mut at)?, &data[at..at]),
+            FIXED64 | FIXED32 | LEN => {
+                let len = match wire {
+                    FIXED64 => 8,
+                    FIXED32 => 4,
+                    _ => get_varint(data, &mut at)? as usize,
+                };
+                let payload = data.get(at..at.saturating_add(len)).ok_or("truncated field")?;
+                at += len;
+                (0, payload)
+            }
+            _ => return Err(format!("unsupported wire type {} of field {}", wire, number)),
+        };
+        fields.push(Field { number, wire, value, payload });
+    }
+    Ok(fields)
+}
+
+/// Encodes a program as a Program message, with the r0 it returns if known
+pub fn encode(bytes: &[u8], result: Option<u64>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() * 2);
+    for insn in bytes.chunks_exact(8).map(Instruction::from_bytes) {
+        // Fields holding 0 are left out, as proto3 does
+        let mut message = Vec::new();
+        let values = [
+            (insn.opcode & 0x07) as u64,
+            (insn.opcode >> 3) as u64,
+            insn.dst as u64,
+            insn.src as u64,
+            zigzag(insn.offset as i16 as i32),
+            zigzag(insn.imm as i32),
+        ];
+        for (number, value) in (1..).zip(values).filter(|&(_, value)| value != 0) {
+            put_varint(&mut message, number << 3 | VARINT);
+            put_varint(&mut message, value);
+        }
+        put_varint(&mut out, INSTRUCTIONS << 3 | LEN);
+        put_varint(&mut out, message.len() as u64);
+        out.extend(message);
+    }
+    if let Some(Expected::Returns(r0)) = result.map(Expected::Returns).or_else(|| eval::evaluate(bytes)) {
+        put_varint(&mut out, EXPECTED_R0 << 3 | VARINT);
+        put_varint(&mut out, r0);
+    }
+    out
+}
+
+/// Decodes a Program message into the program and the r0 it expects, if it has one
+pub fn decode(data: &[u8]) -> Result<(Vec<u8>, Option<u64>), String> {
+    let mut bytes = Vec::new();
+    let mut expected = None;
+    for Field { number, wire, value, payload } in fields(data)? {
+        match (number, wire) {
+            (INSTRUCTIONS, LEN) => {
+                let mut values = [0u64; 6];
+                for Field { number, wire, value, .. } in fields(payload)? {
+                    // Unknown fields are skipped, as protobuf parsers do
+                    if wire == VARINT && (1..=6).contains(&number) {
+                        values[number as usize - 1] = value;
+                    }
+                }
+                let [class, code, dst, src, offset, imm] = values;
+                let opcode = ((code as u8) << 3) | (class as u8 & 0x07);
+                let insn = Instruction::new(opcode, dst as u8 & 0x0f, src as u8 & 0x0f, unzigzag(offset) as u16, unzigzag(imm) as u32);
+                bytes.extend_from_slice(&insn.to_bytes());
+            }
+            (EXPECTED_R0, VARINT) => expected = Some(value),
+            _ => {}
+        }
+    }
+    Ok((bytes, expected))
+}
+
+/// Converts a Program message from `path`, or stdin for "-", to a program in --format
+pub fn run(args: &Args, path: &str) {
+    let data = if path == "-" {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data).expect("Failed to read message from stdin");
+        data
+    } else {
+        fs::read(path).expect("Failed to read message")
+    };
+    let (bytes, expected) = decode(&data).unwrap_or_else(|err| panic!("{} is not a Program message: {}", path, err));
+    write_output(args, 0, &bytes, expected);
+}
@@ -0,0 +1,229 @@
+//! C-like pseudocode rendering of programs, for sharing findings
+
+use crate::Instruction;
+use std::collections::BTreeSet;
+
+fn reg(is64: bool, r: u8) -> String {
+    format!("{}{}", if is64 { 'r' } else { 'w' }, r)
+}
+
+fn size_name(opcode: u8, signed: bool) -> String {
+    let bits = match opcode & 0x18 {
+        0x00 => 32,
+        0x08 => 16,
+        0x10 => 8,
+        _ => 64,
+    };
+    format!("{}{}", if signed { 's' } else { 'u' }, bits)
+}
+
+/// Formats a pointer expression such as `(u32 *)(r10 - 8)`
+fn ptr(opcode: u8, signed: bool, base: u8, offset: u16) -> String {
+    let off = offset as i16;
+    let sign = if off < 0 { '-' } else { '+' };
+    format!("({} *)(r{} {} {})", size_name(opcode, signed), base, sign, off.unsigned_abs())
+}
+
+fn mem(opcode: u8, signed: bool, base: u8, offset: u16) -> String {
+    format!("*{}", ptr(opcode, signed, base, offset))
+}
+
+fn label(target: i64, len: usize) -> String {
+    if target < 0 || target >= len as i64 {
+        format!("L{} /* out of bounds */", target)
+    } else {
+        format!("L{}", target)
+    }
+}
+
+fn invalid(insn: &Instruction) -> String {
+    format!(
+        "/* invalid: op={:#04x} dst={} src={} off={} imm={:#x} */",
+        insn.opcode, insn.dst, insn.src, insn.offset as i16, insn.imm
+    )
+}
+
+/// Returns the slot a jump or pseudo-call at `pc` transfers control to
+fn jump_target(pc: usize, insn: &Instruction) -> Option<i64> {
+    let class = insn.opcode & 0x07;
+    if class != 0x05 && class != 0x06 {
+        return None;
+    }
+    match insn.opcode & 0xf0 {
+        0x00 if class == 0x06 => Some(pc as i64 + 1 + insn.imm as i32 as i64),
+        0x80 if insn.src == 1 => Some(pc as i64 + 1 + insn.imm as i32 as i64),
+        0x80 | 0x90 => None,
+        _ => Some(pc as i64 + 1 + insn.offset as i16 as i64),
+    }
+}
+
+fn render_alu(insn: &Instruction) -> String {
+    let is64 = insn.opcode & 0x07 == 0x07;
+    let dst = reg(is64, insn.dst);
+    let src = if insn.opcode & 0x08 != 0 {
+        reg(is64, insn.src)
+    } else {
+        (insn.imm as i32).to_string()
+    };
+    let signed = insn.offset == 1;
+
+    let op = match insn.opcode & 0xf0 {
+        0x00 => "+",
+        0x10 => "-",
+        0x20 => "*",
+        0x30 if signed => "s/",
+        0x30 => "/",
+        0x40 => "|",
+        0x50 => "&",
+        0x60 => "<<",
+        0x70 => ">>",
+        0x80 => return format!("{} = -{};", dst, dst),
+        0x90 if signed => "s%",
+        0x90 => "%",
+        0xa0 => "^",
+        0xb0 => {
+            return match insn.offset {
+                8 | 16 | 32 => format!("{} = (s{}){};", dst, insn.offset, src),
+                _ => format!("{} = {};", dst, src),
+            }
+        }
+        0xc0 => "s>>",
+        0xd0 => {
+            // Byte swaps always produce a 64-bit result
+            let func = match (is64, insn.opcode & 0x08) {
+                (true, _) => "bswap",
+                (false, 0) => "le",
+                (false, _) => "be",
+            };
+            return format!("r{} = {}{}(r{});", insn.dst, func, insn.imm, insn.dst);
+        }
+        _ => return invalid(insn),
+    };
+    format!("{} = {} {} {};", dst, dst, op, src)
+}
+
+fn render_atomic(insn: &Instruction) -> String {
+    let is64 = insn.opcode & 0x18 == 0x18;
+    let ptr = ptr(insn.opcode, false, insn.dst, insn.offset);
+    let src = reg(is64, insn.src);
+
+    let (op, name) = match insn.imm & !0x01 {
+        0x00 => ("+", "add"),
+        0x40 => ("|", "or"),
+        0x50 => ("&", "and"),
+        0xa0 => ("^", "xor"),
+        _ => ("", ""),
+    };
+    match insn.imm {
+        0x00 | 0x40 | 0x50 | 0xa0 => format!("lock *{} {}= {};", ptr, op, src),
+        0x01 | 0x41 | 0x51 | 0xa1 => format!("{} = atomic_fetch_{}({}, {});", src, name, ptr, src),
+        0xe1 => format!("{} = xchg({}, {});", src, ptr, src),
+        0xf1 => format!("{} = cmpxchg({}, {}, {});", reg(is64, 0), ptr, reg(is64, 0), src),
+        _ => invalid(insn),
+    }
+}
+
+fn render_jmp(pc: usize, insn: &Instruction, len: usize) -> String {
+    let is64 = insn.opcode & 0x07 == 0x05;
+    let target = jump_target(pc, insn).map(|t| label(t, len));
+
+    let op = match insn.opcode & 0xf0 {
+        0x00 => return format!("goto {};", target.unwrap_or_default()),
+        0x80 => {
+            let args = "r1, r2, r3, r4, r5";
+            return match insn.src {
+                0 => format!("r0 = helper_{}({});", insn.imm as i32, args),
+                1 => format!("r0 = {}({});", target.unwrap_or_default(), args),
+                2 => format!("r0 = kfunc_{}({});", insn.imm as i32, args),
+                _ => invalid(insn),
+            };
+        }
+        0x90 => return "return r0;".to_string(),
+        0x10 => "==",
+        0x20 => ">",
+        0x30 => ">=",
+        0x40 => "&",
+        0x50 => "!=",
+        0x60 => "s>",
+        0x70 => "s>=",
+        0xa0 => "<",
+        0xb0 => "<=",
+        0xc0 => "s<",
+        0xd0 => "s<=",
+        _ => return invalid(insn),
+    };
+    let src = if insn.opcode & 0x08 != 0 {
+        reg(is64, insn.src)
+    } else {
+        (insn.imm as i32).to_string()
+    };
+    format!("if ({} {} {}) goto {};", reg(is64, insn.dst), op, src, target.unwrap_or_default())
+}
+
+fn render_insn(pc: usize, insn: &Instruction, next: Option<&Instruction>, len: usize) -> String {
+    let mode = insn.opcode & 0xe0;
+    match insn.opcode & 0x07 {
+        0x00 if insn.opcode == 0x18 => {
+            let Some(next) = next else {
+                return format!("r{} = /* truncated lddw */;", insn.dst);
+            };
+            let value = ((next.imm as u64) << 32) | insn.imm as u64;
+            match insn.src {
+                0 => format!("r{} = {:#x} ll;", insn.dst, value),
+                src => format!("r{} = ld_pseudo(src={}, {:#x}, {:#x});", insn.dst, src, insn.imm, next.imm),
+            }
+        }
+        0x01 if mode == 0x60 || mode == 0x80 => {
+            format!("r{} = {};", insn.dst, mem(insn.opcode, mode == 0x80, insn.src, insn.offset))
+        }
+        0x02 if mode == 0x60 => {
+            format!("{} = {};", mem(insn.opcode, false, insn.dst, insn.offset), insn.imm as i32)
+        }
+        0x03 if mode == 0x60 => {
+            format!("{} = r{};", mem(insn.opcode, false, insn.dst, insn.offset), insn.src)
+        }
+        0x03 if mode == 0xc0 => render_atomic(insn),
+        0x04 | 0x07 => render_alu(insn),
+        0x05 | 0x06 => render_jmp(pc, insn, len),
+        _ => invalid(insn),
+    }
+}
+
+/// Renders a program as pseudocode, with labels on every branch target
+pub fn render(bytes: &[u8]) -> String {
+    render_with(bytes, false)
+}
+
+/// Like `render`, but prefixes every instruction with its slot number
+pub fn render_numbered(bytes: &[u8]) -> String {
+    render_with(bytes, true)
+}
+
+fn render_with(bytes: &[u8], numbered: bool) -> String {
+    let slots: Vec<Instruction> = bytes.chunks_exact(8).map(Instruction::from_bytes).collect();
+
+    let mut targets = BTreeSet::new();
+    let mut pc = 0;
+    while pc < slots.len() {
+        if let Some(target) = jump_target(pc, &slots[pc]) {
+            targets.insert(target);
+        }
+        pc += if slots[pc].opcode == 0x18 { 2 } else { 1 };
+    }
+
+    let mut output = String::new();
+    let mut pc = 0;
+    while pc < slots.len() {
+        if targets.contains(&(pc as i64)) {
+            output.push_str(&format!("L{}:\n", pc));
+        }
+        let text = render_insn(pc, &slots[pc], slots.get(pc + 1), slots.len());
+        if numbered {
+            output.push_str(&format!("{:>4}:   {}\n", pc, text));
+        } else {
+            output.push_str(&format!("    {}\n", text));
+        }
+        pc += if slots[pc].opcode == 0x18 { 2 } else { 1 };
+    }
+    output
+}
@@ -0,0 +1,95 @@
+//! Runs rbpf on other architectures, by running this binary's `exec` subcommand under
+//! qemu-user, and compares the outcomes against the native run
+
+use crate::vm::{self, Outcome};
+use crate::{format_program, generate_test, limits, output_path, repro, write_output, Args, Endian, GenOptions};
+use rand::Rng;
+use std::fs;
+use std::process::Command;
+
+/// An architecture and the command running `ebpf_fuzzer exec @@` on it
+struct Arch<'a> {
+    name: &'a str,
+    cmd: &'a str,
+}
+
+fn parse_arch(spec: &str) -> Arch<'_> {
+    let (name, cmd) = spec.split_once('=').unwrap_or_else(|| panic!("Expected NAME=COMMAND, got {:?}", spec));
+    assert!(cmd.contains("@@"), "Command for {} has no @@ for the program path", name);
+    Arch { name, cmd }
+}
+
+/// Runs one program under an emulated architecture, returning the outcome `exec` printed,
+/// or a description of how the run failed
+fn emulate(args: &Args, cmd: &str, path: &str) -> Result<String, String> {
+    let mut command = Command::new("sh");
+    limits::apply(&mut command, args);
+    let output = command
+        .arg("-c")
+        .arg(cmd.replace("@@", &repro::quote(path)))
+        .output()
+        .expect("Failed to run emulator");
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !stdout.is_empty() {
+        return Ok(stdout);
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let last = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("no output");
+    Err(format!("exited with {}: {}", output.status, last.trim()))
+}
+
+/// Whether an emulated outcome matches the native one. Only the kind of error is compared,
+/// as rbpf's messages include host addresses.
+fn agrees(native: &Outcome, emulated: &str) -> bool {
+    match native {
+        Outcome::Returned(_) => emulated == native.to_string(),
+        Outcome::Error(_) => emulated.starts_with("error:"),
+        Outcome::Panicked(_) => emulated.starts_with("panicked:"),
+        Outcome::TimedOut => emulated.starts_with("timed out"),
+    }
+}
+
+/// Runs every generated program through rbpf natively and under each architecture's
+/// command, and archives those whose outcomes differ
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, arches: &[String]) {
+    let arches: Vec<Arch> = arches.iter().map(|spec| parse_arch(spec)).collect();
+    let input = std::env::temp_dir().join(format!("ebpf_fuzzer_qemu_{}", std::process::id()));
+    let input = input.to_string_lossy();
+    let mut findings = 0;
+
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, opts);
+        let native = vm::run(&bytes);
+        fs::write(&*input, format_program(&bytes, Endian::Le, None, result)).expect("Failed to write program");
+
+        let mut problems = Vec::new();
+        for arch in &arches {
+            match emulate(args, arch.cmd, &input) {
+                Ok(emulated) if agrees(&native, &emulated) => {}
+                Ok(emulated) => problems.push(format!("native {}, {} {}", native, arch.name, emulated)),
+                Err(err) => problems.push(format!("{} {}", arch.name, err)),
+            }
+        }
+        if problems.is_empty() {
+            continue;
+        }
+        println!("program {}: {}", i, problems.join("; "));
+        findings += 1;
+
+        write_output(args, i, &bytes, result);
+        if let Some(path) = output_path(args, i) {
+            let saved = format!("{}.exec", path);
+            fs::copy(&*input, &saved).expect("Failed to save program");
+            let rerun: Vec<String> = arches.iter().map(|arch| arch.cmd.replace("@@", &repro::quote(&saved))).collect();
+            repro::write(&path, i.into(), opts.seed, &rerun.join("\n"));
+        }
+    }
+
+    let _ = fs::remove_file(&*input);
+    println!("{} findings across {} programs", findings, args.count);
+    if findings > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,57 @@
+//! Imports the queues and crash directories of byte-level fuzzers such as AFL++ and
+//! libFuzzer, whose files are bare little-endian instructions, as conformance tests whose
+//! expected result is what the reference interpreter computes
+
+use crate::interp::{self, Outcome};
+use crate::rejections::reason;
+use crate::{write_output, Args};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files at `path`, the files directly inside it for a directory. Hidden files and
+/// directories, such as AFL++'s .state, are left out.
+fn files(path: &Path) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<_> = fs::read_dir(path)
+        .expect("Failed to read queue directory")
+        .map(|entry| entry.expect("Failed to read queue directory").path())
+        .filter(|path| path.is_file() && !path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes every file at `paths`, queue entries or directories of them, that holds whole
+/// instructions and returns under the reference interpreter to the output, with the value
+/// it returns as the expected result. The others are skipped and counted by reason.
+pub fn run(args: &Args, paths: &[String]) {
+    let mut imported = 0;
+    let mut skipped: BTreeMap<String, u32> = BTreeMap::new();
+    for path in paths.iter().flat_map(|path| files(Path::new(path))) {
+        let bytes = fs::read(&path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+        let skip = if bytes.is_empty() || !bytes.len().is_multiple_of(8) {
+            "not whole instructions".to_string()
+        } else {
+            match interp::run(&bytes) {
+                Outcome::Returned(result) => {
+                    write_output(args, imported, &bytes, Some(result));
+                    imported += 1;
+                    continue;
+                }
+                Outcome::Error(err) => format!("error: {}", reason(&err).unwrap_or_default()),
+                Outcome::Unspecified => "result left unspecified".to_string(),
+            }
+        };
+        *skipped.entry(skip).or_default() += 1;
+    }
+
+    println!("imported {} programs, skipped {} files", imported, skipped.values().sum::<u32>());
+    let mut reasons: Vec<_> = skipped.into_iter().collect();
+    reasons.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (reason, count) in reasons {
+        println!("{:>8}  {}", count, reason);
+    }
+}
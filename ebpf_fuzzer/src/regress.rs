@@ -0,0 +1,71 @@
+//! A corpus of programs reproducing bugs BPF implementations have had, run through the
+//! oracles so new implementations and rbpf releases can be checked against that history.
+//! Each program is in the conformance format, with a comment line naming the bug.
+
+use crate::eval::Expected;
+use crate::interp;
+use crate::oracle;
+use crate::parse_program;
+use crate::vm::{self, Outcome};
+
+const CORPUS: &[(&str, &str)] = &[
+    ("arsh32-zero-extension", include_str!("../regressions/arsh32-zero-extension.data")),
+    ("div32-src-truncation", include_str!("../regressions/div32-src-truncation.data")),
+    ("jump-before-start", include_str!("../regressions/jump-before-start.data")),
+    ("jump-into-lddw", include_str!("../regressions/jump-into-lddw.data")),
+    ("lddw-last-slot", include_str!("../regressions/lddw-last-slot.data")),
+    ("mod32-src-truncation", include_str!("../regressions/mod32-src-truncation.data")),
+    ("mod32-zero-divisor", include_str!("../regressions/mod32-zero-divisor.data")),
+    ("mov32-imm-zero-extension", include_str!("../regressions/mov32-imm-zero-extension.data")),
+    ("neg32-zero-extension", include_str!("../regressions/neg32-zero-extension.data")),
+    ("shift-count-mask", include_str!("../regressions/shift-count-mask.data")),
+];
+
+/// What a corpus program must do, from its `-- result` or `-- error` section
+fn expected(text: &str) -> Expected {
+    let mut lines = text.lines().map(str::trim).skip_while(|line| *line != "-- result" && *line != "-- error");
+    let section = lines.next().expect("Regression program without a result or error");
+    let value = lines.next().unwrap_or_default();
+    if section == "-- error" {
+        return Expected::Error(value.to_string());
+    }
+    Expected::Returns(u64::from_str_radix(value.trim_start_matches("0x"), 16).expect("Invalid regression result"))
+}
+
+/// The bug a corpus program reproduces, from its `# regression:` comment
+fn note(text: &str) -> &str {
+    text.lines().find_map(|line| line.strip_prefix("# regression: ")).unwrap_or_default()
+}
+
+/// Runs every corpus program through rbpf and the reference interpreter and reports those
+/// where either misses the recorded outcome or they disagree
+pub fn run() {
+    let mut failed = 0;
+    for (name, text) in CORPUS {
+        let bytes = parse_program(text);
+        let expected = expected(text);
+        let outcome = vm::run(&bytes);
+        let reference = interp::run(&bytes);
+
+        let result = match expected {
+            Expected::Returns(r0) => Some(r0),
+            Expected::Error(_) => None,
+        };
+        let mut problems = oracle::problems(&bytes, result, &outcome, &reference);
+        if matches!(expected, Expected::Error(_)) && !matches!(outcome, Outcome::Error(_)) {
+            problems.push(format!("expected {}, rbpf {}", expected, outcome));
+        }
+
+        if problems.is_empty() {
+            println!("ok    {}", name);
+        } else {
+            failed += 1;
+            println!("FAIL  {}: {}\n      {}", name, note(text), problems.join("; "));
+        }
+    }
+
+    println!("{} of {} regressions failed", failed, CORPUS.len());
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,97 @@
+//! Groups verifier rejections by reason across a campaign. The most common reasons show
+//! which generation constraint is worth adding next.
+
+use std::collections::BTreeMap;
+
+/// Starts of log lines that never hold the reason: summaries, libbpf messages and the
+/// precision-tracking and branch-state lines of kernel logs
+const NOISE: &[&str] = &[
+    "processed ",
+    "verification time",
+    "stack depth",
+    "-- BEGIN PROG LOAD LOG",
+    "-- END PROG LOAD LOG",
+    "libbpf:",
+    "load failed",
+    "mark_precise",
+    "last_idx",
+    "from ",
+    "func#",
+    "Func#",
+    ";",
+];
+
+/// Replaces numbers by N, except in register names such as R2 or r10
+fn template(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut prev: Option<char> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            out.push(c);
+            prev = Some(c);
+            continue;
+        }
+        let register = matches!(prev, Some('R' | 'r')) && {
+            let before = out.chars().rev().nth(1);
+            !before.is_some_and(|b| b.is_ascii_alphanumeric())
+        };
+        let mut number = c.to_string();
+        while let Some(&next) = chars.peek() {
+            if !next.is_ascii_hexdigit() && next != 'x' {
+                break;
+            }
+            number.push(next);
+            chars.next();
+        }
+        out.push_str(if register { &number } else { "N" });
+        prev = number.chars().last();
+    }
+    out
+}
+
+/// Whether a line, past its instruction index, dumps an instruction or register state
+fn is_trace(line: &str) -> bool {
+    let first = line.split_whitespace().next().unwrap_or("");
+    let state = first.strip_prefix('R').and_then(|rest| rest.split_once('=')).is_some_and(|(reg, _)| reg.starts_with(|c: char| c.is_ascii_digit()));
+    line.starts_with('(') || state
+}
+
+/// The reason a verifier log gives for rejecting a program, the last line that is not an
+/// instruction trace or a summary, with numbers outside register names replaced by N so
+/// rejections of the same kind group together
+pub fn reason(log: &str) -> Option<String> {
+    log.lines().rev().map(str::trim).filter(|line| !line.is_empty() && !NOISE.iter().any(|n| line.starts_with(n))).find_map(|line| {
+        // Kernel and PREVAIL logs prefix lines with the instruction index
+        let line = match line.split_once(": ") {
+            Some((index, rest)) if index.chars().all(|c| c.is_ascii_digit()) => rest,
+            _ => line,
+        };
+        (!is_trace(line)).then(|| template(line))
+    })
+}
+
+/// Counts of rejections per reason
+#[derive(Default)]
+pub struct Tally {
+    counts: BTreeMap<String, u32>,
+    total: u32,
+}
+
+impl Tally {
+    pub fn add(&mut self, log: &str) {
+        let reason = reason(log).unwrap_or_else(|| "(no reason in log)".to_string());
+        *self.counts.entry(reason).or_default() += 1;
+        self.total += 1;
+    }
+
+    /// Prints reasons from the most common down, with their share of all rejections
+    pub fn print(&self, title: &str) {
+        println!("{} rejections of {}:", self.total, title);
+        let mut counts: Vec<(&String, &u32)> = self.counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (reason, &count) in counts {
+            println!("{:>8} {:>5.1}%  {}", count, count as f64 * 100.0 / self.total as f64, reason);
+        }
+    }
+}
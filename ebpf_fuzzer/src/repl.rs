@@ -0,0 +1,115 @@
+//! Interactive session for building, mutating and running a single program
+
+use crate::{generate_random_instruction, generate_test, mutate, pseudo, render_program, vm, Args, GenOptions, MutationOp};
+use clap::ValueEnum;
+use rand::Rng;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+gen [SIZE]         generate a new program in the selected mode
+show               print the program with slot numbers
+regen SLOT         re-roll the instruction at SLOT from the enabled templates
+set SLOT HEX       overwrite SLOT with a raw 64-bit value, as in `-- raw` lines
+mutate OP          apply a mutation operator (as in `mutate --ops`)
+run                verify and interpret the program with rbpf
+save PATH          write the program in the selected --format
+help               show this help
+quit               leave the session";
+
+struct Session<'a, R: Rng> {
+    rng: &'a mut R,
+    args: &'a Args,
+    opts: &'a GenOptions,
+    program: Vec<u8>,
+    /// What the program returns, while it is as generated and that is known
+    result: Option<u64>,
+}
+
+impl<R: Rng> Session<'_, R> {
+    fn slot(&self, word: Option<&str>) -> Result<usize, String> {
+        let slots = self.program.len() / 8;
+        let slot: usize = word.ok_or("missing slot")?.parse().map_err(|_| "invalid slot")?;
+        if slot >= slots {
+            return Err(format!("slot out of range (program has {} slots)", slots));
+        }
+        Ok(slot)
+    }
+
+    fn execute(&mut self, command: &str, mut words: std::str::SplitWhitespace) -> Result<(), String> {
+        match command {
+            "gen" => {
+                let size = match words.next() {
+                    Some(word) => word.parse().map_err(|_| "invalid size")?,
+                    None => self.opts.random_size(self.rng),
+                };
+                // As in a generation run, so modes, padding and pins all apply
+                (self.program, self.result) = generate_test(self.rng, size, self.opts);
+                print!("{}", pseudo::render_numbered(&self.program));
+            }
+            "show" => print!("{}", pseudo::render_numbered(&self.program)),
+            "regen" => {
+                let slot = self.slot(words.next())?;
+                // Keep the slot count, so a LD_DW_IMM is only replaced by another one
+                let wide = self.program[slot * 8] == 0x18;
+                let insn = loop {
+                    let insn = generate_random_instruction(self.rng, self.opts);
+                    if (insn.opcode == 0x18) == wide {
+                        break insn;
+                    }
+                };
+                self.program[slot * 8..slot * 8 + 8].copy_from_slice(&insn.to_bytes());
+                self.result = None;
+                print!("{}", pseudo::render_numbered(&self.program));
+            }
+            "set" => {
+                let slot = self.slot(words.next())?;
+                let hex = words.next().ok_or("missing value")?;
+                let value = u64::from_str_radix(hex.trim_start_matches("0x"), 16).map_err(|_| "invalid hex value")?;
+                self.program[slot * 8..slot * 8 + 8].copy_from_slice(&value.to_le_bytes());
+                self.result = None;
+                print!("{}", pseudo::render_numbered(&self.program));
+            }
+            "mutate" => {
+                let name = words.next().ok_or("missing operator")?;
+                let op = MutationOp::from_str(name, true).map_err(|_| format!("unknown operator: {}", name))?;
+                mutate::apply(self.rng, op, &mut self.program, self.opts);
+                self.result = None;
+                print!("{}", pseudo::render_numbered(&self.program));
+            }
+            "run" => println!("{}", vm::run(&self.program)),
+            "save" => {
+                let path = words.next().ok_or("missing path")?;
+                std::fs::write(path, render_program(self.args, &self.program, self.result)).map_err(|err| err.to_string())?;
+            }
+            "help" => println!("{}", HELP),
+            _ => return Err(format!("unknown command: {} (try help)", command)),
+        }
+        Ok(())
+    }
+}
+
+/// Reads commands from stdin until quit or end of input
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions) {
+    let mut session = Session { rng, args, opts, program: Vec::new(), result: None };
+    let stdin = io::stdin();
+
+    loop {
+        print!("> ");
+        io::stdout().flush().expect("Failed to flush stdout");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("Failed to read command") == 0 {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        if command == "quit" || command == "exit" {
+            break;
+        }
+        if let Err(err) = session.execute(command, words) {
+            println!("{}", err);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Markdown bug reports for saved findings, ready to paste into an issue tracker
+
+use crate::interp;
+use crate::oracle;
+use crate::vm;
+use crate::{decode_input, pseudo};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Path of a file saved next to a finding, e.g. its reproducer or the target's stderr
+fn sibling_path(finding: &Path, extension: &str) -> PathBuf {
+    let mut path = finding.as_os_str().to_owned();
+    path.push(format!(".{}", extension));
+    path.into()
+}
+
+fn sibling(finding: &Path, extension: &str) -> Option<String> {
+    fs::read(sibling_path(finding, extension)).ok().map(|data| String::from_utf8_lossy(&data).into_owned())
+}
+
+/// The seed a reproducer regenerates its run with
+fn seed(script: &str) -> Option<&str> {
+    let mut words = script.split_whitespace();
+    while let Some(word) = words.next() {
+        if let Some(seed) = word.strip_prefix("--seed=") {
+            return Some(seed);
+        }
+        if word == "--seed" {
+            return words.next();
+        }
+    }
+    None
+}
+
+/// The class of the findings in a directory, from the name findings::write gives it
+fn class(dir: &Path) -> &'static str {
+    match dir.file_name().and_then(|name| name.to_str()) {
+        Some("crashes") => "Crash",
+        Some("hangs") => "Hang",
+        Some("divergences") => "Divergence",
+        Some("mismatches") => "Verdict mismatch",
+        _ => "Finding",
+    }
+}
+
+/// Escapes text for a table cell
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+fn environment() -> String {
+    let mut text = format!("- ebpf_fuzzer {}\n- {} on {}\n", env!("CARGO_PKG_VERSION"), std::env::consts::OS, std::env::consts::ARCH);
+    if let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") {
+        let _ = writeln!(text, "- kernel {}", release.trim());
+    }
+    text
+}
+
+fn render(finding: &Path, class: &str, bytes: &[u8]) -> String {
+    let outcome = vm::run(bytes);
+    let reference = interp::run(bytes);
+    let problems = oracle::problems(bytes, None, &outcome, &reference);
+    let script = sibling(finding, "sh");
+    let log = sibling(finding, "log");
+    let summary = problems
+        .first()
+        .cloned()
+        .or_else(|| log.as_deref().and_then(|log| log.lines().find(|line| !line.trim().is_empty())).map(|line| line.trim().to_string()))
+        .unwrap_or_else(|| "see below".to_string());
+
+    let mut text = format!("# {}: {}\n\n", class, summary);
+    let _ = writeln!(text, "Found by ebpf_fuzzer in `{}`.\n", finding.display());
+    if !problems.is_empty() {
+        text.push_str("## Problems\n\n");
+        for problem in &problems {
+            let _ = writeln!(text, "- {}", problem);
+        }
+        text.push('\n');
+    }
+
+    let _ = writeln!(text, "## Program\n\n```\n{}```\n", pseudo::render_numbered(bytes));
+    text.push_str("Raw bytes, one little-endian instruction per line:\n\n```\n");
+    for insn in bytes.chunks_exact(8) {
+        let _ = writeln!(text, "{}", insn.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" "));
+    }
+    text.push_str("```\n\n");
+
+    let _ = writeln!(text, "## Outcomes\n\n| Oracle | Outcome |\n| --- | --- |");
+    let _ = writeln!(text, "| rbpf | `{}` |", cell(&outcome.to_string()));
+    let _ = writeln!(text, "| Reference interpreter | `{}` |\n", cell(&reference.to_string()));
+    if let Some(log) = &log {
+        let _ = writeln!(text, "<details><summary>Target output</summary>\n\n```\n{}\n```\n\n</details>\n", log.trim_end());
+    }
+
+    text.push_str("## Reproduction\n\n");
+    match &script {
+        Some(script) => {
+            if let Some(seed) = seed(script) {
+                let _ = writeln!(text, "Seed: `{}`\n", seed);
+            }
+            let _ = writeln!(text, "```sh\n{}```\n", script);
+        }
+        None => text.push_str("No reproducer was saved with this finding; the program above is the input.\n\n"),
+    }
+
+    let _ = write!(text, "## Environment\n\n{}", environment());
+    text
+}
+
+/// Files in a directory, and in its subdirectories for findings saved in bundles
+fn files(dir: &Path, depth: u32) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).expect("Failed to read finding directory") {
+        let path = entry.expect("Failed to read finding directory").path();
+        if path.is_dir() {
+            if depth > 0 {
+                files.extend(self::files(&path, depth - 1));
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Writes `<finding>.md` for every finding in `dir` or in a bundle there, that is every file
+/// saved with a reproducer or a log
+pub fn run(dir: &str) {
+    let dir = Path::new(dir);
+    let mut findings: Vec<PathBuf> = files(dir, 1)
+        .into_iter()
+        .filter(|path| sibling_path(path, "sh").exists() || sibling_path(path, "log").exists())
+        .collect();
+    findings.sort();
+
+    let mut skipped = 0;
+    for finding in &findings {
+        let data = fs::read(finding).expect("Failed to read finding");
+        let Some(bytes) = (!data.starts_with(b"\x7fELF")).then_some(data).and_then(decode_input) else {
+            // ELF objects and pseudo-C cannot be read back
+            skipped += 1;
+            continue;
+        };
+        let path = format!("{}.md", finding.display());
+        fs::write(&path, render(finding, class(dir), &bytes)).expect("Failed to write report");
+        println!("{}", path);
+    }
+    println!("{} reports, {} findings skipped", findings.len() - skipped, skipped);
+}
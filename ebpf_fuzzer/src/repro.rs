@@ -0,0 +1,58 @@
+//! Shell scripts reproducing saved findings
+
+use crate::config;
+use std::fs;
+
+/// Quotes a word for sh, leaving plain words untouched
+pub fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,%+@".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// The command line of this run, with options from the environment and the config file and
+/// the seed spelled out
+pub fn args_with_seed(seed: u64) -> Vec<String> {
+    let mut words = config::args().to_vec();
+    if !words.iter().any(|w| w == "--seed" || w.starts_with("--seed=")) {
+        words.splice(1..1, ["--seed".to_string(), seed.to_string()]);
+    }
+    words
+}
+
+fn invocation(seed: u64) -> String {
+    args_with_seed(seed).iter().map(|w| quote(w)).collect::<Vec<_>>().join(" ")
+}
+
+/// Writes `<path>.sh`, which regenerates the run that saved finding `index` at `path`
+/// and then runs `rerun_cmd` to re-run the target on it
+pub fn write(path: &str, index: u64, seed: u64, rerun_cmd: &str) {
+    let script = format!(
+        "#!/bin/sh\n\
+         # Reproducer for finding {} of ebpf_fuzzer {}\n\
+         \n\
+         # Regenerate the run that produced {}\n\
+         {}\n\
+         \n\
+         # Re-run the target on the saved input\n\
+         {}\n",
+        index,
+        env!("CARGO_PKG_VERSION"),
+        quote(path),
+        invocation(seed),
+        rerun_cmd,
+    );
+
+    let script_path = format!("{}.sh", path);
+    fs::write(&script_path, script).expect("Failed to write reproducer script");
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).expect("Failed to make reproducer executable");
+    }
+}
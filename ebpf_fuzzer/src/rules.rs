@@ -0,0 +1,198 @@
+//! Rules steering structured generation, written as short sentences so they can be given on
+//! the command line or in a config file: `require r0 == 5 at exit`, `forbid div, mod` and
+//! `stack_usage <= 64`
+
+use crate::{parse_number, Instruction};
+
+/// Bytes of stack a program has
+pub const STACK_SIZE: u16 = 512;
+
+/// Instruction families `forbid` takes: ALU operations of either width, memory classes and
+/// jumps by mnemonic
+const FAMILIES: &[&str] = &[
+    "add", "sub", "mul", "div", "or", "and", "lsh", "rsh", "neg", "mod", "xor", "mov", "arsh", "end", "lddw", "ld", "ldx",
+    "st", "stx", "atomic", "ja", "jeq", "jgt", "jge", "jset", "jne", "jsgt", "jsge", "call", "exit", "jlt", "jle", "jslt",
+    "jsle",
+];
+
+/// The family of an instruction, as `forbid` names it
+fn family(opcode: u8) -> &'static str {
+    let op = (opcode >> 4) as usize;
+    match opcode & 0x07 {
+        0x04 | 0x07 => FAMILIES[op.min(13)],
+        0x00 if opcode == 0x18 => "lddw",
+        0x00 => "ld",
+        0x01 => "ldx",
+        0x02 => "st",
+        0x03 if opcode & 0xe0 == 0xc0 => "atomic",
+        0x03 => "stx",
+        _ => FAMILIES[20 + op.min(13)],
+    }
+}
+
+/// A single rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// r0 holds this value at exit
+    Require(u64),
+    /// No instruction of these families appears
+    Forbid(Vec<&'static str>),
+    /// Programs store to and load from at most this many bytes of stack below r10
+    StackUsage(u16),
+}
+
+/// Parses `require r0 == VALUE at exit`, `forbid FAMILY[, FAMILY...]` or
+/// `stack_usage <= BYTES`, with values in decimal, negative decimal or 0x hex
+pub fn parse(s: &str) -> Result<Rule, String> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    match words.as_slice() {
+        ["require", "r0", "==", value, "at", "exit"] => {
+            parse_number(value).map(Rule::Require).ok_or_else(|| format!("invalid value in {}", s))
+        }
+        ["require", ..] => Err(format!("expected require r0 == VALUE at exit, the only register structured programs set: {}", s)),
+        ["forbid", ..] => {
+            let names = s.trim_start()["forbid".len()..].split(',').map(str::trim);
+            let families = names.map(|name| {
+                FAMILIES.iter().copied().find(|&family| family == name).ok_or_else(|| format!("unknown family {} (one of {})", name, FAMILIES.join(", ")))
+            });
+            families.collect::<Result<Vec<_>, _>>().map(Rule::Forbid)
+        }
+        ["stack_usage", "<=", bytes] => match bytes.parse::<u16>() {
+            Ok(bytes) if bytes <= STACK_SIZE => Ok(Rule::StackUsage(bytes)),
+            _ => Err(format!("stack usage must be a byte count up to the {}-byte stack: {}", STACK_SIZE, s)),
+        },
+        _ => Err(format!("expected require r0 == VALUE at exit, forbid FAMILY[, FAMILY...] or stack_usage <= BYTES: {}", s)),
+    }
+}
+
+/// The rules of a run, combined
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    /// What r0 must hold at exit, or None to pick it at random
+    pub result: Option<u64>,
+    forbidden: Vec<&'static str>,
+    /// Bytes below r10 programs may use, or None to leave the stack alone
+    pub stack_usage: Option<u16>,
+}
+
+impl Rules {
+    /// Combines rules, failing on those no structured program can meet together
+    pub fn new(rules: &[Rule]) -> Result<Self, String> {
+        let mut combined = Rules::default();
+        for rule in rules {
+            match rule {
+                Rule::Require(value) if combined.result.is_some_and(|r| r != *value) => {
+                    return Err(format!("rules require both {:#x} and {:#x} in r0 at exit", combined.result.unwrap(), value));
+                }
+                Rule::Require(value) => combined.result = Some(*value),
+                Rule::Forbid(families) => combined.forbidden.extend(families),
+                Rule::StackUsage(bytes) => combined.stack_usage = Some(combined.stack_usage.map_or(*bytes, |b| b.min(*bytes))),
+            }
+        }
+
+        let allowed = |family: &str| !combined.forbidden.contains(&family);
+        if !allowed("lddw") && !allowed("mov") {
+            return Err("structured programs need lddw or mov to load constants".to_string());
+        }
+        if !["add", "sub", "or", "xor"].into_iter().any(allowed) {
+            return Err("structured programs need add, sub, or or xor to accumulate with".to_string());
+        }
+        if let Some(result) = combined.result.filter(|&r| !allowed("lddw") && r != r as i32 as u64) {
+            return Err(format!("without lddw, r0 at exit must fit a sign-extended 32-bit immediate, which {:#x} does not", result));
+        }
+        Ok(combined)
+    }
+
+    /// Whether the rules let `insn` appear
+    pub fn allows(&self, insn: &Instruction) -> bool {
+        !self.forbidden.contains(&family(insn.opcode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_parse() {
+        let cases = [
+            ("require r0 == 5 at exit", Rule::Require(5)),
+            ("require  r0 ==\t0x10 at exit", Rule::Require(0x10)),
+            ("require r0 == -1 at exit", Rule::Require(u64::MAX)),
+            ("forbid div, mod", Rule::Forbid(vec!["div", "mod"])),
+            ("  forbid jeq", Rule::Forbid(vec!["jeq"])),
+            ("stack_usage <= 64", Rule::StackUsage(64)),
+            ("stack_usage <= 512", Rule::StackUsage(STACK_SIZE)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn malformed_rules_fail_with_their_reason() {
+        let families = FAMILIES.join(", ");
+        let cases = [
+            ("require r0 == x at exit", "invalid value in require r0 == x at exit".to_string()),
+            (
+                "require r1 == 5 at exit",
+                "expected require r0 == VALUE at exit, the only register structured programs set: require r1 == 5 at exit".to_string(),
+            ),
+            ("forbid div, jmp", format!("unknown family jmp (one of {})", families)),
+            ("forbid", format!("unknown family  (one of {})", families)),
+            ("stack_usage <= 513", "stack usage must be a byte count up to the 512-byte stack: stack_usage <= 513".to_string()),
+            ("stack_usage <= -1", "stack usage must be a byte count up to the 512-byte stack: stack_usage <= -1".to_string()),
+            (
+                "stack_usage < 64",
+                "expected require r0 == VALUE at exit, forbid FAMILY[, FAMILY...] or stack_usage <= BYTES: stack_usage < 64".to_string(),
+            ),
+            ("", "expected require r0 == VALUE at exit, forbid FAMILY[, FAMILY...] or stack_usage <= BYTES: ".to_string()),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).unwrap_err(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn rules_combine_or_fail_when_unsatisfiable() {
+        let combined = Rules::new(&[Rule::StackUsage(64), Rule::Require(5), Rule::StackUsage(32), Rule::Require(5)]).unwrap();
+        assert_eq!((combined.result, combined.stack_usage), (Some(5), Some(32)));
+
+        let cases = [
+            (vec![Rule::Require(1), Rule::Require(2)], "rules require both 0x1 and 0x2 in r0 at exit"),
+            (vec![Rule::Forbid(vec!["lddw", "mov"])], "structured programs need lddw or mov to load constants"),
+            (vec![Rule::Forbid(vec!["add", "sub", "or", "xor"])], "structured programs need add, sub, or or xor to accumulate with"),
+            (
+                vec![Rule::Forbid(vec!["lddw"]), Rule::Require(1 << 32)],
+                "without lddw, r0 at exit must fit a sign-extended 32-bit immediate, which 0x100000000 does not",
+            ),
+        ];
+        for (rules, expected) in cases {
+            assert_eq!(Rules::new(&rules).unwrap_err(), expected, "{:?}", rules);
+        }
+    }
+
+    #[test]
+    fn families_name_instructions_as_forbid_does() {
+        let cases = [
+            (0x07, "add"),
+            (0x04, "add"),
+            (0xb7, "mov"),
+            (0xd4, "end"),
+            (0x18, "lddw"),
+            (0x20, "ld"),
+            (0x61, "ldx"),
+            (0x62, "st"),
+            (0x63, "stx"),
+            (0xdb, "atomic"),
+            (0x05, "ja"),
+            (0x1d, "jeq"),
+            (0x85, "call"),
+            (0x95, "exit"),
+            (0xde, "jsle"),
+        ];
+        for (opcode, expected) in cases {
+            assert_eq!(family(opcode), expected, "{:#x}", opcode);
+        }
+    }
+}
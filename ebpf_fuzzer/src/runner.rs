@@ -0,0 +1,194 @@
+//! Feeds generated programs to an external target, e.g. a sanitizer-instrumented loader
+
+use crate::adaptive::Tuner;
+#[cfg(unix)]
+use crate::afl::{Forkserver, Status};
+use crate::findings::{self, Class, Counts};
+use crate::mismatch::Mismatches;
+use crate::rejections::Tally;
+use crate::{generate_test, limits, render_program, repro, Args, GenOptions};
+use rand::Rng;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::Duration;
+
+/// Markers sanitizer runtimes print at the start of a report
+const SANITIZER_MARKERS: &[(&str, &str)] = &[
+    ("ERROR: AddressSanitizer", "asan"),
+    ("ERROR: LeakSanitizer", "lsan"),
+    ("WARNING: MemorySanitizer", "msan"),
+    ("WARNING: ThreadSanitizer", "tsan"),
+    ("runtime error:", "ubsan"),
+];
+
+#[derive(Debug)]
+enum Verdict {
+    /// The target accepted or rejected the program without incident
+    Clean,
+    /// A sanitizer printed a report
+    Sanitizer(&'static str),
+    /// The target was killed by a signal
+    Signal(i32),
+    /// The target ran past the forkserver timeout
+    TimedOut,
+}
+
+impl Verdict {
+    fn class(&self) -> Option<Class> {
+        match self {
+            Verdict::Clean => None,
+            Verdict::Sanitizer(_) | Verdict::Signal(_) => Some(Class::Crash),
+            Verdict::TimedOut => Some(Class::Hang),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Clean => write!(f, "clean"),
+            Verdict::Sanitizer(name) => write!(f, "{} report", name),
+            Verdict::Signal(signal) => write!(f, "killed by signal {}", signal),
+            Verdict::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+fn classify(output: &Output) -> Verdict {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some((_, name)) = SANITIZER_MARKERS.iter().find(|(marker, _)| stderr.contains(marker)) {
+        return Verdict::Sanitizer(name);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = output.status.signal() {
+            return Verdict::Signal(signal);
+        }
+    }
+    Verdict::Clean
+}
+
+/// Substitutes the input path for @@, or returns None if the target reads stdin
+fn target_command(target_cmd: &str, path: &str) -> Option<String> {
+    target_cmd.contains("@@").then(|| target_cmd.replace("@@", path))
+}
+
+/// Runs the target once through its forkserver, or None if it timed out
+#[cfg(unix)]
+fn execute_forked(forkserver: &mut Forkserver, input: &[u8]) -> Option<Output> {
+    use std::os::unix::process::ExitStatusExt;
+    let status = match forkserver.run(input) {
+        Status::Exited(code) => ExitStatus::from_raw(code << 8),
+        Status::Signaled(signal) => ExitStatus::from_raw(signal),
+        Status::TimedOut => return None,
+    };
+    Some(Output { status, stdout: Vec::new(), stderr: forkserver.stderr() })
+}
+
+fn execute(args: &Args, target_cmd: &str, input: &[u8]) -> Output {
+    let path = std::env::temp_dir().join(format!("ebpf_fuzzer_run_{}", std::process::id()));
+    fs::write(&path, input).expect("Failed to write target input");
+
+    let cmd = target_command(target_cmd, &repro::quote(&path.to_string_lossy()));
+    let mut command = Command::new("sh");
+    limits::apply(&mut command, args);
+    let mut child = command
+        .arg("-c")
+        .arg(cmd.as_deref().unwrap_or(target_cmd))
+        .stdin(if cmd.is_some() { Stdio::null() } else { Stdio::piped() })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run target");
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The target may exit without reading all of its input
+        let _ = stdin.write_all(input);
+    }
+    child.wait_with_output().expect("Failed to wait for target")
+}
+
+/// Runs every generated program through the target and archives those that trip a sanitizer
+/// or crash it as crashes. With `rejections`, programs the target exits with an error on are
+/// grouped by the reason in its stderr, and the first of each group of verdicts the crate's
+/// model disagrees with is archived as a mismatch. With `adaptive`, generation is tuned from how far the
+/// target's verifier gets into each program. With `forkserver`, the target is started once
+/// under its AFL forkserver, and programs it runs longer than the timeout on are archived as
+/// hangs.
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, target_cmd: &str, rejections: bool, adaptive: bool, forkserver: Option<Duration>) {
+    let mut findings = Counts::default();
+    let mut tally = Tally::default();
+    let mut mismatches = Mismatches::default();
+    let mut tuner = adaptive.then(|| Tuner::new(opts));
+    #[cfg(unix)]
+    let mut forkserver = forkserver.map(|timeout| {
+        Forkserver::start(args, target_cmd, timeout).unwrap_or_else(|e| {
+            eprintln!("Failed to start {} under its forkserver: {}", target_cmd, e);
+            std::process::exit(1);
+        })
+    });
+    // Forkservers need Unix pipes and System V shared memory
+    #[cfg(not(unix))]
+    let _ = forkserver;
+
+    for i in 0..args.count {
+        let tuned = tuner.as_mut().map(|tuner| tuner.options(rng, opts));
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, tuned.as_ref().unwrap_or(opts));
+        let input = render_program(args, &bytes, result);
+        #[cfg(unix)]
+        let output = match &mut forkserver {
+            Some(forkserver) => execute_forked(forkserver, &input),
+            None => Some(execute(args, target_cmd, &input)),
+        };
+        #[cfg(not(unix))]
+        let output = Some(execute(args, target_cmd, &input));
+        if let (Some(tuner), Some(output)) = (&mut tuner, &output) {
+            tuner.update(opts, &bytes, output.status.success(), &String::from_utf8_lossy(&output.stderr));
+        }
+
+        let verdict = output.as_ref().map_or(Verdict::TimedOut, classify);
+        let (class, description) = match verdict.class() {
+            Some(class) => (class, verdict.to_string()),
+            None if rejections => {
+                let output = output.as_ref().expect("Only timeouts leave no output");
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let rejected = output.status.code().is_some_and(|code| code != 0);
+                if rejected {
+                    tally.add(&stderr);
+                }
+                match mismatches.check(&bytes, if rejected { Err(&stderr) } else { Ok(()) }) {
+                    Some(mismatch) => (Class::Mismatch, mismatch),
+                    None => continue,
+                }
+            }
+            None => continue,
+        };
+        println!("program {}: {}", i, description);
+        findings.add(class);
+
+        if let Some(path) = findings::write(args, class, i.into(), Some(opts.seed), &bytes, result) {
+            let stderr = output.map_or_else(Vec::new, |output| output.stderr);
+            fs::write(format!("{}.log", path), stderr).expect("Failed to write target report");
+            let rerun = target_command(target_cmd, &repro::quote(&path))
+                .unwrap_or_else(|| format!("{} < {}", target_cmd, repro::quote(&path)));
+            repro::write(&path, i.into(), opts.seed, &rerun);
+        }
+    }
+
+    if rejections {
+        tally.print("the target");
+        mismatches.print("the target");
+    }
+    if let Some(tuner) = &tuner {
+        tuner.print(opts);
+    }
+    println!("{} across {} programs", findings, args.count);
+    if findings.total() > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,66 @@
+//! Coverage of rbpf itself, from the SanitizerCoverage 8-bit counters of an instrumented
+//! build. Every instrumented module registers its counters through
+//! `__sanitizer_cov_8bit_counters_init` before main, so this also picks up C libraries linked
+//! in and built with clang's `-fsanitize-coverage=inline-8bit-counters`.
+
+use crate::fuzz::bucket;
+use std::sync::Mutex;
+
+/// Counter regions as [start, stop) addresses
+static REGIONS: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+/// Called by the instrumentation with the counters of each module
+#[no_mangle]
+pub extern "C" fn __sanitizer_cov_8bit_counters_init(start: *mut u8, stop: *mut u8) {
+    let mut regions = REGIONS.lock().unwrap();
+    if start < stop && !regions.contains(&(start as usize, stop as usize)) {
+        regions.push((start as usize, stop as usize));
+    }
+}
+
+fn counters(start: usize, stop: usize) -> &'static mut [u8] {
+    // SAFETY: the instrumentation registered the region, which lives as long as the process
+    unsafe { std::slice::from_raw_parts_mut(start as *mut u8, stop - start) }
+}
+
+/// Counter hits no run has reached yet, across all regions
+pub struct Feedback {
+    regions: Vec<(usize, usize)>,
+    virgin: Vec<u8>,
+}
+
+impl Feedback {
+    pub fn new() -> Feedback {
+        let regions = REGIONS.lock().unwrap().clone();
+        assert!(
+            !regions.is_empty(),
+            "No SanitizerCoverage counters registered; build with RUSTFLAGS=\"-Cpasses=sancov-module -Cllvm-args=-sanitizer-coverage-level=3 -Cllvm-args=-sanitizer-coverage-inline-8bit-counters\""
+        );
+        let size = regions.iter().map(|(start, stop)| stop - start).sum();
+        println!("tracking {} coverage counters", size);
+        Feedback { regions, virgin: vec![0xff; size] }
+    }
+
+    /// Clears the counters, so that only what runs from now on is counted
+    pub fn reset(&self) {
+        for &(start, stop) in &self.regions {
+            counters(start, stop).fill(0);
+        }
+    }
+
+    /// Whether the code run since the last reset reached a hit-count bucket no earlier run did
+    pub fn new_coverage(&mut self) -> bool {
+        let mut new = false;
+        let mut virgin = self.virgin.iter_mut();
+        for &(start, stop) in &self.regions {
+            for (count, virgin) in counters(start, stop).iter().zip(&mut virgin) {
+                let hit = bucket(*count);
+                if hit & *virgin != 0 {
+                    *virgin &= !hit;
+                    new = true;
+                }
+            }
+        }
+        new
+    }
+}
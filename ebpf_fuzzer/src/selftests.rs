@@ -0,0 +1,413 @@
+//! Imports the programs of the kernel's BPF selftests as seeds. test_verifier-style sources
+//! spell their programs as arrays of the instruction macros from linux/filter.h, e.g.
+//! `.insns = { BPF_MOV64_IMM(BPF_REG_0, 0), BPF_EXIT_INSN() }`, which this expands.
+
+use crate::{write_output, Args, Instruction};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Constants of linux/bpf_common.h and linux/bpf.h the macros and their arguments use
+const CONSTANTS: &[(&str, i64)] = &[
+    ("BPF_LD", 0x00), ("BPF_LDX", 0x01), ("BPF_ST", 0x02), ("BPF_STX", 0x03),
+    ("BPF_ALU", 0x04), ("BPF_JMP", 0x05), ("BPF_JMP32", 0x06), ("BPF_ALU64", 0x07),
+    ("BPF_W", 0x00), ("BPF_H", 0x08), ("BPF_B", 0x10), ("BPF_DW", 0x18),
+    ("BPF_IMM", 0x00), ("BPF_ABS", 0x20), ("BPF_IND", 0x40), ("BPF_MEM", 0x60),
+    ("BPF_MEMSX", 0x80), ("BPF_ATOMIC", 0xc0), ("BPF_XADD", 0xc0),
+    ("BPF_ADD", 0x00), ("BPF_SUB", 0x10), ("BPF_MUL", 0x20), ("BPF_DIV", 0x30),
+    ("BPF_OR", 0x40), ("BPF_AND", 0x50), ("BPF_LSH", 0x60), ("BPF_RSH", 0x70),
+    ("BPF_NEG", 0x80), ("BPF_MOD", 0x90), ("BPF_XOR", 0xa0), ("BPF_MOV", 0xb0),
+    ("BPF_ARSH", 0xc0), ("BPF_END", 0xd0),
+    ("BPF_JA", 0x00), ("BPF_JEQ", 0x10), ("BPF_JGT", 0x20), ("BPF_JGE", 0x30),
+    ("BPF_JSET", 0x40), ("BPF_JNE", 0x50), ("BPF_JSGT", 0x60), ("BPF_JSGE", 0x70),
+    ("BPF_CALL", 0x80), ("BPF_EXIT", 0x90), ("BPF_JLT", 0xa0), ("BPF_JLE", 0xb0),
+    ("BPF_JSLT", 0xc0), ("BPF_JSLE", 0xd0),
+    ("BPF_K", 0x00), ("BPF_X", 0x08), ("BPF_TO_LE", 0x00), ("BPF_TO_BE", 0x08),
+    ("BPF_FROM_LE", 0x00), ("BPF_FROM_BE", 0x08),
+    ("BPF_FETCH", 0x01), ("BPF_XCHG", 0xe1), ("BPF_CMPXCHG", 0xf1),
+    ("BPF_PSEUDO_MAP_FD", 1), ("BPF_PSEUDO_MAP_VALUE", 2), ("BPF_PSEUDO_BTF_ID", 3),
+    ("BPF_PSEUDO_FUNC", 4), ("BPF_PSEUDO_MAP_IDX", 5), ("BPF_PSEUDO_MAP_IDX_VALUE", 6),
+    ("BPF_PSEUDO_CALL", 1), ("BPF_PSEUDO_KFUNC_CALL", 2),
+    ("BPF_REG_0", 0), ("BPF_REG_1", 1), ("BPF_REG_2", 2), ("BPF_REG_3", 3),
+    ("BPF_REG_4", 4), ("BPF_REG_5", 5), ("BPF_REG_6", 6), ("BPF_REG_7", 7),
+    ("BPF_REG_8", 8), ("BPF_REG_9", 9), ("BPF_REG_10", 10), ("BPF_REG_FP", 10),
+    ("BPF_REG_ARG1", 1), ("BPF_REG_ARG2", 2), ("BPF_REG_ARG3", 3), ("BPF_REG_ARG4", 4),
+    ("BPF_REG_ARG5", 5), ("BPF_REG_CTX", 6),
+];
+
+/// C integer types casts in the arguments may name, which the evaluator skips
+const TYPES: &[&str] = &["u8", "u16", "u32", "u64", "s8", "s16", "s32", "s64", "__u8", "__u16", "__u32", "__u64", "__s8", "__s16", "__s32", "__s64", "int", "unsigned", "long"];
+
+/// Helper IDs by their BPF_FUNC_ name, from the ___BPF_FUNC_MAPPER list of linux/bpf.h.
+/// Older headers list names only, numbered from bpf_unspec (0); newer ones give each ID.
+fn helper_ids(header: &str) -> Vec<(String, i64)> {
+    let text = fs::read_to_string(header).expect("Failed to read helper header");
+    let start = text.find("BPF_FUNC_MAPPER(FN").expect("No __BPF_FUNC_MAPPER in helper header");
+    let mut ids = Vec::new();
+    for line in text[start..].lines().skip(1) {
+        if let Some(args) = line.trim().strip_prefix("FN(").and_then(|rest| rest.split(')').next()) {
+            let mut words = args.split(',').map(str::trim);
+            let name = words.next().unwrap_or_default();
+            let id = words.next().and_then(|id| id.parse().ok()).unwrap_or(ids.len() as i64);
+            ids.push((format!("BPF_FUNC_{}", name), id));
+        }
+        if !line.trim_end().ends_with('\\') {
+            break;
+        }
+    }
+    ids
+}
+
+/// Removes comments and preprocessor lines
+fn strip(source: &str) -> String {
+    let mut text = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("/*") {
+        text.push_str(&rest[..start]);
+        rest = rest[start..].find("*/").map_or("", |end| &rest[start + end + 2..]);
+    }
+    text.push_str(rest);
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .map(|line| line.find("//").map_or(line, |comment| &line[..comment]))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits on the commas outside parentheses and braces, leaving out empty parts
+fn split_top(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(text[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Bodies of the instruction arrays in a source: `.insns = { ... }` initializers and
+/// `struct bpf_insn NAME[] = { ... }` definitions
+fn arrays(text: &str) -> Vec<&str> {
+    let mut bodies = Vec::new();
+    let mut rest = text;
+    loop {
+        let next = [".insns", "struct bpf_insn"].iter().filter_map(|marker| rest.find(marker)).min();
+        let Some(at) = next else { break };
+        let Some(open) = rest[at..].find('{').map(|open| at + open) else { break };
+        // Only direct initializers, not e.g. a function taking a struct bpf_insn pointer
+        if rest[at..open].contains(';') || !rest[at..open].contains('=') {
+            rest = &rest[at + 1..];
+            continue;
+        }
+        let mut depth = 0;
+        let close = rest[open..].char_indices().find_map(|(i, c)| {
+            depth += match c {
+                '{' => 1,
+                '}' => -1,
+                _ => 0,
+            };
+            (depth == 0).then_some(open + i)
+        });
+        let Some(close) = close else { break };
+        bodies.push(&rest[open + 1..close]);
+        rest = &rest[close..];
+    }
+    bodies
+}
+
+/// Evaluates the integer expressions macro arguments are, with a recursive descent over
+/// C's binary operators from `|` down to `*`
+struct Expr<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+    names: &'a HashMap<String, i64>,
+}
+
+impl<'a> Expr<'a> {
+    fn eval(text: &'a str, names: &'a HashMap<String, i64>) -> Result<i64, String> {
+        let mut tokens = Vec::new();
+        let mut rest = text.trim();
+        while !rest.is_empty() {
+            let len = if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+                rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len())
+            } else if rest.starts_with("<<") || rest.starts_with(">>") {
+                2
+            } else {
+                rest.chars().next().unwrap().len_utf8()
+            };
+            tokens.push(&rest[..len]);
+            rest = rest[len..].trim_start();
+        }
+        let mut expr = Expr { tokens, pos: 0, names };
+        let value = expr.binary(0)?;
+        match expr.tokens.get(expr.pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(op) = self.peek().filter(|op| LEVELS[level].contains(op)) {
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match op {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err("division by zero".to_string()),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let token = self.peek().ok_or("missing operand")?;
+        self.pos += 1;
+        match token {
+            "-" => Ok(self.unary()?.wrapping_neg()),
+            "~" => Ok(!self.unary()?),
+            "+" => self.unary(),
+            // A cast, which the truncation to the instruction's fields makes moot
+            "(" if self.peek().is_some_and(|t| TYPES.contains(&t)) => {
+                while self.peek().is_some_and(|t| t != ")") {
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                self.unary()
+            }
+            "(" => {
+                let value = self.binary(0)?;
+                match self.peek() {
+                    Some(")") => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("unbalanced parentheses".to_string()),
+                }
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => {
+                let digits = token.trim_end_matches(['u', 'U', 'l', 'L']);
+                let parsed = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16).map(|v| v as i64),
+                    None => digits.parse(),
+                };
+                parsed.map_err(|_| format!("invalid number {}", token))
+            }
+            _ => self.names.get(token).copied().ok_or_else(|| format!("unknown name {}", token)),
+        }
+    }
+}
+
+/// Expands one instruction macro into its slots
+fn expand(call: &str, names: &HashMap<String, i64>) -> Result<Vec<Instruction>, String> {
+    let open = call.find('(').ok_or_else(|| format!("not a macro: {}", call))?;
+    let name = call[..open].trim();
+    let body = call[open + 1..].strip_suffix(')').ok_or_else(|| format!("not a macro: {}", call))?;
+    let args: Vec<i64> = split_top(body).into_iter().map(|arg| Expr::eval(arg, names)).collect::<Result<_, _>>()?;
+    let arity = |n: usize| if args.len() == n { Ok(()) } else { Err(format!("{} takes {} arguments", name, n)) };
+    let insn = |code: i64, dst: i64, src: i64, off: i64, imm: i64| Instruction::new(code as u8, dst as u8 & 0xf, src as u8 & 0xf, off as u16, imm as u32);
+    let lddw = |dst: i64, src: i64, off1: i64, off2: i64, imm1: i64, imm2: i64| vec![insn(0x18, dst, src, off1, imm1), insn(0, 0, 0, off2, imm2)];
+    let a = |i: usize| args[i];
+
+    let insns = match name {
+        "BPF_ALU64_REG" | "BPF_ALU32_REG" | "BPF_ALU64_IMM" | "BPF_ALU32_IMM" => {
+            arity(3)?;
+            let class = if name.starts_with("BPF_ALU64") { 0x07 } else { 0x04 };
+            if name.ends_with("REG") { vec![insn(class | a(0) | 0x08, a(1), a(2), 0, 0)] } else { vec![insn(class | a(0), a(1), 0, 0, a(2))] }
+        }
+        "BPF_ALU64_REG_OFF" | "BPF_ALU32_REG_OFF" | "BPF_ALU64_IMM_OFF" | "BPF_ALU32_IMM_OFF" => {
+            arity(4)?;
+            let class = if name.starts_with("BPF_ALU64") { 0x07 } else { 0x04 };
+            if name.ends_with("REG_OFF") { vec![insn(class | a(0) | 0x08, a(1), a(2), a(3), 0)] } else { vec![insn(class | a(0), a(1), 0, a(3), a(2))] }
+        }
+        "BPF_ENDIAN" => {
+            arity(3)?;
+            vec![insn(0x04 | 0xd0 | a(0), a(1), 0, 0, a(2))]
+        }
+        "BPF_BSWAP" => {
+            arity(2)?;
+            vec![insn(0x07 | 0xd0, a(0), 0, 0, a(1))]
+        }
+        "BPF_MOV64_REG" | "BPF_MOV32_REG" => {
+            arity(2)?;
+            vec![insn(if name == "BPF_MOV64_REG" { 0xbf } else { 0xbc }, a(0), a(1), 0, 0)]
+        }
+        "BPF_MOV64_IMM" | "BPF_MOV32_IMM" => {
+            arity(2)?;
+            vec![insn(if name == "BPF_MOV64_IMM" { 0xb7 } else { 0xb4 }, a(0), 0, 0, a(1))]
+        }
+        "BPF_MOVSX64_REG" | "BPF_MOVSX32_REG" => {
+            arity(3)?;
+            vec![insn(if name == "BPF_MOVSX64_REG" { 0xbf } else { 0xbc }, a(0), a(1), a(2), 0)]
+        }
+        "BPF_MOV64_RAW" | "BPF_MOV32_RAW" => {
+            arity(4)?;
+            vec![insn(if name == "BPF_MOV64_RAW" { 0xb7 } else { 0xb4 } | (a(0) & 0x08), a(1), a(2), 0, a(3))]
+        }
+        "BPF_ZEXT_REG" => {
+            arity(1)?;
+            vec![insn(0xbc, a(0), a(0), 0, 1)]
+        }
+        "BPF_LD_IMM64" => {
+            arity(2)?;
+            lddw(a(0), 0, 0, 0, a(1), a(1) >> 32)
+        }
+        "BPF_LD_IMM64_RAW" => {
+            arity(3)?;
+            lddw(a(0), a(1), 0, 0, a(2), a(2) >> 32)
+        }
+        "BPF_LD_IMM64_RAW_FULL" => {
+            arity(6)?;
+            lddw(a(0), a(1), a(2), a(3), a(4), a(5))
+        }
+        "BPF_LD_MAP_FD" => {
+            arity(2)?;
+            lddw(a(0), 1, 0, 0, a(1), 0)
+        }
+        "BPF_LD_MAP_VALUE" => {
+            arity(3)?;
+            lddw(a(0), 2, 0, 0, a(1), a(2))
+        }
+        "BPF_LD_ABS" => {
+            arity(2)?;
+            vec![insn(0x20 | a(0), 0, 0, 0, a(1))]
+        }
+        "BPF_LD_IND" => {
+            arity(3)?;
+            vec![insn(0x40 | a(0), 0, a(1), 0, a(2))]
+        }
+        "BPF_LDX_MEM" | "BPF_LDX_MEMSX" | "BPF_STX_MEM" => {
+            arity(4)?;
+            let code = match name {
+                "BPF_LDX_MEM" => 0x61,
+                "BPF_LDX_MEMSX" => 0x81,
+                _ => 0x63,
+            };
+            vec![insn(code | a(0), a(1), a(2), a(3), 0)]
+        }
+        "BPF_ST_MEM" => {
+            arity(4)?;
+            vec![insn(0x62 | a(0), a(1), 0, a(2), a(3))]
+        }
+        "BPF_ATOMIC_OP" => {
+            arity(5)?;
+            vec![insn(0xc3 | a(0), a(2), a(3), a(4), a(1))]
+        }
+        "BPF_STX_XADD" => {
+            arity(4)?;
+            vec![insn(0xc3 | a(0), a(1), a(2), a(3), 0)]
+        }
+        "BPF_JMP_REG" | "BPF_JMP32_REG" => {
+            arity(4)?;
+            vec![insn(if name == "BPF_JMP_REG" { 0x05 } else { 0x06 } | a(0) | 0x08, a(1), a(2), a(3), 0)]
+        }
+        "BPF_JMP_IMM" | "BPF_JMP32_IMM" => {
+            arity(4)?;
+            vec![insn(if name == "BPF_JMP_IMM" { 0x05 } else { 0x06 } | a(0), a(1), 0, a(3), a(2))]
+        }
+        "BPF_JMP_A" => {
+            arity(1)?;
+            vec![insn(0x05, 0, 0, a(0), 0)]
+        }
+        "BPF_JMP32_A" => {
+            arity(1)?;
+            vec![insn(0x06, 0, 0, 0, a(0))]
+        }
+        "BPF_CALL_REL" => {
+            arity(1)?;
+            vec![insn(0x85, 0, 1, 0, a(0))]
+        }
+        "BPF_EMIT_CALL" => {
+            arity(1)?;
+            vec![insn(0x85, 0, 0, 0, a(0))]
+        }
+        "BPF_CALL_KFUNC" => {
+            arity(2)?;
+            vec![insn(0x85, 0, 2, a(0), a(1))]
+        }
+        "BPF_RAW_INSN" => {
+            arity(5)?;
+            vec![insn(a(0), a(1), a(2), a(3), a(4))]
+        }
+        "BPF_EXIT_INSN" => {
+            arity(0)?;
+            vec![insn(0x95, 0, 0, 0, 0)]
+        }
+        _ => return Err(format!("unknown macro {}", name)),
+    };
+    Ok(insns)
+}
+
+/// The C sources under `path`, or `path` itself if it is a file
+fn sources(path: &Path) -> Vec<std::path::PathBuf> {
+    if !path.is_dir() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<_> = fs::read_dir(path)
+        .expect("Failed to read selftest directory")
+        .map(|entry| entry.expect("Failed to read selftest directory").path())
+        .filter(|path| path.is_dir() || path.extension().is_some_and(|ext| ext == "c"))
+        .flat_map(|path| sources(&path))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Writes every program spelled out in the selftest sources at `paths`, files or
+/// directories of them, to the output. Programs calling helpers by their BPF_FUNC_ name
+/// need the helper IDs from `header`, linux/bpf.h. Programs using anything else the
+/// importer does not know, such as offsetof, are skipped and counted by reason.
+pub fn run(args: &Args, paths: &[String], header: Option<&str>) {
+    let mut names: HashMap<String, i64> = CONSTANTS.iter().map(|&(name, value)| (name.to_string(), value)).collect();
+    names.extend(header.map(helper_ids).unwrap_or_default());
+
+    let mut imported = 0;
+    let mut skipped: BTreeMap<String, u32> = BTreeMap::new();
+    for path in paths.iter().flat_map(|path| sources(Path::new(path))) {
+        let text = strip(&fs::read_to_string(&path).unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err)));
+        for body in arrays(&text) {
+            let program: Result<Vec<Instruction>, String> = split_top(body).into_iter().map(|call| expand(call, &names)).collect::<Result<Vec<_>, _>>().map(|insns| insns.concat());
+            match program {
+                Ok(insns) if !insns.is_empty() => {
+                    let bytes: Vec<u8> = insns.iter().flat_map(|insn| insn.to_bytes()).collect();
+                    write_output(args, imported, &bytes, None);
+                    imported += 1;
+                }
+                Ok(_) => {}
+                Err(reason) => *skipped.entry(reason).or_default() += 1,
+            }
+        }
+    }
+
+    println!("imported {} programs, skipped {}", imported, skipped.values().sum::<u32>());
+    let mut reasons: Vec<_> = skipped.into_iter().collect();
+    reasons.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (reason, count) in reasons {
+        println!("{:>8}  {}", count, reason);
+    }
+}
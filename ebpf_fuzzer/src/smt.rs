@@ -0,0 +1,329 @@
+//! Programs synthesized to meet constraints on what they compute: a skeleton of ALU
+//! instructions and conditional jumps is generated at random, with its immediates left
+//! unknown, and z3 solves the instruction semantics for immediates meeting the constraints
+
+use crate::interp::{self, Outcome};
+use crate::{parse_number, reserved_fields, write_output, Args, GenOptions, Instruction, Template};
+use rand::seq::IndexedRandom;
+use rand::Rng;
+use z3::ast::{Ast, BV};
+use z3::{Config, Context, Params, SatResult, Solver};
+
+/// Skeletons tried for a program before its constraints are given up on
+const ATTEMPTS: u32 = 100;
+/// Milliseconds z3 gets for a skeleton
+const TIMEOUT_MS: u32 = 5_000;
+
+/// A condition a synthesized program must meet
+#[derive(Debug, Clone)]
+pub enum Constraint {
+    /// A register holds a value at exit, or does not
+    Register { reg: u8, value: u64, equal: bool },
+    /// The conditional jump with this ordinal among those executed goes this way
+    Branch { ordinal: usize, taken: bool },
+}
+
+/// Parses "rN=VALUE", "rN!=VALUE", "taken=N" or "not-taken=N", with values in decimal,
+/// negative decimal or 0x hex
+pub fn parse(s: &str) -> Result<Constraint, String> {
+    let number = |v: &str| parse_number(v.trim()).ok_or_else(|| format!("invalid number: {}", v.trim()));
+
+    if let Some((reg, value)) = s.split_once("!=").or_else(|| s.split_once('=')) {
+        let equal = !s.contains("!=");
+        match reg.trim() {
+            "taken" | "not-taken" if equal => {
+                return Ok(Constraint::Branch { ordinal: number(value)? as usize, taken: reg.trim() == "taken" });
+            }
+            reg => {
+                if let Some(reg) = reg.strip_prefix('r').and_then(|n| n.parse::<u8>().ok()).filter(|&r| r < 10) {
+                    return Ok(Constraint::Register { reg, value: number(value)?, equal });
+                }
+            }
+        }
+    }
+    Err(format!("expected rN=VALUE, rN!=VALUE, taken=N or not-taken=N with N below 10 for registers: {}", s))
+}
+
+/// Whether the templates' instructions can be solved for: ALU operations and conditional jumps
+fn solvable(template: &Template) -> bool {
+    match template.opcode & 0x07 {
+        0x04 | 0x07 => true,
+        0x05 | 0x06 => !matches!(template.opcode & 0xf0, 0x00 | 0x80 | 0x90),
+        _ => false,
+    }
+}
+
+/// Builds z3 terms for the instructions of a program along the path it takes
+struct Skeleton<'ctx> {
+    ctx: &'ctx Context,
+    solver: Solver<'ctx>,
+    insns: Vec<Instruction>,
+    /// Unknown immediates, with the instruction and for LD_DW_IMM the half they belong to
+    unknowns: Vec<(usize, BV<'ctx>)>,
+    regs: Vec<BV<'ctx>>,
+}
+
+impl<'ctx> Skeleton<'ctx> {
+    fn constant(&self, value: u64, bits: u32) -> BV<'ctx> {
+        BV::from_u64(self.ctx, value, bits)
+    }
+
+    /// A fresh unknown immediate of the next instruction, or its second slot after LD_DW_IMM
+    fn unknown(&mut self) -> BV<'ctx> {
+        let imm = BV::new_const(self.ctx, format!("imm{}", self.unknowns.len()), 32);
+        self.unknowns.push((self.insns.len(), imm.clone()));
+        imm
+    }
+
+    /// The immediate of `insn`: as drawn if the template fixes it, it is reserved or selects
+    /// a byte order width, unknown otherwise
+    fn imm(&mut self, insn: &Instruction, template: &Template) -> BV<'ctx> {
+        if template.imm.is_some() || reserved_fields(insn.opcode).imm || byte_order(insn.opcode) {
+            self.constant(insn.imm as u64, 32)
+        } else {
+            self.unknown()
+        }
+    }
+
+    /// Loads an unknown 64-bit value into `reg`
+    fn load(&mut self, reg: u8) {
+        let low = self.unknown();
+        self.insns.push(Instruction::new(0x18, reg, 0, 0, 0));
+        let high = self.unknown();
+        self.insns.push(Instruction::new(0, 0, 0, 0, 0));
+        self.regs[reg as usize] = high.concat(&low);
+    }
+
+    /// Appends an ALU instruction, computing its result as `eval::alu` does
+    fn alu(&mut self, insn: Instruction, template: &Template) {
+        let is64 = insn.opcode & 0x07 == 0x07;
+        let op = insn.opcode & 0xf0;
+        let byte_swap = byte_order(insn.opcode);
+        let imm = self.imm(&insn, template);
+        let operand = if insn.opcode & 0x08 != 0 { self.regs[insn.src as usize].clone() } else { imm.sign_ext(32) };
+        let bits = if is64 { 64 } else { 32 };
+        let (d, s) = if is64 {
+            (self.regs[insn.dst as usize].clone(), operand)
+        } else {
+            (self.regs[insn.dst as usize].extract(31, 0), operand.extract(31, 0))
+        };
+        // Verifiers reject division by an immediate zero and shifts by an immediate past the width
+        if insn.opcode & 0x08 == 0 && !reserved_fields(insn.opcode).imm && !byte_swap {
+            match op {
+                0x30 | 0x90 => self.solver.assert(&s._eq(&self.constant(0, bits)).not()),
+                0x60 | 0x70 | 0xc0 => self.solver.assert(&imm.bvult(&self.constant(bits as u64, 32))),
+                _ => {}
+            }
+        }
+
+        let zero = self.constant(0, bits);
+        let is_zero = s._eq(&zero);
+        let signed = insn.offset == 1;
+        let amount = s.bvand(&self.constant(bits as u64 - 1, bits));
+        let result = match op {
+            0x00 => d.bvadd(&s),
+            0x10 => d.bvsub(&s),
+            0x20 => d.bvmul(&s),
+            0x30 => is_zero.ite(&zero, &if signed { d.bvsdiv(&s) } else { d.bvudiv(&s) }),
+            0x40 => d.bvor(&s),
+            0x50 => d.bvand(&s),
+            0x60 => d.bvshl(&amount),
+            0x70 => d.bvlshr(&amount),
+            0x80 => d.bvneg(),
+            0x90 => is_zero.ite(&d, &if signed { d.bvsrem(&s) } else { d.bvurem(&s) }),
+            0xa0 => d.bvxor(&s),
+            0xb0 => match insn.offset {
+                8 | 16 | 32 if (insn.offset as u32) < bits => s.extract(insn.offset as u32 - 1, 0).sign_ext(bits - insn.offset as u32),
+                _ => s,
+            },
+            0xc0 => d.bvashr(&amount),
+            _ => self.byte_swap(insn),
+        };
+        self.regs[insn.dst as usize] = if is64 || byte_swap { result } else { result.zero_ext(32) };
+        self.insns.push(insn);
+    }
+
+    /// The result of a byte order instruction: to_le truncates on a little-endian machine,
+    /// to_be and bswap swap the bytes
+    fn byte_swap(&self, insn: Instruction) -> BV<'ctx> {
+        let bits = match insn.imm {
+            16 | 32 => insn.imm,
+            _ => 64,
+        };
+        let d = self.regs[insn.dst as usize].extract(bits - 1, 0);
+        let value = if insn.opcode == 0xd4 {
+            d
+        } else {
+            (1..bits / 8).fold(d.extract(7, 0), |swapped, byte| swapped.concat(&d.extract(byte * 8 + 7, byte * 8)))
+        };
+        if bits < 64 {
+            value.zero_ext(64 - bits)
+        } else {
+            value
+        }
+    }
+
+    /// Appends a conditional jump, with whether it is taken added to the path conditions
+    fn jump(&mut self, insn: Instruction, template: &Template, taken: bool) {
+        let imm = self.imm(&insn, template);
+        let operand = if insn.opcode & 0x08 != 0 { self.regs[insn.src as usize].clone() } else { imm.sign_ext(32) };
+        let (d, s, bits) = if insn.opcode & 0x07 == 0x05 {
+            (self.regs[insn.dst as usize].clone(), operand, 64)
+        } else {
+            (self.regs[insn.dst as usize].extract(31, 0), operand.extract(31, 0), 32)
+        };
+        let condition = match insn.opcode & 0xf0 {
+            0x10 => d._eq(&s),
+            0x20 => d.bvugt(&s),
+            0x30 => d.bvuge(&s),
+            0x40 => d.bvand(&s)._eq(&self.constant(0, bits)).not(),
+            0x50 => d._eq(&s).not(),
+            0x60 => d.bvsgt(&s),
+            0x70 => d.bvsge(&s),
+            0xa0 => d.bvult(&s),
+            0xb0 => d.bvule(&s),
+            0xc0 => d.bvslt(&s),
+            _ => d.bvsle(&s),
+        };
+        self.solver.assert(&if taken { condition } else { condition.not() });
+        self.insns.push(insn);
+    }
+}
+
+/// Synthesizes a program of about `size` instructions meeting `constraints`, and returns it
+/// with the r0 it exits with, or None if z3 finds no immediates for the skeleton drawn
+fn synthesize<R: Rng>(rng: &mut R, ctx: &Context, size: u32, opts: &GenOptions, constraints: &[Constraint]) -> Option<(Vec<u8>, u64)> {
+    let templates: Vec<&Template> = opts.templates.iter().copied().filter(|t| solvable(t)).collect();
+    let (jumps, alus): (Vec<&Template>, Vec<&Template>) = templates.into_iter().partition(|t| matches!(t.opcode & 0x07, 0x05 | 0x06));
+    assert!(!alus.is_empty(), "No ALU instruction enabled to synthesize programs from");
+
+    // Registers the program computes with, all loaded with unknown values first
+    let writable: Vec<u8> = opts.writable_regs.iter().copied().filter(|&r| r < 10).collect();
+    let count = rng.random_range(1..=3);
+    let mut regs: Vec<u8> = writable.choose_multiple(rng, count).copied().collect();
+    for constraint in constraints {
+        if let Constraint::Register { reg, .. } = *constraint {
+            assert!(opts.writable_regs.contains(&reg), "r{} in a constraint is not writable", reg);
+            regs.push(reg);
+        }
+    }
+    regs.push(0);
+    regs.sort();
+    regs.dedup();
+
+    let branches = constraints.iter().filter_map(|c| match c {
+        Constraint::Branch { ordinal, .. } => Some(ordinal + 1),
+        _ => None,
+    });
+    let needed = branches.max().unwrap_or(0);
+    assert!(needed == 0 || !jumps.is_empty(), "No conditional jump enabled for a branch constraint");
+
+    let mut params = Params::new(ctx);
+    params.set_u32("timeout", TIMEOUT_MS);
+    let solver = Solver::new(ctx);
+    solver.set_params(&params);
+    let mut skeleton = Skeleton { ctx, solver, insns: Vec::new(), unknowns: Vec::new(), regs: (0..11).map(|_| BV::from_u64(ctx, 0, 64)).collect() };
+    for &reg in &regs {
+        skeleton.load(reg);
+    }
+
+    let size = (size as usize).max(needed * 2);
+    let mut body = 0;
+    let mut ordinal = 0;
+    while body < size {
+        let remaining = size - body;
+        // Every jump still needed for a branch constraint takes two instructions at least
+        let spare = remaining - 2 * needed.saturating_sub(ordinal + 1);
+        let jump = !jumps.is_empty() && (remaining <= 2 * needed.saturating_sub(ordinal) || rng.random_bool(0.2));
+        if !jump || spare < 2 {
+            let template = *alus.choose(rng).unwrap();
+            skeleton.alu(instruction(rng, template, &regs), template);
+            body += 1;
+            continue;
+        }
+
+        let template = *jumps.choose(rng).unwrap();
+        let taken = constraints.iter().find_map(|c| match *c {
+            Constraint::Branch { ordinal: o, taken } if o == ordinal => Some(taken),
+            _ => None,
+        });
+        let taken = taken.unwrap_or_else(|| rng.random_bool(0.5));
+        // Taken jumps skip code off the path, which never runs; others fall through into the path
+        let skip = rng.random_range(1..=(spare - 1).min(3));
+        let mut insn = instruction(rng, template, &regs);
+        insn.offset = skip as u16;
+        skeleton.jump(insn, template, taken);
+        body += 1;
+        ordinal += 1;
+        if taken {
+            for _ in 0..skip {
+                let template = *alus.choose(rng).unwrap();
+                let filler = instruction(rng, template, &regs);
+                skeleton.insns.push(filler);
+            }
+            body += skip;
+        }
+    }
+    skeleton.insns.push(Instruction::new(0x95, 0, 0, 0, 0));
+
+    for constraint in constraints {
+        if let Constraint::Register { reg, value, equal } = *constraint {
+            let holds = skeleton.regs[reg as usize]._eq(&BV::from_u64(ctx, value, 64));
+            skeleton.solver.assert(&if equal { holds } else { holds.not() });
+        }
+    }
+    if skeleton.solver.check() != SatResult::Sat {
+        return None;
+    }
+    let model = skeleton.solver.get_model()?;
+
+    let Skeleton { mut insns, unknowns, regs: values, .. } = skeleton;
+    for (at, imm) in &unknowns {
+        insns[*at].imm = model.eval(imm, true)?.as_u64()? as u32;
+    }
+    let r0 = model.eval(&values[0], true)?.as_u64()?;
+    let bytes: Vec<u8> = insns.iter().flat_map(|insn| insn.to_bytes()).collect();
+    // The reference interpreter catches semantics z3 was given wrong
+    match interp::run(&bytes) {
+        Outcome::Returned(returned) if returned == r0 => Some((bytes, r0)),
+        outcome => panic!("Synthesized program expected to return {:#x}, but the reference interpreter {}", r0, outcome),
+    }
+}
+
+/// Whether an opcode is a byte order instruction, whose immediate is the width it swaps
+fn byte_order(opcode: u8) -> bool {
+    opcode & 0xf0 == 0xd0 && matches!(opcode & 0x07, 0x04 | 0x07)
+}
+
+/// An instruction of `template` on registers of `regs`, with its fields drawn at random.
+/// Immediates are small and nonzero so instructions off the path pass verification.
+fn instruction<R: Rng>(rng: &mut R, template: &Template, regs: &[u8]) -> Instruction {
+    let reserved = reserved_fields(template.opcode);
+    let dst = *regs.choose(rng).unwrap();
+    let src = match template.src {
+        Some(src) => src,
+        None if reserved.src => 0,
+        None => *regs.choose(rng).unwrap(),
+    };
+    let imm = match template.imm {
+        Some(imm) => imm,
+        None if reserved.imm => 0,
+        None if byte_order(template.opcode) => *[16, 32, 64].choose(rng).unwrap(),
+        None => rng.random_range(1..32),
+    };
+    Instruction::new(template.opcode, dst, src, template.offset.unwrap_or(0), imm)
+}
+
+/// Writes --count programs meeting every constraint, with the r0 they exit with as their result
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions, constraints: &[Constraint]) {
+    let ctx = Context::new(&Config::new());
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let program = (0..ATTEMPTS).find_map(|_| synthesize(rng, &ctx, size, opts, constraints));
+        let Some((bytes, r0)) = program else {
+            eprintln!("No program of {} instructions meeting the constraints found in {} attempts", size, ATTEMPTS);
+            std::process::exit(1);
+        };
+        write_output(args, i, &bytes, Some(r0));
+    }
+}
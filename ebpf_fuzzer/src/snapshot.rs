@@ -0,0 +1,80 @@
+//! Golden snapshots of generated corpora, to catch changes in what a seed produces
+
+use crate::{cover_templates, generate_index, isa, program_path, program_seed, render_program, repro, setup_vm, templates_in, Args, GenOptions};
+use clap::Parser;
+use std::fs;
+
+/// Records the arguments of a generation run, including its seed, and the files it wrote:
+/// its --count programs and the `added` ones --cover-all-templates wrote after them
+pub fn record(path: &str, args: &Args, opts: &GenOptions, added: u32) {
+    let mut snapshot = format!(
+        "# ebpf_fuzzer {} snapshot, check with `ebpf_fuzzer verify {}`\n",
+        env!("CARGO_PKG_VERSION"),
+        path
+    );
+    for arg in repro::args_with_seed(opts.seed).iter().skip(1) {
+        snapshot.push_str(&format!("arg {}\n", arg));
+    }
+    for i in 0..args.count + added {
+        let Some(file) = program_path(args, i, program_seed(opts.seed, i)) else {
+            eprintln!("--snapshot needs --output to name program files");
+            std::process::exit(2);
+        };
+        snapshot.push_str(&format!("file {}\n", file));
+    }
+    fs::write(path, snapshot).expect("Failed to write snapshot");
+}
+
+/// Regenerates the corpus recorded in a snapshot and compares it byte for byte with the stored files
+pub fn verify(path: &str) {
+    let snapshot = fs::read_to_string(path).expect("Failed to read snapshot");
+    let mut recorded = vec!["ebpf_fuzzer".to_string()];
+    let mut files = Vec::new();
+    for line in snapshot.lines().filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        match line.split_once(' ') {
+            Some(("arg", arg)) => recorded.push(arg.to_string()),
+            Some(("file", file)) => files.push(file.to_string()),
+            _ => panic!("Malformed snapshot line: {}", line),
+        }
+    }
+
+    let args = Args::parse_from(recorded);
+    setup_vm(&args);
+    isa::load(args.isa_spec.as_deref(), args.isa_profile);
+    let opts = GenOptions::from_args(&args);
+
+    // The same programs the run wrote, in the same order
+    let mut programs = Vec::new();
+    let mut covered = vec![false; opts.templates.len()];
+    for i in 0..args.count {
+        let (_, bytes, result) = generate_index(&opts, i);
+        for t in templates_in(&bytes, &opts) {
+            covered[t] = true;
+        }
+        programs.push(render_program(&args, &bytes, result));
+    }
+    if args.cover_all_templates {
+        programs.extend(cover_templates(&opts, args.count, &covered).iter().map(|bytes| render_program(&args, bytes, None)));
+    }
+    assert_eq!(files.len(), programs.len(), "Snapshot lists a different number of files than the run now writes");
+
+    let mut drifted = 0;
+    for (file, program) in files.iter().zip(&programs) {
+        match fs::read(file) {
+            Ok(stored) if stored == *program => {}
+            Ok(_) => {
+                println!("{}: differs from what the recorded seed now generates", file);
+                drifted += 1;
+            }
+            Err(err) => {
+                println!("{}: {}", file, err);
+                drifted += 1;
+            }
+        }
+    }
+
+    println!("{} of {} files drifted from {}", drifted, files.len(), path);
+    if drifted > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,308 @@
+//! Structured generation: programs built around algebraic identities, so the value
+//! they return is known by construction rather than by running them
+
+use crate::program::{Program, R0};
+use crate::{cfg, vm, GenOptions, Instruction};
+use rand::seq::{IndexedRandom, SliceRandom};
+use rand::Rng;
+
+// ALU operations, to combine with a class and source bit
+const ADD: u8 = 0x00;
+const SUB: u8 = 0x10;
+const MUL: u8 = 0x20;
+const DIV: u8 = 0x30;
+const OR: u8 = 0x40;
+const AND: u8 = 0x50;
+const LSH: u8 = 0x60;
+const RSH: u8 = 0x70;
+const NEG: u8 = 0x80;
+const MOD: u8 = 0x90;
+const XOR: u8 = 0xa0;
+const MOV: u8 = 0xb0;
+const ARSH: u8 = 0xc0;
+
+const ALU64: u8 = 0x07;
+const ALU32: u8 = 0x04;
+const X: u8 = 0x08;
+
+fn alu(op: u8, dst: u8, src: u8) -> Instruction {
+    Instruction::new(ALU64 | X | op, dst, src, 0, 0)
+}
+
+fn alu_imm(op: u8, dst: u8, imm: u32) -> Instruction {
+    Instruction::new(ALU64 | op, dst, 0, 0, imm)
+}
+
+fn alu32(op: u8, dst: u8, src: u8) -> Instruction {
+    Instruction::new(ALU32 | X | op, dst, src, 0, 0)
+}
+
+/// Scratch registers of an identity, and the inputs it holds for
+struct Regs {
+    x: u8,
+    y: u8,
+    t: u8,
+    u: u8,
+}
+
+/// Code leaving 0 in `t` whatever `x` and `y` hold. Each one checks an implementation
+/// computes its operations consistently with the others.
+fn identity<R: Rng>(rng: &mut R, r: &Regs) -> Vec<Instruction> {
+    let Regs { x, y, t, u } = *r;
+    match rng.random_range(0..11) {
+        // (x | y) - (x & y) - (x ^ y)
+        0 => vec![alu(MOV, t, x), alu(OR, t, y), alu(MOV, u, x), alu(AND, u, y), alu(SUB, t, u), alu(MOV, u, x), alu(XOR, u, y), alu(SUB, t, u)],
+        // (x + y) - (x ^ y) - ((x & y) << 1)
+        1 => vec![alu(MOV, t, x), alu(ADD, t, y), alu(MOV, u, x), alu(XOR, u, y), alu(SUB, t, u), alu(MOV, u, x), alu(AND, u, y), alu_imm(LSH, u, 1), alu(SUB, t, u)],
+        // (x / y) * y + x % y - x, which also holds for y == 0 since x / 0 == 0 and x % 0 == x
+        2 => vec![alu(MOV, t, x), alu(DIV, t, y), alu(MUL, t, y), alu(MOV, u, x), alu(MOD, u, y), alu(ADD, t, u), alu(SUB, t, x)],
+        // -x + x
+        3 => vec![alu(MOV, t, x), alu_imm(NEG, t, 0), alu(ADD, t, x)],
+        // 32-bit moves zero-extend: (u32)x - ((x << 32) >> 32)
+        4 => vec![alu(MOV, t, x), alu32(MOV, t, t), alu(MOV, u, x), alu_imm(LSH, u, 32), alu_imm(RSH, u, 32), alu(SUB, t, u)],
+        // 32-bit additions zero-extend: (u32)(x + y) - (((x + y) << 32) >> 32)
+        5 => vec![alu(MOV, t, x), alu32(ADD, t, y), alu(MOV, u, x), alu(ADD, u, y), alu_imm(LSH, u, 32), alu_imm(RSH, u, 32), alu(SUB, t, u)],
+        // Swapping bytes twice only truncates, whatever the host byte order
+        6 => {
+            let (bits, mask) = *[(16, 0xffff), (32, 0), (64, 0)].choose(rng).unwrap();
+            let swap = Instruction::new(*[0xd4, 0xdc].choose(rng).unwrap(), t, 0, 0, bits);
+            let mut code = vec![alu(MOV, t, x), swap, swap, alu(MOV, u, x)];
+            match bits {
+                16 => code.push(alu_imm(AND, u, mask)),
+                32 => code.extend([alu_imm(LSH, u, 32), alu_imm(RSH, u, 32)]),
+                _ => {}
+            }
+            code.push(alu(SUB, t, u));
+            code
+        }
+        // (x s>> 63) + (x >> 63), as the arithmetic shift yields -1 for negative x
+        7 => vec![alu(MOV, t, x), alu_imm(ARSH, t, 63), alu(MOV, u, x), alu_imm(RSH, u, 63), alu(ADD, t, u)],
+        // (x s/ y) * y + x s% y - x, including y == 0 and INT64_MIN s/ -1
+        8 => {
+            let sdiv = Instruction::new(ALU64 | X | DIV, t, y, 1, 0);
+            let smod = Instruction::new(ALU64 | X | MOD, u, y, 1, 0);
+            vec![alu(MOV, t, x), sdiv, alu(MUL, t, y), alu(MOV, u, x), smod, alu(ADD, t, u), alu(SUB, t, x)]
+        }
+        // (s8)x - ((x << 56) s>> 56)
+        9 => {
+            let movsx = Instruction::new(ALU64 | X | MOV, t, x, 8, 0);
+            vec![movsx, alu(MOV, u, x), alu_imm(LSH, u, 56), alu_imm(ARSH, u, 56), alu(SUB, t, u)]
+        }
+        // bswap64(bswap64(x)) - x
+        _ => {
+            let bswap = Instruction::new(0xd7, t, 0, 0, 64);
+            vec![alu(MOV, t, x), bswap, bswap, alu(SUB, t, x)]
+        }
+    }
+}
+
+/// A random ALU operation on `dst`, changing the inputs identities see
+fn noise<R: Rng>(rng: &mut R, dst: u8, srcs: &[u8]) -> Instruction {
+    let class = if rng.random_bool(0.5) { ALU64 } else { ALU32 };
+    let op = *[ADD, SUB, MUL, DIV, OR, AND, LSH, RSH, NEG, MOD, XOR, MOV, ARSH].choose(rng).unwrap();
+    let width = if class == ALU64 { 64 } else { 32 };
+    if op == NEG {
+        Instruction::new(class | op, dst, 0, 0, 0)
+    } else if rng.random_bool(0.5) {
+        Instruction::new(class | X | op, dst, *srcs.choose(rng).unwrap(), 0, 0)
+    } else if matches!(op, LSH | RSH | ARSH) {
+        Instruction::new(class | op, dst, 0, 0, rng.random_range(0..width))
+    } else {
+        Instruction::new(class | op, dst, 0, 0, rng.random())
+    }
+}
+
+/// Code through the memory area at r1, or None if it has no room. Round trips and reads of
+/// bytes no earlier block stored to leave 0 in `t`, loads of either width put whatever the
+/// area holds in `x` or `y`; `stored` marks the bytes stores may have changed.
+fn memory<R: Rng>(rng: &mut R, r: &Regs, mem: &[u8], stored: &mut [bool]) -> Option<(Vec<Instruction>, bool)> {
+    // Size bits and bytes of each access width
+    let (bits, size) = *[(0x10, 1), (0x08, 2), (0x00, 4), (0x18, 8)].choose(rng).unwrap();
+    let room = mem.len().checked_sub(size)?.min(i16::MAX as usize);
+    let off = rng.random_range(0..=room);
+    match rng.random_range(0..3) {
+        // (x stored then loaded back) - (x truncated to the width)
+        0 => {
+            stored[off..off + size].fill(true);
+            Some((round_trip(r, 1, off as u16, bits, size), true))
+        }
+        // A byte the program never stored to, minus its initial value whatever the byte order
+        1 => {
+            let untouched: Vec<usize> = (0..=room).filter(|&i| !stored[i]).collect();
+            let &off = untouched.choose(rng)?;
+            Some((vec![Instruction::new(0x71, r.t, 1, off as u16, 0), alu_imm(SUB, r.t, mem[off] as u32)], true))
+        }
+        _ => {
+            let dst = *[r.x, r.y].choose(rng).unwrap();
+            Some((vec![Instruction::new(0x61 | bits, dst, 1, off as u16, 0)], false))
+        }
+    }
+}
+
+/// (x stored at `offset` from `base` then loaded back) - (x truncated to the access width)
+fn round_trip(r: &Regs, base: u8, offset: u16, bits: u8, size: usize) -> Vec<Instruction> {
+    let mut code = vec![
+        Instruction::new(0x63 | bits, base, r.x, offset, 0),
+        Instruction::new(0x61 | bits, r.t, base, offset, 0),
+        alu(MOV, r.u, r.x),
+    ];
+    match size {
+        1 | 2 => code.push(alu_imm(AND, r.u, (1 << (8 * size)) - 1)),
+        4 => code.extend([alu_imm(LSH, r.u, 32), alu_imm(RSH, r.u, 32)]),
+        _ => {}
+    }
+    code.push(alu(SUB, r.t, r.u));
+    code
+}
+
+/// A round trip through the `usage` bytes of stack below r10, or None if the width drawn
+/// does not fit. It leaves 0 in `t`.
+fn stack<R: Rng>(rng: &mut R, r: &Regs, usage: u16) -> Option<Vec<Instruction>> {
+    let (bits, size) = *[(0x10, 1), (0x08, 2), (0x00, 4), (0x18, 8)].choose(rng).unwrap();
+    // Verifiers reject stack accesses not aligned to their width
+    let below = size * rng.random_range(1..=(usage as usize / size).max(1));
+    (below <= usage as usize).then(|| round_trip(r, 10, (below as i16).wrapping_neg() as u16, bits, size))
+}
+
+fn lddw(dst: u8, value: u64) -> [Instruction; 2] {
+    [Instruction::new(0x18, dst, 0, 0, value as u32), Instruction::new(0, 0, 0, 0, (value >> 32) as u32)]
+}
+
+/// Generates a program of about `size` instructions returning a random constant, or the one
+/// a rule requires, which is kept as its result. The program mixes random operations on
+/// two inputs with identities that always compute 0 from them, sums those into an accumulator
+/// and adds the constant to it in r0, so any miscomputed operation shows up in the result.
+/// With a memory area, some blocks load and store through r1 within it, and with a
+/// stack_usage rule through r10 within the bytes it allows.
+pub fn generate<R: Rng>(rng: &mut R, size: u32, opts: &GenOptions) -> Program {
+    let mem = vm::memory();
+    // r1 keeps pointing to the memory area
+    let first = if mem.is_empty() { 1 } else { 2 };
+    let mut regs: Vec<u8> = opts.writable_regs.iter().copied().filter(|r| (first..=9).contains(r)).collect();
+    if regs.len() < 5 {
+        eprintln!("--structured programs need five writable registers among r{}-r9", first);
+        std::process::exit(2);
+    }
+    regs.shuffle(rng);
+    let r = Regs { x: regs[0], y: regs[1], t: regs[2], u: regs[3] };
+    let acc = regs[4];
+    let rules = &opts.rules;
+    let allowed = |code: &[Instruction]| code.iter().all(|insn| rules.allows(insn) && opts.templates.iter().any(|t| t.matches(insn)));
+
+    // Constants are loaded with lddw or mov, whichever the rules leave; Rules::new checks
+    // they leave one, and one of the accumulators
+    let (wide, narrow) = (rules.allows(&lddw(0, 0)[0]), rules.allows(&alu_imm(MOV, 0, 0)));
+    // Accumulating with these keeps 0 at 0
+    let accumulators: Vec<u8> = [ADD, SUB, OR, XOR].into_iter().filter(|&op| rules.allows(&alu(op, 0, 0))).collect();
+
+    // Every register starts out defined, as verifiers require
+    let mut blocks: Vec<Vec<Instruction>> = Vec::new();
+    let mut init = if narrow { vec![alu_imm(MOV, acc, 0)] } else { lddw(acc, 0).to_vec() };
+    for &reg in regs.iter().filter(|&&reg| reg != acc) {
+        if wide && (!narrow || rng.random_bool(0.5)) {
+            init.extend(lddw(reg, rng.random()));
+        } else {
+            init.push(alu_imm(MOV, reg, rng.random()));
+        }
+    }
+    blocks.push(init);
+
+    let mut stored = vec![false; mem.len()];
+    let mut slots = blocks[0].len();
+    while slots < size as usize {
+        let block = if !mem.is_empty() && rng.random_bool(0.25) {
+            let Some((mut code, zero)) = memory(rng, &r, mem, &mut stored) else {
+                continue;
+            };
+            if !allowed(&code) {
+                continue;
+            }
+            if zero {
+                code.push(alu(*accumulators.choose(rng).unwrap(), acc, r.t));
+            }
+            code
+        } else if rules.stack_usage.is_some_and(|usage| usage > 0) && rng.random_bool(0.25) {
+            let Some(mut code) = stack(rng, &r, rules.stack_usage.unwrap()).filter(|code| allowed(code)) else {
+                continue;
+            };
+            code.push(alu(*accumulators.choose(rng).unwrap(), acc, r.t));
+            code
+        } else if rng.random_bool(0.5) {
+            let mut code = identity(rng, &r);
+            if !allowed(&code) {
+                continue;
+            }
+            code.push(alu(*accumulators.choose(rng).unwrap(), acc, r.t));
+            code
+        } else {
+            let dst = *[r.x, r.y].choose(rng).unwrap();
+            let code = vec![noise(rng, dst, &regs)];
+            if !allowed(&code) {
+                continue;
+            }
+            code
+        };
+        slots += block.len();
+        blocks.push(block);
+    }
+
+    // Forward branches may skip whole blocks: identities hold whichever way they go
+    let jumps: Vec<Option<Instruction>> = (0..blocks.len())
+        .map(|i| {
+            if i == 0 || !rng.random_bool(0.2) {
+                return None;
+            }
+            let class = if rng.random_bool(0.5) { 0x05 } else { 0x06 };
+            let op = *[0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0x70, 0xa0, 0xb0, 0xc0, 0xd0].choose(rng).unwrap();
+            let jump = if rng.random_bool(0.5) {
+                Instruction::new(class | X | op, r.x, r.y, 0, 0)
+            } else {
+                Instruction::new(class | op, *[r.x, r.y].choose(rng).unwrap(), 0, 0, rng.random())
+            };
+            allowed(&[jump]).then_some(jump)
+        })
+        .collect();
+    let mut program = Program::new();
+    // The start of every block, and the epilogue, reachable from every block
+    let starts: Vec<_> = (0..=blocks.len()).map(|_| program.label()).collect();
+
+    // The epilogue loads the result into r0 and accumulates the 0 in the accumulator into it
+    let result = match rules.result {
+        Some(result) => result,
+        None if wide => rng.random::<u64>(),
+        None => rng.random::<i32>() as u64,
+    };
+    let accumulate = if accumulators.contains(&ADD) { ADD } else { accumulators[0] };
+    for (i, (block, jump)) in blocks.iter().zip(&jumps).enumerate() {
+        program.bind(starts[i]);
+        if let Some(jump) = *jump {
+            let target = rng.random_range(i + 1..=(i + 8).min(blocks.len()));
+            program.jump(jump, starts[target]);
+        }
+        let mut insns = block.iter();
+        while let Some(insn) = insns.next() {
+            if insn.opcode == 0x18 {
+                let high = insns.next().unwrap().imm as u64;
+                program.lddw(insn.dst, high << 32 | insn.imm as u64);
+            } else {
+                program.push(*insn);
+            }
+        }
+    }
+    program.bind(starts[blocks.len()]);
+    if wide {
+        program.lddw(R0, result);
+    } else {
+        program.mov64(R0, result as u32);
+    }
+    program.push(alu(accumulate, R0, acc)).exit();
+    program.result = Some(result);
+
+    // Verifiers require every path to exit. The accumulator holds 0 all along, so paths that
+    // would not can go to the epilogue (load, accumulate, exit) from anywhere and still return the result.
+    // Without ja, they get a copy of the epilogue instead.
+    let nodes = program.nodes_mut();
+    let epilogue = nodes.len() - 3;
+    cfg::close_paths(nodes, epilogue, !rules.allows(&Instruction::new(0x05, 0, 0, 0, 0)));
+    program
+}
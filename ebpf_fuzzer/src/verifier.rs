@@ -0,0 +1,88 @@
+//! Fuzzes rbpf's verifier on its own: programs are only loaded, never run, and what the
+//! verifier decides is checked against the crate's model of programs that must be refused
+
+use crate::findings::{self, Class, Counts};
+use crate::mismatch::Mismatches;
+use crate::vm;
+use crate::{generate_test, repro, templates_in, Args, GenOptions, Template};
+use rand::Rng;
+
+/// Programs holding a template, and how many of those the verifier accepted
+#[derive(Default, Clone, Copy)]
+struct Rate {
+    programs: u32,
+    accepted: u32,
+}
+
+impl Rate {
+    fn percent(self) -> f64 {
+        100.0 * self.accepted as f64 / self.programs as f64
+    }
+}
+
+/// The opcode of a template, with the fields it fixes
+fn label(template: &Template) -> String {
+    let mut label = format!("{:#04x}", template.opcode);
+    if let Some(src) = template.src {
+        label.push_str(&format!(" src={}", src));
+    }
+    if let Some(imm) = template.imm {
+        label.push_str(&format!(" imm={:#x}", imm));
+    }
+    if let Some(offset) = template.offset {
+        label.push_str(&format!(" off={}", offset as i16));
+    }
+    label
+}
+
+/// Runs every generated program through rbpf's verifier only, and archives those it panics
+/// on and the first of each group of verdicts the crate's model disagrees with. Prints how
+/// often programs holding each template were accepted, least often first.
+pub fn run<R: Rng>(rng: &mut R, args: &Args, opts: &GenOptions) {
+    let mut rates = vec![Rate::default(); opts.templates.len()];
+    let mut accepted = 0;
+    let mut findings = Counts::default();
+    let mut mismatches = Mismatches::default();
+
+    for i in 0..args.count {
+        let size = opts.random_size(rng);
+        let (bytes, result) = generate_test(rng, size, opts);
+        let verdict = vm::verify_catching(&bytes);
+
+        let mut present = vec![false; opts.templates.len()];
+        for t in templates_in(&bytes, opts) {
+            present[t] = true;
+        }
+        let ok = matches!(verdict, Ok(Ok(())));
+        for (rate, _) in rates.iter_mut().zip(&present).filter(|(_, &present)| present) {
+            rate.programs += 1;
+            rate.accepted += ok as u32;
+        }
+        accepted += ok as u32;
+
+        let problem = match &verdict {
+            Err(msg) => (Class::Crash, format!("verifier panicked: {}", msg)),
+            Ok(verdict) => match mismatches.check(&bytes, verdict.as_ref().map(|_| ()).map_err(String::as_str)) {
+                Some(mismatch) => (Class::Mismatch, mismatch),
+                None => continue,
+            },
+        };
+        println!("program {}: {}: {}", i, problem.0, problem.1);
+        findings.add(problem.0);
+        if let Some(path) = findings::write(args, problem.0, i.into(), Some(opts.seed), &bytes, result) {
+            repro::write(&path, i.into(), opts.seed, &format!("cat {}", repro::quote(&path)));
+        }
+    }
+
+    println!("verifier acceptance per template (programs holding it):");
+    let mut order: Vec<usize> = (0..rates.len()).filter(|&t| rates[t].programs > 0).collect();
+    order.sort_by(|&a, &b| rates[a].percent().total_cmp(&rates[b].percent()));
+    for t in order {
+        println!("  {:<24} {:>5.1}% ({})", label(opts.templates[t]), rates[t].percent(), rates[t].programs);
+    }
+    mismatches.print("rbpf's verifier");
+    println!("{} across {} programs, {} accepted", findings, args.count, accepted);
+    if findings.total() > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,210 @@
+//! Runs programs through rbpf
+
+use crate::helpers;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+static HELPERS: OnceLock<Vec<u32>> = OnceLock::new();
+static MEMORY: OnceLock<Vec<u8>> = OnceLock::new();
+static MBUFF: OnceLock<bool> = OnceLock::new();
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// How long a program may run before it counts as a hang, unless set otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Size of the metadata buffer r1 points to in mbuff mode
+pub const MBUFF_SIZE: usize = 32;
+/// Offset in the metadata buffer of the pointer to the start of the memory area
+pub const MBUFF_DATA: usize = 8;
+/// Offset in the metadata buffer of the pointer one past the end of the memory area
+pub const MBUFF_DATA_END: usize = 16;
+
+/// Outcome of running a program in the rbpf interpreter
+#[derive(Debug)]
+pub enum Outcome {
+    /// The program ran to completion and returned r0
+    Returned(u64),
+    /// rbpf refused to load or execute the program
+    Error(String),
+    /// rbpf panicked, which is always a bug
+    Panicked(String),
+    /// The program was still running when the timeout ran out. rbpf counts no steps, so a
+    /// loop it never leaves would otherwise stall the caller for good.
+    TimedOut,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Returned(r0) => write!(f, "returned {:#x}", r0),
+            Outcome::Error(err) => write!(f, "error: {}", err),
+            Outcome::Panicked(msg) => write!(f, "panicked: {}", msg),
+            Outcome::TimedOut => write!(f, "timed out after {}ms", timeout().as_millis()),
+        }
+    }
+}
+
+/// Registers the helpers in `ranges` with the VM instead of every kernel helper, if any
+pub fn register(ranges: &[RangeInclusive<u32>]) {
+    if ranges.is_empty() {
+        return;
+    }
+    let ids: BTreeSet<u32> = ranges.iter().flat_map(|range| range.clone()).collect();
+    if HELPERS.set(ids.into_iter().collect()).is_err() {
+        panic!("Helpers registered after the VM was already in use");
+    }
+}
+
+/// Sorted IDs of the helpers registered with the VM, each stubbed out
+pub fn helpers() -> &'static [u32] {
+    HELPERS.get_or_init(|| helpers::HELPER_IDS.collect())
+}
+
+/// Gives programs a memory area of `size` bytes through r1 instead of an empty one, filled
+/// with a fixed pattern so loads from it have known results
+pub fn set_memory(size: usize) {
+    if size == 0 {
+        return;
+    }
+    load_memory((0..size).map(|i| (i as u32).wrapping_mul(0x9e37_79b9).rotate_right(24) as u8).collect());
+}
+
+/// Gives programs `bytes` as the memory area through r1
+pub fn load_memory(bytes: Vec<u8>) {
+    if MEMORY.set(bytes).is_err() {
+        panic!("Memory set after the VM was already in use");
+    }
+}
+
+/// Contents of the memory area programs get through r1 when they start
+pub fn memory() -> &'static [u8] {
+    MEMORY.get_or_init(Vec::new)
+}
+
+/// Runs programs as rbpf's mbuff VMs do: r1 points to a metadata buffer holding pointers to
+/// the start and end of the memory area, rather than to the area itself
+pub fn use_mbuff() {
+    if MBUFF.set(true).is_err() {
+        panic!("Mbuff mode enabled after the VM was already in use");
+    }
+}
+
+/// Lets programs run for `timeout` before they count as hangs
+pub fn set_timeout(timeout: Duration) {
+    if TIMEOUT.set(timeout).is_err() {
+        panic!("Timeout set after the VM was already in use");
+    }
+}
+
+/// How long programs may run before they count as hangs
+pub fn timeout() -> Duration {
+    *TIMEOUT.get_or_init(|| DEFAULT_TIMEOUT)
+}
+
+/// Whether r1 points to a metadata buffer rather than to the memory area
+pub fn mbuff() -> bool {
+    *MBUFF.get_or_init(|| false)
+}
+
+/// The metadata buffer for a memory area at `start`, with the pointers at `MBUFF_DATA` and
+/// `MBUFF_DATA_END` and a fixed pattern around them
+pub fn metadata(start: u64) -> [u8; MBUFF_SIZE] {
+    let mut mbuff: [u8; MBUFF_SIZE] = std::array::from_fn(|i| 0xa0 | i as u8);
+    let end = start + memory().len() as u64;
+    mbuff[MBUFF_DATA..MBUFF_DATA + 8].copy_from_slice(&start.to_le_bytes());
+    mbuff[MBUFF_DATA_END..MBUFF_DATA_END + 8].copy_from_slice(&end.to_le_bytes());
+    mbuff
+}
+
+/// Runs rbpf's verifier on a program
+pub fn verify(bytes: &[u8]) -> Result<(), String> {
+    rbpf::EbpfVmRaw::new(Some(bytes)).map(|_| ()).map_err(|err| err.to_string())
+}
+
+/// Runs rbpf's verifier on a program as `verify` does, or returns what it panicked with
+pub fn verify_catching(bytes: &[u8]) -> Result<Result<(), String>, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| verify(bytes))).map_err(panic_message)
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    payload
+        .downcast_ref::<String>()
+        .cloned()
+        .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+        .unwrap_or_default()
+}
+
+/// A thread programs run on, so that one still running at the timeout can be left behind
+struct Executor {
+    programs: Sender<Vec<u8>>,
+    outcomes: Receiver<Outcome>,
+}
+
+impl Executor {
+    fn start() -> Executor {
+        let (programs, requests) = mpsc::channel::<Vec<u8>>();
+        let (results, outcomes) = mpsc::channel();
+        thread::Builder::new()
+            .name("rbpf".to_string())
+            .spawn(move || {
+                for bytes in requests {
+                    if results.send(execute(&bytes)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to start the VM thread");
+        Executor { programs, outcomes }
+    }
+}
+
+thread_local! {
+    static EXECUTOR: RefCell<Option<Executor>> = const { RefCell::new(None) };
+}
+
+/// Verifies and interprets a program with a fresh copy of the memory area, passed through a
+/// metadata buffer in mbuff mode, with every registered helper stubbed out. A program still
+/// running at the timeout is left to spin on its thread, which the next program replaces.
+pub fn run(bytes: &[u8]) -> Outcome {
+    EXECUTOR.with(|executor| {
+        let mut executor = executor.borrow_mut();
+        let running = executor.get_or_insert_with(Executor::start);
+        running.programs.send(bytes.to_vec()).expect("VM thread went away");
+        running.outcomes.recv_timeout(timeout()).unwrap_or_else(|_| {
+            *executor = None;
+            Outcome::TimedOut
+        })
+    })
+}
+
+fn execute(bytes: &[u8]) -> Outcome {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut mem = memory().to_vec();
+        if mbuff() {
+            let mbuff = metadata(mem.as_ptr() as u64);
+            let mut vm = rbpf::EbpfVmMbuff::new(Some(bytes))?;
+            for &id in helpers() {
+                vm.register_helper(id, helpers::stub)?;
+            }
+            return vm.execute_program(&mem, &mbuff);
+        }
+        let mut vm = rbpf::EbpfVmRaw::new(Some(bytes))?;
+        for &id in helpers() {
+            vm.register_helper(id, helpers::stub)?;
+        }
+        vm.execute_program(&mut mem)
+    }));
+
+    match result {
+        Ok(Ok(r0)) => Outcome::Returned(r0),
+        Ok(Err(err)) => Outcome::Error(err.to_string()),
+        Err(payload) => Outcome::Panicked(panic_message(payload)),
+    }
+}